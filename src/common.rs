@@ -26,6 +26,15 @@ pub fn read_exact(file: &mut Box<dyn Read>, buf: &mut [u8]) -> usize {
     return total_read;
 }
 
+/** or_exit
+ * Unwraps a result, printing the error and exiting on failure
+ * Parameters: Result
+ * Returns: Value
+ */
+pub fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    return result.unwrap_or_else(|err| { eprintln!("{}", err); exit(1); });
+}
+
 /** write_safe
  * Writes data to stdout with broken pipe handling
  * Parameters: Output file writer, Data buffer
@@ -49,6 +58,25 @@ pub fn get_file_stem(file_path: &str) -> String {
     return Path::new(file_path).file_stem().unwrap_or(std::ffi::OsStr::new("pipe")).to_str().unwrap().to_string();
 }
 
+/** get_input_size
+ * Gets the size of the input file
+ * Parameters: File path
+ * Returns: File size in bytes, 0 for pipe or unknown
+ */
+pub fn get_input_size(file_path: &str) -> u64 {
+    if PIPEIN.contains(&file_path) { return 0; }
+    return std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+}
+
+/** format_progress
+ * Formats the progress of a process
+ * Parameters: Progress in percent
+ * Returns: Formatted progress string, empty if unknown
+ */
+pub fn format_progress(progress: Option<f64>) -> String {
+    return match progress { Some(p) => format!(" progress={:.1}%", p), None => String::new() };
+}
+
 /** format_time
  * Formats time in seconds to human-readable format
  * Parameters: Time in seconds
@@ -4,54 +4,140 @@
  * Description: Decoder implementation example
  */
 
-use frad::{Decoder, ASFH, PCMFormat, f64cvt::f64_to_any};
+use frad::{head, wav, Decoder, PCMFormat, WindowKind, ASFH};
 use crate::{
-    common::{self, check_overwrite, get_file_stem, read_exact, write_safe, PIPEIN, PIPEOUT},
-    tools::{cli::CliParams, process::ProcessInfo}
+    common::{self, check_overwrite, get_file_stem, get_input_size, read_exact, write_safe, PIPEIN, PIPEOUT},
+    header::read_meta,
+    tools::{cli::CliParams, loudness::Loudness, pcmproc::{self, DownmixTarget}, process::{LogFormat, ProcessInfo}, timestretch::TimeStretch}
 };
-use std::{fs::File, io::{Read, Write}, path::Path, process::exit};
+use std::{fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::Path, process::exit};
 
 use rodio::{buffer::SamplesBuffer, OutputStream, Sink};
 use same_file::is_same_file;
 
 /** write
  * Writes PCM data to file or sink
- * Parameters: Output file/sink, PCM data, PCM format, Sample rate
- * Parameters: Output file, PCM data
+ * Parameters: Output file, Output sink, PCM data, PCM bytes, Sample rate
  * Returns: None
  */
-fn write(file: &mut Box<dyn Write>, sink: Option<&mut Sink>, pcm: Vec<Vec<f64>>, fmt: &PCMFormat, srate: u32) {
-    if pcm.is_empty() { return; }
+fn write(file: &mut Box<dyn Write>, sink: Option<&mut Sink>, pcm: Vec<Vec<f64>>, bytes: &[u8], srate: u32) {
     match sink {
-        Some(s) => s.append(SamplesBuffer::new(
-                pcm[0].len() as u16, srate,
-                pcm.into_iter().flatten().map(|x| x as f32).collect::<Vec<f32>>()
-            )),
-        None => {
-            let pcm_bytes: Vec<u8> = pcm.into_iter().flatten().flat_map(|x| f64_to_any(x, fmt)).collect();
-            write_safe(file, &pcm_bytes);
+        Some(s) => {
+            // rodio panics on zero channels
+            // A time stretcher flushing on a layout change leaves mixed channel counts in one block
+            for run in pcm.chunk_by(|a, b| a.len() == b.len()) {
+                if run[0].is_empty() { continue; } // rodio panics on zero channels
+                s.append(SamplesBuffer::new(
+                    run[0].len() as u16, srate,
+                    run.iter().flatten().map(|&x| x as f32).collect::<Vec<f32>>()
+                ));
+            }
+        },
+        None => { if !bytes.is_empty() { write_safe(file, bytes); } }
+    }
+}
+
+/** WavOutput
+ * RIFF/WAVE wrapping of one output file
+ */
+struct WavOutput {
+    tag: u16, fmt: PCMFormat,
+    srate: u32, channels: u16,
+    data_len: u64,
+    started: bool,
+}
+
+impl WavOutput {
+    fn new(tag: u16, fmt: PCMFormat) -> WavOutput {
+        return WavOutput { tag, fmt, srate: 0, channels: 0, data_len: 0, started: false };
+    }
+
+    /** write
+     * Writes PCM data, preceded by the header on the first data of the file
+     * Parameters: Output file, PCM bytes, Sample rate, Channel count, Pipe output flag
+     */
+    fn write(&mut self, file: &mut Box<dyn Write>, bytes: &[u8], srate: u32, channels: u16, pipe: bool) {
+        if bytes.is_empty() { return; }
+        if !self.started {
+            (self.srate, self.channels, self.started) = (srate, channels, true);
+            // Pipe output cannot be patched, so it claims the maximum size
+            let data_len = if pipe { u32::MAX as u64 } else { 0 };
+            write_safe(file, &wav::header(self.tag, self.fmt, self.srate, self.channels, data_len));
+        }
+        write_safe(file, bytes);
+        self.data_len += bytes.len() as u64;
+    }
+
+    /** finish
+     * Word-aligns the data chunk and patches the header with its size, then resets for the next file
+     * Parameters: Output file, Output file path, None for pipe output
+     */
+    fn finish(&mut self, file: &mut Box<dyn Write>, path: Option<&str>) {
+        // A file without audio still gets a valid, empty WAV
+        if !self.started { write_safe(file, &wav::header(self.tag, self.fmt, self.srate, self.channels, 0)); }
+        else {
+            if self.data_len % 2 == 1 { write_safe(file, &[0]); }
+            file.flush().unwrap();
+            if let Some(path) = path {
+                let mut patch = OpenOptions::new().write(true).open(path).unwrap();
+                patch.seek(SeekFrom::Start(0)).unwrap();
+                patch.write_all(&wav::header(self.tag, self.fmt, self.srate, self.channels, self.data_len)).unwrap();
+            }
         }
+        *self = WavOutput { data_len: 0, started: false, ..*self };
     }
 }
 
+/** downmix
+ * Downmixes decoded PCM and converts it again to the output format
+ * Parameters: Decoder, Target layout, PCM data, PCM bytes, Unknown layout warning flag
+ */
+fn downmix(decoder: &mut Decoder, layout: DownmixTarget, pcm: &mut Vec<Vec<f64>>, bytes: &mut Vec<u8>, warn: &mut bool) {
+    let channels = match pcm.first() { Some(sample) => sample.len() as u16, None => return };
+    if channels <= layout.channels() { return; }
+    if !pcmproc::can_downmix(channels) {
+        if *warn { eprintln!("No downmix known for {} channels, passing them through", channels); *warn = false; }
+        return;
+    }
+    let (mixed, channels) = pcmproc::downmix(&pcm.concat(), channels, layout);
+    *pcm = mixed.chunks(channels as usize).map(|x| x.to_vec()).collect();
+    *bytes = decoder.pcm_bytes(pcm);
+}
+
 /** logging_decode
  * Logs a message to stderr
- * Parameters: Log level, Process info, Linefeed flag, ASFH
+ * Parameters: Log level, Log format, Process info, Linefeed flag, ASFH
  */
-fn logging_decode(loglevel: u8, log: &ProcessInfo, linefeed: bool, asfh: &ASFH) {
+fn logging_decode(loglevel: u8, logfmt: LogFormat, log: &ProcessInfo, linefeed: bool, asfh: &ASFH) {
     if loglevel == 0 { return; }
+    if logfmt == LogFormat::Json {
+        let mut record = match log.get_record(linefeed) { Some(record) => record, None => return };
+        if loglevel > 1 {
+            record["profile"] = asfh.profile.into();
+            record["bits"] = asfh.bit_depth_bits().into();
+            record["channels"] = asfh.channels.into();
+            record["srate"] = asfh.srate.into();
+            record["ecc"] = if asfh.ecc { asfh.ecc_ratio.to_vec().into() } else { serde_json::Value::Null };
+        }
+        eprintln!("{}", record);
+        return;
+    }
 
-    let mut out = Vec::new();
-
-    out.push(format!("size={}B time={} bitrate={}bit/s speed={}x    ",
+    let status = format!("size={}B time={} bitrate={}bit/s speed={}x",
         common::format_si(log.get_total_size() as f64), common::format_time(log.get_duration()), common::format_si(log.get_bitrate()), common::format_speed(log.get_speed())
-    ));
-    if loglevel > 1 {
-        out.push(format!("Profile {}, {}bits {}ch@{}Hz, ECC={}    ", asfh.profile,
-            frad::BIT_DEPTHS[asfh.profile as usize][asfh.bit_depth_index as usize], asfh.channels, asfh.srate,
+    );
+    let info = if loglevel > 1 {
+        Some(format!("Profile {}, {}bits {}ch@{}Hz, ECC={}", asfh.profile,
+            asfh.bit_depth_bits(), asfh.channels, asfh.srate,
             if asfh.ecc { format!("{}/{}", asfh.ecc_ratio[0], asfh.ecc_ratio[1]) } else { "disabled".to_string() }
-        ));
-    }
+        ))
+    } else { None };
+    // The progress bar holds a single line, so stream info follows the status
+    let line = match &info { Some(info) => format!("{} | {}", status, info), None => status.clone() };
+    if log.draw_bar(line, linefeed) { return; }
+
+    let mut out = vec![format!("{}{}    ", status, common::format_progress(log.get_progress()))];
+    if let Some(info) = info { out.push(format!("{}    ", info)); }
 
     let line_count = out.len() - 1;
     eprint!("{}", out.join("\n"));
@@ -60,6 +146,48 @@ fn logging_decode(loglevel: u8, log: &ProcessInfo, linefeed: bool, asfh: &ASFH)
     else { for _ in 0..line_count { eprint!("\x1b[1A"); } eprint!("\r"); }
 }
 
+/** logging_loudness
+ * Logs the loudness of the whole decode to stderr
+ * Parameters: Log format, Loudness meter
+ */
+fn logging_loudness(logfmt: LogFormat, meter: &Loudness) {
+    let (integrated, range, peak) = (Loudness::integrated(meter.blocks()), meter.range(), meter.true_peak());
+    if logfmt == LogFormat::Json {
+        let finite = |x: f64| if x.is_finite() { x.into() } else { serde_json::Value::Null };
+        eprintln!("{}", serde_json::json!({
+            "integrated": integrated.map_or(serde_json::Value::Null, finite),
+            "range": range.map_or(serde_json::Value::Null, finite),
+            "true_peak": finite(peak),
+        }));
+        return;
+    }
+    let format = |x: Option<f64>, unit: &str| x.filter(|x| x.is_finite()).map_or("-inf".to_string(), |x| format!("{:.1}{}", x, unit));
+    eprintln!("integrated={} range={} true-peak={}",
+        format(integrated, "LUFS"), format(range.or(Some(0.0)), "LU"), format(Some(peak), "dBTP"));
+}
+
+/** scan_peak
+ * Decodes a FrAD file without output to find its absolute peak, the first pass of normalisation
+ * Parameters: Input file, ECC flag, Overlap window, Pre-roll and post-roll to trim, Total sample count
+ * Returns: Absolute peak
+ */
+fn scan_peak(rfile: &str, fix_error: bool, window: WindowKind, trim: (u64, u64), total: Option<u64>) -> f64 {
+    let mut readfile: Box<dyn Read> = Box::new(File::open(rfile).unwrap());
+    let mut decoder = Decoder::new(fix_error);
+    decoder.set_overlap_window(window);
+    decoder.set_trim(trim.0, trim.1);
+    decoder.set_total_samples(total);
+
+    let mut peak = 0.0f64;
+    loop {
+        let mut buf = vec![0u8; 32768];
+        let readlen = read_exact(&mut readfile, &mut buf);
+        if readlen == 0 && decoder.is_empty() { break; }
+        peak = peak.max(pcmproc::peak(&decoder.process(&buf[..readlen]).pcm));
+    }
+    return peak.max(pcmproc::peak(&decoder.flush().pcm));
+}
+
 /** decode
  * Decodes any found FrAD frames in the input file to f64be PCM
  * Parameters: Input file, CLI parameters
@@ -77,14 +205,32 @@ pub fn decode(rfile: String, mut params: CliParams, play: bool) {
         eprintln!("Input and output files cannot be the same"); exit(1);
     }
 
+    // WAV output holds the little-endian counterpart of the PCM format
+    let mut wav = if params.wav && !play {
+        let Some((tag, fmt)) = wav::wav_format(params.pcm) else {
+            eprintln!("{}", frad::FradError::UnsupportedWavFormat(params.pcm)); exit(1);
+        };
+        params.pcm = fmt;
+        Some(WavOutput::new(tag, fmt))
+    } else { None };
+    let ext = if wav.is_some() { "wav" } else { "pcm" };
+
     if wfile_prim.is_empty() { wfile_prim = get_file_stem(&rfile); }
-    else if wfile_prim.ends_with(".pcm") { wfile_prim = wfile_prim[..wfile_prim.len() - 4].to_string(); }
+    else if wfile_prim.ends_with(&format!(".{}", ext)) { wfile_prim = wfile_prim[..wfile_prim.len() - 4].to_string(); }
 
-    let mut wfile = format!("{}.pcm", wfile_prim);
+    let mut wfile = format!("{}.{}", wfile_prim, ext);
     if !wpipe { check_overwrite(&wfile, params.overwrite); }
 
-    let mut readfile: Box<dyn Read> = if !rpipe { Box::new(File::open(rfile).unwrap()) } else { Box::new(std::io::stdin()) };
-    let mut writefile: Box<dyn Write> = if !wpipe { Box::new(File::create(wfile).unwrap()) } else { Box::new(std::io::stdout()) };
+    // Pre-roll, post-roll and sample count are recorded in the header, pipe input leaves them to the decoder
+    let meta = if !rpipe { read_meta(&rfile) } else { Vec::new() };
+    let (preroll, postroll) = head::get_roll(&meta);
+    // Without trimming, the original sample count still cuts the padding of the last frame
+    let total = head::get_total_samples(&meta).map(|n| if params.trim { n } else { preroll + n + postroll });
+    let (preroll, postroll) = if params.trim { (preroll, postroll) } else { (0, 0) };
+
+    let input_size = get_input_size(&rfile);
+    let mut readfile: Box<dyn Read> = if !rpipe { Box::new(File::open(&rfile).unwrap()) } else { Box::new(std::io::stdin()) };
+    let mut writefile: Box<dyn Write> = if !wpipe { Box::new(File::create(&wfile).unwrap()) } else { Box::new(std::io::stdout()) };
 
     let (_stream, _stream_handle, mut sink) = if play {
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
@@ -93,11 +239,25 @@ pub fn decode(rfile: String, mut params: CliParams, play: bool) {
     } else { (None, None, None) };
 
     params.speed = if params.speed > 0.0 { params.speed } else { 1.0 };
-    sink.as_mut().map(|s| { s.set_speed(params.speed as f32); params.loglevel = 0; });
-    let pcm_fmt = params.pcm;
+    // Time-stretch the PCM to keep the pitch, or let the sink resample it, as keys are meant to shift it
+    let mut stretch = if play && params.preserve_pitch && !params.transpose && params.speed != 1.0 { Some(TimeStretch::new(params.speed)) } else { None };
+    sink.as_mut().map(|s| { s.set_speed(if stretch.is_some() { 1.0 } else { params.speed as f32 }); params.loglevel = 0; });
 
     let mut decoder = Decoder::new(params.enable_ecc);
+    if !play { decoder.set_output_format(Some(params.pcm)); }
+    decoder.set_dither(params.dither);
+    decoder.set_overlap_window(params.overlap_window);
+    if !rpipe { decoder.set_trim(preroll, postroll); decoder.set_total_samples(total); }
+    else { decoder.set_header_trim(Some(params.trim)); }
+    // Files can be read twice to scale by their peak, streams are limited at the target instead
+    if let Some(target) = params.normalize {
+        if !rpipe && !wpipe { decoder.set_gain(pcmproc::peak_gain(scan_peak(&rfile, params.enable_ecc, params.overlap_window, (preroll, postroll), total), target)); }
+        else { decoder.set_ceiling(Some(pcmproc::db_to_linear(target))); }
+    }
     let (mut no, mut procinfo) = (0, ProcessInfo::new());
+    procinfo.set_total_input_bytes(input_size);
+    let mut meter = if params.loudness { Some(Loudness::new()) } else { None };
+    let mut dmx_warn = !params.quiet;
     loop {
         let mut buf = vec![0u8; 32768];
         let readlen = read_exact(&mut readfile, &mut buf);
@@ -105,21 +265,58 @@ pub fn decode(rfile: String, mut params: CliParams, play: bool) {
 
         let decoded = decoder.process(&buf[..readlen]);
         procinfo.update(readlen, decoded.pcm.len(), decoded.srate);
-        write(&mut writefile, sink.as_mut(), decoded.pcm, &pcm_fmt, decoded.srate);
-        logging_decode(params.loglevel, &procinfo, false, decoder.get_asfh());
+        procinfo.update_input(readlen);
+        if let Some(m) = meter.as_mut() { m.process(&decoded.pcm, decoded.srate); }
+        let (mut pcm, mut bytes) = (decoded.pcm, decoded.bytes);
+        if let Some(layout) = params.downmix { downmix(&mut decoder, layout, &mut pcm, &mut bytes, &mut dmx_warn); }
+        let channels = pcm.first().map_or(0, |x| x.len()) as u16;
+        if let Some(ts) = stretch.as_mut() {
+            pcm = ts.process(pcm);
+            if decoded.crit { pcm.extend(ts.flush()); }
+        }
+        match wav.as_mut() {
+            Some(w) => w.write(&mut writefile, &bytes, decoded.srate, channels, wpipe),
+            None => write(&mut writefile, sink.as_mut(), pcm, &bytes, decoded.srate)
+        }
+        logging_decode(params.loglevel, params.logfmt, &procinfo, false, decoder.get_asfh());
 
-        if decoded.crit && !wpipe {
+        // Past the split limit, later segments are concatenated into the last file
+        if decoded.crit && !wpipe && params.max_splits != 0 && no >= params.max_splits {
+            if no == params.max_splits && !params.quiet {
+                eprintln!("Split limit of {} reached, appending further segments to {}", params.max_splits, wfile);
+            }
+            no = params.max_splits + 1;
+        }
+        else if decoded.crit && !wpipe {
             procinfo.block();
-            no += 1; wfile = format!("{}.{}.pcm", wfile_prim, no);
+            if let Some(w) = wav.as_mut() { w.finish(&mut writefile, Some(&wfile)); }
+            no += 1; wfile = format!("{}.{}.{}", wfile_prim, no, ext);
             check_overwrite(&wfile, params.overwrite);
-            writefile = Box::new(File::create(wfile).unwrap());
+            writefile = Box::new(File::create(&wfile).unwrap());
             procinfo.unblock();
         }
     }
     let decoded = decoder.flush();
     procinfo.update(0, decoded.pcm.len(), decoded.srate);
-    write(&mut writefile, sink.as_mut(), decoded.pcm, &pcm_fmt, decoded.srate);
-    logging_decode(params.loglevel, &procinfo, true, decoder.get_asfh());
+    if let Some(m) = meter.as_mut() { m.process(&decoded.pcm, decoded.srate); }
+    let (mut pcm, mut bytes) = (decoded.pcm, decoded.bytes);
+    if let Some(layout) = params.downmix { downmix(&mut decoder, layout, &mut pcm, &mut bytes, &mut dmx_warn); }
+    let channels = pcm.first().map_or(0, |x| x.len()) as u16;
+    if let Some(ts) = stretch.as_mut() { pcm = ts.process(pcm); pcm.extend(ts.flush()); }
+    match wav.as_mut() {
+        Some(w) => {
+            w.write(&mut writefile, &bytes, decoded.srate, channels, wpipe);
+            w.finish(&mut writefile, if wpipe { None } else { Some(&wfile) });
+        },
+        None => write(&mut writefile, sink.as_mut(), pcm, &bytes, decoded.srate)
+    }
+    logging_decode(params.loglevel, params.logfmt, &procinfo, true, decoder.get_asfh());
+    if let Some(m) = meter { logging_loudness(params.logfmt, &m); }
+    if !params.quiet {
+        for (start, end) in decoder.dropped_frame_ranges() {
+            eprintln!("Dropped frames detected: sequence {} ~ {}", start, end);
+        }
+    }
 
     sink.map(|s| s.sleep_until_end());
 }
\ No newline at end of file
@@ -4,12 +4,12 @@
  * Description: Encoder implementation example
  */
 
-use frad::{Encoder, profiles::LOSSLESS, head};
+use frad::{f64cvt::{any_to_f64, f64_to_any}, Encoder, Endian::Big, FradError, PCMFormat, profiles::{compact, COMPACT, LOSSLESS}, head::{self, PictureType}, wav};
 use crate::{
-    common::{check_overwrite, format_si, format_speed, format_time, get_file_stem, read_exact, write_safe, PIPEIN, PIPEOUT},
-    tools::{cli::CliParams, process::ProcessInfo}
+    common::{check_overwrite, or_exit, format_progress, format_si, format_speed, format_time, get_file_stem, get_input_size, read_exact, write_safe, PIPEIN, PIPEOUT},
+    tools::{cli::CliParams, process::{LogFormat, ProcessInfo}, resample::Resampler}
 };
-use std::{fs::File, io::{Read, Write}, path::Path, process::exit};
+use std::{fs::File, io::{Cursor, Read, Write}, path::Path, process::exit};
 use same_file::is_same_file;
 
 /** set_files
@@ -47,65 +47,189 @@ pub fn set_files(rfile: String, mut wfile: String, profile: u8, overwrite: bool)
 
 /** logging_encode
  * Logs a message to stderr
- * Parameters: Log level, Processing info, line feed flag
+ * Parameters: Log level, Log format, Processing info, line feed flag
  */
-pub fn logging_encode(loglevel: u8, log: &ProcessInfo, linefeed: bool) {
+pub fn logging_encode(loglevel: u8, logfmt: LogFormat, log: &ProcessInfo, linefeed: bool) {
     if loglevel == 0 { return; }
-    eprint!("size={}B time={} bitrate={}bit/s speed={}x    \r",
+    if logfmt == LogFormat::Json {
+        let record = match log.get_record(linefeed) { Some(record) => record, None => return };
+        eprintln!("{}", record);
+        return;
+    }
+    let status = format!("size={}B time={} bitrate={}bit/s speed={}x",
         format_si(log.get_total_size() as f64), format_time(log.get_duration()), format_si(log.get_bitrate()), format_speed(log.get_speed())
     );
+    if log.draw_bar(status, linefeed) { return; }
+    eprint!("size={}B time={} bitrate={}bit/s speed={}x{}    \r",
+        format_si(log.get_total_size() as f64), format_time(log.get_duration()), format_si(log.get_bitrate()), format_speed(log.get_speed()),
+        format_progress(log.get_progress())
+    );
     if linefeed { eprintln!(); }
 }
 
-/** encode
- * Encodes PCM to FrAD
- * Parameters: Input file, CLI parameters, Log level
+/** warn_inexact
+ * Warns if the encoder found input not exactly representable at its bit depth
+ * Parameters: Encoder
  */
-pub fn encode(input: String, params: CliParams) {
-    if input.is_empty() { eprintln!("Input file must be given"); exit(1); }
-
-    let mut encoder = Encoder::new(params.profile, params.pcm);
-    if params.srate == 0 { eprintln!("Sample rate should be set except zero"); exit(1); }
-    if params.channels == 0 { eprintln!("Channel count should be set except zero"); exit(1); }
-
-    encoder.set_srate(params.srate);
-    encoder.set_channels(params.channels as u16);
+pub fn warn_inexact(encoder: &Encoder) {
+    let frames = encoder.get_inexact_frames();
+    if frames == 0 { return; }
+    eprintln!("Warning: Input of {} frames is not exactly representable in {} bits, encoding is not lossless", frames, encoder.get_bit_depth());
+}
 
-    encoder.set_frame_size(params.frame_size);
+/** configure
+ * Applies the frame size and non-critical encoder options of the CLI parameters
+ * Parameters: Encoder, CLI parameters
+ */
+pub fn configure(encoder: &mut Encoder, params: &CliParams) {
+    or_exit(encoder.set_frame_size(params.frame_size));
 
-    encoder.set_ecc(params.enable_ecc, params.ecc_ratio);
+    if let Err(e) = encoder.set_ecc(params.enable_ecc, params.ecc_ratio) {
+        eprintln!("{}", e);
+        eprintln!("Setting ECC to default 96 24");
+        or_exit(encoder.set_ecc(params.enable_ecc, [96, 24]));
+    }
     encoder.set_little_endian(params.little_endian);
-    encoder.set_bit_depth(params.bits);
     encoder.set_overlap_ratio(params.overlap_ratio);
+    encoder.set_frame_sequence(params.frame_seq);
+    encoder.set_dc_frames(params.dc_frames);
+    encoder.set_lossless_predictor(params.predictor);
+    encoder.set_analysis_window(params.window);
+    encoder.set_overlap_window(params.overlap_window);
+    encoder.set_check_inexact(params.warn_inexact && !params.quiet);
+    encoder.set_build_index(params.build_index);
+    encoder.set_verify_output(params.verify_output);
+
+    encoder.set_loss_level(Encoder::loss_level_from_index(params.losslevel));
+    encoder.set_target_bitrate(params.bitrate.map(|x| x.saturating_mul(1000)));
+}
+
+/** read_wav
+ * Reads the WAV header of the input, if any, and takes the stream format from it
+ * Parameters: Input file reader, Input size, CLI parameters
+ * Returns: Reader of the PCM data, PCM data size in bytes, 0 if unknown
+ */
+pub fn read_wav(mut readfile: Box<dyn Read>, input_size: u64, params: &mut CliParams) -> (Box<dyn Read>, u64) {
+    // 1. Peek the magic, pipes cannot seek back so the peeked bytes are chained in front
+    let mut peek = vec![0u8; wav::HEADER_LEN];
+    let peeked = read_exact(&mut readfile, &mut peek);
+    peek.truncate(peeked);
+    let is_wav = wav::is_wav(&peek);
+    let mut readfile: Box<dyn Read> = Box::new(Cursor::new(peek).chain(readfile));
+    if !is_wav { return (readfile, input_size); }
+
+    // 2. Stream format, explicit settings must agree with the header
+    let info = or_exit(wav::read_header(&mut readfile));
+    or_exit(wav::check_format(&info, params.srate, params.channels));
+    (params.srate, params.channels, params.pcm) = (info.srate, info.channels, info.fmt);
+
+    // 3. Stop at the end of the data chunk, chunks after it are not audio
+    return match info.data_len {
+        Some(len) => (Box::new(readfile.take(len)), len),
+        None => (readfile, 0)
+    };
+}
+
+/** resample
+ * Resamples a chunk of PCM to f64be, keeping a partial frame for the next chunk
+ * Parameters: Resampler, Input PCM format, Channel count, Partial frame buffer, PCM chunk, End of stream flag
+ * Returns: Resampled f64be PCM
+ */
+fn resample(resampler: &mut Resampler, fmt: PCMFormat, channels: u16, rest: &mut Vec<u8>, chunk: &[u8], end: bool) -> Vec<u8> {
+    rest.extend(chunk);
+    let (bytes_per_sample, frame_bytes) = (fmt.bit_depth() / 8, fmt.bit_depth() / 8 * channels as usize);
+    let whole = rest.len() / frame_bytes * frame_bytes;
+    let pcm: Vec<f64> = rest.drain(..whole).collect::<Vec<u8>>().chunks(bytes_per_sample).map(|x| any_to_f64(x, &fmt)).collect();
+
+    let mut out = resampler.process(&pcm);
+    if end { out.extend(resampler.flush()); }
+    return out.into_iter().flat_map(|x| f64_to_any(x, &PCMFormat::F64(Big))).collect();
+}
 
-    let loss_level = 1.25_f64.powi(params.losslevel as i32) / 19.0 + 0.5;
-    encoder.set_loss_level(loss_level);
+/** encode
+ * Encodes PCM or WAV to FrAD
+ * Parameters: Input file, CLI parameters, Log level
+ */
+pub fn encode(input: String, mut params: CliParams) {
+    if input.is_empty() { eprintln!("Input file must be given"); exit(1); }
 
-    let (mut readfile, mut writefile) = set_files(input, params.output, params.profile, params.overwrite);
+    let input_size = get_input_size(&input);
+    let (readfile, mut writefile) = set_files(input, params.output.clone(), params.profile, params.overwrite);
+    let (mut readfile, input_size) = read_wav(readfile, input_size, &mut params);
 
-    let mut image = Vec::new();
+    if params.srate == 0 { eprintln!("Sample rate should be set except zero"); exit(1); }
+    if params.channels == 0 { eprintln!("Channel count should be set except zero"); exit(1); }
+
+    // Compact profiles take a fixed set of rates, others are converted to the nearest one if asked
+    let mut resampler = None;
+    let srate = if params.resample && COMPACT.contains(&params.profile) && !compact::SRATES.contains(&params.srate) {
+        let target = *compact::SRATES.iter().min_by_key(|&&x| x.abs_diff(params.srate)).unwrap();
+        if !params.quiet { eprintln!("Resampling {} Hz to {} Hz", params.srate, target); }
+        resampler = Some(Resampler::new(params.srate, target, params.channels));
+        target
+    } else if COMPACT.contains(&params.profile) && !compact::SRATES.contains(&params.srate) {
+        let target = compact::get_valid_srate(params.srate);
+        eprintln!("{}", FradError::InvalidSampleRate(params.srate));
+        eprintln!("Auto-adjusting to: {}", target);
+        target
+    } else { params.srate };
+    let mut encoder = or_exit(Encoder::new(params.profile, if resampler.is_some() { PCMFormat::F64(Big) } else { params.pcm }));
+
+    or_exit(encoder.set_srate(srate));
+    or_exit(encoder.set_channels(params.channels));
+    or_exit(encoder.set_bit_depth(params.bits));
+
+    configure(&mut encoder, &params);
+    encoder.set_preroll_samples(params.preroll as usize);
+    encoder.set_postroll_samples(params.postroll as usize);
+
+    // Images from the ID3 tag, and the given image over the one of its picture type
+    let mut images = params.images;
     if !params.image_path.is_empty() {
+        let mut image = Vec::new();
         match File::open(&params.image_path) {
             Ok(mut imgfile) => { imgfile.read_to_end(&mut image).unwrap(); },
             Err(_) => { eprintln!("Image not found"); }
         }
+        let itype = params.image_type.unwrap_or(PictureType::FrontCover);
+        images.retain(|(_, t)| *t != itype);
+        images.push((image, itype));
     }
 
-    write_safe(&mut writefile, &head::builder(&params.meta, image, None));
+    let mut meta = params.meta;
+    head::set_roll(&mut meta, params.preroll, params.postroll);
+    // Pipe input and streamed WAV have no known length, so their sample count cannot be recorded up front
+    if input_size != 0 {
+        let samples = input_size / (params.channels as usize * params.pcm.bit_depth() / 8) as u64;
+        head::set_total_samples(&mut meta, resampler.as_ref().map_or(samples, |r| r.output_len(samples)));
+    }
+    write_safe(&mut writefile, &head::builder(&meta, &images, &[]));
 
-    let mut procinfo = ProcessInfo::new();
+    let (mut procinfo, mut rest) = (ProcessInfo::new(), Vec::new());
+    procinfo.set_total_input_bytes(input_size);
     loop {
         let mut pcm_buf = vec![0u8; 32768];
         let readlen = read_exact(&mut readfile, &mut pcm_buf);
         if readlen == 0 { break; }
 
-        let encoded = encoder.process(&pcm_buf[..readlen]);
+        let pcm = match resampler.as_mut() {
+            Some(r) => resample(r, params.pcm, params.channels, &mut rest, &pcm_buf[..readlen], false),
+            None => pcm_buf[..readlen].to_vec()
+        };
+        let encoded = or_exit(encoder.process(&pcm));
+        procinfo.update(encoded.buf.len(), encoded.samples, encoder.get_srate());
+        procinfo.update_input(readlen);
+        write_safe(&mut writefile, &encoded.buf);
+        logging_encode(params.loglevel, params.logfmt, &procinfo, false);
+    }
+    if let Some(r) = resampler.as_mut() {
+        let encoded = or_exit(encoder.process(&resample(r, params.pcm, params.channels, &mut rest, &[], true)));
         procinfo.update(encoded.buf.len(), encoded.samples, encoder.get_srate());
         write_safe(&mut writefile, &encoded.buf);
-        logging_encode(params.loglevel, &procinfo, false);
     }
-    let encoded = encoder.flush();
+    let encoded = or_exit(encoder.flush());
     procinfo.update(encoded.buf.len(), encoded.samples, encoder.get_srate());
     write_safe(&mut writefile, &encoded.buf);
-    logging_encode(params.loglevel, &procinfo, true);
+    logging_encode(params.loglevel, params.logfmt, &procinfo, true);
+    warn_inexact(&encoder);
 }
\ No newline at end of file
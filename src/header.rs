@@ -4,10 +4,10 @@
  * Description: Metadata modificator for FrAD
  */
 
-use frad::{common::{SIGNATURE, FRM_SIGN}, head};
+use frad::{common::{SIGNATURE, FRM_SIGN}, head::{self, PictureType}, index, Decoder};
 use crate::{
     common::{get_file_stem, move_all},
-    tools::cli::{CliParams, META_ADD, META_OVERWRITE, META_PARSE, META_REMOVE, META_RMIMG}
+    tools::{cli::{CliParams, META_ADD, META_FIX, META_OVERWRITE, META_PARSE, META_REMOVE, META_REPLAYGAIN, META_RMIMG, META_TOC}, loudness::Loudness, vorbismeta}
 };
 use std::{fs::File, io::{Read, Seek, SeekFrom, Write}, path::Path, process::exit};
 
@@ -16,6 +16,182 @@ use infer;
 use serde_json::{json, Value};
 use tempfile::NamedTempFile;
 
+/** read_header
+ * Reads the metadata and images of a FrAD file
+ * Parameters: File path
+ * Returns: Metadata, Images with their picture types, empty if the file has no header
+ */
+pub fn read_header(file_name: &str) -> (Vec<(String, Vec<u8>)>, Vec<(Vec<u8>, PictureType)>) {
+    let empty = (Vec::new(), Vec::new());
+    let Ok(mut rfile) = File::open(file_name) else { return empty; };
+    let mut head = vec![0u8; 64];
+    if rfile.read_exact(&mut head).is_err() || head[0..4] != SIGNATURE { return empty; }
+
+    let head_len = u64::from_be_bytes(head[8..16].try_into().unwrap()).max(64);
+    let mut blocks = Vec::new();
+    rfile.take(head_len - 64).read_to_end(&mut blocks).unwrap();
+    let (meta, images, _) = head::parser(blocks);
+    return (meta, images);
+}
+
+/** read_meta
+ * Reads the metadata of a FrAD file
+ * Parameters: File path
+ * Returns: Metadata, empty if the file has no header
+ */
+pub fn read_meta(file_name: &str) -> Vec<(String, Vec<u8>)> { return read_header(file_name).0; }
+
+/** header_end
+ * Finds the actual end of the header by walking its blocks
+ * Parameters: Input file, File length
+ * Returns: Header length, None if the blocks do not end at a frame or EOF
+ */
+fn header_end(rfile: &mut File, file_len: u64) -> Option<u64> {
+    let mut pos = 64;
+    loop {
+        if pos == file_len { return Some(pos); }
+
+        let mut buf = Vec::new();
+        rfile.seek(SeekFrom::Start(pos)).unwrap();
+        rfile.take(11).read_to_end(&mut buf).unwrap();
+        if buf.starts_with(&FRM_SIGN) { return Some(pos); }
+
+        let length = head::block_length(&buf)?;
+        if pos + length > file_len { return None; }
+        pos += length;
+    }
+}
+
+/** keep_version
+ * Stamps a rebuilt header with the format version of the header it replaces
+ * Parameters: New header, Old header
+ */
+fn keep_version(head_new: &mut [u8], head_old: &[u8]) {
+    if let Some(version) = head::version(head_old) { head_new[4] = version; }
+}
+
+/** fix
+ * Recomputes the header length from the actual blocks and rewrites the header in place
+ * Parameters: File path, Input file, Header length in the file, Fixed-size part of the header
+ * Returns: FrAD file with fixed header
+ */
+fn fix(file_name: &str, mut rfile: File, head_len: u64, head_old: &[u8]) {
+    if head_len == 0 { eprintln!("No header found, nothing to fix."); return; }
+    let file_len = rfile.metadata().unwrap().len();
+
+    let head_end = match header_end(&mut rfile, file_len) {
+        Some(end) => end,
+        None => { eprintln!("Could not locate the end of the header."); exit(1); }
+    };
+
+    // Parse the blocks only, the fixed-size part may hold the broken length
+    let mut blocks = vec![0u8; (head_end - 64) as usize];
+    rfile.seek(SeekFrom::Start(64)).unwrap();
+    rfile.read_exact(&mut blocks).unwrap();
+    let (meta, images, contents) = head::parser(blocks);
+    let mut head_new = head::builder(&meta, &images, &contents);
+    keep_version(&mut head_new, head_old);
+
+    let mut temp = NamedTempFile::new().unwrap();
+    move_all(&mut rfile, temp.as_file_mut(), 16777216);
+
+    let mut wfile = File::create(file_name).unwrap();
+    wfile.write_all(&head_new).unwrap();
+    temp.seek(SeekFrom::Start(0)).unwrap();
+    move_all(temp.as_file_mut(), &mut wfile, 16777216);
+
+    if head_len != head_new.len() as u64 {
+        eprintln!("Header length fixed: {} -> {} bytes", head_len, head_new.len());
+    }
+}
+
+/** measure
+ * Decodes a FrAD file and measures its loudness
+ * Parameters: File path, ECC enable flag
+ * Returns: Gating block powers of the file
+ */
+fn measure(file_name: &str, fix_error: bool) -> Vec<f64> {
+    let mut rfile = File::open(file_name).unwrap();
+    let (mut decoder, mut meter) = (Decoder::new(fix_error), Loudness::new());
+
+    let mut buf = vec![0u8; 32768];
+    loop {
+        let readlen = rfile.read(&mut buf).unwrap();
+        if readlen == 0 { break; }
+        let decoded = decoder.process(&buf[..readlen]);
+        meter.process(&decoded.pcm, decoded.srate);
+    }
+    let decoded = decoder.flush();
+    meter.process(&decoded.pcm, decoded.srate);
+    return meter.blocks().to_vec();
+}
+
+/** retag
+ * Replaces metadata entries of a FrAD file without touching the audio
+ * Parameters: File path, Metadata to set
+ */
+fn retag(file_name: &str, tags: Vec<(String, Vec<u8>)>) {
+    let mut rfile = File::open(file_name).unwrap();
+    let mut head = vec![0u8; 64];
+    rfile.read_exact(&mut head).unwrap();
+    let head_len = if head[0..4] == SIGNATURE { u64::from_be_bytes(head[8..16].try_into().unwrap()) } else { 0 };
+
+    rfile.seek(SeekFrom::Start(0)).unwrap();
+    let mut head_old = vec![0u8; head_len as usize];
+    rfile.read_exact(&mut head_old).unwrap();
+    let (meta_old, images, contents) = head::parser(head_old);
+
+    let mut meta: Vec<(String, Vec<u8>)> = meta_old.into_iter().filter(|(key, _)| !tags.iter().any(|(k, _)| k == key)).collect();
+    meta.extend(tags);
+    let mut head_new = head::builder(&meta, &images, &contents);
+    keep_version(&mut head_new, &head);
+
+    let mut temp = NamedTempFile::new().unwrap();
+    move_all(&mut rfile, temp.as_file_mut(), 16777216);
+
+    let mut wfile = File::create(file_name).unwrap();
+    wfile.write_all(&head_new).unwrap();
+    temp.seek(SeekFrom::Start(0)).unwrap();
+    move_all(temp.as_file_mut(), &mut wfile, 16777216);
+}
+
+/** replaygain
+ * Computes track and album gain of FrAD files and stores them as ReplayGain metadata
+ * Parameters: First file path, CLI parameters
+ * Returns: FrAD files with ReplayGain metadata
+ */
+fn replaygain(file_name: String, params: CliParams) {
+    let files: Vec<String> = std::iter::once(file_name).chain(params.files).collect();
+    for file in &files {
+        let mut head = vec![0u8; 4];
+        let valid = File::open(file).and_then(|mut f| f.read_exact(&mut head)).is_ok();
+        if !valid || (head != SIGNATURE && head != FRM_SIGN) { eprintln!("It seems {} is not a valid FrAD file.", file); exit(1); }
+    }
+
+    // Album loudness gates the blocks of every track together
+    let blocks: Vec<Vec<f64>> = files.iter().map(|file| measure(file, params.enable_ecc)).collect();
+    let album = Loudness::integrated(&blocks.concat());
+    let gain = |lufs: Option<f64>| lufs.map(|l| format!("{:+.2} dB", params.reference - l));
+
+    for (file, blocks) in files.iter().zip(&blocks) {
+        let track = Loudness::integrated(blocks);
+        let mut tags = Vec::new();
+        if let Some(g) = gain(track) { tags.push(("REPLAYGAIN_TRACK_GAIN".to_string(), g.into_bytes())); }
+        if let Some(g) = gain(album) { tags.push(("REPLAYGAIN_ALBUM_GAIN".to_string(), g.into_bytes())); }
+
+        if !params.quiet {
+            match track {
+                Some(l) => eprintln!("{}: {:.2} LUFS, track gain {}", file, l, gain(track).unwrap()),
+                None => eprintln!("{}: silent, no track gain", file)
+            }
+        }
+        if !tags.is_empty() { retag(file, tags); }
+    }
+    if !params.quiet {
+        if let Some(l) = album { eprintln!("Album: {:.2} LUFS, album gain {}", l, gain(album).unwrap()); }
+    }
+}
+
 /** modify
  * Modify the metadata of a FrAD file
  * Parameters: File path, Modification type, Metadata, Image path
@@ -24,6 +200,7 @@ use tempfile::NamedTempFile;
 pub fn modify(file_name: String, modtype: String, params: CliParams) {
     if file_name.is_empty() { eprintln!("Input file must be given"); exit(1); }
     else if !Path::new(&file_name).exists() { eprintln!("Input file does not exist"); exit(1); }
+    if modtype == META_REPLAYGAIN { return replaygain(file_name, params); }
 
     let mut head = vec![0u8; 64];
 
@@ -39,29 +216,47 @@ pub fn modify(file_name: String, modtype: String, params: CliParams) {
         }
     };
 
+    if modtype == META_FIX { return fix(&file_name, rfile, head_len, &head); }
+
     rfile.seek(SeekFrom::Start(0)).unwrap();
     let mut head_old = vec![0u8; head_len as usize];
     rfile.read_exact(&mut head_old).unwrap();
 
-    let (mut meta_old, img_old, _itype) = head::parser(head_old);
-    let (mut meta_new, mut img_new) = (Vec::new(), Vec::new());
+    // The table of contents counts from the end of the header, so it survives any rewrite
+    let (mut meta_old, mut images_old, mut contents) = head::parser(head_old);
+    let (mut meta_new, mut images_new) = (Vec::new(), Vec::new());
+    // A lone kept image takes the picture type given without a new image
+    if let (Some(itype), true, 1) = (params.image_type, params.image_path.is_empty(), images_old.len()) {
+        if modtype != META_RMIMG { images_old[0].1 = itype; }
+    }
 
     if modtype == META_PARSE {
-        let mut json: Vec<Value> = Vec::new();
-        for (key, data) in meta_old {
-            let (data, itype) = match String::from_utf8(data.clone()) {
-                Ok(data_str) => (data_str.to_string(), "string".to_string()),
-                Err(_) => (BASE64_STANDARD.encode(data).to_string(), "base64".to_string())
-            };
-            json.push(json!({"key": key, "type": itype, "value": data}));
-        }
         let mut wfile = params.output;
-
         if wfile.is_empty() { wfile = get_file_stem(&file_name); }
-        File::create(format!("{}.json", wfile)).unwrap().write_all(serde_json::to_string_pretty(&json).unwrap().as_bytes()).unwrap();
-        if !img_old.is_empty() {
-            let img_suffix = if let Some(imgtype) = infer::get(&img_old) { imgtype.extension() } else { "img" };
-            File::create(format!("{}.{}", wfile, img_suffix)).unwrap().write_all(&img_old).unwrap();
+
+        // VorbisMeta if asked for by the extension, JSONMeta otherwise
+        if let Some(stem) = wfile.strip_suffix(".tags") {
+            let (text, skipped) = vorbismeta::build(&meta_old);
+            for key in skipped { eprintln!("VorbisMeta cannot hold binary data, left out: {}", key); }
+            File::create(&wfile).unwrap().write_all(text.as_bytes()).unwrap();
+            wfile = stem.to_string();
+        }
+        else {
+            let mut json: Vec<Value> = Vec::new();
+            for (key, data) in meta_old {
+                let (data, itype) = match String::from_utf8(data.clone()) {
+                    Ok(data_str) => (data_str.to_string(), "string".to_string()),
+                    Err(_) => (BASE64_STANDARD.encode(data).to_string(), "base64".to_string())
+                };
+                json.push(json!({"key": key, "type": itype, "value": data}));
+            }
+            File::create(format!("{}.json", wfile)).unwrap().write_all(serde_json::to_string_pretty(&json).unwrap().as_bytes()).unwrap();
+        }
+        // The first image keeps the plain name, later ones are named after their picture type
+        for (i, (img, itype)) in images_old.iter().enumerate() {
+            let img_suffix = if let Some(imgtype) = infer::get(img) { imgtype.extension() } else { "img" };
+            let name = if i == 0 { format!("{}.{}", wfile, img_suffix) } else { format!("{}.{}.{}.{}", wfile, i, itype.name(), img_suffix) };
+            File::create(name).unwrap().write_all(img).unwrap();
         }
 
         return;
@@ -77,30 +272,52 @@ pub fn modify(file_name: String, modtype: String, params: CliParams) {
             Err(_) => { eprintln!("Image not found"); }
         }
     }
+    let itype = params.image_type.unwrap_or(PictureType::FrontCover);
 
     match modtype.as_str() {
         META_ADD => {
             if !meta_old.is_empty() { meta_new.append(&mut meta_old); }
             meta_new.extend(params.meta);
-            if !img_old.is_empty() { img_new = img_old; }
-            if !img.is_empty() { img_new = img; }
+            images_new = images_old;
+            // New images replace the ones of their picture types
+            for (img, itype) in params.images {
+                images_new.retain(|(_, t)| *t != itype);
+                images_new.push((img, itype));
+            }
+            if !img.is_empty() {
+                images_new.retain(|(_, t)| *t != itype);
+                images_new.push((img, itype));
+            }
         }
         META_REMOVE => {
             meta_new = meta_old.into_iter().filter(|(title, _)| !params.meta.iter().any(|(t, _)| t == title)).collect();
-            img_new = img_old;
+            images_new = images_old;
         }
         META_RMIMG => {
             meta_new = meta_old;
-            img_new = Vec::new();
+            // Only the images of the given picture type, or all of them
+            if let Some(itype) = params.image_type { images_new = images_old.into_iter().filter(|(_, t)| *t != itype).collect(); }
         }
         META_OVERWRITE => {
             meta_new = params.meta;
-            img_new = img;
+            images_new = params.images;
+            if !img.is_empty() {
+                images_new.retain(|(_, t)| *t != itype);
+                images_new.push((img, itype));
+            }
+        }
+        META_TOC => {
+            (meta_new, images_new) = (meta_old, images_old);
+            contents = match index::read(temp.as_file_mut()) {
+                Some(entries) => entries,
+                None => { eprintln!("No index footer found, encode with --index to build one."); exit(1); }
+            };
         }
         _ => { eprintln!("Invalid modification type."); std::process::exit(1); }
     }
 
-    let head_new = head::builder(&meta_new, img_new, None);
+    let mut head_new = head::builder(&meta_new, &images_new, &contents);
+    keep_version(&mut head_new, &head);
 
     let mut wfile = File::create(&file_name).unwrap();
     wfile.write_all(&head_new).unwrap();
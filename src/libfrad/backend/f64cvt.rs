@@ -7,6 +7,17 @@
 use crate::{PCMFormat, Endian};
 use half::f16;
 
+/** DitherKind
+ * Dither added to integer PCM before rounding, to decorrelate the rounding error from the signal
+ *   Tpdf adds triangular noise of two LSBs peak to peak, which turns the distortion on quiet passages
+ *   into a constant noise floor
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DitherKind {
+    None,
+    Tpdf,
+}
+
 /** norm_into
  * Normalise integer sample beteween -1.0 and 1.0
  * Parameters: Unnormalised sample, PCM format
@@ -22,14 +33,14 @@ fn norm_into(mut x: f64, pcm_fmt: &PCMFormat) -> f64 {
 
 /** norm_from
  * Denormalise f64 sample to integer dynamic range
- * Parameters: Normalised sample, PCM format
+ * Parameters: Normalised sample, PCM format, Dither in LSBs
  * Returns: Denormalised sample
  */
-fn norm_from(mut x: f64, pcm_fmt: &PCMFormat) -> f64 {
+fn norm_from(mut x: f64, pcm_fmt: &PCMFormat, dither: f64) -> f64 {
     return if pcm_fmt.float() { x }
     else {
         x = if pcm_fmt.signed() { x } else { x + 1.0 };
-        return (x * pcm_fmt.scale()).round();
+        return (x * pcm_fmt.scale() + dither).round();
     };
 }
 
@@ -57,34 +68,6 @@ macro_rules! from_f64 {
     };
 }
 
-/** macro! int24_to_32
- * Convert 24-bit integer to 32-bit integer
- * Parameters: Byte array, Endian, Signed flag
- * Returns: 32-bit integer
- */
-macro_rules! int24_to_32 {
-    ($bytes:expr, $endian:expr, $signed:expr) => {{
-        let sign_bit = if $endian.eq(&Endian::Big) { $bytes[0] } else { $bytes[2] } & 0x80;
-        let extra_byte = if !$signed || sign_bit == 0 { 0 } else { 0xFF };
-        if $endian.eq(&Endian::Big) { [extra_byte, $bytes[0], $bytes[1], $bytes[2]] }
-        else { [$bytes[0], $bytes[1], $bytes[2], extra_byte] }
-    }};
-}
-
-/** macro! int32_to_24
- * Convert 32-bit integer to 24-bit integer
- * Parameters: 32-bit integer, Endian, Signed flag
- * Returns: Byte array
- */
-macro_rules! int32_to_24 {
-    ($x:expr, $endian:expr, $signed:expr) => {{
-        let (lo, hi) = if $signed { (-0x800000, 0x7fffff) } else { (0, 0xffffff) };
-        let y = $x.max(lo).min(hi);
-        if $endian.eq(&Endian::Big) { [(y >> 16) as u8, (y >> 8) as u8, y as u8] }
-        else { [y as u8, (y >> 8) as u8, (y >> 16) as u8] }
-    }};
-}
-
 /** any_to_f64
  * Convert single sample to f64 via PCM format
  * Parameters: Byte array, PCM format
@@ -100,13 +83,13 @@ pub fn any_to_f64(bytes: &[u8], pcm_fmt: &PCMFormat) -> f64 {
 
             PCMFormat::I8 => i8::from_ne_bytes(bytes.try_into().unwrap()) as f64,
             PCMFormat::I16(en) => to_f64!(i16, bytes, en) as f64,
-            PCMFormat::I24(en) => to_f64!(i32, int24_to_32!(bytes, en, true), en) as f64,
+            PCMFormat::I24(_) => pcm_fmt.int24_from_bytes(bytes) as f64,
             PCMFormat::I32(en) => to_f64!(i32, bytes, en) as f64,
             PCMFormat::I64(en) => to_f64!(i64, bytes, en) as f64,
 
             PCMFormat::U8 => u8::from_ne_bytes(bytes.try_into().unwrap()) as f64,
             PCMFormat::U16(en) => to_f64!(u16, bytes, en) as f64,
-            PCMFormat::U24(en) => to_f64!(u32, int24_to_32!(bytes, en, false), en) as f64,
+            PCMFormat::U24(_) => pcm_fmt.int24_from_bytes(bytes) as f64,
             PCMFormat::U32(en) => to_f64!(u32, bytes, en) as f64,
             PCMFormat::U64(en) => to_f64!(u64, bytes, en) as f64,
         }, pcm_fmt
@@ -118,8 +101,18 @@ pub fn any_to_f64(bytes: &[u8], pcm_fmt: &PCMFormat) -> f64 {
  * Parameters: f64, PCM format
  * Returns: Byte array
  */
-pub fn f64_to_any(mut x: f64, pcm_fmt: &PCMFormat) -> Vec<u8> {
-    x = norm_from(x, pcm_fmt);
+pub fn f64_to_any(x: f64, pcm_fmt: &PCMFormat) -> Vec<u8> {
+    return f64_to_any_dithered(x, pcm_fmt, 0.0);
+}
+
+/** f64_to_any_dithered
+ * Convert f64 to single sample via PCM format, adding dither before rounding to an integer format
+ *   Float formats are not rounded, so the dither is ignored for them
+ * Parameters: f64, PCM format, Dither in LSBs
+ * Returns: Byte array
+ */
+pub fn f64_to_any_dithered(mut x: f64, pcm_fmt: &PCMFormat, dither: f64) -> Vec<u8> {
+    x = norm_from(x, pcm_fmt, dither);
 
     return match pcm_fmt {
         PCMFormat::F16(en) => from_f64!(f16, f16::from_f64(x), en).to_vec(),
@@ -128,13 +121,13 @@ pub fn f64_to_any(mut x: f64, pcm_fmt: &PCMFormat) -> Vec<u8> {
 
         PCMFormat::I8 => (x as i8).to_ne_bytes().to_vec(),
         PCMFormat::I16(en) => from_f64!(i16, x as i16, en).to_vec(),
-        PCMFormat::I24(en) => int32_to_24!(x as i32, en, true).to_vec(),
+        PCMFormat::I24(_) => pcm_fmt.int24_to_bytes(x as i32).to_vec(),
         PCMFormat::I32(en) => from_f64!(i32, x as i32, en).to_vec(),
         PCMFormat::I64(en) => from_f64!(i64, x as i64, en).to_vec(),
 
         PCMFormat::U8 => (x as u8).to_ne_bytes().to_vec(),
         PCMFormat::U16(en) => from_f64!(u16, x as u16, en).to_vec(),
-        PCMFormat::U24(en) => int32_to_24!(x as i32, en, false).to_vec(),
+        PCMFormat::U24(_) => pcm_fmt.int24_to_bytes(x as i32).to_vec(),
         PCMFormat::U32(en) => from_f64!(u32, x as u32, en).to_vec(),
         PCMFormat::U64(en) => from_f64!(u64, x as u64, en).to_vec(),
     };
@@ -5,27 +5,25 @@
  */
 
 pub mod bitcvt; pub mod f64cvt; pub mod pcmformat;
+pub mod rng;
 use core::f64::consts::PI;
 
 pub use pcmformat::{PCMFormat, Endian};
 
 /** linspace
- * Generates a linear spaced vector
- * Parameters: Start value, Stop value, Number of values
- * Returns: Linear spaced vector
+ * Fills a slice with linear spaced values
+ * Parameters: Start value, Stop value, Output slice
  */
-pub fn linspace(start: f64, stop: f64, num: usize) -> Vec<f64> {
-    if num == 0 { return vec![]; }
-    if num == 1 { return vec![(start + stop) / 2.0]; }
+pub fn linspace(start: f64, stop: f64, out: &mut [f64]) {
+    let num = out.len();
+    if num == 0 { return; }
+    if num == 1 { out[0] = (start + stop) / 2.0; return; }
     let step = (stop - start) / (num - 1) as f64;
 
-    let mut result = Vec::with_capacity(num);
-    for i in 0..num {
-        let value = if i == num - 1 { stop }
+    for (i, value) in out.iter_mut().enumerate() {
+        *value = if i == num - 1 { stop }
         else { start + step * i as f64 };
-        result.push(value);
     }
-    return result;
 }
 
 /** hanning_math
@@ -53,6 +51,79 @@ pub fn hanning_in_overlap(olap_len: usize) -> Vec<f64> {
     .chain(res).collect();
 }
 
+/** WindowKind
+ * Overlap window of Compact profiles, not signalled in the stream so both ends must agree (default Hann)
+ *   Hann and Linear fades sum to one, so the decoder crossfades the two copies of the overlap.
+ *   Sine and Vorbis fades are power-complementary: the encoder fades the copies out and in as well,
+ *   leaving the head of a frame landed on by a seek faded in
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WindowKind {
+    #[default]
+    Hann,
+    Sine,
+    Vorbis,
+    Linear,
+}
+
+impl WindowKind {
+    /** fade_in
+     * Generates the fade-in window of the overlap, its reverse is the fade-out
+     * Parameters: Length of the window
+     * Returns: Fade-in window
+     */
+    pub fn fade_in(self, olap_len: usize) -> Vec<f64> {
+        return match self {
+            WindowKind::Hann => hanning_in_overlap(olap_len),
+            WindowKind::Sine => sine_in_overlap(olap_len),
+            WindowKind::Vorbis => vorbis_in_overlap(olap_len),
+            WindowKind::Linear => linear_in_overlap(olap_len),
+        };
+    }
+
+    /** power_complementary
+     * Gets if the squares of the fades sum to one, so the encoder applies them as well
+     * Returns: Power-complementary flag
+     */
+    pub fn power_complementary(self) -> bool { return matches!(self, WindowKind::Sine | WindowKind::Vorbis); }
+}
+
+/** overlap_points
+ * Sample points of a fade-in window, symmetric around the middle as in hanning_in_overlap
+ * Parameters: Length of the window
+ * Returns: Points in (0, 1)
+ */
+fn overlap_points(olap_len: usize) -> impl Iterator<Item = f64> {
+    return (1..=olap_len).map(move |i| i as f64 / (olap_len as f64 + 1.0));
+}
+
+/** sine_in_overlap
+ * Generates a fade-in sine window, the square root of Hann
+ * Parameters: Length of the window
+ * Returns: Fade-in sine window
+ */
+pub fn sine_in_overlap(olap_len: usize) -> Vec<f64> {
+    return overlap_points(olap_len).map(|x| (PI / 2.0 * x).sin()).collect();
+}
+
+/** vorbis_in_overlap
+ * Generates a fade-in Vorbis window
+ * Parameters: Length of the window
+ * Returns: Fade-in Vorbis window
+ */
+pub fn vorbis_in_overlap(olap_len: usize) -> Vec<f64> {
+    return overlap_points(olap_len).map(|x| (PI / 2.0 * (PI / 2.0 * x).sin().powi(2)).sin()).collect();
+}
+
+/** linear_in_overlap
+ * Generates a fade-in linear window
+ * Parameters: Length of the window
+ * Returns: Fade-in linear window
+ */
+pub fn linear_in_overlap(olap_len: usize) -> Vec<f64> {
+    return overlap_points(olap_len).collect();
+}
+
 pub trait Transpose<T> {
     fn trans(&self) -> Vec<Vec<T>> where T: Clone;
 }
@@ -88,6 +159,40 @@ impl<T: Eq> VecPatternFind<T> for Vec<T> {
     }
 }
 
+/** SignFind
+ * Byte search for 4-byte signatures, e.g. the frame signature
+ */
+pub trait SignFind {
+    fn find_sign(&self, sign: &[u8; 4]) -> Option<usize>;
+}
+
+impl SignFind for [u8] {
+    /** find_sign
+     * Finds the first signature, scanning a word at a time for its last byte
+     *   and comparing the rest only where that byte turns up
+     * Parameters: Signature
+     * Returns: Index of the signature, None if not found
+     */
+    fn find_sign(&self, sign: &[u8; 4]) -> Option<usize> {
+        const LO: u64 = 0x0101_0101_0101_0101;
+        const HI: u64 = LO << 7;
+        let last = LO * sign[3] as u64;
+        let matches = |i: usize| self[i] == sign[3] && self[i - 3..i] == sign[..3];
+
+        // 1. Eight bytes at a time, a zero byte of word ^ last marks a candidate
+        let mut i = 3;
+        while i + 8 <= self.len() {
+            let word = u64::from_le_bytes(self[i..i + 8].try_into().unwrap()) ^ last;
+            if word.wrapping_sub(LO) & !word & HI != 0 {
+                if let Some(j) = (i..i + 8).find(|&j| matches(j)) { return Some(j - 3); }
+            }
+            i += 8;
+        }
+        // 2. The remaining bytes one by one
+        return (i..self.len()).find(|&j| matches(j)).map(|j| j - 3);
+    }
+}
+
 pub trait Prepend<T> {
     fn prepend(&mut self, other: &[T]) where T: Clone;
 }
@@ -4,7 +4,7 @@
  * Description: Enum for PCM format
  */
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PCMFormat {
     F16(Endian), F32(Endian), F64(Endian),
     I8, I16(Endian), I24(Endian), I32(Endian), I64(Endian),
@@ -37,7 +37,97 @@ impl PCMFormat {
             _ => 1.0
         }
     }
+
+    /** int24_from_bytes
+     * Reads a 24-bit integer sample, sign-extended if the format is signed
+     * Parameters: 3-byte sample
+     * Returns: Sample value in i32
+     */
+    pub fn int24_from_bytes(&self, bytes: &[u8]) -> i32 {
+        let (b, big) = (&bytes[..3], self.endian() == Endian::Big);
+        let x = if big { (b[0] as i32) << 16 | (b[1] as i32) << 8 | b[2] as i32 }
+        else { (b[2] as i32) << 16 | (b[1] as i32) << 8 | b[0] as i32 };
+        return if self.signed() { x << 8 >> 8 } else { x };
+    }
+
+    /** int24_to_bytes
+     * Writes a 24-bit integer sample, clamped to the format's range
+     * Parameters: Sample value in i32
+     * Returns: 3-byte sample
+     */
+    pub fn int24_to_bytes(&self, x: i32) -> [u8; 3] {
+        let (lo, hi) = if self.signed() { (-0x800000, 0x7fffff) } else { (0, 0xffffff) };
+        let y = x.clamp(lo, hi);
+        return if self.endian() == Endian::Big { [(y >> 16) as u8, (y >> 8) as u8, y as u8] }
+        else { [y as u8, (y >> 8) as u8, (y >> 16) as u8] };
+    }
+
+    /** endian
+     * Gets the byte order of the format, big endian for single-byte formats
+     * Returns: Endian
+     */
+    fn endian(&self) -> Endian {
+        match self {
+            PCMFormat::I8 | PCMFormat::U8 => Endian::Big,
+            PCMFormat::F16(en) | PCMFormat::F32(en) | PCMFormat::F64(en) |
+            PCMFormat::I16(en) | PCMFormat::I24(en) | PCMFormat::I32(en) | PCMFormat::I64(en) |
+            PCMFormat::U16(en) | PCMFormat::U24(en) | PCMFormat::U32(en) | PCMFormat::U64(en) => *en,
+        }
+    }
 }
 
-#[derive(Clone, Copy, PartialEq)]
-pub enum Endian { Big, Little }
\ No newline at end of file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endian { Big, Little }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::f64cvt::{any_to_f64, f64_to_any};
+
+    const INT24_FORMATS: [PCMFormat; 4] = [
+        PCMFormat::I24(Endian::Big), PCMFormat::I24(Endian::Little),
+        PCMFormat::U24(Endian::Big), PCMFormat::U24(Endian::Little),
+    ];
+
+    /** int24_bytes
+     * Lays out the 24-bit pattern of a value in a format's byte order
+     */
+    fn int24_bytes(fmt: &PCMFormat, x: u32) -> [u8; 3] {
+        let [_, b0, b1, b2] = x.to_be_bytes();
+        return if fmt.endian() == Endian::Big { [b0, b1, b2] } else { [b2, b1, b0] };
+    }
+
+    #[test]
+    fn int24_round_trips_every_value() {
+        for fmt in INT24_FORMATS {
+            for x in 0..1u32 << 24 {
+                let bytes = int24_bytes(&fmt, x);
+                let value = fmt.int24_from_bytes(&bytes);
+                let expected = if fmt.signed() { (x << 8) as i32 >> 8 } else { x as i32 };
+                assert_eq!(value, expected, "{:?}", fmt);
+                assert_eq!(fmt.int24_to_bytes(value), bytes, "{:?}", fmt);
+            }
+        }
+    }
+
+    #[test]
+    fn int24_round_trips_every_value_through_f64() {
+        for fmt in INT24_FORMATS {
+            for x in 0..1u32 << 24 {
+                let bytes = int24_bytes(&fmt, x);
+                assert_eq!(f64_to_any(any_to_f64(&bytes, &fmt), &fmt), bytes, "{:?}", fmt);
+            }
+        }
+    }
+
+    #[test]
+    fn int24_clamps_out_of_range_values() {
+        for endian in [Endian::Big, Endian::Little] {
+            let (signed, unsigned) = (PCMFormat::I24(endian), PCMFormat::U24(endian));
+            assert_eq!(signed.int24_to_bytes(0x800000), signed.int24_to_bytes(0x7fffff));
+            assert_eq!(signed.int24_to_bytes(-0x800001), signed.int24_to_bytes(-0x800000));
+            assert_eq!(unsigned.int24_to_bytes(0x1000000), unsigned.int24_to_bytes(0xffffff));
+            assert_eq!(unsigned.int24_to_bytes(-1), unsigned.int24_to_bytes(0));
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,55 @@
+/**                                 Small RNG                                 */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Small seeded pseudo-random generator for stochastic quantisation and the stress encode mode
+ *   xorshift64*, identical on every platform for the same seed
+ */
+
+pub struct SmallRng { state: u64 }
+
+impl SmallRng {
+    pub fn new(seed: u64) -> SmallRng {
+        // Zero is a fixed point of xorshift
+        return SmallRng { state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed } };
+    }
+
+    /** next_u64
+     * Draws the next value
+     * Returns: Pseudo-random u64
+     */
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545f4914f6cdd1d);
+    }
+
+    /** unit
+     * Draws a value in [0, 1)
+     * Returns: Pseudo-random f64 of 53 bits
+     */
+    pub fn unit(&mut self) -> f64 { return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64; }
+
+    /** range
+     * Draws a value in a half-open range
+     * Parameters: Lower bound, Upper bound (exclusive, greater than the lower bound)
+     * Returns: Pseudo-random value
+     */
+    #[cfg(feature = "stress")]
+    pub fn range(&mut self, lo: u64, hi: u64) -> u64 { return lo + self.next_u64() % (hi - lo); }
+
+    /** bool
+     * Draws a flag
+     * Returns: Pseudo-random flag
+     */
+    #[cfg(feature = "stress")]
+    pub fn bool(&mut self) -> bool { return self.next_u64() >> 63 == 1; }
+
+    /** choose
+     * Picks an item
+     * Parameters: Non-empty slice
+     * Returns: Pseudo-randomly picked item
+     */
+    #[cfg(feature = "stress")]
+    pub fn choose<T: Copy>(&mut self, items: &[T]) -> T { return items[self.range(0, items.len() as u64) as usize]; }
+}
\ No newline at end of file
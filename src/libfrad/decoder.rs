@@ -5,17 +5,123 @@
  */
 
 use crate::{
-    backend::{hanning_in_overlap, SplitFront, VecPatternFind},
-    common:: {crc16_ansi, crc32, FRM_SIGN},
-    fourier::{self, profiles::{COMPACT, LOSSLESS}},
-    tools::  {asfh::{ASFH, ParseResult::{Complete, Incomplete, ForceFlush}}, ecc},
+    PCMFormat, Endian, f64cvt::{f64_to_any_dithered, DitherKind},
+    backend::{rng::SmallRng, WindowKind, SignFind, SplitFront},
+    common:: {crc16_ansi, crc32, FRM_SIGN, SIGNATURE},
+    fourier::{self, backend::core::DctPlan, profiles::{COMPACT, LOSSLESS}, remap_depth_index, FORMAT_VERSION},
+    tools::  {head, asfh::{ASFH, ParseResult::{Complete, Incomplete, ForceFlush}}, ecc::{self, EccParams, RSCodec}},
 };
 
+use core::ops::Range;
+#[cfg(feature = "std")]
+use crate::error::FradError;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// Longest header waited for to read its gapless info, longer ones are decoded past untrimmed
+const HEADER_WAIT_MAX: usize = 1 << 24;
+
+/** DecodeLimit
+ * Decoding budget, in samples per channel or in seconds
+ */
+#[derive(Clone, Copy)]
+pub enum DecodeLimit {
+    Samples(u64),
+    Seconds(f64),
+}
+
+/** Concealment
+ * Handling of frames that failed checksum and could not be repaired
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Concealment {
+    Off,
+    Interpolate,
+}
+
 pub struct DecodeResult {
     pub pcm: Vec<Vec<f64>>,
+    pub bytes: Vec<u8>,
     pub srate: u32,
     pub frames: usize,
     pub crit: bool,
+    // Position of the first sample in its stream format segment, PTS = start_sample / srate
+    pub start_sample: u64,
+    // CRC check of the frames as read, before ECC repair; None if none of them stores a CRC
+    pub crc_ok: Option<bool>,
+}
+
+impl DecodeResult {
+    /** new
+     * Makes a decode result without a CRC check
+     * Parameters: PCM, PCM bytes, Sample rate, Frame count, Critical info modification flag, Start sample
+     * Returns: Decode result
+     */
+    pub fn new(pcm: Vec<Vec<f64>>, bytes: Vec<u8>, srate: u32, frames: usize, crit: bool, start_sample: u64) -> DecodeResult {
+        return DecodeResult { pcm, bytes, srate, frames, crit, start_sample, crc_ok: None };
+    }
+
+    /** with_crc
+     * Sets the CRC check of the result
+     * Parameters: CRC check, None if no frame stores a CRC
+     * Returns: Decode result
+     */
+    pub fn with_crc(mut self, crc_ok: Option<bool>) -> DecodeResult { self.crc_ok = crc_ok; return self; }
+
+    /** crc_ok
+     * Gets the CRC check of the frames in the result
+     * Returns: true if every CRC matched, false if any did not, None if no frame stores a CRC
+     */
+    pub fn crc_ok(&self) -> Option<bool> { return self.crc_ok; }
+
+    /** is_corrupt
+     * Checks if any frame in the result failed its CRC, even if ECC repaired it afterwards
+     * Returns: Corrupt flag
+     */
+    pub fn is_corrupt(&self) -> bool { return self.crc_ok == Some(false); }
+
+    /** channels
+     * Gets the channel count of the decoded PCM
+     * Returns: Channel count, 0 if no sample was decoded
+     */
+    pub fn channels(&self) -> usize { return self.pcm.first().map_or(0, |s| s.len()); }
+
+    /** samples
+     * Gets the sample count per channel of the decoded PCM
+     * Returns: Sample count
+     */
+    pub fn samples(&self) -> usize { return self.pcm.len(); }
+
+    /** is_empty
+     * Checks if the result holds no PCM, consumers should skip it instead of reading its channels
+     * Returns: Empty flag
+     */
+    pub fn is_empty(&self) -> bool { return self.samples() == 0 || self.channels() == 0; }
+}
+
+/** read_at
+ * Reads up to a given length from a position of a seekable stream
+ * Parameters: Seekable stream, Position, Length
+ * Returns: Read bytes, shorter than the length at the end of the stream
+ */
+#[cfg(feature = "std")]
+fn read_at<R: Read + Seek>(file: &mut R, pos: u64, len: usize) -> Result<Vec<u8>, FradError> {
+    let io = |e: std::io::Error| FradError::Io(e.kind());
+    file.seek(SeekFrom::Start(pos)).map_err(io)?;
+    let mut buf = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut buf).map_err(io)?;
+    return Ok(buf);
+}
+
+/** overlap_tail
+ * Gets the overlap tail length of a frame, output with the next frame
+ * Parameters: ASFH of the frame
+ * Returns: Tail length, zero for non-COMPACT or non-overlapping frames
+ */
+fn overlap_tail(asfh: &ASFH) -> u64 {
+    if !COMPACT.contains(&asfh.profile) || asfh.overlap_ratio == 0 { return 0; }
+    let fsize = asfh.fsize as u64;
+    return fsize - fsize * (asfh.overlap_ratio as u64 - 1) / asfh.overlap_ratio as u64;
 }
 
 /** Decoder
@@ -25,9 +131,58 @@ pub struct Decoder {
     asfh: ASFH, info: ASFH,
     buffer: Vec<u8>,
     overlap_fragment: Vec<Vec<f64>>,
+    // Fade-in window of the last overlap, its length is the overlap length
+    olap_window: WindowKind, fade_in: Vec<f64>,
+    dct: Option<DctPlan>,
+    rs: Option<RSCodec>,
 
     fix_error: bool,
     broken_frame: bool,
+    format_version: u8,
+
+    limit: Option<DecodeLimit>,
+    decoded_samples: u64,
+    decoded_secs: f64,
+
+    // Samples output so far, the bytes and samples of the frames walked, and the bytes before the first frame
+    samples_out: u64,
+    walked_bytes: u64, walked_samples: u64,
+    lead_bytes: u64,
+
+    // Output position in the current segment, and where a seek landed in a segment yet to be signalled
+    position: u64,
+    seek_start: Option<u64>,
+
+    last_seq: u64,
+    dropped: Vec<(u64, u64)>,
+
+    out_format: Option<PCMFormat>,
+    out_format_map: Vec<(Range<usize>, PCMFormat)>,
+    dither: DitherKind, dither_rng: SmallRng,
+    // Output gain, and the peak limiter with its current gain
+    gain: f64,
+    ceiling: Option<f64>, ceiling_gain: f64,
+
+    concealment: Concealment,
+    last_frame: Vec<Vec<f64>>,
+    concealed: bool,
+    lossless: Option<bool>,
+    // CRC check of the frames decoded since the last result
+    crc_ok: Option<bool>,
+
+    #[cfg(feature = "std")]
+    debug_sink: Option<Box<dyn Write>>,
+
+    trim_pre: usize, trim_post: usize,
+    held: Vec<Vec<f64>>,
+    total: Option<u64>,
+    // Gapless info to take from a header at the start of the stream, with the roll trim flag
+    header_trim: Option<bool>,
+
+    // Samples per peak point, 0 if disabled
+    peak_span: usize,
+    peak_points: Vec<(f32, f32)>,
+    peak_pending: (f32, f32, usize),
 }
 
 impl Decoder {
@@ -36,10 +191,409 @@ impl Decoder {
             asfh: ASFH::new(), info: ASFH::new(),
             buffer: Vec::new(),
             overlap_fragment: Vec::new(),
+            olap_window: WindowKind::Hann, fade_in: Vec::new(),
+            dct: None,
+            rs: None,
 
             fix_error,
             broken_frame: false,
+            format_version: FORMAT_VERSION,
+
+            limit: None,
+            decoded_samples: 0,
+            decoded_secs: 0.0,
+
+            samples_out: 0,
+            walked_bytes: 0, walked_samples: 0,
+            lead_bytes: 0,
+
+            position: 0,
+            seek_start: None,
+
+            last_seq: 0,
+            dropped: Vec::new(),
+
+            out_format: None,
+            out_format_map: Vec::new(),
+            dither: DitherKind::None, dither_rng: SmallRng::new(0),
+            gain: 1.0,
+            ceiling: None, ceiling_gain: 1.0,
+
+            concealment: Concealment::Off,
+            last_frame: Vec::new(),
+            concealed: false,
+            lossless: None,
+            crc_ok: None,
+
+            #[cfg(feature = "std")]
+            debug_sink: None,
+
+            trim_pre: 0, trim_post: 0,
+            held: Vec::new(),
+            total: None,
+            header_trim: Some(true),
+
+            peak_span: 0,
+            peak_points: Vec::new(),
+            peak_pending: (f32::MAX, f32::MIN, 0),
+        };
+    }
+
+    /** set_format_version
+     * Sets the format version of the stream, selecting its bit depth tables and Profile 1 mid/side
+     *   Read from the file header automatically when the stream starts with one,
+     *   headerless streams from writers before version 1 need 0 to read --le Profile 1 frames right
+     * Parameters: Format version
+     */
+    pub fn set_format_version(&mut self, version: u8) { self.format_version = version; }
+
+    /** set_concealment
+     * Sets the concealment mode for corrupt frames
+     * Parameters: Concealment mode
+     */
+    pub fn set_concealment(&mut self, concealment: Concealment) { self.concealment = concealment; }
+
+    /** set_ecc_params
+     * Sets the Reed-Solomon code variant of the stream, as frames do not record it
+     * Parameters: Code variant, None for the FrAD default
+     */
+    pub fn set_ecc_params(&mut self, params: Option<EccParams>) { self.asfh.ecc_params = params; }
+
+    /** conceal
+     * Synthesises a lost frame by mirroring the last good frame and fading it out
+     * Returns: Concealed frame
+     */
+    fn conceal(&self) -> Vec<Vec<f64>> {
+        let (fsize, channels) = (self.asfh.fsize as usize, self.asfh.channels as usize);
+        let len = self.last_frame.len();
+        if len == 0 || self.last_frame[0].len() != channels { return vec![vec![0.0; channels]; fsize]; }
+
+        return (0..fsize).map(|i| {
+            // Mirror around the frame boundary to keep the waveform continuous
+            let j = i % (2 * len);
+            let idx = if j < len { len - 1 - j } else { j - len };
+            let gain = 1.0 - i as f64 / fsize as f64;
+            self.last_frame[idx].iter().map(|x| x * gain).collect()
+        }).collect();
+    }
+
+    /** set_trim
+     * Sets the pre-roll and post-roll to cut from the whole decoded output, read them with head::get_roll
+     *   Post-roll samples are held back until flush, and dropped there
+     * Parameters: Pre-roll samples, Post-roll samples
+     */
+    pub fn set_trim(&mut self, preroll: u64, postroll: u64) {
+        (self.trim_pre, self.trim_post) = (preroll as usize, postroll as usize);
+        self.header_trim = None;
+    }
+
+    /** set_total_samples
+     * Sets the original sample count to output after the pre-roll, read it with head::get_total_samples
+     *   Everything past it, the post-roll and the padding of the last frame, is cut without holding back
+     *   The count stops applying at the first change of sample rate or channels
+     * Parameters: Samples per channel, None if unknown
+     */
+    pub fn set_total_samples(&mut self, samples: Option<u64>) { self.total = samples; self.header_trim = None; }
+
+    /** set_header_trim
+     * Sets how the gapless info of a header at the start of the stream is applied, set_trim and set_total_samples override it
+     *   With trim, the pre-roll, post-roll and the padding of the last frame are cut, so a stream decodes
+     *   to exactly the samples it was encoded from; without it, only the padding is cut
+     * Parameters: Trim flag, None to ignore the header (default: Some(true))
+     */
+    pub fn set_header_trim(&mut self, trim: Option<bool>) { self.header_trim = trim; }
+
+    /** read_header_trim
+     * Takes the gapless info from a header at the start of the buffer, once it is whole
+     * Parameters: End of input flag
+     * Returns: Whether to wait for more of the header
+     */
+    fn read_header_trim(&mut self, eof: bool) -> bool {
+        let Some(trim) = self.header_trim else { return false; };
+        // 1. Wait until the header length is known, if the buffer may start with a header
+        let n = self.buffer.len().min(SIGNATURE.len());
+        if self.buffer[..n] != SIGNATURE[..n] { self.header_trim = None; return false; }
+        if self.buffer.len() < 16 { return !eof; }
+
+        // 2. Wait for the whole header, unless it is implausibly long
+        let head_len = u64::from_be_bytes(self.buffer[8..16].try_into().unwrap()).max(64) as usize;
+        if self.buffer.len() < head_len { return !eof && head_len <= HEADER_WAIT_MAX; }
+        self.header_trim = None;
+
+        // 3. Pre-roll, post-roll and sample count, as set_trim and set_total_samples
+        let (meta, _, _) = head::parser(self.buffer[64..head_len].to_vec());
+        let (preroll, postroll) = head::get_roll(&meta);
+        let total = head::get_total_samples(&meta);
+        (self.trim_pre, self.trim_post, self.total) = if trim { (preroll as usize, postroll as usize, total) }
+        else { (0, 0, total.map(|n| preroll + n + postroll)) };
+        return false;
+    }
+
+    /** apply_trim
+     * Cuts the remaining pre-roll and holds back the post-roll from the output,
+     *   or cuts the output to the original sample count if known
+     * Parameters: Output PCM
+     */
+    fn apply_trim(&mut self, pcm: &mut Vec<Vec<f64>>) {
+        if self.trim_pre != 0 {
+            let n = self.trim_pre.min(pcm.len());
+            pcm.drain(..n); self.trim_pre -= n;
+        }
+        if let Some(total) = self.total.as_mut() {
+            let n = (*total).min(pcm.len() as u64);
+            pcm.truncate(n as usize); *total -= n;
+        }
+        else if self.trim_post != 0 {
+            self.held.append(pcm);
+            let release = self.held.len().saturating_sub(self.trim_post);
+            *pcm = self.held.drain(..release).collect();
+        }
+    }
+
+    /** set_debug_frame_sink
+     * Sets the sink to dump every decoded frame before overlap-add, for diagnosing overlap artifacts
+     *   Each frame is written as [ Sample rate u32be | Channels u16be | Samples u32be | Interleaved f64be PCM ]
+     *   The sink is dropped on its first write error
+     * Parameters: Debug frame sink
+     */
+    #[cfg(feature = "std")]
+    pub fn set_debug_frame_sink(&mut self, sink: Box<dyn Write>) { self.debug_sink = Some(sink); }
+
+    /** dump_frame
+     * Writes a decoded frame to the debug sink, if set
+     * Parameters: Decoded frame before overlap-add
+     */
+    #[cfg(feature = "std")]
+    fn dump_frame(&mut self, pcm: &[Vec<f64>]) {
+        let Some(sink) = self.debug_sink.as_mut() else { return; };
+        let mut buf = Vec::with_capacity(10 + pcm.len() * self.asfh.channels as usize * 8);
+        buf.extend(self.asfh.srate.to_be_bytes());
+        buf.extend((pcm.first().map_or(0, |x| x.len()) as u16).to_be_bytes());
+        buf.extend((pcm.len() as u32).to_be_bytes());
+        pcm.iter().flatten().for_each(|x| buf.extend(x.to_be_bytes()));
+        if sink.write_all(&buf).is_err() { self.debug_sink = None; }
+    }
+
+    /** enable_peak_track
+     * Enables waveform peak tracking, reducing the output to min/max pairs over all channels
+     *   Points gathered so far are discarded
+     * Parameters: Samples per peak point, 0 to disable
+     */
+    pub fn enable_peak_track(&mut self, samples_per_point: usize) {
+        self.peak_span = samples_per_point;
+        self.peak_points.clear();
+        self.peak_pending = (f32::MAX, f32::MIN, 0);
+    }
+
+    /** peaks
+     * Get the waveform peaks of the output so far, the last point may cover fewer samples
+     * Returns: (Minimum, Maximum) per point
+     */
+    pub fn peaks(&self) -> Vec<(f32, f32)> {
+        let mut peaks = self.peak_points.clone();
+        let (min, max, count) = self.peak_pending;
+        if count != 0 { peaks.push((min, max)); }
+        return peaks;
+    }
+
+    /** track_peaks
+     * Folds the output PCM into the peak points, if tracking is enabled
+     * Parameters: Output PCM
+     */
+    fn track_peaks(&mut self, pcm: &[Vec<f64>]) {
+        if self.peak_span == 0 { return; }
+        for sample in pcm {
+            let (min, max, count) = &mut self.peak_pending;
+            for &x in sample { (*min, *max) = (min.min(x as f32), max.max(x as f32)); }
+            *count += 1;
+            if *count == self.peak_span {
+                self.peak_points.push((*min, *max));
+                self.peak_pending = (f32::MAX, f32::MIN, 0);
+            }
+        }
+    }
+
+    /** set_output_format
+     * Sets the PCM format of the byte output, None to output f64 PCM only
+     * Parameters: Output PCM format
+     */
+    pub fn set_output_format(&mut self, fmt: Option<PCMFormat>) { self.out_format = fmt; }
+
+    /** set_output_format_map
+     * Sets the PCM format of the byte output per channel group
+     *   Channels not in any group follow the output format, or f64be if not set
+     * Parameters: Channel ranges and their PCM formats
+     */
+    pub fn set_output_format_map(&mut self, map: &[(Range<usize>, PCMFormat)]) { self.out_format_map = map.to_vec(); }
+
+    /** set_dither
+     * Sets the dither added before rounding to integer output formats, float outputs are left as they are
+     * Parameters: Dither kind
+     */
+    pub fn set_dither(&mut self, dither: DitherKind) { self.dither = dither; }
+
+    /** set_overlap_window
+     * Sets the overlap window of Compact profiles, which must match the one of the encoder
+     * Parameters: Overlap window
+     */
+    pub fn set_overlap_window(&mut self, window: WindowKind) { self.olap_window = window; self.fade_in.clear(); }
+
+    /** set_gain
+     * Sets the gain of the decoded PCM, applied before the peak limiter and the output conversion
+     * Parameters: Linear gain
+     */
+    pub fn set_gain(&mut self, gain: f64) { self.gain = gain; }
+
+    /** set_ceiling
+     * Sets a peak limiter on the decoded PCM, for streams whose peak is not known in advance
+     *   Its gain drops at once to keep every sample within the ceiling, and recovers over about 50 ms
+     * Parameters: Peak ceiling in full scale, None to disable
+     */
+    pub fn set_ceiling(&mut self, ceiling: Option<f64>) { self.ceiling = ceiling; self.ceiling_gain = 1.0; }
+
+    /** apply_gain
+     * Applies the gain and the peak limiter to the decoded PCM
+     * Parameters: Decoded PCM, Sample rate
+     */
+    fn apply_gain(&mut self, pcm: &mut [Vec<f64>], srate: u32) {
+        if self.gain != 1.0 { pcm.iter_mut().for_each(|s| s.iter_mut().for_each(|x| *x *= self.gain)); }
+        let Some(ceiling) = self.ceiling else { return; };
+
+        let release = 1.0 - (-1.0 / (0.05 * srate.max(1) as f64)).exp();
+        for sample in pcm.iter_mut() {
+            let peak = sample.iter().fold(0.0f64, |a, x| a.max(x.abs()));
+            self.ceiling_gain += (1.0 - self.ceiling_gain) * release;
+            if peak * self.ceiling_gain > ceiling { self.ceiling_gain = ceiling / peak; }
+            sample.iter_mut().for_each(|x| *x *= self.ceiling_gain);
+        }
+    }
+
+    /** pcm_bytes
+     * Converts the decoded PCM to interleaved bytes in the output format
+     *   Also for PCM processed after decoding, with the same dither and format map
+     * Parameters: Decoded PCM
+     * Returns: PCM bytes, empty if no output format is set
+     */
+    pub fn pcm_bytes(&mut self, pcm: &[Vec<f64>]) -> Vec<u8> {
+        if self.out_format.is_none() && self.out_format_map.is_empty() { return Vec::new(); }
+        let default = self.out_format.unwrap_or(PCMFormat::F64(Endian::Big));
+        let channels = pcm.first().map_or(0, |x| x.len());
+        let fmts: Vec<PCMFormat> = (0..channels).map(|c|
+            self.out_format_map.iter().find(|(range, _)| range.contains(&c)).map_or(default, |(_, fmt)| *fmt)
+        ).collect();
+        let mut bytes = Vec::with_capacity(pcm.len() * fmts.iter().map(|fmt| fmt.bit_depth() / 8).sum::<usize>());
+        for sample in pcm {
+            for (&x, fmt) in sample.iter().zip(&fmts) {
+                // Triangular noise is the difference of two uniform ones, drawn for integer samples only
+                let dither = match self.dither { DitherKind::Tpdf if !fmt.float() => self.dither_rng.unit() - self.dither_rng.unit(), _ => 0.0 };
+                bytes.extend(f64_to_any_dithered(x, fmt, dither));
+            }
+        }
+        return bytes;
+    }
+
+    /** result
+     * Makes a decode result
+     * Parameters: Decoded PCM, Sample rate, Frame count, Critical info modification flag
+     * Returns: Decode result
+     */
+    fn result(&mut self, mut pcm: Vec<Vec<f64>>, srate: u32, frames: usize, crit: bool) -> DecodeResult {
+        // Samples without channels carry no audio, and would divide by zero downstream
+        if pcm.first().is_some_and(|s| s.is_empty()) { pcm.clear(); }
+        self.apply_trim(&mut pcm);
+        // The budget counts the output, so the trimmed pre-roll does not use it up
+        self.apply_limit(&mut pcm, srate);
+        self.apply_gain(&mut pcm, srate);
+        self.track_peaks(&pcm);
+        let bytes = self.pcm_bytes(&pcm);
+        let start_sample = self.position;
+        self.position += pcm.len() as u64;
+        self.samples_out += pcm.len() as u64;
+        // The next result starts a new segment, from where a seek landed in it if it did
+        if crit { self.position = self.seek_start.take().unwrap_or(0); }
+        return DecodeResult::new(pcm, bytes, srate, frames, crit, start_sample).with_crc(self.crc_ok.take());
+    }
+
+    /** dropped_frame_ranges
+     * Get the ranges of missing frame sequence numbers found so far
+     * Returns: Inclusive ranges of dropped sequence numbers
+     */
+    pub fn dropped_frame_ranges(&self) -> Vec<(u64, u64)> { return self.dropped.clone(); }
+
+    /** is_lossless_stream
+     * Check if every frame decoded so far is bit-exact to its source
+     *   A frame is bit-exact if its profile is LOSSLESS, its CRC matches or ECC repaired it,
+     *   and it is not faded into the overlap tail of a previous COMPACT frame.
+     *   Any gap in the frame sequence numbers makes the stream lossy as well.
+     *   Profile 4 is exact only for input representable in its bit depth, which the stream cannot tell
+     * Returns: Lossless flag, None before the first frame
+     */
+    pub fn is_lossless_stream(&self) -> Option<bool> {
+        return self.lossless.map(|x| x && self.dropped.is_empty());
+    }
+
+    /** check_sequence
+     * Check the frame sequence number for gaps, if present
+     */
+    fn check_sequence(&mut self) {
+        if !LOSSLESS.contains(&self.asfh.profile) || self.asfh.seq == 0 { return; }
+        if self.last_seq != 0 && self.asfh.seq > self.last_seq + 1 {
+            self.dropped.push((self.last_seq + 1, self.asfh.seq - 1));
+        }
+        self.last_seq = self.asfh.seq;
+    }
+
+    /** samples_decoded
+     * Gets the sample count per channel output so far, the sum of every result's samples
+     * Returns: Sample count
+     */
+    pub fn samples_decoded(&self) -> u64 { return self.samples_out; }
+
+    /** estimate_total_samples
+     * Estimates the sample count per channel of the whole stream, e.g. for progress bars
+     *   Exact if a header at the start of the stream holds the sample count and no seek was made,
+     *   else extrapolated from the samples per byte of the frames walked so far
+     * Parameters: Stream length in bytes
+     * Returns: Estimated sample count, None before the first frame
+     */
+    pub fn estimate_total_samples(&self, file_len: u64) -> Option<u64> {
+        if let Some(total) = self.total { return Some(self.samples_out + total); }
+        if self.walked_bytes == 0 { return None; }
+        let frames_len = file_len.saturating_sub(self.lead_bytes) as u128;
+        return Some((frames_len * self.walked_samples as u128 / self.walked_bytes as u128) as u64);
+    }
+
+    /** set_decode_limit
+     * Sets the decoding budget; decoding stops once it is reached
+     * Parameters: Decode limit, None for unlimited
+     */
+    pub fn set_decode_limit(&mut self, limit: Option<DecodeLimit>) { self.limit = limit; }
+
+    /** limit_reached
+     * Check if the decoding budget has been reached, so no more input is needed
+     * Returns: Limit reached flag
+     */
+    pub fn limit_reached(&self) -> bool {
+        return match self.limit {
+            Some(DecodeLimit::Samples(n)) => self.decoded_samples >= n,
+            Some(DecodeLimit::Seconds(t)) => self.decoded_secs >= t,
+            None => false,
+        };
+    }
+
+    /** apply_limit
+     * Truncate the decoded PCM to the remaining decoding budget
+     * Parameters: Decoded PCM, Sample rate
+     */
+    fn apply_limit(&mut self, pcm: &mut Vec<Vec<f64>>, srate: u32) {
+        let remaining = match self.limit {
+            Some(DecodeLimit::Samples(n)) => n.saturating_sub(self.decoded_samples) as usize,
+            Some(DecodeLimit::Seconds(t)) if srate != 0 => ((t - self.decoded_secs).max(0.0) * srate as f64).ceil() as usize,
+            _ => pcm.len(),
         };
+        pcm.truncate(remaining);
+        self.decoded_samples += pcm.len() as u64;
+        if srate != 0 { self.decoded_secs += pcm.len() as f64 / srate as f64; }
     }
 
     /** overlap
@@ -48,9 +602,18 @@ impl Decoder {
      * Returns: PCM with overlap applied
      */
     fn overlap(&mut self, mut frame: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        // 0. If the overlap buffer does not fit this frame's channel layout, discard it
+        //    This only happens on corrupted streams, as channel changes are flushed on crit
+        let frame_chnl = frame.first().map_or(0, |x| x.len());
+        if self.overlap_fragment.iter().any(|x| x.len() != frame_chnl) || self.overlap_fragment.len() > frame.len() {
+            self.overlap_fragment.clear();
+        }
+
         // 1. If overlap buffer not empty, apply Forward linear overlap-add
         if !self.overlap_fragment.is_empty() {
-            let fade_in = hanning_in_overlap(self.overlap_fragment.len());
+            // The overlap length rarely changes, so the window is only rebuilt when it does
+            if self.fade_in.len() != self.overlap_fragment.len() { self.fade_in = self.olap_window.fade_in(self.overlap_fragment.len()); }
+            let fade_in = &self.fade_in;
             for i in 0..self.overlap_fragment.len() { for j in 0..frame[i].len() {
                 frame[i][j] = frame[i][j] * fade_in[i] + self.overlap_fragment[i][j] * fade_in[fade_in.len() - i - 1];
             }}
@@ -67,12 +630,93 @@ impl Decoder {
         return frame;
     }
 
+    /** seek_to_sample
+     * Scans the frame headers of a stream and moves it to the frame holding the target sample
+     *   Positions count output samples from the stream start, pre-roll included, and a COMPACT frame
+     *   contributes its samples less its overlap tail. The decoder is reset for the landed frame,
+     *   and the pre-roll and total sample count are dropped as they only hold from the start
+     * Parameters: Seekable FrAD stream, Target sample
+     * Returns: First sample of the landed frame, the target rounded down to a frame boundary
+     */
+    #[cfg(feature = "std")]
+    pub fn seek_to_sample<R: Read + Seek>(&mut self, file: &mut R, sample: u64) -> Result<u64, FradError> {
+        // 1. Skip the file header
+        let head = read_at(file, 0, 16)?;
+        let mut pos = if head.len() == 16 && head[0..4] == SIGNATURE { u64::from_be_bytes(head[8..16].try_into().unwrap()) } else { 0 };
+        if self.walked_bytes == 0 { self.lead_bytes = pos; }
+        if let Some(version) = head::version(&head) { self.format_version = version; }
+
+        // 2. Walk the frame headers, accumulating the samples each frame outputs
+        let (mut position, mut tail, mut landed) = (0u64, 0u64, (0u64, pos));
+        let (mut segment, mut landed_segment) = (0u64, (0u64, ASFH::new()));
+        let mut prev = ASFH::new();
+        loop {
+            let mut buf = read_at(file, pos, 4096)?;
+            let more = buf.len() == 4096;
+            let Some(i) = buf.find_sign(&FRM_SIGN) else {
+                if !more { break; }
+                pos += (buf.len() - FRM_SIGN.len() + 1) as u64; continue;
+            };
+            let start = pos + i as u64;
+            buf.split_front(i + FRM_SIGN.len());
+
+            let mut asfh = ASFH::new();
+            asfh.buffer = FRM_SIGN.to_vec();
+            let result = asfh.read(&mut buf);
+            pos = start + asfh.buffer.len() as u64;
+            match result {
+                // A header cut off by the read window is read again from its start
+                Incomplete if more && i != 0 => { pos = start; continue; },
+                Incomplete => break,
+                // A force-flush frame outputs the pending overlap tail
+                ForceFlush => { (position, tail) = (position + tail, 0); continue; },
+                Complete => {}
+            }
+            // So does a change of sample rate or channels
+            if asfh.srate != 0 && prev.srate != 0 && !asfh.criteq(&prev) { position += tail; segment = position; }
+            if position > sample { break; }
+            landed = (position, start);
+            landed_segment = (segment, asfh.clone());
+
+            let own_tail = overlap_tail(&asfh);
+            (position, tail) = (position + asfh.fsize as u64 - own_tail, own_tail);
+            pos += asfh.frmbytes;
+            if asfh.srate != 0 { prev = asfh; }
+        }
+
+        // 3. Reset the decoder, keeping the stream info so a format change is still signalled
+        self.buffer.clear(); self.asfh.clear();
+        self.overlap_fragment.clear(); self.held.clear();
+        (self.broken_frame, self.concealed, self.last_seq) = (false, false, 0);
+        (self.trim_pre, self.total) = (0, None);
+
+        // 4. Output position in the landed segment, deferred to its critical result if the format changes
+        let offset = landed.0 - landed_segment.0;
+        if landed_segment.1.criteq(&self.info) || self.info.srate == 0 { (self.position, self.seek_start) = (offset, None); }
+        else { (self.position, self.seek_start) = (0, Some(offset)); }
+
+        file.seek(SeekFrom::Start(landed.1)).map_err(|e| FradError::Io(e.kind()))?;
+        return Ok(landed.0);
+    }
+
     /** is_empty
      * Check if the buffer is shorter than the frame sign or no more data input while frame is broken
      * Returns: Empty flag
      */
     pub fn is_empty(&self) -> bool { return self.buffer.len() < FRM_SIGN.len() || self.broken_frame; }
 
+    /** bytes_needed
+     * Get how many more bytes complete the current frame, for readers that request exact amounts
+     *   While the header is being read, only the rest of the header is counted,
+     *   as the frame length is not known yet
+     * Returns: Bytes to complete the frame or its header, None if no frame signature is found yet
+     */
+    pub fn bytes_needed(&self) -> Option<usize> {
+        if self.asfh.all_set { return Some((self.asfh.frmbytes as usize).saturating_sub(self.buffer.len())); }
+        if !self.asfh.buffer.starts_with(&FRM_SIGN) { return None; }
+        return Some(self.asfh.bytes_needed().saturating_sub(self.buffer.len()));
+    }
+
     /** get_asfh
      * Get a reference to the ASFH struct
      * Returns: Immutable reference to the ASFH struct
@@ -85,7 +729,10 @@ impl Decoder {
      * Returns: Decoded PCM, Sample rate, Critical info modification flag
      */
     pub fn process(&mut self, stream: &[u8]) -> DecodeResult {
+        if self.limit_reached() { return self.result(Vec::new(), self.info.srate, 0, false); }
         self.buffer.extend(stream);
+        if let Some(version) = head::version(&self.buffer) { self.format_version = version; }
+        if self.read_header_trim(stream.is_empty()) { return self.result(Vec::new(), self.info.srate, 0, false); }
         let (mut ret_pcm, mut frames) = (Vec::new(), 0);
 
         loop {
@@ -93,34 +740,68 @@ impl Decoder {
             /* 1. Decoding FrAD Frame */
             if self.asfh.all_set {
                 // 1.0. If the buffer is not enough to decode the frame, break
-                // 1.0.1. If the stream is empty while ASFH is set (which means broken frame), break
-                if stream.is_empty() { self.broken_frame = true; break; }
+                // 1.0.1. If the stream is empty as well while ASFH is set (which means broken frame), break
+                if self.buffer.len() < self.asfh.frmbytes as usize { self.broken_frame = stream.is_empty(); break; }
                 self.broken_frame = false;
-                if self.buffer.len() < self.asfh.frmbytes as usize { break; }
 
                 // 1.1. Split out the frame data
                 let mut frad: Vec<u8> = self.buffer.split_front(self.asfh.frmbytes as usize);
+                self.walk_frame();
 
                 // 1.2. Correct the error if ECC is enabled
-                if self.asfh.ecc {
-                    let repair =  self.fix_error && ( // and if the user requested
-                        // and if CRC mismatch
-                        LOSSLESS.contains(&self.asfh.profile) && crc32(&frad) != self.asfh.crc32 ||
-                        COMPACT.contains(&self.asfh.profile) && crc16_ansi(&frad) != self.asfh.crc16
-                    );
-                    frad = ecc::decode(frad, self.asfh.ecc_ratio, repair);
+                let conceal = self.concealment != Concealment::Off;
+                let repairable = self.asfh.ecc && self.fix_error || conceal;
+                // Every stored CRC is checked for the caller, COMPACT frames store one only with ECC
+                let crc_ok = if LOSSLESS.contains(&self.asfh.profile) { Some(crc32(&frad) == self.asfh.crc32) }
+                else if COMPACT.contains(&self.asfh.profile) && self.asfh.ecc { Some(crc16_ansi(&frad) == self.asfh.crc16) }
+                else { None };
+                if let Some(ok) = crc_ok { self.crc_ok = Some(self.crc_ok.unwrap_or(true) && ok); }
+                let mismatch = crc_ok == Some(false);
+                let corrupt = repairable && mismatch; // if the user requested and if CRC mismatch
+                let mut lost = corrupt;
+                if self.asfh.ecc && !frad.is_empty() {
+                    // Implausible ratios are passed through, as correcting with them would take ages
+                    let repair = self.fix_error && corrupt && ecc::is_plausible(self.asfh.ecc_ratio);
+                    let mask;
+                    (frad, mask) = ecc::decode_checked(frad, ecc::get_codec_with_params(&mut self.rs, self.asfh.ecc_ratio, self.asfh.ecc_params.unwrap_or_default()), repair);
+                    lost = corrupt && (!repair || mask.contains(&false));
                 }
 
-                // 1.3. Decode the FrAD frame
-                let mut pcm =
-                match self.asfh.profile {
-                    1 => fourier::profile1::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.srate, self.asfh.fsize),
-                    2 => fourier::profile2::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.srate, self.asfh.fsize),
-                    4 => fourier::profile4::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.endian),
-                    _ => fourier::profile0::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.endian)
+                // 1.3. Decode the FrAD frame, or conceal it if lost
+                let (channels, fsize) = (self.asfh.channels as usize, self.asfh.fsize as usize);
+                let silence = || vec![vec![0.0; channels]; fsize];
+                let decoded = if lost && conceal { Ok(self.conceal()) }
+                // Zero sample rate is only possible in a corrupt lossless header, return silence
+                else if self.asfh.srate == 0 { Ok(silence()) }
+                else {
+                    match self.asfh.profile {
+                        1 => fourier::profile1::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.srate, self.asfh.fsize,
+                            self.asfh.endian && self.format_version >= fourier::profile1::MID_SIDE_VERSION, &mut self.dct),
+                        2 => fourier::profile2::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.srate, self.asfh.fsize, &mut self.dct),
+                        3 => fourier::profile3::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.fsize),
+                        4 => fourier::profile4::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.endian, self.asfh.fsize),
+                        _ => fourier::profile0::digital(frad, self.asfh.bit_depth_index, self.asfh.channels, self.asfh.endian, self.asfh.fsize)
+                    }
                 };
+                // Undecodable frames are lost as well, concealed if enabled or else silent
+                let failed = decoded.is_err();
+                lost |= failed;
+                let mut pcm = match decoded { Ok(pcm) => pcm, Err(_) if conceal => self.conceal(), Err(_) => silence() };
+                if conceal {
+                    // Fade the first good frame back in after a concealed one
+                    if !lost && self.concealed {
+                        let fade = (pcm.len() / 8).max(1);
+                        for (i, sample) in pcm.iter_mut().take(fade).enumerate() { sample.iter_mut().for_each(|x| *x *= i as f64 / fade as f64); }
+                    }
+                    if !lost { self.last_frame = pcm.clone(); }
+                    self.concealed = lost;
+                }
 
-                // 1.4. Apply overlap
+                // 1.4. Track bit-exactness, dump the frame to the debug sink and apply overlap
+                let exact = LOSSLESS.contains(&self.asfh.profile) && (!mismatch || corrupt && !lost) && !failed && self.overlap_fragment.is_empty();
+                self.lossless = Some(self.lossless.unwrap_or(true) && exact);
+                #[cfg(feature = "std")]
+                self.dump_frame(&pcm);
                 pcm = self.overlap(pcm);
 
                 // 1.5. Append the decoded PCM and clear header
@@ -132,16 +813,19 @@ impl Decoder {
             else {
                 // 2.1. If the header buffer not found, find the header buffer
                 if !self.asfh.buffer.starts_with(&FRM_SIGN) {
-                    match self.buffer.find_pattern(&FRM_SIGN) {
+                    match self.buffer.find_sign(&FRM_SIGN) {
                         // If pattern found in the buffer
                         // 2.1.1. Split out the buffer to the header buffer
                         Some(i) => {
+                            if self.walked_bytes == 0 { self.lead_bytes += i as u64; }
                             self.buffer.split_front(i);
                             self.asfh.buffer = self.buffer.split_front(FRM_SIGN.len());
                         },
                         // 2.1.2. else, Split out the buffer to the last 3 bytes and return
                         None => {
-                            self.buffer.split_front(self.buffer.len().saturating_sub(FRM_SIGN.len() - 1));
+                            let skip = self.buffer.len().saturating_sub(FRM_SIGN.len() - 1);
+                            if self.walked_bytes == 0 { self.lead_bytes += skip as u64; }
+                            self.buffer.split_front(skip);
                             break;
                         }
                     }
@@ -153,25 +837,57 @@ impl Decoder {
                 match header_result {
                     // 2.3.1. If header is complete and not forced to flush, continue
                     Complete => {
-                        // 2.3.1.1. If any critical parameter has changed, flush the overlap buffer
+                        // 2.3.1.0. Bit depth index of an older format version in the current tables
+                        if let Some(index) = remap_depth_index(self.format_version, self.asfh.profile, self.asfh.bit_depth_index) {
+                            self.asfh.bit_depth_index = index;
+                        }
+                        self.check_sequence();
+                        // 2.3.1.1. If the sample rate is invalid, decode the frame as silence without touching the stream info
+                        if self.asfh.srate == 0 { continue; }
+                        // 2.3.1.2. If any critical parameter has changed, flush the overlap buffer
                         if !self.asfh.criteq(&self.info) {
                             let (srate, chnl) = (self.info.srate, self.info.channels);
                             self.info = self.asfh.clone();
                             if srate != 0 || chnl != 0 { // If the info struct is not empty
-                                ret_pcm.extend(self.flush().pcm); // Flush the overlap buffer
-                                return DecodeResult { pcm: ret_pcm, srate, frames, crit: true }; // Set the critical flag and break
+                                // Flush the overlap buffer, keeping the parsed header of the new segment's first frame
+                                ret_pcm.extend(std::mem::take(&mut self.overlap_fragment));
+                                let result = self.result(ret_pcm, srate, frames, true); // Set the critical flag and break
+                                self.total = None; // The sample count of the header only covers one stream format
+                                return result;
                             }
                         }
                     },
                     // 2.3.2. If header is complete and forced to flush, flush and return
-                    ForceFlush => { ret_pcm.extend(self.flush().pcm); break; },
+                    ForceFlush => { ret_pcm.extend(self.flush_overlap()); break; },
                     // 2.3.3. If header is incomplete, return
                     Incomplete => break,
                 }
             }
         }
 
-        return DecodeResult { pcm: ret_pcm, srate: self.asfh.srate, frames, crit: false };
+        return self.result(ret_pcm, self.info.srate, frames, false);
+    }
+
+    /** walk_frame
+     * Accumulate the bytes and output samples of the current frame for the total sample estimate
+     *   A COMPACT frame outputs its samples but the overlap tail, which the next frame outputs
+     */
+    fn walk_frame(&mut self) {
+        self.walked_bytes += self.asfh.buffer.len() as u64 + self.asfh.frmbytes;
+        self.walked_samples += self.asfh.fsize as u64 - overlap_tail(&self.asfh);
+    }
+
+    /** flush_overlap
+     * Extract and clear the overlap buffer and clear the ASFH struct
+     *   The buffer is returned as-is: frames are decoded with their analysis window undone,
+     *   so the tail is at full amplitude and only the cross-fade with a next frame would weight it
+     * Returns: Overlap buffer
+     */
+    fn flush_overlap(&mut self) -> Vec<Vec<f64>> {
+        let ret_pcm = self.overlap_fragment.clone();
+        self.overlap_fragment.clear();
+        self.asfh.clear();
+        return ret_pcm;
     }
 
     /** flush
@@ -180,19 +896,357 @@ impl Decoder {
      */
     pub fn flush(&mut self) -> DecodeResult {
         // 1. Extract the overlap buffer
-        // 2. Update stream info
-        // 3. Clear the overlap buffer
-        // 4. Clear the ASFH struct
+        // 2. Clear the overlap buffer and the ASFH struct
+        // 3. Trim and truncate to the decoding budget
+        // 4. Drop the held post-roll
         // 5. Return exctacted buffer
 
-        let ret_pcm = self.overlap_fragment.clone();
-        self.overlap_fragment.clear();
-        self.asfh.clear();
-        return DecodeResult {
-            pcm: ret_pcm,
-            srate: self.asfh.srate,
-            frames: 0,
-            crit: true,
-        };
+        let ret_pcm = self.flush_overlap();
+        let result = self.result(ret_pcm, self.info.srate, 0, true);
+        self.held.clear();
+        return result;
+    }
+
+    /** frames
+     * Pulls decoded PCM from a reader, read in 32 KiB chunks
+     *   Yields every process result with PCM or the critical flag set, so sample rate and
+     *   channel changes are seen, then the final flush if it holds PCM.
+     *   A read error ends the input as EOF would
+     * Parameters: FrAD stream reader
+     * Returns: Iterator of decode results
+     */
+    #[cfg(feature = "std")]
+    pub fn frames<'a, R: Read + 'a>(&'a mut self, reader: R) -> impl Iterator<Item = DecodeResult> + 'a {
+        return Frames { decoder: self, reader, buf: vec![0; 32768], done: false };
+    }
+}
+
+/** Frames
+ * Iterator state of Decoder::frames
+ */
+#[cfg(feature = "std")]
+struct Frames<'a, R: Read> {
+    decoder: &'a mut Decoder,
+    reader: R,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for Frames<'_, R> {
+    type Item = DecodeResult;
+
+    fn next(&mut self) -> Option<DecodeResult> {
+        while !self.done {
+            let readlen = loop {
+                match self.reader.read(&mut self.buf) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    result => break result.unwrap_or(0),
+                }
+            };
+            if readlen == 0 && self.decoder.is_empty() {
+                self.done = true;
+                let decoded = self.decoder.flush();
+                return if decoded.is_empty() { None } else { Some(decoded) };
+            }
+            let decoded = self.decoder.process(&self.buf[..readlen]);
+            if !decoded.is_empty() || decoded.crit { return Some(decoded); }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::{Endian, PCMFormat}, Encoder};
+
+    /** sine
+     * Makes a stereo test tone
+     */
+    fn sine(samples: usize) -> Vec<Vec<f64>> {
+        return (0..samples).map(|i| vec![0.5 * (i as f64 * 0.05).sin(), 0.25 * (i as f64 * 0.011).sin()]).collect();
+    }
+
+    /** encode
+     * Encodes PCM at 48 kHz, 16 bits, with the encoder set up by the caller
+     */
+    fn encode(profile: u8, pcm: &[Vec<f64>], setup: impl FnOnce(&mut Encoder)) -> Vec<u8> {
+        let mut encoder = Encoder::new(profile, PCMFormat::F64(Endian::Big)).unwrap();
+        encoder.set_srate(48000).unwrap();
+        encoder.set_channels(pcm[0].len() as u16).unwrap();
+        encoder.set_bit_depth(16).unwrap();
+        encoder.set_frame_size(2048).unwrap();
+        setup(&mut encoder);
+        let bytes: Vec<u8> = pcm.concat().iter().flat_map(|x| x.to_be_bytes()).collect();
+        let mut frad = encoder.process(&bytes).unwrap().buf;
+        frad.extend(encoder.flush().unwrap().buf);
+        return frad;
+    }
+
+    /** decode
+     * Decodes a whole stream in one call and flushes
+     */
+    fn decode(decoder: &mut Decoder, frad: &[u8]) -> Vec<Vec<f64>> {
+        let mut pcm = decoder.process(frad).pcm;
+        pcm.extend(decoder.flush().pcm);
+        return pcm;
+    }
+
+    #[test]
+    fn decode_limit_counts_trimmed_output() {
+        let pcm = sine(4800);
+        let frad = encode(4, &pcm, |encoder| encoder.set_preroll_samples(1000));
+        for limit in [500, 1000, 3000] {
+            let mut decoder = Decoder::new(false);
+            decoder.set_trim(1000, 0);
+            decoder.set_decode_limit(Some(DecodeLimit::Samples(limit)));
+            let decoded = decode(&mut decoder, &frad);
+            assert_eq!(decoded.len() as u64, limit);
+            assert!(decoder.limit_reached());
+            assert!(decoded.iter().zip(&pcm).all(|(x, y)| x.iter().zip(y).all(|(a, b)| (a - b).abs() < 1e-3)));
+        }
+    }
+
+    /** frame_starts
+     * Finds the offsets of the frame signatures of a stream
+     */
+    fn frame_starts(frad: &[u8]) -> Vec<usize> {
+        return (0..frad.len().saturating_sub(3)).filter(|&i| frad[i..i + 4] == FRM_SIGN).collect();
+    }
+
+    #[test]
+    fn concealment_replaces_corrupt_frames() {
+        let pcm = sine(2048 * 6);
+        let mut frad = encode(4, &pcm, |_| {});
+        let starts = frame_starts(&frad);
+        frad[starts[3] - 10] ^= 0xff;
+
+        let mut decoder = Decoder::new(false);
+        decoder.set_concealment(Concealment::Interpolate);
+        let decoded = decode(&mut decoder, &frad);
+        assert_eq!(decoded.len(), pcm.len());
+        // The corrupt frame fades out the previous frame mirrored
+        let lost = &decoded[2048 * 2..2048 * 3];
+        assert!(lost.iter().flatten().any(|x| *x != 0.0));
+        assert!(lost.iter().zip(decoded[..2048 * 2].iter().rev()).all(|(x, y)| x.iter().zip(y).all(|(a, b)| a.abs() <= b.abs())));
+        assert!(lost[0].iter().zip(&decoded[2048 * 2 - 1]).all(|(a, b)| a == b));
+        // The next frame fades back in over its first eighth
+        assert_eq!(decoded[2048 * 3 + 256..], decode(&mut Decoder::new(false), &frad)[2048 * 3 + 256..]);
+        assert_eq!(decoder.is_lossless_stream(), Some(false));
+    }
+
+    #[test]
+    fn concealment_keeps_compact_frames_without_crc() {
+        // Compact frames store a CRC only with ECC, so none of these may be taken as corrupt
+        let frad = encode(1, &sine(2048 * 6), |_| {});
+        let mut decoder = Decoder::new(false);
+        decoder.set_concealment(Concealment::Interpolate);
+        assert_eq!(decode(&mut decoder, &frad), decode(&mut Decoder::new(false), &frad));
+    }
+
+
+    /** snr
+     * Gets the signal-to-noise ratio of decoded PCM to its source in dB
+     */
+    fn snr(source: &[Vec<f64>], decoded: &[Vec<f64>]) -> f64 {
+        let (mut signal, mut noise) = (0.0, 0.0);
+        for (x, y) in source.iter().flatten().zip(decoded.iter().flatten()) { (signal, noise) = (signal + x * x, noise + (x - y).powi(2)); }
+        return 10.0 * (signal / noise).log10();
+    }
+
+    /** decode_with
+     * Decodes a Profile 1 stream with the given overlap window
+     */
+    fn decode_with(window: WindowKind, frad: &[u8]) -> Vec<Vec<f64>> {
+        let mut decoder = Decoder::new(false);
+        decoder.set_overlap_window(window);
+        return decode(&mut decoder, frad);
+    }
+
+    #[test]
+    fn overlap_window_round_trips() {
+        let pcm = sine(2048 * 8);
+        for window in [WindowKind::Hann, WindowKind::Sine, WindowKind::Vorbis, WindowKind::Linear] {
+            let frad = encode(1, &pcm, |encoder| { encoder.set_overlap_ratio(2); encoder.set_overlap_window(window); });
+            let decoded = decode_with(window, &frad);
+            assert!(decoded.len() >= pcm.len());
+            let snr = snr(&pcm, &decoded);
+            assert!(snr > 24.0, "{:?} round trip at {:.1} dB", window, snr);
+        }
+    }
+
+    #[test]
+    fn mismatched_overlap_window_adds_error() {
+        let pcm = sine(2048 * 8);
+        let frad = encode(1, &pcm, |encoder| { encoder.set_overlap_ratio(2); encoder.set_overlap_window(WindowKind::Sine); });
+        let matched = snr(&pcm, &decode_with(WindowKind::Sine, &frad));
+        let mismatched = snr(&pcm, &decode_with(WindowKind::Hann, &frad));
+        assert!(matched - mismatched > 6.0, "Sine at {:.1} dB, decoded as Hann at {:.1} dB", matched, mismatched);
+    }
+
+    #[test]
+    fn lossless_decodes_to_the_source_length() {
+        let pcm = sine(44101);
+        for profile in [0, 4] {
+            let mut meta = Vec::new();
+            head::set_total_samples(&mut meta, pcm.len() as u64);
+            let mut frad = head::builder(&meta, &[], &[]);
+            frad.extend(encode(profile, &pcm, |_| {}));
+
+            // Whole and piped in chunks, the header's sample count cuts the padding of the last frame
+            for chunk in [frad.len(), 4096] {
+                let mut decoder = Decoder::new(false);
+                let mut decoded: Vec<Vec<f64>> = frad.chunks(chunk).flat_map(|c| decoder.process(c).pcm).collect();
+                decoded.extend(decoder.flush().pcm);
+                assert_eq!(decoded.len(), pcm.len(), "Profile {} in {}-byte chunks", profile, chunk);
+                assert!(decoded.iter().zip(&pcm).all(|(x, y)| x.iter().zip(y).all(|(a, b)| (a - b).abs() < 1e-3)));
+            }
+        }
+    }
+
+    #[test]
+    fn zero_srate_frame_decodes_as_silence() {
+        let pcm = sine(2048 * 4);
+        for profile in [0, 4] {
+            let mut frad = encode(profile, &pcm, |_| {});
+            let starts = frame_starts(&frad);
+            frad[starts[1] + 0xc..starts[1] + 0x10].copy_from_slice(&0u32.to_be_bytes());
+
+            let mut decoder = Decoder::new(false);
+            let result = decoder.process(&frad);
+            assert!(!result.crit);
+            assert_eq!((result.srate, result.frames), (48000, starts.len()));
+            let mut decoded = result.pcm;
+            decoded.extend(decoder.flush().pcm);
+            assert_eq!(decoded.len(), pcm.len());
+            for (i, (x, y)) in decoded.iter().zip(&pcm).enumerate() {
+                let y = if (2048..4096).contains(&i) { &vec![0.0; 2] } else { y };
+                assert!(x.iter().zip(y).all(|(a, b)| (a - b).abs() < 1e-3), "Profile {} at sample {}", profile, i);
+            }
+        }
+    }
+
+    #[test]
+    fn overlap_discards_a_mismatched_fragment() {
+        let mut decoder = Decoder::new(false);
+        decoder.asfh.profile = 4;
+        // A stereo fragment before a 6-channel frame, and one longer than the frame
+        for (fragment, frame) in [(vec![vec![1.0; 2]; 128], vec![vec![0.5; 6]; 2048]), (vec![vec![1.0; 2]; 256], vec![vec![0.5; 2]; 128])] {
+            decoder.overlap_fragment = fragment;
+            let expected = frame.clone();
+            assert_eq!(decoder.overlap(frame), expected);
+            assert!(decoder.overlap_fragment.is_empty());
+        }
+    }
+
+    #[test]
+    fn cut_stream_changes_channels_cleanly() {
+        // A stereo stream cut before its flush frame, so its overlap fragment is pending at the 6-channel one
+        let stereo = sine(2048 * 3);
+        let surround: Vec<Vec<f64>> = stereo.iter().map(|x| [x.clone(), x.clone(), x.clone()].concat()).collect();
+        let mut encoder = Encoder::new(1, PCMFormat::F64(Endian::Big)).unwrap();
+        encoder.set_srate(48000).unwrap();
+        encoder.set_channels(2).unwrap();
+        encoder.set_bit_depth(16).unwrap();
+        encoder.set_frame_size(2048).unwrap();
+        encoder.set_overlap_ratio(4);
+        let mut frad = encoder.process(&stereo.concat().iter().flat_map(|x| x.to_be_bytes()).collect::<Vec<u8>>()).unwrap().buf;
+        frad.extend(encode(1, &surround, |encoder| encoder.set_overlap_ratio(4)));
+
+        let mut decoder = Decoder::new(false);
+        let first = decoder.process(&frad);
+        assert!(first.crit);
+        assert!(!first.pcm.is_empty() && first.pcm.iter().all(|x| x.len() == 2));
+        let mut second = decoder.process(&[]).pcm;
+        second.extend(decoder.flush().pcm);
+        assert!(second.len() >= surround.len());
+        assert!(second.iter().all(|x| x.len() == 6));
+        assert!(snr(&surround, &second) > 20.0);
+    }
+
+    /** decode_chunks
+     * Decodes a stream to PCM and 24-bit bytes, fed in chunks of the given size
+     */
+    fn decode_chunks(frad: &[u8], chunk: usize) -> (Vec<Vec<f64>>, Vec<u8>) {
+        let mut decoder = Decoder::new(false);
+        decoder.set_output_format(Some(PCMFormat::I24(Endian::Little)));
+        let (mut pcm, mut bytes) = (Vec::new(), Vec::new());
+        for c in frad.chunks(chunk) { let result = decoder.process(c); pcm.extend(result.pcm); bytes.extend(result.bytes); }
+        let result = decoder.flush();
+        pcm.extend(result.pcm); bytes.extend(result.bytes);
+        return (pcm, bytes);
+    }
+
+    #[test]
+    fn chunked_decoding_matches_one_shot() {
+        let pcm = sine(2048 * 4 + 1000);
+        let frad = encode(1, &pcm, |encoder| encoder.set_overlap_ratio(4));
+        let one_shot = decode_chunks(&frad, frad.len());
+        for chunk in [1, 7, 1000, 4096] { assert!(decode_chunks(&frad, chunk) == one_shot, "{}-byte chunks", chunk); }
+
+        // The flushed tail is at full level, no worse than the rest of the stream
+        let peak_err = |range: Range<usize>| range.flat_map(|i| (0..2).map(move |c| (i, c))).map(|(i, c)| (one_shot.0[i][c] - pcm[i][c]).abs()).fold(0.0, f64::max);
+        assert!(peak_err(pcm.len() - 600..pcm.len()) <= peak_err(0..pcm.len() - 600));
+    }
+    #[test]
+    fn lossless_terminator_is_optional() {
+        let pcm = sine(2048 * 3 + 500);
+        let plain = encode(4, &pcm, |_| {});
+        let terminated = encode(4, &pcm, |encoder| encoder.set_emit_terminator(true));
+        assert_eq!(terminated.len(), plain.len() + 32);
+        assert_eq!(terminated[..plain.len()], plain[..]);
+
+        let (a, b) = (decode(&mut Decoder::new(false), &plain), decode(&mut Decoder::new(false), &terminated));
+        assert_eq!(a.len(), pcm.len());
+        assert!(a == b);
+    }
+
+    #[test]
+    fn compact_flush_closes_every_frame() {
+        let pcm = sine(2048 * 3 + 500);
+        let frad = encode(1, &pcm, |_| {});
+        // Frames written while flushing are each followed by a force-flush header, and one more closes the stream
+        let flags: Vec<u8> = frame_starts(&frad).iter().map(|&i| frad[i + 10] & 1).collect();
+        assert!(flags.ends_with(&[0, 1, 1]), "{:?}", flags);
+        assert!(decode(&mut Decoder::new(false), &frad).len() >= pcm.len());
+    }
+    #[test]
+    fn endian_bit_is_mid_side_only_from_version_1() {
+        // Earlier writers set the endian bit of Profile 1 frames on --le, under a version 0 header
+        let pcm = sine(2048 * 3);
+        let mut frad = encode(1, &pcm, |_| {});
+        for i in frame_starts(&frad) { frad[i + 8] |= 0b1000; }
+        let mut head = head::builder(&Vec::new(), &[], &[]);
+        head[4] = 0;
+
+        let plain = decode(&mut Decoder::new(false), &encode(1, &pcm, |_| {}));
+        let old = decode(&mut Decoder::new(false), &[head.clone(), frad.clone()].concat());
+        assert!(old == plain);
+
+        let mut headerless = Decoder::new(false);
+        headerless.set_format_version(0);
+        assert!(decode(&mut headerless, &frad) == plain);
+
+        // The same frames under the current version are read as mid/side
+        head[4] = FORMAT_VERSION;
+        assert!(decode(&mut Decoder::new(false), &[head, frad].concat()) != plain);
+    }
+    #[test]
+    #[cfg(feature = "std")]
+    fn seek_reads_headers_across_the_window() {
+        let frad = encode(4, &sine(2048 * 6), |_| {});
+        let starts = frame_starts(&frad);
+        // Junk puts the next frame signature 6 bytes before the end of a 4096-byte read window
+        let mut padded = frad[..starts[3]].to_vec();
+        padded.extend(vec![0u8; 4090]);
+        padded.extend(&frad[starts[3]..]);
+
+        for stream in [frad, padded] {
+            let mut file = std::io::Cursor::new(stream);
+            let landed = Decoder::new(false).seek_to_sample(&mut file, 2048 * 4 + 100).unwrap();
+            assert_eq!(landed, 2048 * 4);
+            assert_eq!(file.get_ref()[file.position() as usize..][..4], FRM_SIGN);
+        }
     }
 }
\ No newline at end of file
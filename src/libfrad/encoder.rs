@@ -5,20 +5,78 @@
  */
 
 use crate::{
-    PCMFormat, f64cvt::any_to_f64,
-    backend::{Prepend, SplitFront},
-    fourier::{self, profiles::{compact, COMPACT}, AVAILABLE, BIT_DEPTHS, SEGMAX},
-    tools::  {asfh::ASFH, ecc},
+    FradError, PCMFormat, f64cvt::{any_to_f64, f64_to_any},
+    backend::{Prepend, SplitFront, WindowKind},
+    fourier::{self, backend::{scratch::Scratch, signal::WindowType, u8pack::{self, Predictor}}, profile1::AnalogueParams, profiles::{compact, COMPACT}, AVAILABLE, BIT_DEPTHS, SEGMAX},
+    tools::  {asfh::ASFH, ecc::{self, EccParams, RSCodec}, index},
 };
+use crate::backend::rng::SmallRng;
 
-use std::process::exit;
-// use rand::prelude::*;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
 
 pub struct EncodeResult {
     pub buf: Vec<u8>,
     pub samples: usize
 }
 
+/** EncoderParams
+ * Critical encoder parameters for validation before constructing an encoder
+ */
+pub struct EncoderParams {
+    pub profile: u8,
+    pub srate: u32,
+    pub channels: u16,
+    pub bit_depth: u16,
+    pub frame_size: u32,
+    pub ecc_ratio: [u8; 2],
+}
+
+// Parameter verifiers, shared by the setters and validate_params
+fn verify_profile(profile: u8) -> Result<(), FradError> {
+    if !AVAILABLE.contains(&profile) { return Err(FradError::InvalidProfile(profile)); }
+    return Ok(());
+}
+fn verify_channels(channels: u16) -> Result<(), FradError> {
+    if channels == 0 { return Err(FradError::InvalidChannels(channels)); }
+    return Ok(());
+}
+fn verify_srate(profile: u8, srate: u32) -> Result<(), FradError> {
+    if srate == 0 || COMPACT.contains(&profile) && !compact::SRATES.contains(&srate) { return Err(FradError::InvalidSampleRate(srate)); }
+    return Ok(());
+}
+fn verify_frame_size(profile: u8, frame_size: u32) -> Result<(), FradError> {
+    if frame_size == 0 || frame_size > SEGMAX[profile as usize] { return Err(FradError::InvalidFrameSize(profile, frame_size)); }
+    return Ok(());
+}
+fn verify_exact_frame_size(profile: u8, frame_size: u32) -> Result<(), FradError> {
+    if COMPACT.contains(&profile) && compact::samples_to_index(frame_size).is_none() { return Err(FradError::InexactFrameSize(frame_size)); }
+    return Ok(());
+}
+fn verify_bit_depth(profile: u8, bit_depth: u16) -> Result<(), FradError> {
+    if bit_depth == 0 || !BIT_DEPTHS[profile as usize].contains(&bit_depth) { return Err(FradError::InvalidBitDepth(profile, bit_depth)); }
+    return Ok(());
+}
+fn verify_ecc_ratio(ecc_ratio: [u8; 2]) -> Result<(), FradError> {
+    if !ecc::is_plausible(ecc_ratio) { return Err(FradError::InvalidEccRatio(ecc_ratio)); }
+    return Ok(());
+}
+
+/** validate_params
+ * Validates encoder parameters without constructing an encoder
+ * Parameters: Encoder parameters
+ * Returns: First invalid parameter found, if any
+ */
+pub fn validate_params(params: &EncoderParams) -> Result<(), FradError> {
+    verify_profile(params.profile)?;
+    verify_srate(params.profile, params.srate)?;
+    verify_channels(params.channels)?;
+    verify_bit_depth(params.profile, params.bit_depth)?;
+    verify_frame_size(params.profile, params.frame_size)?;
+    verify_ecc_ratio(params.ecc_ratio)?;
+    return Ok(());
+}
+
 /** Encoder
  * Struct for FrAD encoder
  */
@@ -26,101 +84,378 @@ pub struct Encoder {
     asfh: ASFH, buffer: Vec<u8>,
     bit_depth: u16, channels: u16,
     fsize: u32, srate: u32,
+    exact_fsize: bool,
     overlap_fragment: Vec<Vec<f64>>,
+    // Overlap window, and its fade-in for power-complementary windows
+    olap_window: WindowKind, fade_in: Vec<f64>,
 
     pcm_format: PCMFormat,
     loss_level: f64,
+    // Target bitrate, and the bits and samples written under it
+    target_bitrate: Option<u32>,
+    cbr_total: (u64, u64), cbr_last: (u64, u64),
+    coeff_clip: f64,
+    joint_stereo: bool,
+    quant_seed: Option<u64>,
+    little_endian: bool,
+    emit_terminator: bool,
+    frame_seq: bool,
+    dc_frames: bool,
+    predictor: Predictor,
+    int_packing: bool,
+    window: WindowType,
+    check_inexact: bool, inexact_frames: u64,
+    verify_output: bool,
+    index: Option<Vec<(u64, u64)>>,
+    total_samples: u64, total_bytes: u64,
+    preroll: usize, postroll: usize,
+    channel_perm: Vec<usize>,
+    rs: Option<RSCodec>,
+    scratch: Scratch,
+    // Stress mode generator, and whether the next frame is drawn already
+    #[cfg(feature = "stress")]
+    stress: Option<(SmallRng, bool)>,
 }
 
 impl Encoder {
-    pub fn new(profile: u8, pcm_format: PCMFormat) -> Encoder {
-        if !AVAILABLE.contains(&profile) { eprintln!("Invalid profile! Available: {:?}", AVAILABLE); exit(1); }
+    pub fn new(profile: u8, pcm_format: PCMFormat) -> Result<Encoder, FradError> {
+        verify_profile(profile)?;
         let mut asfh = ASFH::new();
         asfh.profile = profile;
-        return Encoder {
+        return Ok(Encoder {
             asfh, buffer: Vec::new(),
             bit_depth: 0, channels: 0,
             fsize: 0, srate: 0,
+            exact_fsize: false,
             overlap_fragment: Vec::new(),
+            olap_window: WindowKind::Hann, fade_in: Vec::new(),
 
             pcm_format,
             loss_level: 0.5,
-        };
+            target_bitrate: None,
+            cbr_total: (0, 0), cbr_last: (0, 0),
+            coeff_clip: f64::INFINITY,
+            joint_stereo: false,
+            quant_seed: None,
+            little_endian: false,
+            emit_terminator: false,
+            frame_seq: false,
+            dc_frames: false,
+            predictor: Predictor::None,
+            int_packing: true,
+            window: WindowType::Rectangular,
+            check_inexact: false, inexact_frames: 0,
+            verify_output: false,
+            index: None,
+            total_samples: 0, total_bytes: 0,
+            preroll: 0, postroll: 0,
+            channel_perm: Vec::new(),
+            rs: None,
+            scratch: Scratch::new(),
+            #[cfg(feature = "stress")]
+            stress: None,
+        });
     }
 
     /** _set_profile
      * Modify the profile while running
      * Parameters: Profile, Sample rate, Channel count, Bit depth, Frame size
+     * Returns: First invalid parameter, the profile is not changed if it is invalid itself
      */
-    pub unsafe fn _set_profile(&mut self, profile: u8, srate: u32, channels: u16, bit_depth: u16, frame_size: u32) {
-        if !AVAILABLE.contains(&profile) { eprintln!("Invalid profile! Available: {:?}", AVAILABLE); exit(1); }
+    pub unsafe fn _set_profile(&mut self, profile: u8, srate: u32, channels: u16, bit_depth: u16, frame_size: u32) -> Result<(), FradError> {
+        verify_profile(profile)?;
 
         self.asfh.profile = profile;
-        self.set_srate(srate);
-        self.set_channels(channels);
-        self.set_bit_depth(bit_depth);
-        self.set_frame_size(frame_size);
+        self.set_srate(srate)?;
+        self.set_channels(channels)?;
+        self.set_bit_depth(bit_depth)?;
+        return self.set_frame_size(frame_size);
     }
 
     // Critical info - set after initialising, before processing (Global)
+    // Flush before changing these mid-stream; buffered PCM is read in the new layout,
+    // and the overlap fragment in the old one is dropped as the previous frame already holds it
     pub fn get_channels(&self) -> u16 { self.channels }
-    pub fn set_channels(&mut self, channels: u16) {
-        if channels == 0 { eprintln!("Channel count cannot be zero"); exit(1); }
+    pub fn set_channels(&mut self, channels: u16) -> Result<(), FradError> {
+        verify_channels(channels)?;
+        if channels != self.channels { self.overlap_fragment.clear(); }
         self.channels = channels;
+        return Ok(());
     }
     pub fn get_srate(&self) -> u32 { self.srate }
-    pub fn set_srate(&mut self, mut srate: u32) {
-        if srate == 0 { eprintln!("Sample rate cannot be zero"); exit(1); }
-        if COMPACT.contains(&self.asfh.profile) {
-            let x = compact::get_valid_srate(srate);
-            if x != srate {
-                eprintln!("Invalid sample rate! Valid rates for profile {}: {:?}\nAuto-adjusting to: {}",
-                self.asfh.profile, compact::SRATES.iter().rev().filter(|&&x| x != 0).cloned().collect::<Vec<u32>>(), x);
-                srate = x;
-            }
-        }
+    pub fn set_srate(&mut self, srate: u32) -> Result<(), FradError> {
+        verify_srate(self.asfh.profile, srate)?;
+        if srate != self.srate { self.overlap_fragment.clear(); }
         self.srate = srate;
+        return Ok(());
     }
 
     // Semi-critical info - set after resetting profile
     pub fn get_frame_size(&self) -> u32 { self.fsize }
-    pub fn set_frame_size(&mut self, frame_size: u32) {
-        if frame_size == 0 { eprintln!("Frame size cannot be zero"); exit(1); }
-        if frame_size > SEGMAX[self.asfh.profile as usize] { eprintln!("Samples per frame cannot exceed {}", SEGMAX[self.asfh.profile as usize]); exit(1); }
+    pub fn set_frame_size(&mut self, frame_size: u32) -> Result<(), FradError> {
+        verify_frame_size(self.asfh.profile, frame_size)?;
+        if self.exact_fsize { verify_exact_frame_size(self.asfh.profile, frame_size)?; }
         self.fsize = frame_size;
+        return Ok(());
+    }
+
+    /** set_exact_frame_size
+     * Makes Compact profiles consume exactly the frame size per frame, overlap included, for fixed hop sizes
+     *   The frame size must be a Compact sample count, as it is no longer rounded up to one
+     * Parameters: Exact frame size flag
+     */
+    pub fn set_exact_frame_size(&mut self, exact: bool) -> Result<(), FradError> {
+        if exact && self.fsize != 0 { verify_exact_frame_size(self.asfh.profile, self.fsize)?; }
+        self.exact_fsize = exact;
+        return Ok(());
     }
     pub fn get_bit_depth(&self) -> u16 { self.bit_depth }
-    pub fn set_bit_depth(&mut self, bit_depth: u16) {
-        if bit_depth == 0 { eprintln!("Bit depth cannot be zero"); exit(1); }
-        if !BIT_DEPTHS[self.asfh.profile as usize].contains(&bit_depth) {
-            eprintln!("Invalid bit depth! Valid depths for profile {}: {:?}",
-            self.asfh.profile, BIT_DEPTHS[self.asfh.profile as usize].iter().filter(|&&x| x != 0).cloned().collect::<Vec<u16>>());
-            exit(1);
-        }
+    pub fn set_bit_depth(&mut self, bit_depth: u16) -> Result<(), FradError> {
+        verify_bit_depth(self.asfh.profile, bit_depth)?;
         self.bit_depth = bit_depth;
+        return Ok(());
     }
 
     // Non-critical info - can be set anytime
-    pub fn set_ecc(&mut self, ecc: bool, mut ecc_ratio: [u8; 2]) {
-        self.asfh.ecc = ecc;
-        let (dsize_zero, exceed_255) = (ecc_ratio[0] == 0, ecc_ratio[0] as u16 + ecc_ratio[1] as u16 > 255);
-        if dsize_zero || exceed_255 {
-            if dsize_zero { eprintln!("ECC data size must not be zero"); }
-            if exceed_255 { eprintln!("ECC data size and check size must not exceed 255, given: {} and {}", ecc_ratio[0], ecc_ratio[1]); }
-            eprintln!("Setting ECC to default 96 24");
-            ecc_ratio = [96, 24];
-        }
-        self.asfh.ecc_ratio = ecc_ratio;
+    pub fn get_ecc(&self) -> (bool, [u8; 2]) { (self.asfh.ecc, self.asfh.ecc_ratio) }
+    pub fn set_ecc(&mut self, ecc: bool, ecc_ratio: [u8; 2]) -> Result<(), FradError> {
+        verify_ecc_ratio(ecc_ratio)?;
+        (self.asfh.ecc, self.asfh.ecc_ratio) = (ecc, ecc_ratio);
+        return Ok(());
     }
-    pub fn set_little_endian(&mut self, little_endian: bool) { self.asfh.endian = little_endian; }
+    pub fn set_ecc_params(&mut self, params: Option<EccParams>) { self.asfh.ecc_params = params; }
+    pub fn get_little_endian(&self) -> bool { self.little_endian }
+    pub fn set_little_endian(&mut self, little_endian: bool) { self.little_endian = little_endian; }
     // pub fn set_profile(&mut self, profile: u8) { self.asfh.profile = profile; }
+    pub fn get_loss_level(&self) -> f64 { self.loss_level }
     pub fn set_loss_level(&mut self, loss_level: f64) {
         self.loss_level = loss_level.abs().max(0.125);
     }
+
+    /** set_target_bitrate
+     * Encodes Profile 1 at a constant bitrate, adjusting the loss level every frame
+     *   The previous frame's size and the stream's average so far nudge the loss level towards the target,
+     *   starting from the level set with set_loss_level. Headers and ECC count in the bitrate as well.
+     *   Profile 2 has no loss level, so it is not affected
+     * Parameters: Target bitrate in bits per second, None to keep the loss level as set
+     */
+    pub fn set_target_bitrate(&mut self, bitrate: Option<u32>) {
+        self.target_bitrate = bitrate.filter(|&x| x != 0);
+        (self.cbr_total, self.cbr_last) = ((0, 0), (0, 0));
+    }
+
+    /** track_bitrate
+     * Adjusts the loss level by how far the bitrate of the previous frame and of the stream miss the target
+     */
+    fn track_bitrate(&mut self) {
+        let Some(target) = self.target_bitrate else { return; };
+        let ((bits, samples), (last_bits, last_samples)) = (self.cbr_total, self.cbr_last);
+        if last_samples == 0 || self.srate == 0 { return; }
+        let rate = |bits: u64, samples: u64| bits as f64 * self.srate as f64 / samples as f64 / target as f64;
+        // Coarser quantisation shrinks frames, so the level follows the overshoot
+        let step = rate(last_bits, last_samples).powf(0.5) * rate(bits, samples).powf(0.5);
+        // At most doubled or halved per frame, and bounded above so an unreachable target cannot run it away
+        self.loss_level = (self.loss_level * step.clamp(0.5, 2.0)).clamp(0.125, 1e6);
+    }
+
+    /** set_coeff_clip
+     * Clamps the masked DCT coefficients of Profile 1 before quantisation, a robustness knob for unclean input
+     *   The limit is in masking threshold steps; one runaway coefficient otherwise widens
+     *   the Exp-Golomb codes of the whole frame. Off by default
+     * Parameters: Maximum magnitude, 0, negative or NaN for no clip
+     */
+    pub fn set_coeff_clip(&mut self, clip: f64) {
+        self.coeff_clip = if clip > 0.0 { clip } else { f64::INFINITY };
+    }
+
+    /** set_joint_stereo
+     * Encodes stereo Profile 1 frames as mid/side, which saves bits on correlated channels
+     *   Signalled by the endian bit of the frame header, as Profile 1 has no byte order to tell,
+     *   and read as such only under format version 1 or later, which the file header records.
+     *   Mono and multichannel frames are left as they are
+     * Parameters: Joint stereo flag
+     */
+    pub fn set_joint_stereo(&mut self, joint_stereo: bool) { self.joint_stereo = joint_stereo; }
+
+    /** set_quant_seed
+     * Quantises Profile 1 with stochastic rounding, drawn from a generator seeded per frame
+     *   Rounding is unbiased but noisier than to the nearest, and costs bits
+     *   The frame generator is seeded from this and the frame's sample position,
+     *   so the output is identical across runs and however the input is chunked
+     * Parameters: Seed
+     */
+    pub fn set_quant_seed(&mut self, seed: u64) { self.quant_seed = Some(seed); }
+
+    /** loss_level_from_index
+     * Maps an integer quality index to the loss level, 0 being the best quality
+     * Parameters: Loss level index
+     * Returns: Loss level(1.25^index / 19 + 0.5)
+     */
+    pub fn loss_level_from_index(index: u8) -> f64 {
+        return 1.25_f64.powi(index as i32) / 19.0 + 0.5;
+    }
+
+    /** loss_level_to_index
+     * Maps a loss level to the nearest integer quality index
+     * Parameters: Loss level
+     * Returns: Loss level index
+     */
+    pub fn loss_level_to_index(loss_level: f64) -> u8 {
+        let x = (loss_level - 0.5) * 19.0;
+        if x.is_nan() || x <= 1.0 { return 0; }
+        return x.log(1.25).round().min(u8::MAX as f64) as u8;
+    }
+    pub fn get_overlap_ratio(&self) -> u16 { self.asfh.overlap_ratio }
     pub fn set_overlap_ratio(&mut self, mut overlap_ratio: u16) {
         if overlap_ratio != 0 { overlap_ratio = overlap_ratio.max(2).min(256); }
         self.asfh.overlap_ratio = overlap_ratio;
     }
+    // An empty frame ends LOSSLESS streams, off by default as older decoders see it as a zero-length frame
+    pub fn set_emit_terminator(&mut self, emit_terminator: bool) { self.emit_terminator = emit_terminator; }
+    // Frame sequence numbers are stored in the reserved field of LOSSLESS headers
+    pub fn set_frame_sequence(&mut self, frame_seq: bool) { self.frame_seq = frame_seq; }
+    // Constant frames are stored as their DC values in Compact profiles, needs a decoder supporting them
+    pub fn set_dc_frames(&mut self, dc_frames: bool) { self.dc_frames = dc_frames; }
+
+    /** set_lossless_predictor
+     * Sets the predictor applied to packed samples in Profiles 0 and 4, signalled in each frame
+     * Parameters: Predictor
+     */
+    pub fn set_lossless_predictor(&mut self, predictor: Predictor) { self.predictor = predictor; }
+
+    /** set_int_packing
+     * Stores integer PCM of up to 32 bits as integers in Profile 4, exact at its own bit depth
+     *   Enabled by default, disable it for decoders reading float frames only
+     * Parameters: Integer packing flag
+     */
+    pub fn set_int_packing(&mut self, int_packing: bool) { self.int_packing = int_packing; }
+
+    /** set_analysis_window
+     * Sets the window applied before the DCT in Compact profiles, signalled in each frame
+     *   The decoder divides it back out, so only windows without zeros are offered;
+     *   enable overlap with any window other than the default rectangular one
+     * Parameters: Window type
+     */
+    pub fn set_analysis_window(&mut self, window: WindowType) { self.window = window; }
+
+    /** set_overlap_window
+     * Sets the overlap window of Compact profiles, not signalled so the decoder must be set to the same
+     * Parameters: Overlap window
+     */
+    pub fn set_overlap_window(&mut self, window: WindowKind) { self.olap_window = window; self.fade_in.clear(); }
+
+    /** set_verify_output
+     * Decodes every frame right after encoding it and compares it with its input, for archival.
+     *   Profiles 3 and 4 must give the input back exactly at the frame's bit depth; the others,
+     *   being lossy, must give finite samples no further from the input than silence would be.
+     *   This roughly doubles the encoding time, as every frame is decoded once more
+     * Parameters: Verification flag
+     */
+    pub fn set_verify_output(&mut self, verify: bool) { self.verify_output = verify; }
+
+    /** set_check_inexact
+     * Checks whether Profile 4 input is exactly representable at the encoded bit depth,
+     *   as such input is not encoded losslessly, e.g. 24 and 32 bits hold only f32 values
+     * Parameters: Check flag
+     */
+    pub fn set_check_inexact(&mut self, check_inexact: bool) { self.check_inexact = check_inexact; }
+    /** get_inexact_frames
+     * Gets the number of frames found not exactly representable, see set_check_inexact
+     * Returns: Inexact frame count
+     */
+    pub fn get_inexact_frames(&self) -> u64 { self.inexact_frames }
+
+    /** set_build_index
+     * Collects the sample position and byte offset of every frame,
+     *   and appends them as an index footer on flush, see tools::index
+     * Parameters: Index flag
+     */
+    pub fn set_build_index(&mut self, build_index: bool) { self.index = if build_index { Some(Vec::new()) } else { None }; }
+
+    /** set_preroll_samples
+     * Sets the silence prepended to the first input, record it with head::set_roll for decoders to trim
+     * Parameters: Pre-roll samples per channel
+     */
+    pub fn set_preroll_samples(&mut self, samples: usize) { self.preroll = samples; }
+
+    /** set_postroll_samples
+     * Sets the silence appended on flush, record it with head::set_roll for decoders to trim
+     * Parameters: Post-roll samples per channel
+     */
+    pub fn set_postroll_samples(&mut self, samples: usize) { self.postroll = samples; }
+
+    /** silence
+     * Generates silence in the input PCM format
+     * Parameters: Samples per channel
+     * Returns: Silent PCM bytes
+     */
+    fn silence(&self, samples: usize) -> Vec<u8> {
+        return f64_to_any(0.0, &self.pcm_format).repeat(samples * self.channels as usize);
+    }
+
+    /** set_channel_permutation
+     * Reorders the input channels before encoding, set after the channel count
+     * Parameters: Input channel index for each output channel, empty for identity
+     */
+    pub fn set_channel_permutation(&mut self, perm: &[usize]) -> Result<(), FradError> {
+        let mut sorted = perm.to_vec(); sorted.sort();
+        if !perm.is_empty() && (perm.len() != self.channels as usize || sorted.iter().enumerate().any(|(i, &c)| i != c)) {
+            return Err(FradError::InvalidChannelPermutation(self.channels));
+        }
+        self.channel_perm = perm.to_vec();
+        return Ok(());
+    }
+
+    /** stress_randomize
+     * Randomises profile, bit depth, frame size, loss level, ECC and overlap of every frame, for stress tests
+     *   The same seed and input always give the same stream.
+     *   Compact profiles are only drawn when the sample rate is one of theirs
+     * Parameters: Seed
+     */
+    #[cfg(feature = "stress")]
+    pub fn stress_randomize(&mut self, seed: u64) { self.stress = Some((SmallRng::new(seed), false)); }
+
+    /** randomise_frame
+     * Draws the parameters of the next frame in stress mode, once per frame however the input is chunked
+     * Returns: Error if a drawn parameter is rejected, which would be a bug in the drawing
+     */
+    #[cfg(feature = "stress")]
+    fn randomise_frame(&mut self) -> Result<(), FradError> {
+        let srate = self.srate;
+        let Some((rng, drawn)) = self.stress.as_mut() else { return Ok(()); };
+        if *drawn { return Ok(()); }
+        *drawn = true;
+
+        let profiles: Vec<u8> = AVAILABLE.iter().copied().filter(|p| !COMPACT.contains(p) || compact::SRATES.contains(&srate)).collect();
+        let profile = rng.choose(&profiles);
+        let depths: Vec<u16> = BIT_DEPTHS[profile as usize].iter().copied().filter(|&x| x != 0).collect();
+        let bit_depth = rng.choose(&depths);
+        let frame_size = if COMPACT.contains(&profile) { rng.choose(&compact::SAMPLES_LI) } else { rng.range(128, 32768) as u32 };
+        let loss_level = rng.range(125, 10000) as f64 / 1000.0;
+        let ecc_data = rng.range(1, 255) as u8;
+        let ecc = (rng.bool(), [ecc_data, rng.range(0, 256 - ecc_data as u64) as u8]);
+        let overlap_ratio = if rng.bool() { rng.range(2, 257) as u16 } else { 0 };
+
+        self.asfh.profile = profile;
+        self.set_bit_depth(bit_depth)?;
+        self.set_frame_size(frame_size)?;
+        self.set_loss_level(loss_level);
+        self.set_ecc(ecc.0, ecc.1)?;
+        self.set_overlap_ratio(overlap_ratio);
+        return Ok(());
+    }
+
+    /** buffered_samples
+     * Get the number of input samples buffered but not encoded yet, for producers to throttle on
+     *   This stays below one frame after each process call, but grows without bound
+     *   while sample rate, channels or frame size are unset, as nothing can be encoded then
+     * Returns: Buffered sample count per channel
+     */
+    pub fn buffered_samples(&self) -> usize {
+        let sample_bytes = self.pcm_format.bit_depth() / 8 * self.channels as usize;
+        if sample_bytes == 0 { return 0; }
+        return self.buffer.len() / sample_bytes;
+    }
 
     /** get_asfh
      * Get a reference to the ASFH struct
@@ -130,11 +465,12 @@ impl Encoder {
 
     /** overlap
      * Overlaps the current frame with the overlap fragment
-     * Parameters: Current frame, Overlap fragment, Overlap rate, Profile
-     * Returns: Overlapped frame, Next overlap fragment
+     * Parameters: Current frame, Last frame flag(no next overlap)
+     * Returns: Overlapped frame
      */
-    fn overlap(&mut self, mut frame: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    fn overlap(&mut self, mut frame: Vec<Vec<f64>>, last: bool) -> Vec<Vec<f64>> {
         // 1. If overlap fragment is not empty,
+        let head = self.overlap_fragment.len();
         if !self.overlap_fragment.is_empty() {
             // prepent the fragment to the frame
             frame.prepend(&self.overlap_fragment);
@@ -142,12 +478,24 @@ impl Encoder {
 
         // 2. If overlap is enabled and profile uses overlap
         let mut next_overlap = Vec::new();
-        if COMPACT.contains(&self.asfh.profile) && self.asfh.overlap_ratio > 1 {
+        if COMPACT.contains(&self.asfh.profile) && self.asfh.overlap_ratio > 1 && !last {
             // Copy the last olap samples to the next overlap fragment
             let overlap_ratio = self.asfh.overlap_ratio as usize;
             let cutoff = frame.len() * (overlap_ratio - 1) / overlap_ratio;
             next_overlap = frame[cutoff..].to_vec();
         }
+
+        // 3. Power-complementary windows fade both copies of the overlap here as well
+        if self.olap_window.power_complementary() {
+            let tail = next_overlap.len();
+            for (len, start, rev) in [(head, 0, false), (tail, frame.len() - tail, true)] {
+                if len == 0 { continue; }
+                if self.fade_in.len() != len { self.fade_in = self.olap_window.fade_in(len); }
+                for i in 0..len { for x in frame[start + i].iter_mut() {
+                    *x *= if rev { self.fade_in[len - i - 1] } else { self.fade_in[i] };
+                }}
+            }
+        }
         self.overlap_fragment = next_overlap;
         return frame;
     }
@@ -155,33 +503,41 @@ impl Encoder {
     /** inner
      * Inner encoder loop
      * Parameters: PCM stream, Flush flag
-     * Returns: Encoded audio data
+     * Returns: Encoded audio data, or an error leaving the stream unconsumed if the bit depth is unset,
+     *   or dropping the failed frame if its samples overflow every bit depth of the profile
      */
-    fn inner(&mut self, stream: &[u8], flush: bool) -> EncodeResult {
+    fn inner(&mut self, stream: &[u8], flush: bool) -> Result<EncodeResult, FradError> {
+        // Bit depth is only required once there is something to encode
+        if self.srate != 0 && self.channels != 0 && self.fsize != 0 { verify_bit_depth(self.asfh.profile, self.bit_depth)?; }
+
+        // Pre-roll goes in front of all input and post-roll after it, once each
+        if self.preroll != 0 && self.channels != 0 {
+            let silence = self.silence(self.preroll);
+            self.buffer.splice(0..0, silence); self.preroll = 0;
+        }
         self.buffer.extend(stream);
+        if flush && self.postroll != 0 && self.channels != 0 {
+            let silence = self.silence(self.postroll);
+            self.buffer.extend(silence); self.postroll = 0;
+        }
         let (mut ret, mut samples) = (Vec::new(), 0);
 
         if self.srate == 0 || self.channels == 0 || self.fsize == 0 {
-            return EncodeResult { buf: ret, samples }
+            return Ok(EncodeResult { buf: ret, samples });
         }
 
         loop {
-            // let rng = &mut rand::thread_rng();
-            // let prf = *AVAILABLE.choose(rng).unwrap();
-            // unsafe {
-            //     self._set_profile(prf, self.srate, self.channels,
-            //         *BIT_DEPTHS[prf as usize].iter().filter(|&&x| x != 0).choose(rng).unwrap(),
-            //         if COMPACT.contains(&prf) { *compact::SAMPLES_LI.choose(rng).unwrap() } else { rng.gen_range(128..32768) }
-            //     );
-            // }
-            // self.set_loss_level(rng.gen_range(0.125..10.0));
-            // let ecc_data = rng.gen_range(1..255);
-            // self.set_ecc(rng.gen_bool(0.5), [ecc_data, rng.gen_range(0..(255 - ecc_data))]);
-            // self.set_overlap_ratio(rng.gen_range(2..256));
+            // Stress mode draws new parameters for every frame
+            #[cfg(feature = "stress")]
+            self.randomise_frame()?;
 
             // 0. Set read length in samples
             let mut rlen = self.fsize as usize;
-            if COMPACT.contains(&self.asfh.profile) {
+            if COMPACT.contains(&self.asfh.profile) && self.exact_fsize && self.overlap_fragment.len() < rlen {
+                // Frame size is a Compact sample count, verified on setting
+                rlen -= self.overlap_fragment.len();
+            }
+            else if COMPACT.contains(&self.asfh.profile) {
                 // Read length = smallest value in SMPLS_LI bigger than frame size and overlap fragment size
                 let li_val = *compact::SAMPLES_LI.iter().filter(|&x| *x >= self.fsize as u32).min().unwrap() as usize;
                 if li_val <= self.overlap_fragment.len() // if overlap fragment is equal or bigger than frame size
@@ -202,50 +558,335 @@ impl Encoder {
 
             // Unravel flat PCM to 2D PCM array
             let mut frame: Vec<Vec<f64>> = pcm_flat.chunks(self.channels as usize).map(Vec::from).collect();
-            if frame.is_empty() { ret.extend(self.asfh.force_flush()); break; } // If frame is empty, break
-            samples += frame.len();
+            if self.channel_perm.len() == self.channels as usize {
+                frame = frame.into_iter().map(|x| self.channel_perm.iter().map(|&c| x.get(c).copied().unwrap_or(0.0)).collect()).collect();
+            }
+            // The last frame of the stream keeps its tail, as no frame follows to fade it back in
+            let last = flush && self.buffer.is_empty();
+            // A power-complementary window faded the tail of the previous frame out,
+            // so the stream ends with one more frame holding the overlap fragment alone
+            let closing = frame.is_empty() && self.olap_window.power_complementary() && !self.overlap_fragment.is_empty();
+            if frame.is_empty() && !closing { // If frame is empty, write force-flush or terminator and break
+                if COMPACT.contains(&self.asfh.profile) || self.emit_terminator { ret.extend(self.asfh.force_flush()); }
+                break;
+            }
+            // Frame starts at the overlap fragment, from the previous input
+            let position = self.total_samples + samples as u64 - self.overlap_fragment.len() as u64;
+            let read = frame.len();
+            samples += read;
 
             // 2. Overlap the frame with the previous overlap fragment
-            frame = self.overlap(frame);
+            frame = self.overlap(frame, last);
             let fsize: u32 = frame.len() as u32;
 
             // 3. Encode the frame
-            if !BIT_DEPTHS[self.asfh.profile as usize].contains(&self.bit_depth) { panic!("Invalid bit depth"); }
+            let check_frame = if self.check_inexact && self.asfh.profile == 4 { frame.concat() } else { Vec::new() };
+            let int_bits = if self.int_packing && !self.pcm_format.float() && self.pcm_format.bit_depth() <= 32 { Some(self.pcm_format.bit_depth() as u16) } else { None };
+            let reference = if self.verify_output { frame.clone() } else { Vec::new() };
+            // Constant bitrate adjusts the loss level from the frames written so far
+            if self.asfh.profile == 1 { self.track_bitrate(); }
             let (mut frad, bit_depth_index, channels, srate) = match self.asfh.profile {
-                1 => fourier::profile1::analogue(frame, self.bit_depth, self.srate, self.loss_level),
-                2 => fourier::profile2::analogue(frame, self.bit_depth, self.srate),
-                4 => fourier::profile4::analogue(frame, self.bit_depth, self.srate, self.asfh.endian),
-                _ => fourier::profile0::analogue(frame, self.bit_depth, self.srate, self.asfh.endian)
+                1 => fourier::profile1::analogue(frame, self.bit_depth, self.srate, AnalogueParams {
+                    loss_level: self.loss_level, clip: self.coeff_clip, joint: self.joint_stereo,
+                    rng: self.quant_seed.map(|seed| SmallRng::new(seed ^ position.wrapping_mul(0x9e3779b97f4a7c15))),
+                    dc_frames: self.dc_frames, window: self.window
+                }, &mut self.scratch),
+                2 => fourier::profile2::analogue(frame, self.bit_depth, self.srate, self.dc_frames, self.window, &mut self.scratch),
+                3 => fourier::profile3::analogue(frame, self.bit_depth, self.srate),
+                4 => fourier::profile4::analogue(frame, self.bit_depth, self.srate, self.little_endian, self.predictor, int_bits)?,
+                _ => fourier::profile0::analogue(frame, self.bit_depth, self.srate, self.little_endian, self.predictor)?
             };
+            if !check_frame.is_empty() && !fourier::profile4::is_int_frame(&frad, bit_depth_index, channels, fsize)
+            && !u8pack::is_exact(&check_frame, fourier::profile4::DEPTHS[bit_depth_index as usize]) {
+                self.inexact_frames += 1;
+            }
+            if self.verify_output && !self.verify_frame(&frad, &reference, bit_depth_index, channels, srate) {
+                return Err(FradError::VerificationFailed(position));
+            }
 
             // 4. Create Reed-Solomon error correction code
-            if self.asfh.ecc {
-                frad = ecc::encode(frad, self.asfh.ecc_ratio);
+            if self.asfh.ecc && !frad.is_empty() {
+                frad = ecc::encode_with_codec(frad, ecc::get_codec_with_params(&mut self.rs, self.asfh.ecc_ratio, self.asfh.ecc_params.unwrap_or_default()));
             }
 
             // 5. Write the frame to the buffer
             (self.asfh.bit_depth_index, self.asfh.channels, self.asfh.fsize, self.asfh.srate) = (bit_depth_index, channels, fsize, srate);
+            self.asfh.endian = match self.asfh.profile {
+                1 => self.joint_stereo && channels == 2,
+                _ => self.little_endian
+            };
+            self.asfh.seq = if self.frame_seq { self.asfh.seq + 1 } else { 0 };
+            if let Some(index) = self.index.as_mut() { index.push((position, self.total_bytes + ret.len() as u64)); }
+            let written = ret.len();
             ret.extend(self.asfh.write(frad));
-            if flush { ret.extend(self.asfh.force_flush()); }
+            if flush && COMPACT.contains(&self.asfh.profile) { ret.extend(self.asfh.force_flush()); }
+            if self.target_bitrate.is_some() {
+                self.cbr_last = (((ret.len() - written) * 8) as u64, read as u64);
+                self.cbr_total = (self.cbr_total.0 + self.cbr_last.0, self.cbr_total.1 + self.cbr_last.1);
+            }
+            #[cfg(feature = "stress")]
+            if let Some((_, drawn)) = self.stress.as_mut() { *drawn = false; }
         }
 
-        return EncodeResult { buf: ret, samples };
+        if flush {
+            // Last frame already holds the overlap fragment, so the next segment starts clean
+            self.overlap_fragment.clear();
+            // Index footer goes after the last frame
+            if let Some(index) = self.index.as_mut() { ret.extend(index::build(index)); index.clear(); }
+        }
+        (self.total_samples, self.total_bytes) = (self.total_samples + samples as u64, self.total_bytes + ret.len() as u64);
+        return Ok(EncodeResult { buf: ret, samples });
+    }
+
+    /** verify_frame
+     * Decodes an encoded frame and compares it with its input, see set_verify_output
+     * Parameters: Encoded frame, Input frame, Bit depth index, Channel count, Sample rate
+     * Returns: Consistency flag
+     */
+    fn verify_frame(&mut self, frad: &[u8], reference: &[Vec<f64>], bit_depth_index: u16, channels: u16, srate: u32) -> bool {
+        // Compact frames are stored and decoded at the padded sample count
+        let fsize = reference.len() as u32;
+        let fsize = if COMPACT.contains(&self.asfh.profile) { *compact::SAMPLES_LI.iter().find(|&&x| x >= fsize).unwrap_or(&fsize) } else { fsize };
+        let decoded = match self.asfh.profile {
+            1 => fourier::profile1::digital(frad.to_vec(), bit_depth_index, channels, srate, fsize, self.joint_stereo && channels == 2, &mut self.scratch.dct),
+            2 => fourier::profile2::digital(frad.to_vec(), bit_depth_index, channels, srate, fsize, &mut self.scratch.dct),
+            3 => fourier::profile3::digital(frad.to_vec(), bit_depth_index, channels, fsize),
+            4 => fourier::profile4::digital(frad.to_vec(), bit_depth_index, channels, self.little_endian, fsize),
+            _ => fourier::profile0::digital(frad.to_vec(), bit_depth_index, channels, self.little_endian, fsize)
+        };
+        let Ok(mut decoded) = decoded else { return false; };
+        decoded.truncate(reference.len());
+        let (decoded, reference) = (decoded.concat(), reference.concat());
+        if decoded.len() != reference.len() || decoded.iter().any(|x| !x.is_finite()) { return false; }
+
+        match self.asfh.profile {
+            // Input as stored at the bit depth
+            3 => {
+                let scale = (1u64 << (fourier::profile3::DEPTHS[bit_depth_index as usize] - 1)) as f64;
+                return reference.iter().zip(&decoded).all(|(x, y)| (x * scale).round().clamp(-scale, scale - 1.0) / scale == *y);
+            },
+            // Integer frames are exact, float ones are the input as stored at the bit depth
+            4 if fourier::profile4::is_int_frame(frad, bit_depth_index, channels, fsize) => return reference == decoded,
+            4 => {
+                let bits = fourier::profile4::DEPTHS[bit_depth_index as usize];
+                return u8pack::unpack(u8pack::pack(reference, bits, false), bits, false) == decoded;
+            },
+            _ => {
+                let error: f64 = reference.iter().zip(&decoded).map(|(x, y)| (x - y).powi(2)).sum();
+                return error <= reference.iter().map(|x| x * x).sum::<f64>() + f64::EPSILON;
+            }
+        }
     }
 
     /** process
      * Processes the input stream
+     *   Input is appended to an internal buffer, and every complete frame in it is encoded before returning,
+     *   so only the remainder shorter than a frame is kept between calls (see buffered_samples)
      * Parameters: Input stream
-     * Returns: Encoded audio data
+     * Returns: Encoded audio data, see inner for the errors
      */
-    pub fn process(&mut self, stream: &[u8]) -> EncodeResult {
+    pub fn process(&mut self, stream: &[u8]) -> Result<EncodeResult, FradError> {
         return self.inner(stream, false);
     }
 
     /** flush
      * Encodes the remaining data in the buffer and flush
-     * Returns: Encoded audio data
+     * Returns: Encoded audio data, see inner for the errors
      */
-    pub fn flush(&mut self) -> EncodeResult {
+    pub fn flush(&mut self) -> Result<EncodeResult, FradError> {
         return self.inner(b"", true);
     }
+}
+
+/** io_error
+ * Converts an encoder error into an I/O error
+ * Parameters: FrAD error
+ * Returns: I/O error
+ */
+#[cfg(feature = "std")]
+fn io_error(e: FradError) -> io::Error {
+    return match e {
+        FradError::Io(kind) => io::Error::from(kind),
+        _ => io::Error::new(io::ErrorKind::InvalidInput, e),
+    };
+}
+
+/** EncoderWriter
+ * std::io::Write adapter around the encoder, writing the FrAD stream to an inner writer
+ *   PCM bytes are taken in the encoder's PCM format and converted by the encoder itself.
+ *   flush ends the stream as Encoder::flush does, so io::copy followed by one flush encodes a whole input
+ */
+#[cfg(feature = "std")]
+pub struct EncoderWriter<W: Write> {
+    encoder: Encoder,
+    inner: W,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> EncoderWriter<W> {
+    pub fn new(encoder: Encoder, inner: W) -> EncoderWriter<W> {
+        return EncoderWriter { encoder, inner };
+    }
+
+    /** encoder
+     * Gets the wrapped encoder, to change its settings between writes
+     * Returns: Mutable reference to the encoder
+     */
+    pub fn encoder(&mut self) -> &mut Encoder { return &mut self.encoder; }
+
+    /** into_inner
+     * Unwraps the adapter without flushing
+     * Returns: Encoder, Inner writer
+     */
+    pub fn into_inner(self) -> (Encoder, W) { return (self.encoder, self.inner); }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for EncoderWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let encoded = self.encoder.process(buf).map_err(io_error)?;
+        self.inner.write_all(&encoded.buf)?;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let encoded = self.encoder.flush().map_err(io_error)?;
+        self.inner.write_all(&encoded.buf)?;
+        return self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::Endian, Decoder};
+
+    /** noise
+     * Makes stereo white or pink noise as f64be bytes, pink by Paul Kellet's filter
+     */
+    fn noise(pink: bool, samples: usize) -> Vec<u8> {
+        let (mut rng, mut b) = (SmallRng::new(1), [[0.0f64; 7]; 2]);
+        let mut bytes = Vec::with_capacity(samples * 16);
+        for _ in 0..samples {
+            for b in b.iter_mut() {
+                let w = rng.unit() * 2.0 - 1.0;
+                let x = if !pink { w * 0.5 } else {
+                    b[0] = 0.99886 * b[0] + w * 0.0555179; b[1] = 0.99332 * b[1] + w * 0.0750759;
+                    b[2] = 0.96900 * b[2] + w * 0.1538520; b[3] = 0.86650 * b[3] + w * 0.3104856;
+                    b[4] = 0.55000 * b[4] + w * 0.5329522; b[5] = -0.7616 * b[5] - w * 0.0168980;
+                    let p = b.iter().sum::<f64>() + w * 0.5362;
+                    b[6] = w * 0.115926;
+                    p * 0.11
+                };
+                bytes.extend(x.to_be_bytes());
+            }
+        }
+        return bytes;
+    }
+
+    #[test]
+    fn target_bitrate_is_met() {
+        let srate = 48000;
+        for pink in [false, true] {
+            let input = noise(pink, srate as usize * 3);
+            for target in [64000, 128000, 256000] {
+                let mut encoder = Encoder::new(1, PCMFormat::F64(Endian::Big)).unwrap();
+                encoder.set_srate(srate).unwrap();
+                encoder.set_channels(2).unwrap();
+                encoder.set_bit_depth(16).unwrap();
+                encoder.set_frame_size(2048).unwrap();
+                encoder.set_target_bitrate(Some(target));
+                let mut size = 0;
+                for chunk in input.chunks(16384) { size += encoder.process(chunk).unwrap().buf.len(); }
+                size += encoder.flush().unwrap().buf.len();
+
+                let bitrate = size as f64 * 8.0 / 3.0;
+                assert!((bitrate / target as f64 - 1.0).abs() < 0.15, "{} noise at {} bps: {} bps", if pink { "Pink" } else { "White" }, target, bitrate);
+            }
+        }
+    }
+
+    #[test]
+    fn target_bitrate_keeps_the_minimum_loss_level() {
+        // Far beyond what the frames can take, the level stays at its minimum
+        let mut encoder = Encoder::new(1, PCMFormat::F64(Endian::Big)).unwrap();
+        encoder.set_srate(48000).unwrap();
+        encoder.set_channels(2).unwrap();
+        encoder.set_bit_depth(16).unwrap();
+        encoder.set_frame_size(2048).unwrap();
+        encoder.set_target_bitrate(Some(u32::MAX));
+        encoder.process(&noise(true, 48000)).unwrap();
+        assert_eq!(encoder.get_loss_level(), 0.125);
+    }
+
+    /** tone
+     * Makes interleaved PCM with a different pitch on each channel
+     */
+    fn tone(channels: usize, samples: usize) -> Vec<f64> {
+        return (0..samples).flat_map(|i| (0..channels).map(move |c| 0.5 * (i as f64 * 0.01 * (c + 1) as f64).sin())).collect();
+    }
+
+    #[test]
+    fn format_change_starts_a_clean_segment() {
+        let segments = [(2, 44100, 10000), (6, 48000, 9000)];
+        for profile in [1, 4] {
+            let mut encoder = Encoder::new(profile, PCMFormat::F64(Endian::Big)).unwrap();
+            encoder.set_bit_depth(16).unwrap();
+            encoder.set_frame_size(2048).unwrap();
+            if profile == 1 { encoder.set_overlap_ratio(4); }
+            let mut frad = Vec::new();
+            for &(channels, srate, samples) in &segments {
+                encoder.set_channels(channels as u16).unwrap();
+                encoder.set_srate(srate).unwrap();
+                let bytes: Vec<u8> = tone(channels, samples).iter().flat_map(|x| x.to_be_bytes()).collect();
+                frad.extend(encoder.process(&bytes).unwrap().buf);
+                frad.extend(encoder.flush().unwrap().buf);
+            }
+
+            // Each critical change closes a segment, a force-flush frame only pauses the decoder
+            let (mut decoder, mut decoded) = (Decoder::new(false), vec![(0, Vec::new())]);
+            let mut result = decoder.process(&frad);
+            loop {
+                let segment = decoded.last_mut().unwrap();
+                segment.0 = result.srate; segment.1.extend(result.pcm);
+                if result.crit { decoded.push((0, Vec::new())); }
+                else if result.frames == 0 && decoder.is_empty() { break; }
+                result = decoder.process(&[]);
+            }
+            decoded.last_mut().unwrap().1.extend(decoder.flush().pcm);
+            assert_eq!(decoded.len(), segments.len());
+            // Lossy frames are padded to a whole frame, the padding must decode to silence as well
+            let tolerance = if profile == 1 { 0.1 } else { 1e-3 };
+            for ((srate_out, pcm), &(channels, srate, samples)) in decoded.iter().zip(&segments) {
+                assert_eq!((*srate_out, pcm[0].len()), (srate, channels));
+                if profile == 4 { assert_eq!(pcm.len(), samples); } else { assert!(pcm.len() >= samples); }
+                let source = tone(channels, samples).into_iter().chain(core::iter::repeat(0.0));
+                assert!(pcm.concat().iter().zip(source).all(|(x, y)| (x - y).abs() < tolerance), "Profile {} at {} Hz", profile, srate);
+            }
+        }
+    }
+    #[test]
+    fn inexact_frames_are_counted() {
+        for (exact, expected) in [(false, true), (true, false)] {
+            let mut encoder = Encoder::new(4, PCMFormat::F64(Endian::Big)).unwrap();
+            encoder.set_srate(48000).unwrap();
+            encoder.set_channels(2).unwrap();
+            encoder.set_bit_depth(32).unwrap();
+            encoder.set_frame_size(2048).unwrap();
+            encoder.set_check_inexact(true);
+            // 32 bits hold f32 values, so a rounded tone is exact and the f64 one is not
+            let pcm: Vec<f64> = tone(2, 5000).into_iter().map(|x| if exact { x as f32 as f64 } else { x }).collect();
+            encoder.process(&pcm.iter().flat_map(|x| x.to_be_bytes()).collect::<Vec<u8>>()).unwrap();
+            encoder.flush().unwrap();
+            assert_eq!(encoder.get_inexact_frames() != 0, expected);
+        }
+    }
+    #[test]
+    fn invalid_settings_are_returned() {
+        assert!(matches!(Encoder::new(7, PCMFormat::F64(Endian::Big)), Err(FradError::InvalidProfile(7))));
+        let mut encoder = Encoder::new(1, PCMFormat::F64(Endian::Big)).unwrap();
+        assert!(matches!(encoder.set_srate(44101), Err(FradError::InvalidSampleRate(44101))));
+        assert!(matches!(encoder.set_ecc(true, [0, 24]), Err(FradError::InvalidEccRatio([0, 24]))));
+        assert_eq!((encoder.get_srate(), encoder.get_ecc()), (0, (false, [0, 0])));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,89 @@
+/**                                Error Types                                */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Error types for FrAD library
+ */
+
+use crate::{backend::PCMFormat, fourier::{profiles::compact, AVAILABLE, BIT_DEPTHS, SEGMAX}, tools::ecc};
+use core::fmt;
+// The only std type here, for the I/O of the seeking and WAV tools, so Io is std-only
+#[cfg(feature = "std")]
+use std::io::ErrorKind;
+
+/** FradError
+ * Error for invalid FrAD parameters, undecodable data or failed stream conversion, with the offending value attached
+ *   Plain values only, so making and matching one allocates nothing
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FradError {
+    InvalidProfile(u8),
+    InvalidChannels(u16),
+    InvalidSampleRate(u32),
+    InvalidFrameSize(u8, u32),
+    InexactFrameSize(u32),
+    InvalidBitDepth(u8, u16),
+    InvalidEccRatio([u8; 2]),
+    UnsupportedWavFormat(PCMFormat),
+    WavFormatChange(u32, u16),
+    InvalidWav(&'static str),
+    WavHeaderConflict(&'static str, u32, u32),
+    InvalidChannelPermutation(u16),
+    SampleOverflow(u8),
+    VerificationFailed(u64),
+    EccDecodeFailed(usize),
+    InvalidFrame(u8),
+    #[cfg(feature = "std")]
+    Io(ErrorKind),
+}
+
+impl fmt::Display for FradError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FradError::InvalidProfile(_) => write!(f, "Invalid profile! Available: {:?}", AVAILABLE),
+            FradError::InvalidChannels(_) => write!(f, "Channel count cannot be zero"),
+            FradError::InvalidSampleRate(0) => write!(f, "Sample rate cannot be zero"),
+            FradError::InvalidSampleRate(srate) => write!(f, "Invalid sample rate {}! Valid rates for compact profiles: {:?}",
+                srate, compact::SRATES.iter().rev().filter(|&&x| x != 0).cloned().collect::<Vec<u32>>()),
+            FradError::InvalidFrameSize(_, 0) => write!(f, "Frame size cannot be zero"),
+            FradError::InvalidFrameSize(profile, fsize) => match SEGMAX.get(profile as usize) {
+                Some(max) => write!(f, "Samples per frame cannot exceed {}", max),
+                None => write!(f, "Invalid frame size {} for unknown profile {}", fsize, profile),
+            },
+            FradError::InexactFrameSize(fsize) => write!(f, "Exact frame size {} is not a compact sample count! Valid counts: {:?}", fsize, compact::SAMPLES_LI),
+            FradError::InvalidBitDepth(_, 0) => write!(f, "Bit depth cannot be zero"),
+            FradError::InvalidBitDepth(profile, bit_depth) => match BIT_DEPTHS.get(profile as usize) {
+                Some(depths) => write!(f, "Invalid bit depth! Valid depths for profile {}: {:?}",
+                    profile, depths.iter().filter(|&&x| x != 0).cloned().collect::<Vec<u16>>()),
+                None => write!(f, "Invalid bit depth {} for unknown profile {}", bit_depth, profile),
+            },
+            FradError::InvalidEccRatio([0, _]) => write!(f, "ECC data size must not be zero"),
+            FradError::InvalidEccRatio(ratio) if ratio[0] as u16 + ratio[1] as u16 > 255 => write!(f, "ECC data size and check size must not exceed 255, given: {} and {}", ratio[0], ratio[1]),
+            FradError::InvalidEccRatio(ratio) => write!(f, "ECC check size must not exceed {} times the data size, given: {} and {}", ecc::MAX_PARITY_PER_DATA, ratio[0], ratio[1]),
+            FradError::UnsupportedWavFormat(fmt) => write!(f, "WAV cannot hold {:?} PCM, use U8, I16, I24, I32, F32 or F64", fmt),
+            FradError::WavFormatChange(srate, channels) => write!(f, "Stream changed to {} channels at {} Hz, WAV cannot change format mid-file", channels, srate),
+            FradError::InvalidWav(reason) => write!(f, "Invalid WAV input: {}", reason),
+            FradError::WavHeaderConflict(setting, given, header) => write!(f, "{} {} conflicts with {} of the WAV header", setting, given, header),
+            FradError::InvalidChannelPermutation(channels) => write!(f, "Channel permutation must be a permutation of 0..{}", channels),
+            FradError::SampleOverflow(profile) => write!(f, "Samples overflow the widest bit depth of profile {}", profile),
+            FradError::VerificationFailed(sample) => write!(f, "Frame at sample {} does not decode back to its input", sample),
+            FradError::EccDecodeFailed(block) => write!(f, "ECC block {} has more errors than its check bytes can correct", block),
+            FradError::InvalidFrame(profile) => write!(f, "Frame data is not decodable as profile {}", profile),
+            #[cfg(feature = "std")]
+            FradError::Io(kind) => write!(f, "I/O error: {}", kind),
+        }
+    }
+}
+
+impl core::error::Error for FradError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_profiles_display_raw_values() {
+        assert_eq!(FradError::InvalidFrameSize(9, 4096).to_string(), "Invalid frame size 4096 for unknown profile 9");
+        assert_eq!(FradError::InvalidBitDepth(255, 24).to_string(), "Invalid bit depth 24 for unknown profile 255");
+        assert!(FradError::InvalidBitDepth(1, 20).to_string().contains("[8, 12, 16, 24, 32, 48, 64]"));
+    }
+}
\ No newline at end of file
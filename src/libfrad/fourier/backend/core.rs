@@ -6,7 +6,69 @@
  */
 
 use core::f64::consts::PI;
-use rustfft::{FftPlanner, num_complex::Complex};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::sync::Arc;
+
+/** DctPlan
+ * FFT plans and twiddle factors for the DCT of one length, built once and reused across frames
+ */
+pub struct DctPlan {
+    size: usize,
+    forward: Arc<dyn Fft<f64>>,
+    inverse: Arc<dyn Fft<f64>>,
+    twiddles: Vec<(f64, f64)>,
+}
+
+impl DctPlan {
+    pub fn new(size: usize) -> DctPlan {
+        let mut planner = FftPlanner::new();
+        let twiddles = (0..size).map(|k| {
+            let angle = -PI * k as f64 / (2.0 * size as f64);
+            (angle.cos(), angle.sin())
+        }).collect();
+        return DctPlan { size, forward: planner.plan_fft_forward(2 * size), inverse: planner.plan_fft_inverse(2 * size), twiddles };
+    }
+
+    /** forward
+     * DCT-II, identical to dct
+     * Parameters: Input signal of the plan's length
+     * Returns: DCT coefficients
+     */
+    pub fn forward(&self, x: &[f64]) -> Vec<f64> {
+        let n = self.size;
+        let mut buffer: Vec<Complex<f64>> = x.iter().chain(x.iter().rev()).map(|v| Complex::new(v / (2.0 * n as f64), 0.0)).collect();
+        self.forward.process(&mut buffer);
+        return buffer.iter().zip(&self.twiddles).map(|(b, (cos, sin))| b.re * cos - b.im * sin).collect();
+    }
+
+    /** inverse
+     * DCT-III, identical to idct
+     * Parameters: DCT coefficients of the plan's length
+     * Returns: Signal
+     */
+    pub fn inverse(&self, y: &[f64]) -> Vec<f64> {
+        let n = self.size;
+        let alpha: Vec<Complex<f64>> = y.iter().zip(&self.twiddles).map(|(v, (cos, sin))| Complex::new(v * cos, v * sin)).collect();
+
+        let mut beta: Vec<Complex<f64>> = alpha.iter().map(|&z| Complex::new(z.re, -z.im))
+        .chain([Complex::new(0.0, 0.0)]).chain(alpha[1..].iter().rev().cloned()).collect();
+        self.inverse.process(&mut beta);
+        return beta[..n].iter().map(|c| c.re).collect();
+    }
+}
+
+/** get_plan
+ * Gets the cached DCT plan, rebuilding it only if the length has changed
+ * Parameters: Plan cache, Transform length
+ * Returns: DCT plan for the length
+ */
+pub fn get_plan(cache: &mut Option<DctPlan>, size: usize) -> &DctPlan {
+    match cache {
+        Some(plan) if plan.size == size => {},
+        _ => { *cache = Some(DctPlan::new(size)); }
+    }
+    return cache.as_ref().unwrap();
+}
 
 pub fn dct(x: Vec<f64>) -> Vec<f64> {
     let n = x.len();
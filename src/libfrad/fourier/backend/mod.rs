@@ -1,3 +1,4 @@
 pub mod core;
 pub mod signal;
-pub mod u8pack;
\ No newline at end of file
+pub mod u8pack;
+pub mod scratch;
\ No newline at end of file
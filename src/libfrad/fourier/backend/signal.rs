@@ -6,6 +6,42 @@
  */
 
 use rustfft::{FftPlanner, num_complex::Complex};
+use std::f64::consts::PI;
+
+/** WindowType
+ * Analysis window applied before the DCT in Compact profiles
+ *   The decoder divides the window back out, so it must have no zeros,
+ *   and quantisation noise grows by its inverse towards the frame edges,
+ *   where the overlap crossfade hides it; use windows with overlap enabled
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowType {
+    Rectangular,
+    Hamming,
+}
+
+impl WindowType {
+    pub fn index(&self) -> u8 {
+        return match self { WindowType::Rectangular => 0, WindowType::Hamming => 1 };
+    }
+    pub fn from_index(index: u8) -> Option<WindowType> {
+        return match index { 0 => Some(WindowType::Rectangular), 1 => Some(WindowType::Hamming), _ => None };
+    }
+}
+
+/** window
+ * Fills a buffer with a window function
+ * Parameters: Window type, Output buffer
+ */
+pub fn window(kind: WindowType, out: &mut [f64]) {
+    let n = out.len().max(2) as f64 - 1.0;
+    for (i, x) in out.iter_mut().enumerate() {
+        *x = match kind {
+            WindowType::Rectangular => 1.0,
+            WindowType::Hamming => 0.54 - 0.46 * (2.0 * PI * i as f64 / n).cos(),
+        };
+    }
+}
 
 /** impulse_filt
  * Finite/Infinite Impulse Response Filter
@@ -1,13 +1,23 @@
 /**                             Bytearray packer                              */
 /**
  * Copyright 2024 HaמuL
- * Description: Packer and unpacker for floats <-> byte arrays
+ * Description: Packer and unpacker for floats and integers <-> byte arrays
  * Dependencies: byteorder, half
  */
 
 use crate::backend::bitcvt;
 use half::f16;
 
+/** Predictor
+ * Lossless predictor on packed sample words, applied per channel
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Predictor {
+    None,
+    Delta,
+    Delta2,
+}
+
 /** cut_float3s
  * Cuts off last bits of floats to make their bit depth to 12, 24, or 48
  * Parameters: Bitstream, Bit depth divisable by 3, Little endian toggle
@@ -50,11 +60,16 @@ fn pack_f16(input: Vec<f64>, little_endian: bool) -> Vec<u8> {
         else { u16::to_le_bytes(f16::from_f64(x).to_bits()) }
     ).collect();
 }
+/** pack_f32
+ * Packs f64 into f32, only exact for values representable in f32
+ *   NaN is stored as zero and out-of-range values are clamped to the f32 range
+ */
 fn pack_f32(input: Vec<f64>, little_endian: bool) -> Vec<u8> {
-    return input.into_iter().flat_map(|x|
-        if !little_endian { f32::to_be_bytes(x as f32) }
-        else { f32::to_le_bytes(x as f32) }
-    ).collect();
+    return input.into_iter().flat_map(|x| {
+        let x = if x.is_nan() { 0.0 } else { x.clamp(f32::MIN as f64, f32::MAX as f64) as f32 };
+        if !little_endian { f32::to_be_bytes(x) }
+        else { f32::to_le_bytes(x) }
+    }).collect();
 }
 fn pack_f64(input: Vec<f64>, little_endian: bool) -> Vec<u8> {
     return input.into_iter().flat_map(|x|
@@ -63,6 +78,17 @@ fn pack_f64(input: Vec<f64>, little_endian: bool) -> Vec<u8> {
     ).collect();
 }
 
+/** is_exact
+ * Checks if every value survives packing at the bit depth unchanged
+ *   Lossless profiles are only lossless for such input
+ * Parameters: Flat f64 array, Bit depth
+ * Returns: Exactness flag
+ */
+pub fn is_exact(input: &[f64], bits: u16) -> bool {
+    return unpack(pack(input.to_vec(), bits, false), bits, false).iter().zip(input)
+    .all(|(a, b)| a.to_bits() == b.to_bits());
+}
+
 /** pad_float3s
  * Pads floats to make them readable directly as 16, 32, or 64 bit floats
  * Parameters: Bitstream, Bit depth divisable by 3, Little endian toggle
@@ -103,7 +129,7 @@ pub fn unpack(mut input: Vec<u8>, bits: u16, mut little_endian: bool) -> Vec<f64
 }
 
 fn unpack_f16(input: Vec<u8>, little_endian: bool) -> Vec<f64> {
-    return input.chunks(2)
+    return input.chunks_exact(2)
     .map(|bytes| {
         f64::from(f16::from_bits(
             if !little_endian { u16::from_be_bytes(bytes.try_into().unwrap()) }
@@ -112,7 +138,7 @@ fn unpack_f16(input: Vec<u8>, little_endian: bool) -> Vec<f64> {
     }).collect();
 }
 fn unpack_f32(input: Vec<u8>, little_endian: bool) -> Vec<f64> {
-    return input.chunks(4)
+    return input.chunks_exact(4)
     .map(|bytes| {
         f64::from(
             if !little_endian { f32::from_be_bytes(bytes.try_into().unwrap()) }
@@ -121,9 +147,189 @@ fn unpack_f32(input: Vec<u8>, little_endian: bool) -> Vec<f64> {
     }).collect();
 }
 fn unpack_f64(input: Vec<u8>, little_endian: bool) -> Vec<f64> {
-    return input.chunks(8)
+    return input.chunks_exact(8)
     .map(|bytes| {
         if !little_endian { f64::from_be_bytes(bytes.try_into().unwrap()) }
         else { f64::from_le_bytes(bytes.try_into().unwrap()) }
     }).collect();
+}
+
+/** to_words
+ * Splits packed samples into integer words
+ * Parameters: Byte array, Bit depth, Little endian toggle
+ * Returns: Sample words
+ */
+fn to_words(bytes: &[u8], bits: usize, little_endian: bool) -> Vec<u64> {
+    if bits % 8 != 0 {
+        return bitcvt::to_bits(bytes.to_vec()).chunks_exact(bits)
+        .map(|x| x.iter().fold(0, |acc, &bit| (acc << 1) | bit as u64)).collect();
+    }
+    return bytes.chunks_exact(bits / 8).map(|x| {
+        if !little_endian { x.iter().fold(0, |acc, &b| (acc << 8) | b as u64) }
+        else { x.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64) }
+    }).collect();
+}
+
+/** from_words
+ * Joins integer words back into packed samples
+ * Parameters: Sample words, Bit depth, Little endian toggle
+ * Returns: Byte array
+ */
+fn from_words(words: Vec<u64>, bits: usize, little_endian: bool) -> Vec<u8> {
+    if bits % 8 != 0 {
+        return bitcvt::to_bytes(words.into_iter().flat_map(|w| (0..bits).rev().map(move |i| (w >> i) & 1 == 1)).collect());
+    }
+    return words.into_iter().flat_map(|w| {
+        let bytes = w.to_be_bytes()[8 - bits / 8..].to_vec();
+        if !little_endian { bytes } else { bytes.into_iter().rev().collect() }
+    }).collect();
+}
+
+/** difference
+ * Differences sample words with the previous word of the same channel, order times
+ * Parameters: Sample words, Channel count, Predictor order, Word mask
+ */
+fn difference(words: &mut [u64], channels: usize, order: u8, mask: u64) {
+    for _ in 0..order {
+        for i in (channels..words.len()).rev() { words[i] = words[i].wrapping_sub(words[i - channels]) & mask; }
+    }
+}
+
+/** integrate
+ * Reverts difference
+ * Parameters: Sample words, Channel count, Predictor order, Word mask
+ */
+fn integrate(words: &mut [u64], channels: usize, order: u8, mask: u64) {
+    for _ in 0..order {
+        for i in channels..words.len() { words[i] = words[i].wrapping_add(words[i - channels]) & mask; }
+    }
+}
+
+/** predict
+ * Replaces packed samples with their per-channel differences, led by the predictor order byte
+ *   The words are differenced as integers with wraparound, so it is exactly reversible
+ * Parameters: Packed samples, Bit depth, Little endian toggle, Channel count, Predictor
+ * Returns: Predicted byte array
+ */
+pub fn predict(packed: Vec<u8>, bits: u16, little_endian: bool, channels: usize, predictor: Predictor) -> Vec<u8> {
+    let (bits, order) = (bits as usize, predictor as u8);
+    if order == 0 || channels == 0 { return packed; }
+    let (little_endian, mask) = (little_endian && bits % 8 == 0, u64::MAX >> (64 - bits));
+
+    let mut words = to_words(&packed, bits, little_endian);
+    difference(&mut words, channels, order, mask);
+    return [order].into_iter().chain(from_words(words, bits, little_endian)).collect();
+}
+
+/** unpredict
+ * Restores packed samples from predict, if the frame carries a predictor order byte
+ *   The byte is detected by the frame being one byte longer than the packed samples
+ * Parameters: Frame data, Bit depth, Little endian toggle, Channel count, Sample count
+ * Returns: Packed samples
+ */
+pub fn unpredict(frad: Vec<u8>, bits: u16, little_endian: bool, channels: usize, samples: usize) -> Vec<u8> {
+    let bits = bits as usize;
+    if channels == 0 || frad.len() != (samples * channels * bits).div_ceil(8) + 1 { return frad; }
+    let (little_endian, mask) = (little_endian && bits % 8 == 0, u64::MAX >> (64 - bits));
+
+    let mut words = to_words(&frad[1..], bits, little_endian);
+    integrate(&mut words, channels, frad[0], mask);
+    return from_words(words, bits, little_endian);
+}
+
+/** pack_int
+ * Packs samples as signed integers of 8, 16, 24 or 32 bits, exact for integer PCM without the float round-trip
+ *   Led by a mode byte: integer flag 0x80, byte width - 1 in bits 2-3, predictor order in bits 0-1
+ * Parameters: Flat f64 array, Integer bit depth, Little endian toggle, Channel count, Predictor
+ * Returns: Byte array, None if any sample is not an integer of the bit depth
+ */
+pub fn pack_int(input: &[f64], bits: u16, little_endian: bool, channels: usize, predictor: Predictor) -> Option<Vec<u8>> {
+    if ![8, 16, 24, 32].contains(&bits) || channels == 0 { return None; }
+    let (bits, order) = (bits as usize, predictor as u8);
+    let (scale, mask) = ((1u64 << (bits - 1)) as f64, u64::MAX >> (64 - bits));
+
+    // 1. Two's complement words, of samples coming back bit-exact from them only
+    let mut words = Vec::with_capacity(input.len());
+    for &x in input {
+        let y = x * scale;
+        if !(-scale..scale).contains(&y) || (y as i64 as f64 / scale).to_bits() != x.to_bits() { return None; }
+        words.push(y as i64 as u64 & mask);
+    }
+
+    // 2. Per-channel differences, as predict does for float words
+    difference(&mut words, channels, order, mask);
+    let mode = 0x80 | ((bits / 8 - 1) as u8) << 2 | order;
+    return Some([mode].into_iter().chain(from_words(words, bits, little_endian)).collect());
+}
+
+/** unpack_int
+ * Unpacks samples packed by pack_int
+ * Parameters: Frame data, Little endian toggle, Channel count, Sample count
+ * Returns: Flat f64 array, None if the frame is not led by an integer mode byte or its length does not match
+ */
+pub fn unpack_int(frad: &[u8], little_endian: bool, channels: usize, samples: usize) -> Option<Vec<f64>> {
+    let mode = *frad.first()?;
+    if mode & 0x80 == 0 || channels == 0 { return None; }
+    let bits = (((mode >> 2) & 3) as usize + 1) * 8;
+    if frad.len() != samples * channels * bits / 8 + 1 { return None; }
+    let (scale, mask) = ((1u64 << (bits - 1)) as f64, u64::MAX >> (64 - bits));
+
+    let mut words = to_words(&frad[1..], bits, little_endian);
+    integrate(&mut words, channels, mode & 0x03, mask);
+    return Some(words.into_iter().map(|w| ((w << (64 - bits)) as i64 >> (64 - bits)) as f64 / scale).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::rng::SmallRng;
+
+    /** int_samples
+     * Makes integer samples of a bit depth, the extremes first and noise after
+     */
+    fn int_samples(bits: u16, len: usize) -> Vec<f64> {
+        let scale = (1u64 << (bits - 1)) as f64;
+        let mut rng = SmallRng::new(bits as u64);
+        let mut samples = vec![-1.0, (scale - 1.0) / scale, 0.0, -1.0 / scale, (scale - 1.0) / scale, -1.0];
+        samples.extend((samples.len()..len).map(|_| ((rng.next_u64() % (1 << bits)) as f64 - scale) / scale));
+        return samples;
+    }
+
+    #[test]
+    fn pack_int_round_trips_every_width() {
+        for bits in [8, 16, 24, 32] {
+            for predictor in [Predictor::None, Predictor::Delta, Predictor::Delta2] {
+                for little_endian in [false, true] {
+                    for channels in [1, 2, 3] {
+                        let input = int_samples(bits, 64 * channels);
+                        let frad = pack_int(&input, bits, little_endian, channels, predictor).unwrap();
+                        assert_eq!(frad[0], 0x80 | ((bits / 8 - 1) as u8) << 2 | predictor as u8);
+                        assert_eq!(frad.len(), input.len() * bits as usize / 8 + 1);
+                        let output = unpack_int(&frad, little_endian, channels, 64).unwrap();
+                        assert!(input.iter().zip(&output).all(|(x, y)| x.to_bits() == y.to_bits()), "{} bits, order {}", bits, predictor as u8);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pack_int_rejects_samples_off_the_grid() {
+        for bits in [8, 16, 24, 32] {
+            let scale = (1u64 << (bits - 1)) as f64;
+            assert!(pack_int(&[1.0], bits, false, 1, Predictor::None).is_none());
+            assert!(pack_int(&[0.5 / scale], bits, false, 1, Predictor::None).is_none());
+            assert!(pack_int(&[-1.0 - 1.0 / scale], bits, false, 1, Predictor::None).is_none());
+            assert!(pack_int(&[f64::NAN], bits, false, 1, Predictor::None).is_none());
+        }
+        assert!(pack_int(&[0.0], 12, false, 1, Predictor::None).is_none());
+    }
+
+    #[test]
+    fn unpack_int_rejects_mismatched_frames() {
+        let frad = pack_int(&int_samples(16, 8), 16, false, 2, Predictor::None).unwrap();
+        assert!(unpack_int(&frad, false, 2, 5).is_none());
+        assert!(unpack_int(&frad[1..], false, 2, 4).is_none());
+        assert!(unpack_int(&[], false, 2, 0).is_none());
+    }
 }
\ No newline at end of file
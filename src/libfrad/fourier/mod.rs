@@ -7,7 +7,7 @@
 pub mod profile0;
 pub mod profile1;
 pub mod profile2;
-// pub mod profile3;
+pub mod profile3;
 pub mod profile4;
 // pub mod profile5;
 // pub mod profile6;
@@ -19,14 +19,14 @@ pub mod tools;
 
 use profiles::compact;
 
-pub const AVAILABLE: [u8; 3] = [0, 1, 4];
+pub const AVAILABLE: [u8; 4] = [0, 1, 3, 4];
 
 pub const SEGMAX: [u32; 8] =
 [
     u32::MAX, // Profile 0
     compact::MAX_SMPL, // Profile 1
     compact::MAX_SMPL, // Profile 2
-    0, // Profile 3
+    u32::MAX, // Profile 3
     u32::MAX, // Profile 4
     0, // Profile 5
     0, // Profile 6
@@ -37,9 +37,27 @@ pub const BIT_DEPTHS: [[u16; 8]; 8] = [
     profile0::DEPTHS,
     profile1::DEPTHS,
     profile2::DEPTHS,
-    [0; 8],
+    profile3::DEPTHS,
     profile4::DEPTHS,
     [0; 8],
     [0; 8],
     [0; 8],
-];
\ No newline at end of file
+];
+
+// Format version written in the file header, selecting the bit depth tables
+//   1: The endian bit of Profile 1 frames flags mid/side, see profile1::MID_SIDE_VERSION
+pub const FORMAT_VERSION: u8 = 1;
+
+// Bit depth tables of every format version, the last one being BIT_DEPTHS
+pub const BIT_DEPTHS_BY_VERSION: [[[u16; 8]; 8]; 2] = [BIT_DEPTHS, BIT_DEPTHS];
+
+/** remap_depth_index
+ * Maps a bit depth index written under a format version to the current tables
+ * Parameters: Format version, Profile, Bit depth index
+ * Returns: Current bit depth index, None if the version or the bit depth is unknown
+ */
+pub fn remap_depth_index(version: u8, profile: u8, index: u16) -> Option<u16> {
+    let depth = BIT_DEPTHS_BY_VERSION.get(version as usize)?[profile as usize][index as usize];
+    if depth == 0 { return None; }
+    return BIT_DEPTHS[profile as usize].iter().position(|&x| x == depth).map(|x| x as u16);
+}
\ No newline at end of file
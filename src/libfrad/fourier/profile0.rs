@@ -4,8 +4,8 @@
  * Description: FrAD Profile 0 encoding and decoding core
  */
 
-use crate::backend::Transpose;
-use super::backend::{u8pack, core::{dct, idct}};
+use crate::{backend::Transpose, FradError};
+use super::backend::{u8pack::{self, Predictor}, core::{dct, idct}};
 use half::f16;
 
 // Bit depth table
@@ -20,10 +20,10 @@ const FLOAT_DR_LIMITS: [f64; 8] = [
 
 /** analogue
  * Encodes PCM to FrAD
- * Parameters: f64 PCM, Bit depth, Little endian toggle (and possibly channel count, but it can be extracted from the PCM shape)
- * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count
+ * Parameters: f64 PCM, Bit depth, Little endian toggle, Predictor (and possibly channel count, but it can be extracted from the PCM shape)
+ * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count, or an overflow of the widest bit depth
  */
-pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, srate: u32, little_endian: bool) -> (Vec<u8>, u16, u16, u32) {
+pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, srate: u32, little_endian: bool, predictor: Predictor) -> Result<(Vec<u8>, u16, u16, u32), FradError> {
     if !DEPTHS.contains(&bit_depth) || bit_depth == 0 { bit_depth = 16; }
     let channels = pcm[0].len();
 
@@ -33,20 +33,24 @@ pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, srate: u32, little_endia
 
     let bit_depth_index = DEPTHS.iter().zip(FLOAT_DR_LIMITS.iter())
     .enumerate().find(|(_, (&value, &limit))| value >= bit_depth && value > 0 && max_abs < limit)
-    .map(|(i, _)| i).unwrap_or_else(|| panic!("Overflow with reaching the max bit depth."));
+    .map(|(i, _)| i).ok_or(FradError::SampleOverflow(0))?;
 
     let frad = u8pack::pack(freqs_flat, DEPTHS[bit_depth_index], little_endian);
+    let frad = u8pack::predict(frad, DEPTHS[bit_depth_index], little_endian, channels, predictor);
 
-    return (frad, bit_depth_index as u16, channels as u16, srate);
+    return Ok((frad, bit_depth_index as u16, channels as u16, srate));
 }
 
 /** digital
  * Decodes FrAD to PCM
- * Parameters: Encoded audio data, Bit depth index, Channel count, Little endian toggle
- * Returns: Decoded PCM
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Little endian toggle, Frame size
+ * Returns: Decoded PCM, or an error for an unused bit depth index
  */
-pub fn digital(frad: Vec<u8>, bit_depth_index: u16, channels: u16, little_endian: bool) -> Vec<Vec<f64>> {
-    let freqs_flat: Vec<f64> = u8pack::unpack(frad, DEPTHS[bit_depth_index as usize], little_endian);
+pub fn digital(frad: Vec<u8>, bit_depth_index: u16, channels: u16, little_endian: bool, fsize: u32) -> Result<Vec<Vec<f64>>, FradError> {
+    let bit_depth = DEPTHS[bit_depth_index as usize];
+    if bit_depth == 0 { return Err(FradError::InvalidBitDepth(0, bit_depth)); }
+    let frad = u8pack::unpredict(frad, bit_depth, little_endian, channels as usize, fsize as usize);
+    let freqs_flat: Vec<f64> = u8pack::unpack(frad, bit_depth, little_endian);
     let freqs: Vec<Vec<f64>> = freqs_flat.chunks(channels as usize).map(|chunk| chunk.to_vec()).collect();
-    return freqs.trans().into_iter().map(idct).collect::<Vec<Vec<f64>>>().trans();
+    return Ok(freqs.trans().into_iter().map(idct).collect::<Vec<Vec<f64>>>().trans());
 }
\ No newline at end of file
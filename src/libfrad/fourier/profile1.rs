@@ -5,30 +5,47 @@
  * Dependencies: miniz_oxide
  */
 
-use crate::backend::{SplitFront, Transpose};
+use crate::{backend::{rng::SmallRng, SplitFront, Transpose}, FradError};
 use super::{
-    backend::core::{dct, idct},
-    compact::{get_valid_srate, SAMPLES_LI},
+    backend::{core::{get_plan, DctPlan}, scratch::Scratch, signal::{window, WindowType}},
+    compact::{self, get_valid_srate, SAMPLES_LI},
     tools::p1tools
 };
 
-use miniz_oxide::{deflate, inflate};
+use miniz_oxide::inflate;
 
 // Bit depth table
 pub const DEPTHS: [u16; 8] = [8, 12, 16, 24, 32, 48, 64, 0];
+// First format version reading the endian bit as mid/side, as earlier writers set it on --le
+pub const MID_SIDE_VERSION: u8 = 1;
 
-/** pad_pcm
- * Pads the PCM to the nearest sample count greater than the original
- * Parameters: f64 PCM
- * Returns: Padded f64 PCM
+/** AnalogueParams
+ * Encoding options of a Profile 1 frame
+ *   Loss level, Masked coefficient clip(INFINITY for none), Joint stereo flag,
+ *   Stochastic rounding generator(None for nearest), Constant frame flag, Analysis window
  */
-pub fn pad_pcm(mut pcm: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-    let len_smpl = pcm.len();
-    let chnl = pcm[0].len();
-    let pad_len = *SAMPLES_LI.iter().find(|&&x| x as usize >= len_smpl).unwrap_or(&(len_smpl as u32)) as usize - len_smpl;
+pub struct AnalogueParams {
+    pub loss_level: f64,
+    pub clip: f64,
+    pub joint: bool,
+    pub rng: Option<SmallRng>,
+    pub dc_frames: bool,
+    pub window: WindowType,
+}
+
+impl Default for AnalogueParams {
+    fn default() -> Self {
+        return AnalogueParams { loss_level: 0.5, clip: f64::INFINITY, joint: false, rng: None, dc_frames: false, window: WindowType::Rectangular };
+    }
+}
 
-    pcm.extend(core::iter::repeat(vec![0.0; chnl]).take(pad_len));
-    return pcm;
+/** padded_len
+ * Gets the nearest sample count greater than or equal to the original
+ * Parameters: Original sample count
+ * Returns: Padded sample count
+ */
+pub fn padded_len(len_smpl: usize) -> usize {
+    return *SAMPLES_LI.iter().find(|&&x| x as usize >= len_smpl).unwrap_or(&(len_smpl as u32)) as usize;
 }
 
 /** get_scale_factors
@@ -43,81 +60,162 @@ pub fn get_scale_factors(bit_depth: u16) -> (f64, f64) {
     return (pcm_scale, thres_scale);
 }
 
-/** analogue
- * Encodes PCM to FrAD Profile 1
- * Parameters: f64 PCM, Bit depth, Sample rate, Loss level (and channel count, same note as profile 0)
- * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count
+/** mid_side
+ * Transforms stereo PCM from left/right to mid/side in place
+ * Parameters: f64 PCM
+ */
+fn mid_side(pcm: &mut [Vec<f64>]) {
+    pcm.iter_mut().for_each(|x| (x[0], x[1]) = ((x[0] + x[1]) / 2.0, (x[0] - x[1]) / 2.0));
+}
+
+/** left_right
+ * Transforms stereo PCM from mid/side back to left/right in place
+ * Parameters: f64 PCM
+ */
+fn left_right(pcm: &mut [Vec<f64>]) {
+    pcm.iter_mut().for_each(|x| (x[0], x[1]) = (x[0] + x[1], x[0] - x[1]));
+}
+
+/** quantise
+ * Transforms, masks and quantises PCM for FrAD Profile 1
+ *   Quantised frequencies and thresholds are left in the scratch
+ * Parameters: f64 PCM, Bit depth, Sample rate, Encoding options(joint stereo and constant frames are not used here), Encoder scratch
+ * Returns: Bit depth, Channel count, Sample rate
  */
-pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, mut srate: u32, mut loss_level: f64) -> (Vec<u8>, u16, u16, u32) {
+fn quantise(pcm: &[Vec<f64>], mut bit_depth: u16, mut srate: u32, params: AnalogueParams, s: &mut Scratch) -> (u16, usize, u32) {
+    let AnalogueParams { mut loss_level, clip, mut rng, window: kind, .. } = params;
     if !DEPTHS.contains(&bit_depth) || bit_depth == 0 { bit_depth = 16; }
     let (pcm_scale, thres_scale) = get_scale_factors(bit_depth);
     (srate, loss_level) = (get_valid_srate(srate), loss_level.abs().max(0.125));
-
-    // 1. Pad and transform PCM with scaling
-    let pcm = pad_pcm(pcm);
-    let pcm_trans: Vec<Vec<f64>> = pcm.trans().iter().map(|x| x.iter().map(|y| y * pcm_scale).collect()).collect();
-
-    // 2. DCT
-    let freqs: Vec<Vec<f64>> = pcm_trans.iter().map(|x| dct(x.to_vec())).collect();
-    let channels = freqs.len();
-
-    // 3. Subband masking and quantisation
-    let (freqs_masked, thresholds): (Vec<Vec<f64>>, Vec<Vec<f64>>) = (0..channels)
-    .into_iter().map(|c| {
+    let (channels, fsize) = (pcm[0].len(), padded_len(pcm.len()));
+    s.resize(channels);
+    s.window.resize(fsize, 0.0);
+    window(kind, &mut s.window);
+
+    // Channels are independent up to the flattening, which keeps the channel order
+    s.for_each_channel(fsize, |c, b, window, plan| {
+        // 1. Pad, window and transform PCM with scaling
+        b.pcm.clear();
+        b.pcm.extend(pcm.iter().map(|x| x[c] * pcm_scale));
+        b.pcm.resize(fsize, 0.0);
+        b.pcm.iter_mut().zip(window).for_each(|(x, w)| *x *= w);
+
+        // 2. DCT
+        *b.freqs = plan.forward(b.pcm);
+
+        // 3. Subband masking and quantisation
         // 3.1. Masking threshold calculation
-        let thres_channel: Vec<f64> = p1tools::mask_thres_mos(
-            freqs[c].clone(), srate, bit_depth, loss_level, p1tools::SPREAD_ALPHA
-        );
+        p1tools::mask_thres_mos(b.freqs, srate, bit_depth, loss_level, p1tools::SPREAD_ALPHA, b.thres);
+        // Mask with the thresholds as the decoder reconstructs them, not the unquantised ones
+        b.thres.iter_mut().for_each(|x| *x = p1tools::thres_decode(p1tools::thres_encode(*x, thres_scale), thres_scale));
 
         // 3.2. Remapping thresholds to DCT bins
         // 3.3. Psychoacoustic masking
-        let mut div_factor: Vec<f64> = p1tools::mapping_from_opus(&thres_channel, freqs[0].len(), srate);
-        div_factor.iter_mut().for_each(|x| if x == &0.0 { *x = core::f64::INFINITY; });
-        let chnl_masked: Vec<f64> = freqs[c].iter().zip(&div_factor).map(|(x, y)| x / y).collect();
-
-        (chnl_masked, thres_channel)
-    }).unzip();
+        p1tools::mapping_from_opus_into(b.thres, fsize, srate, b.div_factor);
+        b.div_factor.iter_mut().for_each(|x| if x == &0.0 { *x = core::f64::INFINITY; });
+        b.masked.clear();
+        b.masked.extend(b.freqs.iter().zip(b.div_factor.iter()).map(|(x, y)| (x / y).clamp(-clip, clip)));
+    });
+
+    // 4. Quantisation and flattening, rounding up with the probability of the fraction if stochastic
+    //    Values under half a step still round to zero, or the masked-out bins would fill with noise
+    s.freqs_flat.clear();
+    s.freqs_flat.extend((0..fsize).flat_map(|i| s.masked.iter().map(move |x| p1tools::quant(x[i]))).map(|q| match rng.as_mut() {
+        Some(r) if q.abs() >= 0.5 => (q + r.unit()).floor() as i64,
+        _ => q.round() as i64
+    }));
+    s.side_flat.clear();
+    s.side_flat.extend((0..p1tools::MOSLEN).flat_map(|i| s.thres.iter().map(move |x| p1tools::thres_encode(x[i], thres_scale))));
+
+    return (bit_depth, channels, srate);
+}
 
-    // 4. Quantisation and flattening
-    let freqs_flat: Vec<i64> = freqs_masked.trans().iter().flat_map(|x| x.iter().map(|y| p1tools::quant(*y).round() as i64)).collect();
-    let thres_flat: Vec<i64> = thresholds.trans().iter().flat_map(|x| x.iter().map(|y| (p1tools::quant(y * thres_scale)).round() as i64)).collect();
+/** analogue
+ * Encodes PCM to FrAD Profile 1
+ * Parameters: f64 PCM, Bit depth, Sample rate, Encoding options, Encoder scratch
+ * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count
+ */
+pub fn analogue(mut pcm: Vec<Vec<f64>>, bit_depth: u16, srate: u32, params: AnalogueParams, s: &mut Scratch) -> (Vec<u8>, u16, u16, u32) {
+    let kind = params.window;
+    // Mid/side transform for stereo only, the rest of the pipeline sees M and S as plain channels
+    if params.joint && pcm[0].len() == 2 { mid_side(&mut pcm); }
+
+    // 0. Constant frame shortcut, full frames only as zero padding breaks the constant
+    if params.dc_frames && pcm.len() == padded_len(pcm.len()) {
+        let depth = if !DEPTHS.contains(&bit_depth) || bit_depth == 0 { 16 } else { bit_depth };
+        if let Some(frad) = compact::dc_encode(&pcm, 0.5 / get_scale_factors(depth).0) {
+            return (frad, DEPTHS.iter().position(|&x| x == depth).unwrap() as u16, pcm[0].len() as u16, get_valid_srate(srate));
+        }
+    }
+
+    // 1 ~ 4. Transform, mask and quantise
+    let (bit_depth, channels, srate) = quantise(&pcm, bit_depth, srate, params, s);
 
     // 5. Exponential Golomb-Rice encoding
-    let freqs_gol: Vec<u8> = p1tools::exp_golomb_encode(freqs_flat);
-    let thres_gol: Vec<u8> = p1tools::exp_golomb_encode(thres_flat);
+    p1tools::exp_golomb_encode_into(&s.freqs_flat, &mut s.freqs_gol);
+    p1tools::exp_golomb_encode_into(&s.side_flat, &mut s.side_gol);
 
     // 6. Connecting data
     //    [ Thresholds length in u32be | Thresholds | Frequencies ]
-    let frad: Vec<u8> = (thres_gol.len() as u32).to_be_bytes().to_vec().into_iter().chain(thres_gol).chain(freqs_gol).collect();
+    s.frad.clear();
+    s.frad.extend((s.side_gol.len() as u32).to_be_bytes());
+    s.frad.extend(&s.side_gol);
+    s.frad.extend(&s.freqs_gol);
 
-    // 7. Zlib compression
-    let frad = deflate::compress_to_vec_zlib(&frad, 10);
+    // 7. Zlib compression, prefixed with the window if any
+    let frad = compact::window_encode(s.deflate(), kind);
 
     return (frad, DEPTHS.iter().position(|&x| x == bit_depth).unwrap() as u16, channels as u16, srate);
 }
 
+/** estimate_analogue_size
+ * Measures the pre-deflate size of a FrAD Profile 1 frame without serialising it
+ *   Deflate usually shrinks this further, so it serves as an upper estimate
+ * Parameters: f64 PCM, Bit depth, Sample rate, Loss level
+ * Returns: Pre-deflate payload size in bytes
+ */
+pub fn estimate_analogue_size(pcm: Vec<Vec<f64>>, bit_depth: u16, srate: u32, loss_level: f64) -> usize {
+    let s = &mut Scratch::new();
+    quantise(&pcm, bit_depth, srate, AnalogueParams { loss_level, ..Default::default() }, s);
+    return 4 + p1tools::exp_golomb_len(&s.side_flat) + p1tools::exp_golomb_len(&s.freqs_flat);
+}
+
 /** digital
  * Decodes FrAD Profile 1 to PCM
- * Parameters: Encoded audio data, Bit depth index, Channel count, Sample rate, Frame size
- * Returns: f64 PCM
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Sample rate, Frame size, Joint stereo flag, DCT plan cache
+ * Returns: f64 PCM, or an error for an undecodable frame
  */
-pub fn digital(mut frad: Vec<u8>, bit_depth_index: u16, channels: u16, srate: u32, fsize: u32) -> Vec<Vec<f64>> {
+pub fn digital(frad: Vec<u8>, bit_depth_index: u16, channels: u16, srate: u32, fsize: u32, joint: bool, plan: &mut Option<DctPlan>) -> Result<Vec<Vec<f64>>, FradError> {
+    let mut pcm = decode(frad, bit_depth_index, channels, srate, fsize, plan)?;
+    if joint && channels == 2 { left_right(&mut pcm); }
+    return Ok(pcm);
+}
+
+/** decode
+ * Decodes FrAD Profile 1 to PCM without the stereo transform
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Sample rate, Frame size, DCT plan cache
+ * Returns: f64 PCM, in mid/side for joint stereo frames, or an error for an undecodable frame
+ */
+fn decode(mut frad: Vec<u8>, bit_depth_index: u16, channels: u16, srate: u32, fsize: u32, plan: &mut Option<DctPlan>) -> Result<Vec<Vec<f64>>, FradError> {
     let (bit_depth, channels) = (DEPTHS[bit_depth_index as usize], channels as usize);
+    if bit_depth == 0 { return Err(FradError::InvalidBitDepth(1, bit_depth)); }
     let ((pcm_scale, thres_scale), fsize) = (get_scale_factors(bit_depth), fsize as usize);
 
-    // 1. Zlib decompression
-    frad = match inflate::decompress_to_vec_zlib(&frad) {
-        Ok(x) => x,
-        Err(_) => { return vec![vec![0.0; channels]; fsize]; }
-    };
+    // 0. Constant frame
+    if let Some(pcm) = compact::dc_decode(&frad, channels, fsize) { return Ok(pcm); }
+
+    // 1. Window prefix and Zlib decompression
+    let kind = compact::window_decode(&mut frad).ok_or(FradError::InvalidFrame(1))?;
+    frad = inflate::decompress_to_vec_zlib(&frad).map_err(|_| FradError::InvalidFrame(1))?;
 
     // 2. Splitting thresholds and frequencies
+    if frad.len() < 4 { return Err(FradError::InvalidFrame(1)); }
     let thres_len = u32::from_be_bytes(frad.split_front(4).try_into().unwrap()) as usize;
     let thres_gol = frad.split_front(thres_len).to_vec();
 
     // 3. Exponential Golomb-Rice decoding
-    let mut freqs_flat: Vec<f64> = p1tools::exp_golomb_decode(frad).into_iter().map(|x| p1tools::dequant(x as f64)).collect();
-    let mut thres_flat: Vec<f64> = p1tools::exp_golomb_decode(thres_gol).into_iter().map(|x| p1tools::dequant(x as f64) / thres_scale).collect();
+    let mut freqs_flat: Vec<f64> = p1tools::exp_golomb_decode(frad, fsize * channels).into_iter().map(|x| p1tools::dequant(x as f64)).collect();
+    let mut thres_flat: Vec<f64> = p1tools::exp_golomb_decode(thres_gol, p1tools::MOSLEN * channels).into_iter().map(|x| p1tools::thres_decode(x, thres_scale)).collect();
     freqs_flat.resize(fsize * channels, 0.0);
     thres_flat.resize(p1tools::MOSLEN * channels, 0.0);
 
@@ -131,8 +229,11 @@ pub fn digital(mut frad: Vec<u8>, bit_depth_index: u16, channels: u16, srate: u3
         .map(|(x, y)| x * y).collect()
     }).collect::<Vec<Vec<f64>>>();
 
-    // 6. Inverse DCT and scaling
-    return freqs.iter().map(|x|
-        idct(x.to_vec()).iter().map(|y| y / pcm_scale).collect()
-    ).collect::<Vec<Vec<f64>>>().trans();
+    // 6. Inverse DCT, inverse windowing and scaling
+    let mut win = vec![0.0; fsize];
+    window(kind, &mut win);
+    let plan = get_plan(plan, fsize);
+    return Ok(freqs.iter().map(|x|
+        plan.inverse(x).iter().zip(&win).map(|(y, w)| y / w / pcm_scale).collect()
+    ).collect::<Vec<Vec<f64>>>().trans());
 }
\ No newline at end of file
@@ -5,80 +5,102 @@
  * Dependencies: miniz_oxide
  */
 
-use crate::backend::{SplitFront, Transpose};
+use crate::{backend::{SplitFront, Transpose}, FradError};
 use super::{
-    backend::core::{dct, idct},
-    compact::get_valid_srate,
-    profile1::{get_scale_factors, pad_pcm},
+    backend::{core::{get_plan, DctPlan}, scratch::Scratch, signal::{window, WindowType}},
+    compact::{self, get_valid_srate},
+    profile1::{get_scale_factors, padded_len},
     tools::{p1tools, p2tools}
 };
 
-use miniz_oxide::{deflate, inflate};
+use miniz_oxide::inflate;
 
 // Bit depth table
 pub const DEPTHS: [u16; 8] = [8, 9, 10, 11, 12, 14, 16, 0];
 
 /** analogue
  * Encodes PCM to FrAD Profile 2
- * Parameters: f64 PCM, Bit depth, Sample rate (and channel count, same note as profile 0)
+ * Parameters: f64 PCM, Bit depth, Sample rate, Constant frame flag, Analysis window, Encoder scratch
  * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count
  */
-pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, mut srate: u32) -> (Vec<u8>, u16, u16, u32) {
+pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, mut srate: u32, dc_frames: bool, kind: WindowType, s: &mut Scratch) -> (Vec<u8>, u16, u16, u32) {
     if !DEPTHS.contains(&bit_depth) || bit_depth == 0 { bit_depth = 16; }
     let (pcm_scale, _) = get_scale_factors(bit_depth);
     srate = get_valid_srate(srate);
-
-    // 1. Pad and transform PCM with scaling
-    let pcm = pad_pcm(pcm);
-
-    // 2. DCT
-    let freqs: Vec<Vec<f64>> = pcm.trans().iter().map(|x| dct(x.to_vec())).collect();
-    let channels = freqs.len();
+    let (channels, fsize) = (pcm[0].len(), padded_len(pcm.len()));
+
+    // 0. Constant frame shortcut, full frames only as zero padding breaks the constant
+    if dc_frames && pcm.len() == fsize {
+        if let Some(frad) = compact::dc_encode(&pcm, 0.5 / pcm_scale) {
+            return (frad, DEPTHS.iter().position(|&x| x == bit_depth).unwrap() as u16, channels as u16, srate);
+        }
+    }
+    s.resize(channels);
+    s.window.resize(fsize, 0.0);
+    window(kind, &mut s.window);
+
+    s.for_each_channel(fsize, |c, b, window, plan| {
+        // 1. Pad, window and transform PCM
+        b.pcm.clear();
+        b.pcm.extend(pcm.iter().map(|x| x[c]));
+        b.pcm.resize(fsize, 0.0);
+        b.pcm.iter_mut().zip(window).for_each(|(x, w)| *x *= w);
+
+        // 2. DCT
+        *b.freqs = plan.forward(b.pcm);
+    });
 
     // 3. TNS analysis
-    let (tns_freqs, lpc) = p2tools::tns_analysis(&freqs);
+    let (tns_freqs, lpc) = p2tools::tns_analysis(&s.freqs);
 
     // 4. Flattening frequencies and thresholds
-    let freqs_flat: Vec<i64> = tns_freqs.trans().iter().flat_map(|x| x.iter().map(|y| (y * pcm_scale) as i64)).collect();
-    let lpc_flat: Vec<i64> = lpc.trans().iter().flat_map(|x| x.iter().map(|y| *y)).collect();
+    s.freqs_flat.clear();
+    s.freqs_flat.extend((0..fsize).flat_map(|i| tns_freqs.iter().map(move |x| (x[i] * pcm_scale) as i64)));
+    s.side_flat.clear();
+    s.side_flat.extend((0..lpc[0].len()).flat_map(|i| lpc.iter().map(move |x| x[i])));
 
     // 5. Exponential Golomb-Rice encoding
-    let freqs_gol: Vec<u8> = p1tools::exp_golomb_encode(freqs_flat);
-    let lpc_gol: Vec<u8> = p1tools::exp_golomb_encode(lpc_flat);
+    p1tools::exp_golomb_encode_into(&s.freqs_flat, &mut s.freqs_gol);
+    p1tools::exp_golomb_encode_into(&s.side_flat, &mut s.side_gol);
 
     // 6. Connecting data
     //    [ LPC length in u32be | Thresholds | Frequencies ]
-    let frad: Vec<u8> = (lpc_gol.len() as u32).to_be_bytes().to_vec().into_iter().chain(lpc_gol).chain(freqs_gol).collect();
+    s.frad.clear();
+    s.frad.extend((s.side_gol.len() as u32).to_be_bytes());
+    s.frad.extend(&s.side_gol);
+    s.frad.extend(&s.freqs_gol);
 
-    // 7. Zlib compression
-    let frad = deflate::compress_to_vec_zlib(&frad, 10);
+    // 7. Zlib compression, prefixed with the window if any
+    let frad = compact::window_encode(s.deflate(), kind);
 
     return (frad, DEPTHS.iter().position(|&x| x == bit_depth).unwrap() as u16, channels as u16, srate);
 }
 
 /** digital
  * Decodes FrAD Profile 2 to PCM
- * Parameters: Encoded audio data, Bit depth index, Channel count, Sample rate, Frame size
- * Returns: f64 PCM
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Sample rate, Frame size, DCT plan cache
+ * Returns: f64 PCM, or an error for an undecodable frame
  */
-pub fn digital(mut frad: Vec<u8>, bit_depth_index: u16, channels: u16, _srate: u32, fsize: u32) -> Vec<Vec<f64>> {
+pub fn digital(mut frad: Vec<u8>, bit_depth_index: u16, channels: u16, _srate: u32, fsize: u32, plan: &mut Option<DctPlan>) -> Result<Vec<Vec<f64>>, FradError> {
     let (bit_depth, channels) = (DEPTHS[bit_depth_index as usize], channels as usize);
+    if bit_depth == 0 { return Err(FradError::InvalidBitDepth(2, bit_depth)); }
     let ((pcm_scale, _), fsize) = (get_scale_factors(bit_depth), fsize as usize);
 
-    // 1. Zlib decompression
-    // frad = match inflate::decompress_to_vec_zlib(&frad) {
-    //     Ok(x) => x,
-    //     Err(_) => { return vec![vec![0.0; channels]; fsize]; }
-    // };
-    frad = inflate::decompress_to_vec_zlib(&frad).unwrap();
+    // 0. Constant frame
+    if let Some(pcm) = compact::dc_decode(&frad, channels, fsize) { return Ok(pcm); }
+
+    // 1. Window prefix and Zlib decompression
+    let kind = compact::window_decode(&mut frad).ok_or(FradError::InvalidFrame(2))?;
+    frad = inflate::decompress_to_vec_zlib(&frad).map_err(|_| FradError::InvalidFrame(2))?;
 
     // 2. Splitting LPC and frequencies
+    if frad.len() < 4 { return Err(FradError::InvalidFrame(2)); }
     let lpc_len = u32::from_be_bytes(frad.split_front(4).try_into().unwrap()) as usize;
     let lpc_gol = frad.split_front(lpc_len).to_vec();
 
     // 3. Exponential Golomb-Rice decoding
-    let mut lpc_flat: Vec<i64> = p1tools::exp_golomb_decode(lpc_gol);
-    let mut freqs_flat: Vec<f64> = p1tools::exp_golomb_decode(frad).into_iter().map(|x| x as f64 / pcm_scale).collect();
+    let mut lpc_flat: Vec<i64> = p1tools::exp_golomb_decode(lpc_gol, (p2tools::TNS_MAX_ORDER + 1) * channels);
+    let mut freqs_flat: Vec<f64> = p1tools::exp_golomb_decode(frad, fsize * channels).into_iter().map(|x| x as f64 / pcm_scale).collect();
     lpc_flat.resize((p2tools::TNS_MAX_ORDER + 1) * channels, 0);
     freqs_flat.resize(fsize * channels, 0.0);
 
@@ -89,6 +111,9 @@ pub fn digital(mut frad: Vec<u8>, bit_depth_index: u16, channels: u16, _srate: u
     // 5. TNS synthesis
     let freqs = p2tools::tns_synthesis(&tns_freqs, &lpc);
 
-    // 6. Inverse DCT and scaling
-    return freqs.iter().map(|x| idct(x.to_vec())).collect::<Vec<Vec<f64>>>().trans();
+    // 6. Inverse DCT and inverse windowing
+    let mut win = vec![0.0; fsize];
+    window(kind, &mut win);
+    let plan = get_plan(plan, fsize);
+    return Ok(freqs.iter().map(|x| plan.inverse(x).iter().zip(&win).map(|(y, w)| y / w).collect()).collect::<Vec<Vec<f64>>>().trans());
 }
\ No newline at end of file
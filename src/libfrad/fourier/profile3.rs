@@ -0,0 +1,98 @@
+/**                              FrAD Profile 3                               */
+/**
+ * Copyright 2024 HaמuL
+ * Description: FrAD Profile 3 encoding and decoding core
+ *   Integer PCM, fixed polynomial prediction per channel and partitioned Rice coding of the residuals
+ */
+
+use crate::FradError;
+use super::tools::p3tools::{residual, restore, rice_decode, rice_encode, BitReader, BitWriter, MAX_ORDER};
+
+// Bit depth table
+pub const DEPTHS: [u16; 8] = [8, 12, 16, 24, 32, 0, 0, 0];
+
+/** escape_width
+ * Gets the raw width of an escaped residual, holding any zigzagged residual of order up to 2
+ * Parameters: Bit depth
+ * Returns: Escape width
+ */
+fn escape_width(bit_depth: u16) -> usize { return bit_depth as usize + 3; }
+
+/** analogue
+ * Encodes PCM to FrAD
+ * Parameters: f64 PCM, Bit depth, Sample rate
+ * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count, Sample rate
+ */
+pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, srate: u32) -> (Vec<u8>, u16, u16, u32) {
+    if !DEPTHS.contains(&bit_depth) || bit_depth == 0 { bit_depth = 16; }
+    let channels = pcm[0].len();
+    let scale = (1u64 << (bit_depth - 1)) as f64;
+
+    let mut w = BitWriter::new();
+    for c in 0..channels {
+        // 1. Quantise to integers, clipping to the bit depth
+        let x: Vec<i64> = pcm.iter().map(|s| (s[c] * scale).round().clamp(-scale, scale - 1.0) as i64).collect();
+
+        // 2. Predictor order with the least absolute residual
+        let (order, r) = (0..=MAX_ORDER).map(|o| (o, residual(&x, o)))
+            .min_by_key(|(_, r)| r.iter().map(|v| v.unsigned_abs() as u128).sum::<u128>()).unwrap();
+
+        // 3. Rice coding
+        w.put(order as u64, 2);
+        rice_encode(&mut w, &r, escape_width(bit_depth));
+    }
+
+    let bit_depth_index = DEPTHS.iter().position(|&x| x == bit_depth).unwrap() as u16;
+    return (w.finish(), bit_depth_index, channels as u16, srate);
+}
+
+/** digital
+ * Decodes FrAD to PCM
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Frame size
+ * Returns: Decoded PCM, or an error for an unused bit depth index
+ */
+pub fn digital(frad: Vec<u8>, bit_depth_index: u16, channels: u16, fsize: u32) -> Result<Vec<Vec<f64>>, FradError> {
+    let mut pcm = vec![vec![0.0; channels as usize]; fsize as usize];
+    let bit_depth = DEPTHS[bit_depth_index as usize];
+    if bit_depth == 0 { return Err(FradError::InvalidBitDepth(3, bit_depth)); }
+    let scale = (1u64 << (bit_depth - 1)) as f64;
+
+    let mut rd = BitReader::new(&frad);
+    for c in 0..channels as usize {
+        let order = (rd.get(2) as usize).min(MAX_ORDER);
+        let mut x = rice_decode(&mut rd, fsize as usize, escape_width(bit_depth));
+        restore(&mut x, order);
+        pcm.iter_mut().zip(x).for_each(|(s, v)| s[c] = v as f64 / scale);
+    }
+    return Ok(pcm);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::rng::SmallRng;
+
+    #[test]
+    fn round_trips_every_bit_depth() {
+        for (index, &bits) in DEPTHS.iter().enumerate().filter(|(_, &bits)| bits != 0) {
+            let scale = (1u64 << (bits - 1)) as f64;
+            let (min, max) = (-1.0, (scale - 1.0) / scale);
+            let mut rng = SmallRng::new(bits as u64);
+            // Full-scale steps first, noise after, over a partial last partition
+            let mut pcm: Vec<Vec<f64>> = (0..64).map(|i| if i % 3 == 0 { vec![min, max] } else { vec![max, min] }).collect();
+            pcm.extend((64..1000).map(|_| (0..2).map(|_| ((rng.next_u64() % (1 << bits)) as f64 - scale) / scale).collect()));
+
+            let (frad, bit_depth_index, channels, _) = analogue(pcm.clone(), bits, 48000);
+            assert_eq!(bit_depth_index as usize, index);
+            assert_eq!(digital(frad, bit_depth_index, channels, pcm.len() as u32).unwrap(), pcm, "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn clips_to_the_bit_depth() {
+        let pcm = vec![vec![1.0, -1.5]; 4];
+        let (frad, bit_depth_index, channels, _) = analogue(pcm, 16, 48000);
+        let max = 32767.0 / 32768.0;
+        assert_eq!(digital(frad, bit_depth_index, channels, 4).unwrap(), vec![vec![max, -1.0]; 4]);
+    }
+}
\ No newline at end of file
@@ -4,7 +4,8 @@
  * Description: FrAD Profile 4 encoding and decoding core
  */
 
-use super::backend::u8pack;
+use crate::FradError;
+use super::backend::u8pack::{self, Predictor};
 use half::f16;
 
 // Bit depth table
@@ -17,32 +18,99 @@ const FLOAT_DR_LIMITS: [f64; 8] = [
     f64::MAX, f64::MAX, f64::INFINITY, f64::INFINITY
 ];
 
+/** is_int_frame
+ * Checks if a frame holds integer samples, led by the mode byte of pack_int
+ *   Float frames are exactly as long as their packed samples, or lead with a predictor order below 0x80
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Frame size
+ * Returns: Integer frame flag
+ */
+pub fn is_int_frame(frad: &[u8], bit_depth_index: u16, channels: u16, fsize: u32) -> bool {
+    let float_len = (fsize as usize * channels as usize * DEPTHS[bit_depth_index as usize] as usize).div_ceil(8);
+    return frad.len() != float_len && frad.first().is_some_and(|&x| x & 0x80 != 0);
+}
+
 /** analogue
  * Encodes PCM to FrAD
- * Parameters: f64 PCM, Bit depth, Little endian toggle (and channel count, same note as profile 0)
- * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count
+ *   Integer PCM no wider than the bit depth is stored as integers, exact without the float round-trip
+ * Parameters: f64 PCM, Bit depth, Little endian toggle, Predictor, Integer bit depth of the source PCM if any
+ *   (and channel count, same note as profile 0)
+ * Returns: Encoded audio data, Encoded bit depth index, Encoded channel count, or an overflow of the widest bit depth
  */
-pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, srate: u32, little_endian: bool) -> (Vec<u8>, u16, u16, u32) {
+pub fn analogue(pcm: Vec<Vec<f64>>, mut bit_depth: u16, srate: u32, little_endian: bool, predictor: Predictor, int_bits: Option<u16>) -> Result<(Vec<u8>, u16, u16, u32), FradError> {
     if !DEPTHS.contains(&bit_depth) || bit_depth == 0 { bit_depth = 16; }
     let channels = pcm[0].len();
 
     let pcm_flat: Vec<f64> = pcm.iter().flat_map(|x| x.iter()).cloned().collect();
+
+    // Integer frames take the index of the narrowest float depth holding them,
+    // falling back to floats if a sample is not an integer or the frame would pass for a float one
+    if let Some(bits) = int_bits.filter(|&bits| bits <= bit_depth) {
+        let index = DEPTHS.iter().position(|&x| x >= bits).unwrap_or(0) as u16;
+        if let Some(frad) = u8pack::pack_int(&pcm_flat, bits, little_endian, channels, predictor) {
+            if is_int_frame(&frad, index, channels as u16, pcm.len() as u32) { return Ok((frad, index, channels as u16, srate)); }
+        }
+    }
     let max_abs = pcm_flat.iter().map(|&x| x.abs()).fold(0.0f64, f64::max);
 
     let bit_depth_index = DEPTHS.iter().zip(FLOAT_DR_LIMITS.iter())
     .enumerate().find(|(_, (&value, &limit))| value >= bit_depth && value > 0 && max_abs < limit)
-    .map(|(i, _)| i).unwrap_or_else(|| panic!("Overflow with reaching the max bit depth."));
+    .map(|(i, _)| i).ok_or(FradError::SampleOverflow(4))?;
 
     let frad = u8pack::pack(pcm_flat, DEPTHS[bit_depth_index], little_endian);
-    return (frad, bit_depth_index as u16, channels as u16, srate);
+    let frad = u8pack::predict(frad, DEPTHS[bit_depth_index], little_endian, channels, predictor);
+    return Ok((frad, bit_depth_index as u16, channels as u16, srate));
 }
 
 /** digital
  * Decodes FrAD to PCM
- * Parameters: Encoded audio data, Bit depth index, Channel count, Little endian toggle
- * Returns: Decoded PCM
+ * Parameters: Encoded audio data, Bit depth index, Channel count, Little endian toggle, Frame size
+ * Returns: Decoded PCM, or an error for an unused bit depth index
  */
-pub fn digital(frad: Vec<u8>, bit_depth_index: u16, channels: u16, little_endian: bool) -> Vec<Vec<f64>> {
-    let pcm_flat: Vec<f64> = u8pack::unpack(frad, DEPTHS[bit_depth_index as usize], little_endian);
-    return pcm_flat.chunks(channels as usize).map(|chunk| chunk.to_vec()).collect();
+pub fn digital(frad: Vec<u8>, bit_depth_index: u16, channels: u16, little_endian: bool, fsize: u32) -> Result<Vec<Vec<f64>>, FradError> {
+    let bit_depth = DEPTHS[bit_depth_index as usize];
+    if bit_depth == 0 { return Err(FradError::InvalidBitDepth(4, bit_depth)); }
+    if is_int_frame(&frad, bit_depth_index, channels, fsize) {
+        if let Some(pcm_flat) = u8pack::unpack_int(&frad, little_endian, channels as usize, fsize as usize) {
+            return Ok(pcm_flat.chunks(channels as usize).map(|chunk| chunk.to_vec()).collect());
+        }
+    }
+    let frad = u8pack::unpredict(frad, bit_depth, little_endian, channels as usize, fsize as usize);
+    let pcm_flat: Vec<f64> = u8pack::unpack(frad, bit_depth, little_endian);
+    return Ok(pcm_flat.chunks(channels as usize).map(|chunk| chunk.to_vec()).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_frames_round_trip() {
+        for bits in [8, 16, 24, 32] {
+            let scale = (1u64 << (bits - 1)) as f64;
+            let pcm: Vec<Vec<f64>> = (0..256).map(|i| vec![-1.0 + i as f64 / scale, (scale - 1.0 - i as f64) / scale]).collect();
+            let (frad, index, channels, _) = analogue(pcm.clone(), 32, 48000, false, Predictor::Delta, Some(bits)).unwrap();
+            assert!(is_int_frame(&frad, index, channels, 256));
+            assert_eq!(digital(frad, index, channels, false, 256).unwrap(), pcm);
+        }
+    }
+
+    #[test]
+    fn int_frame_of_float_length_falls_back_to_floats() {
+        // 2 mono 8-bit samples are 1 + 2 bytes as integers, the length of 2 12-bit floats
+        let pcm = vec![vec![-1.0], vec![0.5]];
+        let (frad, index, channels, _) = analogue(pcm.clone(), 12, 48000, false, Predictor::None, Some(8)).unwrap();
+        assert_eq!(frad.len(), 3);
+        assert!(!is_int_frame(&frad, index, channels, 2));
+        assert_eq!(digital(frad, index, channels, false, 2).unwrap(), pcm);
+    }
+
+    #[test]
+    fn float_frames_are_not_int_frames() {
+        // Negative floats lead with the sign bit, but are exactly as long as their packed samples
+        let pcm = vec![vec![-0.25]; 16];
+        let (frad, index, channels, _) = analogue(pcm.clone(), 16, 48000, false, Predictor::None, None).unwrap();
+        assert_eq!(frad[0] & 0x80, 0x80);
+        assert!(!is_int_frame(&frad, index, channels, 16));
+        assert_eq!(digital(frad, index, channels, false, 16).unwrap(), pcm);
+    }
 }
\ No newline at end of file
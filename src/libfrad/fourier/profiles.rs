@@ -5,12 +5,14 @@
  */
 
 // LOSSLESS profiles
-pub const LOSSLESS: [u8; 2] = [0, 4];
+pub const LOSSLESS: [u8; 3] = [0, 3, 4];
 // Compact profiles
 pub const COMPACT: [u8; 2] = [1, 2];
 
 // Compact profiles table
 pub mod compact {
+    use crate::fourier::backend::signal::WindowType;
+
     // Sample rate table
     pub const SRATES: [u32; 12] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000];
 
@@ -34,9 +36,17 @@ pub mod compact {
         (192, [   192,   384,   768,  1536,  3072,  6144, 12288, 24576]),
     ];
 
-    // Get sample count multiplier from value
-    pub fn get_samples_from_value(key: &u32) -> u32 {
-        return SAMPLES.iter().find(|&(_, v)| v.iter().any(|&x| x == *key)).unwrap().0;
+    // Pack a sample count into its 5-bit index [ Multiplier index(2) | log2 exponent(3) ],
+    // None if the count is not in the table
+    pub fn samples_to_index(fsize: u32) -> Option<u16> {
+        return SAMPLES.iter().enumerate().find_map(|(px, (_, v))|
+            v.iter().position(|&x| x == fsize).map(|exp| (px << 3 | exp) as u16)
+        );
+    }
+
+    // Unpack a sample count from its 5-bit index, None if the multiplier index is out of the table
+    pub fn index_to_samples(index: u16) -> Option<u32> {
+        return SAMPLES.get(index as usize >> 3).map(|(_, v)| v[index as usize & 0b111]);
     }
 
     // Sample count list
@@ -51,6 +61,47 @@ pub mod compact {
         16384, 18432, 24576
     ];
 
+    // Marker for constant(DC) frames, never the first byte of a zlib stream
+    pub const DC_MARKER: u8 = 0x00;
+
+    // Encode a constant frame as the marker and f64be DC value of each channel,
+    // if every sample stays within tolerance of its channel mean
+    pub fn dc_encode(pcm: &[Vec<f64>], tolerance: f64) -> Option<Vec<u8>> {
+        let channels = pcm.first()?.len();
+        let mut frad = vec![DC_MARKER];
+        for c in 0..channels {
+            let dc = pcm.iter().map(|x| x[c]).sum::<f64>() / pcm.len() as f64;
+            if pcm.iter().any(|x| (x[c] - dc).abs() > tolerance) { return None; }
+            frad.extend(dc.to_be_bytes());
+        }
+        return Some(frad);
+    }
+
+    // Marker for windowed frames, followed by the window index and the zlib stream
+    pub const WINDOW_MARKER: u8 = 0x01;
+
+    // Prefix a zlib stream with its analysis window, nothing for the rectangular window
+    pub fn window_encode(frad: Vec<u8>, kind: WindowType) -> Vec<u8> {
+        if kind == WindowType::Rectangular { return frad; }
+        return [WINDOW_MARKER, kind.index()].into_iter().chain(frad).collect();
+    }
+
+    // Strip the window prefix of a frame, None if the window is unknown
+    pub fn window_decode(frad: &mut Vec<u8>) -> Option<WindowType> {
+        if frad.first() != Some(&WINDOW_MARKER) { return Some(WindowType::Rectangular); }
+        let kind = WindowType::from_index(*frad.get(1)?);
+        frad.drain(..2.min(frad.len()));
+        return kind;
+    }
+
+    // Decode a constant frame, None if the data is not one
+    pub fn dc_decode(frad: &[u8], channels: usize, fsize: usize) -> Option<Vec<Vec<f64>>> {
+        if frad.first() != Some(&DC_MARKER) { return None; }
+        let mut dc: Vec<f64> = frad[1..].chunks_exact(8).map(|x| f64::from_be_bytes(x.try_into().unwrap())).collect();
+        dc.resize(channels, 0.0);
+        return Some(vec![dc; fsize]);
+    }
+
     pub const MAX_SMPL: u32 = max_smpl();
     const fn max_smpl() -> u32 {
         let (mut max, mut i) = (0, 0);
@@ -60,4 +111,32 @@ pub mod compact {
             } i += 1;
         } return max;
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compact::*;
+
+    #[test]
+    fn samples_index_round_trips() {
+        let mut indices = Vec::new();
+        for fsize in SAMPLES_LI {
+            let index = samples_to_index(fsize).unwrap();
+            assert!(index < 1 << 5);
+            assert_eq!(index_to_samples(index), Some(fsize));
+            indices.push(index);
+        }
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), SAMPLES_LI.len());
+
+        // Every index of the table decodes to a listed count, the rest to none
+        for index in 0..1 << 5 {
+            match index_to_samples(index) {
+                Some(fsize) => assert!(SAMPLES_LI.contains(&fsize)),
+                None => assert!(index >> 3 >= SAMPLES.len() as u16),
+            }
+        }
+        for fsize in [0, 1, 127, 129, 2047, 2049, 3000, 24577, u32::MAX] { assert_eq!(samples_to_index(fsize), None); }
+    }
 }
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub mod p1tools;
-pub mod p2tools;
\ No newline at end of file
+pub mod p2tools;
+pub mod p3tools;
\ No newline at end of file
@@ -5,7 +5,6 @@
  */
 
 use crate::backend::{bitcvt, linspace};
-use core::iter::repeat;
 
 pub const SPREAD_ALPHA: f64 = 0.8;
 const QUANT_ALPHA: f64 = 0.75;
@@ -23,6 +22,8 @@ const MODIFIED_OPUS_SUBBANDS: [u32; 28] = [
  * Returns: Range of bins
  */
 fn get_bin_range(len: usize, srate: u32, i: usize) -> core::ops::Range<usize> {
+    // Zero sample rate maps no bins, instead of casting NaN
+    if srate == 0 { return 0..0; }
     let start = (MODIFIED_OPUS_SUBBANDS[i] as f64 / (srate as f64 / 2.0) * len as f64).round() as usize;
     let end = (MODIFIED_OPUS_SUBBANDS[i + 1] as f64 / (srate as f64 / 2.0) * len as f64).round() as usize;
     return start.min(len)..end.min(len);
@@ -30,17 +31,15 @@ fn get_bin_range(len: usize, srate: u32, i: usize) -> core::ops::Range<usize> {
 
 /** mask_thres_mos
  * Calculates the masking threshold for each subband
- * Parameters: DCT Array, Sample rate, Bit depth, Loss level, Alpha(Constant for now)
- * Returns: Masking threshold array
+ * Parameters: DCT Array, Sample rate, Bit depth, Loss level, Alpha(Constant for now), Output thresholds
  */
-pub fn mask_thres_mos(mut freqs: Vec<f64>, srate: u32, bit_depth: u16, loss_level: f64, alpha: f64) -> Vec<f64> {
-    freqs = freqs.iter().map(|x| x.abs()).collect();
-    let mut thres = vec![0.0; MOSLEN];
-    let pcm_scale = (1 << (bit_depth - 1)) as f64;
+pub fn mask_thres_mos(freqs: &[f64], srate: u32, bit_depth: u16, loss_level: f64, alpha: f64, thres: &mut Vec<f64>) {
+    thres.clear(); thres.resize(MOSLEN, 0.0);
+    let pcm_scale = 2.0f64.powi(bit_depth as i32 - 1);
 
     // for each subband
     for i in 0..MOSLEN {
-        let subfreqs = freqs[get_bin_range(freqs.len(), srate, i)].to_vec();
+        let subfreqs = &freqs[get_bin_range(freqs.len(), srate, i)];
         if subfreqs.is_empty() { continue; }
         // Centre frequency of the subband
         let f = (MODIFIED_OPUS_SUBBANDS[i] as f64 + MODIFIED_OPUS_SUBBANDS[i + 1] as f64) / 2.0;
@@ -49,12 +48,10 @@ pub fn mask_thres_mos(mut freqs: Vec<f64>, srate: u32, bit_depth: u16, loss_leve
             (3.64 * (f / 1000.0).powf(-0.8) - 6.5 * (-0.6 * (f / 1000.0 - 3.3).powi(2)).exp() + 1e-3 * (f / 1000.0).powi(4)) / 20.0
         ) / pcm_scale;
         // Root mean square
-        let sfq = (subfreqs.iter().map(|x| x.powi(2)).sum::<f64>() / subfreqs.len() as f64).sqrt().powf(alpha);
+        let sfq = (subfreqs.iter().map(|x| x.abs().powi(2)).sum::<f64>() / subfreqs.len() as f64).sqrt().powf(alpha);
         // Larger value between mapped_freq[i]^alpha and ATH in absolute amplitude
         thres[i] = sfq.max(absolute_hearing_threshold.min(1.0)) * loss_level;
     }
-
-    return thres;
 }
 
 /** mapping_from_opus
@@ -63,15 +60,23 @@ pub fn mask_thres_mos(mut freqs: Vec<f64>, srate: u32, bit_depth: u16, loss_leve
  * Returns: Inverse-mapped thresholds
  */
 pub fn mapping_from_opus(mapped_thres: &[f64], freqs_len: usize, srate: u32) -> Vec<f64> {
-    let mut thres = vec![0.0; freqs_len];
+    let mut thres = Vec::new();
+    mapping_from_opus_into(mapped_thres, freqs_len, srate, &mut thres);
+    return thres;
+}
+
+/** mapping_from_opus_into
+ * Maps the thresholds from the modified Opus subbands into a reusable buffer
+ * Parameters: MOS-Mapped thresholds, Length of the DCT Array, Sample rate, Output thresholds
+ */
+pub fn mapping_from_opus_into(mapped_thres: &[f64], freqs_len: usize, srate: u32, thres: &mut Vec<f64>) {
+    thres.clear(); thres.resize(freqs_len, 0.0);
 
     for i in 0..MOSLEN-1 {
         let range = get_bin_range(freqs_len, srate, i);
         // Linearly spaced values between the mapped thresholds
-        thres[range.clone()].copy_from_slice(&linspace(mapped_thres[i], mapped_thres[i + 1], range.end - range.start));
+        linspace(mapped_thres[i], mapped_thres[i + 1], &mut thres[range]);
     }
-
-    return thres;
 }
 
 /** quant
@@ -88,47 +93,162 @@ pub fn quant(x: f64) -> f64 { return x.signum() * x.abs().powf(QUANT_ALPHA); }
  */
 pub fn dequant(y: f64) -> f64 { return y.signum() * y.abs().powf(1.0 / QUANT_ALPHA); }
 
+/** thres_encode
+ * Quantises a masking threshold to its stored integer
+ *   Positive thresholds are kept at 1 or above, as 0 would silence their whole subband
+ * Parameters: Threshold, Threshold scale factor
+ * Returns: Quantised threshold
+ */
+pub fn thres_encode(thres: f64, thres_scale: f64) -> i64 {
+    let q = quant(thres * thres_scale).round() as i64;
+    return if thres > 0.0 { q.max(1) } else { q };
+}
+
+/** thres_decode
+ * Reconstructs a masking threshold from its stored integer, the inverse of thres_encode
+ * Parameters: Quantised threshold, Threshold scale factor
+ * Returns: Threshold
+ */
+pub fn thres_decode(thres: i64, thres_scale: f64) -> f64 { return dequant(thres as f64) / thres_scale; }
+
 /** exp_golomb_encode
  * Encodes any integer array with Exponential Golomb Encoding
  * Parameters: Integer array
  * Returns: Encoded binary data
  */
 pub fn exp_golomb_encode(data: Vec<i64>) -> Vec<u8> {
-    if data.is_empty() { return vec![0]; }
+    let mut encoded = Vec::new();
+    exp_golomb_encode_into(&data, &mut encoded);
+    return encoded;
+}
+
+/** exp_golomb_encode_into
+ * Encodes any integer array with Exponential Golomb Encoding into a reusable buffer
+ * Parameters: Integer array, Output buffer
+ */
+pub fn exp_golomb_encode_into(data: &[i64], encoded: &mut Vec<u8>) {
+    encoded.clear();
+    if data.is_empty() { encoded.push(0); return; }
     let dmax = data.iter().map(|x| x.abs()).max().unwrap();
     let k = if dmax > 0 { (dmax as f64).log2().ceil() as u8 } else { 0 };
+    encoded.push(k);
 
-    let mut encoded_binary: Vec<bool> = bitcvt::to_bits(vec![k]);
-
-    for n in data {
+    // Bit accumulator, holds less than 8 pending bits between writes
+    let (mut acc, mut nbits) = (0u128, 0usize);
+    for &n in data {
         let x = if n > 0 { (n << 1) - 1 } else { -n << 1 } + (1 << k);
-        let code: Vec<bool> = bitcvt::to_bits(x.to_be_bytes().to_vec()).iter().skip_while(|&x| !x).cloned().collect();
-        encoded_binary.extend(repeat(false).take(code.len() - (k + 1) as usize));
-        encoded_binary.extend(code);
+        let code_len = (64 - x.leading_zeros()) as usize;
+        // Leading zeros, then the code itself
+        put_bits(&mut acc, &mut nbits, 0, code_len - (k + 1) as usize, encoded);
+        put_bits(&mut acc, &mut nbits, x as u64, code_len, encoded);
     }
-    return bitcvt::to_bytes(encoded_binary);
+    if nbits > 0 { encoded.push((acc << (8 - nbits)) as u8); }
+}
+
+/** put_bits
+ * Appends bits to the bit accumulator and flushes full bytes
+ * Parameters: Accumulator, Pending bit count, Value, Bit width, Output buffer
+ */
+fn put_bits(acc: &mut u128, nbits: &mut usize, value: u64, width: usize, out: &mut Vec<u8>) {
+    *acc = (*acc << width) | value as u128; *nbits += width;
+    while *nbits >= 8 { *nbits -= 8; out.push((*acc >> *nbits) as u8); }
+    *acc &= (1 << *nbits) - 1;
+}
+
+/** exp_golomb_len
+ * Gets the byte length of Exponential Golomb encoded data without encoding
+ * Parameters: Integer array
+ * Returns: Encoded byte length
+ */
+pub fn exp_golomb_len(data: &[i64]) -> usize {
+    if data.is_empty() { return 1; }
+    let dmax = data.iter().map(|x| x.abs()).max().unwrap();
+    let k = if dmax > 0 { (dmax as f64).log2().ceil() as u8 } else { 0 };
+
+    let bits: usize = 8 + data.iter().map(|&n| {
+        let x = if n > 0 { (n << 1) - 1 } else { -n << 1 } + (1 << k);
+        let code_len = (64 - x.leading_zeros()) as usize;
+        code_len * 2 - (k + 1) as usize
+    }).sum::<usize>();
+    return bits.div_ceil(8);
 }
 
 /** exp_golomb_decode
  * Decodes any integer array with Exponential Golomb Encoding
- * Parameters: Binary data
+ *   Corrupt or truncated data ends the decoding, keeping the integers decoded so far
+ * Parameters: Binary data, Maximum integer count
  * Returns: Decoded integer array
  */
-pub fn exp_golomb_decode(data: Vec<u8>) -> Vec<i64> {
-    let k = data[0] as usize;
+pub fn exp_golomb_decode(data: Vec<u8>, max: usize) -> Vec<i64> {
+    let Some(&k) = data.first() else { return Vec::new(); };
+    let k = k as usize;
+    // Codes of i64 values fit in 63 bits
+    if k > 62 { return Vec::new(); }
     let (data, kx, mut decoded, mut idx) =
-        (bitcvt::to_bits(data[1..].to_vec()), 1 << k, Vec::new(), 0);
+        (bitcvt::to_bits(data[1..].to_vec()), 1i64 << k, Vec::new(), 0);
 
-    while idx < data.len() {
-        let m = data[idx..].iter().position(|&x| x).unwrap_or(data.len());
-        if m == data.len() { break; }
+    while idx < data.len() && decoded.len() < max {
+        let Some(m) = data[idx..].iter().position(|&x| x) else { break; };
         let cwlen = (m * 2) + k + 1;
+        if m + k + 1 > 63 || idx + cwlen > data.len() { break; }
 
-        let cache = &data[(idx + m)..(idx + cwlen).min(data.len())];
+        let cache = &data[(idx + m)..(idx + cwlen)];
         let n = cache.iter().fold(0, |acc, &bit| { (acc << 1) | (bit as i64) }) - kx;
         decoded.push(if n & 1 == 1 { (n + 1) >> 1 } else { -(n >> 1) });
         idx += cwlen;
     }
 
     return decoded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fourier::profile1::DEPTHS;
+
+    /** thres_scales
+     * Threshold scale factors of every Profile 1 bit depth, as the encoder derives them
+     */
+    fn thres_scales() -> Vec<f64> {
+        return DEPTHS.iter().filter(|&&bits| bits != 0).map(|&bits| 3.0_f64.sqrt().powi(16 - bits as i32)).collect();
+    }
+
+    #[test]
+    fn thresholds_round_trip_within_a_step() {
+        for scale in thres_scales() {
+            for e in -60..=60 {
+                let thres = 10.0_f64.powf(e as f64 / 10.0) / scale;
+                let q = thres_encode(thres, scale);
+                if q <= 1 { continue; }
+                // Off by at most half a quantisation step, and stable on re-encoding
+                let step = (dequant(q as f64 + 0.5) - dequant(q as f64 - 0.5)) / scale;
+                let decoded = thres_decode(q, scale);
+                assert!((decoded - thres).abs() <= step / 2.0 + thres * 1e-12, "{} at scale {}", thres, scale);
+                assert_eq!(thres_encode(decoded, scale), q);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_and_tiny_thresholds() {
+        for scale in thres_scales() {
+            assert_eq!(thres_encode(0.0, scale), 0);
+            assert_eq!(thres_decode(0, scale), 0.0);
+            // Positive thresholds never quantise to 0, which would silence the subband
+            for thres in [f64::MIN_POSITIVE, 1e-300, 1e-12 / scale, 0.5 / scale] {
+                assert_eq!(thres_encode(thres, scale), 1);
+                assert!(thres_decode(1, scale) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn large_thresholds() {
+        for scale in thres_scales() {
+            for thres in [1e6 / scale, 1e9 / scale, 1e12 / scale] {
+                let decoded = thres_decode(thres_encode(thres, scale), scale);
+                assert!((decoded / thres - 1.0).abs() < 1e-3, "{} at scale {}", thres, scale);
+            }
+        }
+    }
 }
\ No newline at end of file
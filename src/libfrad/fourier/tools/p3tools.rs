@@ -0,0 +1,186 @@
+/**                              Profile 3 Tools                              */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Fixed polynomial prediction and partitioned Rice coding tools for Profile 3
+ */
+
+// Highest fixed predictor order
+pub const MAX_ORDER: usize = 2;
+// Residuals per Rice partition
+pub const PARTITION: usize = 256;
+// Quotients from this value on are escaped to a raw residual
+const ESCAPE: u64 = 32;
+
+/** BitWriter
+ * MSB-first bit writer
+ */
+pub struct BitWriter { buf: Vec<u8>, acc: u64, nbits: usize }
+
+impl BitWriter {
+    pub fn new() -> BitWriter { return BitWriter { buf: Vec::new(), acc: 0, nbits: 0 }; }
+
+    /** put
+     * Appends the lower bits of a value
+     * Parameters: Value, Bit width up to 64
+     */
+    pub fn put(&mut self, value: u64, width: usize) {
+        for i in (0..width).rev() {
+            self.acc = self.acc << 1 | (value >> i & 1); self.nbits += 1;
+            if self.nbits == 8 { self.buf.push(self.acc as u8); (self.acc, self.nbits) = (0, 0); }
+        }
+    }
+
+    /** finish
+     * Pads the last byte with zeros
+     * Returns: Written bytes
+     */
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 { self.buf.push((self.acc << (8 - self.nbits)) as u8); }
+        return self.buf;
+    }
+}
+
+/** BitReader
+ * MSB-first bit reader, reading zeros past the end so corrupt data cannot panic
+ */
+pub struct BitReader<'a> { buf: &'a [u8], pos: usize }
+
+impl<'a> BitReader<'a> {
+    pub fn new(buf: &'a [u8]) -> BitReader<'a> { return BitReader { buf, pos: 0 }; }
+
+    /** get
+     * Reads a value
+     * Parameters: Bit width up to 64
+     * Returns: Value
+     */
+    pub fn get(&mut self, width: usize) -> u64 {
+        let mut value = 0;
+        for _ in 0..width {
+            let bit = self.buf.get(self.pos / 8).map_or(0, |b| b >> (7 - self.pos % 8) & 1);
+            value = value << 1 | bit as u64; self.pos += 1;
+        }
+        return value;
+    }
+}
+
+/** residual
+ * Applies a fixed polynomial predictor
+ * Parameters: Samples of one channel, Predictor order
+ * Returns: Residuals, the first order samples are kept as-is
+ */
+pub fn residual(x: &[i64], order: usize) -> Vec<i64> {
+    return (0..x.len()).map(|i| match order {
+        _ if i < order => x[i],
+        1 => x[i] - x[i - 1],
+        2 => x[i] - 2 * x[i - 1] + x[i - 2],
+        _ => x[i]
+    }).collect();
+}
+
+/** restore
+ * Reverts a fixed polynomial predictor in place
+ * Parameters: Residuals of one channel, Predictor order
+ */
+pub fn restore(x: &mut [i64], order: usize) {
+    for i in order..x.len() {
+        x[i] = match order {
+            1 => x[i].wrapping_add(x[i - 1]),
+            2 => x[i].wrapping_add(x[i - 1].wrapping_mul(2)).wrapping_sub(x[i - 2]),
+            _ => x[i]
+        };
+    }
+}
+
+/** rice_cost
+ * Gets the bit length of a Rice coded partition without coding
+ * Parameters: Zigzagged residuals, Rice parameter, Escape width
+ * Returns: Bit length
+ */
+fn rice_cost(u: &[u64], k: usize, raw: usize) -> usize {
+    return u.iter().map(|&x| if x >> k < ESCAPE { (x >> k) as usize + 1 + k } else { ESCAPE as usize + raw }).sum();
+}
+
+/** rice_encode
+ * Rice codes residuals in partitions, each led by its 6-bit Rice parameter
+ * Parameters: Bit writer, Residuals, Escape width holding any zigzagged residual
+ */
+pub fn rice_encode(w: &mut BitWriter, r: &[i64], raw: usize) {
+    for part in r.chunks(PARTITION) {
+        let u: Vec<u64> = part.iter().map(|&x| ((x << 1) ^ (x >> 63)) as u64).collect();
+
+        // Parameter from the mean, refined by the exact cost of its neighbours
+        let mean = u.iter().sum::<u64>() / u.len() as u64;
+        let guess = (64 - mean.leading_zeros() as usize).saturating_sub(1).min(raw);
+        let k = (guess.saturating_sub(1)..=(guess + 1).min(raw)).min_by_key(|&k| rice_cost(&u, k, raw)).unwrap();
+
+        w.put(k as u64, 6);
+        for &x in &u {
+            if x >> k < ESCAPE { w.put(u64::MAX, (x >> k) as usize); w.put(0, 1); w.put(x, k); }
+            else { w.put(u64::MAX, ESCAPE as usize); w.put(x, raw); }
+        }
+    }
+}
+
+/** rice_decode
+ * Decodes Rice coded residuals
+ * Parameters: Bit reader, Residual count, Escape width
+ * Returns: Residuals
+ */
+pub fn rice_decode(rd: &mut BitReader, len: usize, raw: usize) -> Vec<i64> {
+    let mut r = Vec::with_capacity(len);
+    while r.len() < len {
+        let k = rd.get(6) as usize;
+        for _ in 0..PARTITION.min(len - r.len()) {
+            let mut q = 0;
+            while q < ESCAPE && rd.get(1) == 1 { q += 1; }
+            let x = if q < ESCAPE { q << k | rd.get(k) } else { rd.get(raw) };
+            r.push((x >> 1) as i64 ^ -((x & 1) as i64));
+        }
+    }
+    return r;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** round_trip
+     * Rice codes residuals and decodes them back
+     */
+    fn round_trip(r: &[i64], raw: usize) -> Vec<i64> {
+        let mut w = BitWriter::new();
+        rice_encode(&mut w, r, raw);
+        let bytes = w.finish();
+        return rice_decode(&mut BitReader::new(&bytes), r.len(), raw);
+    }
+
+    #[test]
+    fn rice_escapes_large_quotients() {
+        // Small residuals keep the parameter low, so the outliers' quotients reach the escape
+        let raw = 16 + 3;
+        let mut r = vec![0i64; PARTITION];
+        (r[10], r[100], r[200]) = (1 << 17, -(1 << 17), 40);
+        let u = |x: i64| ((x << 1) ^ (x >> 63)) as u64;
+        // The mean is about 2^11, so the parameter is at most 12
+        assert!(u(r[10]) >> 12 >= ESCAPE);
+        assert_eq!(round_trip(&r, raw), r);
+    }
+
+    #[test]
+    fn rice_codes_partial_partitions() {
+        for len in [1, PARTITION - 1, PARTITION + 1, PARTITION * 3 + 17] {
+            let r: Vec<i64> = (0..len as i64).map(|i| (i * 7919 % 601) - 300).collect();
+            assert_eq!(round_trip(&r, 11), r);
+        }
+    }
+
+    #[test]
+    fn predictors_restore() {
+        let x: Vec<i64> = (0..100).map(|i| i * i - 50 * i).collect();
+        for order in 0..=MAX_ORDER {
+            let mut r = residual(&x, order);
+            restore(&mut r, order);
+            assert_eq!(r, x);
+        }
+    }
+}
\ No newline at end of file
@@ -2,6 +2,10 @@
 /**
  * Copyright 2024 HaמuL
  * Description: Fourier Analogue-in-Digital Rust Master Library
+ *   Not no_std yet: the codec needs std for the FFT (rustfft has no no_std build)
+ *   and for float math (sin, cos, powi, ln, ... would have to come from libm).
+ *   I/O is behind the std feature, on by default: the decoder seek, frame reader and debug sink,
+ *   EncoderWriter, FradError::Io, the reader side of tools::{index, wav}, and the http and cbor features
  */
 
 mod backend;
@@ -11,13 +15,21 @@ mod tools;
 mod encoder;
 mod decoder;
 mod repairer;
+mod error;
 
-pub use backend::{PCMFormat, Endian, f64cvt};
-pub use fourier::{AVAILABLE, BIT_DEPTHS, SEGMAX, profiles};
-pub use tools::head;
+pub use backend::{PCMFormat, Endian, SignFind, VecPatternFind, WindowKind, f64cvt::{self, DitherKind}};
+pub use fourier::{AVAILABLE, BIT_DEPTHS, BIT_DEPTHS_BY_VERSION, FORMAT_VERSION, SEGMAX, remap_depth_index, profiles, profile1::estimate_analogue_size, backend::{signal::WindowType, u8pack::Predictor}};
+pub use tools::{ecc, format::{self, FrameLayout}, head, index, requant, split::split_channels, validate::{validate, StreamEnd, ValidationReport}, wav};
+#[cfg(feature = "std")]
+pub use tools::wav::decode_to_wav;
+#[cfg(feature = "http")]
+pub use tools::http::RangeReader;
 
 pub mod common;
 pub use tools::asfh::ASFH;
-pub use encoder::{Encoder, EncodeResult};
-pub use decoder::{Decoder, DecodeResult};
-pub use repairer::Repairer;
\ No newline at end of file
+pub use encoder::{Encoder, EncodeResult, EncoderParams, validate_params};
+#[cfg(feature = "std")]
+pub use encoder::EncoderWriter;
+pub use error::FradError;
+pub use decoder::{Concealment, Decoder, DecodeLimit, DecodeResult};
+pub use repairer::{Repairer, RepairStats};
\ No newline at end of file
@@ -5,44 +5,52 @@
  */
 
 use crate::{
-    backend::{SplitFront, VecPatternFind},
+    backend::{SignFind, SplitFront},
     common:: {crc16_ansi, crc32, FRM_SIGN},
+    error::FradError,
     fourier::profiles::{COMPACT, LOSSLESS},
-    tools::  {asfh::{ASFH, ParseResult::{Complete, Incomplete, ForceFlush}}, ecc},
+    tools::  {asfh::{ASFH, ParseResult::{Complete, Incomplete, ForceFlush}}, ecc::{self, RSCodec}},
 };
 
+/** RepairStats
+ * Frame counts of a repair, a frame is corrupt if its CRC mismatches
+ *   Corrected frames were repaired by ECC, uncorrectable ones could not be, or had no usable ECC to try
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RepairStats {
+    pub frames_total: u64,
+    pub frames_corrected: u64,
+    pub frames_uncorrectable: u64,
+}
+
 /** Repairer
  * Struct for FrAD repairer
  */
 pub struct Repairer {
     asfh: ASFH,
     buffer: Vec<u8>,
+    rs_dec: Option<RSCodec>,
+    rs_enc: Option<RSCodec>,
 
     ecc_ratio: [u8; 2],
     broken_frame: bool,
+    stats: RepairStats,
 }
 
 impl Repairer {
-    pub fn new(mut ecc_ratio: [u8; 2]) -> Repairer {
-        if ecc_ratio[0] == 0 {
-            eprintln!("ECC data size must not be zero");
-            eprintln!("Setting ECC to default 96 24");
-            ecc_ratio = [96, 24];
-        }
-        if ecc_ratio[0] as u16 + ecc_ratio[1] as u16 > 255 {
-            eprintln!("ECC data size and check size must not exceed 255, given: {} and {}",
-                ecc_ratio[0], ecc_ratio[1]);
-            eprintln!("Setting ECC to default 96 24");
-            ecc_ratio = [96, 24];
-        }
+    pub fn new(ecc_ratio: [u8; 2]) -> Result<Repairer, FradError> {
+        if !ecc::is_plausible(ecc_ratio) { return Err(FradError::InvalidEccRatio(ecc_ratio)); }
 
-        return Repairer {
+        return Ok(Repairer {
             asfh: ASFH::new(),
             buffer: Vec::new(),
+            rs_dec: None,
+            rs_enc: None,
 
             ecc_ratio,
             broken_frame: false,
-        };
+            stats: RepairStats::default(),
+        });
     }
 
     /** is_empty
@@ -57,6 +65,12 @@ impl Repairer {
      */
     pub fn get_asfh(&self) -> &ASFH { return &self.asfh; }
 
+    /** stats
+     * Get the frame counts of the repair so far
+     * Returns: Repair statistics
+     */
+    pub fn stats(&self) -> RepairStats { return self.stats; }
+
     /** process
      * Process the input stream and repair the FrAD stream
      * Parameters: Input stream
@@ -80,15 +94,20 @@ impl Repairer {
                 let mut frad: Vec<u8> = self.buffer.split_front(self.asfh.frmbytes as usize);
 
                 // 1.2. Correct the error if ECC is enabled
-                if self.asfh.ecc {
-                    let repair = // and if CRC mismatch
-                        LOSSLESS.contains(&self.asfh.profile) && crc32(&frad) != self.asfh.crc32 ||
+                self.stats.frames_total += 1;
+                if self.asfh.ecc && !frad.is_empty() {
+                    let corrupt = LOSSLESS.contains(&self.asfh.profile) && crc32(&frad) != self.asfh.crc32 ||
                         COMPACT.contains(&self.asfh.profile) && crc16_ansi(&frad) != self.asfh.crc16;
-                    frad = ecc::decode(frad, self.asfh.ecc_ratio, repair);
+                    let repair = corrupt && ecc::is_plausible(self.asfh.ecc_ratio);
+                    let mask;
+                    (frad, mask) = ecc::decode_checked(frad, ecc::get_codec(&mut self.rs_dec, self.asfh.ecc_ratio), repair);
+                    let failed = mask.contains(&false);
+                    if repair && !failed { self.stats.frames_corrected += 1; }
+                    else if corrupt { self.stats.frames_uncorrectable += 1; }
                 }
 
                 // 1.3. Create Reed-Solomon error correction code
-                frad = ecc::encode(frad, self.ecc_ratio);
+                if !frad.is_empty() { frad = ecc::encode_with_codec(frad, ecc::get_codec(&mut self.rs_enc, self.ecc_ratio)); }
                 (self.asfh.ecc, self.asfh.ecc_ratio) = (true, self.ecc_ratio);
 
                 // 1.4. Write the frame data to the buffer
@@ -102,7 +121,7 @@ impl Repairer {
             else {
                 // 2.1. If the header buffer not found, find the header buffer
                 if !self.asfh.buffer.starts_with(&FRM_SIGN) {
-                    match self.buffer.find_pattern(&FRM_SIGN) {
+                    match self.buffer.find_sign(&FRM_SIGN) {
                         // If pattern found in the buffer
                         // 2.1.1. Split out the buffer to the header buffer
                         Some(i) => {
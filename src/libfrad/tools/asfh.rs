@@ -7,9 +7,13 @@
 use crate::{
     backend::SplitFront,
     common::{crc16_ansi, crc32, FRM_SIGN},
-    fourier::profiles::{compact::{self, get_srate_index}, COMPACT}
+    fourier::{profiles::{compact::{self, get_srate_index}, COMPACT}, BIT_DEPTHS},
+    tools::ecc::EccParams
 };
 
+// Reserved sample prefix signalling the extended CSS
+const CSS_EXT_PREFIX: u16 = 0b11;
+
 /** encode_pfb
  * Encodes PFloat byte (containing necessary info for the frame)
  * Parameters: Profile, ECC toggle, Little-endian toggle, Bit depth index
@@ -24,17 +28,24 @@ fn encode_pfb(profile: u8, enable_ecc: bool, little_endian: bool, bit_depth_inde
 
 /** encode_css
  * Encodes channel-srate-samples byte for Compact Profiles
+ *   Over 64 channels, the reserved sample prefix 0b11 signals an extension byte
+ *   of [ Sample prefix(2) | Upper channel bits(6) ], up to 4096 channels
  * Parameters: Channel count, Sample rate, Sample count
  * Returns: Encoded CSS
  */
 fn encode_css(channels: u16, srate: u32, fsize: u32, force_flush: bool) -> Vec<u8> {
-    let chnl = (channels as u16 - 1) << 10;
+    let extended = channels > 64;
+    let chnl = ((channels as u16 - 1) & 0b111111) << 10;
     let srate = get_srate_index(srate) << 6;
     let fsize = *compact::SAMPLES_LI.iter().find(|&&x| x >= fsize).unwrap();
-    let mult = compact::get_samples_from_value(&fsize);
-    let px = (compact::SAMPLES.iter().position(|&(key, _)| key == mult).unwrap() as u16) << 4;
-    let fsize = ((fsize as f64 / mult as f64).log2() as u16) << 1;
-    return (chnl | srate | px | fsize | force_flush as u16).to_be_bytes().to_vec();
+    let index = compact::samples_to_index(fsize).unwrap();
+    let px_val = index >> 3;
+    let px = if extended { CSS_EXT_PREFIX } else { px_val } << 4;
+    let fsize = (index & 0b111) << 1;
+
+    let mut css = (chnl | srate | px | fsize | force_flush as u16).to_be_bytes().to_vec();
+    if extended { css.push((px_val << 6 | (channels - 1) >> 6) as u8); }
+    return css;
 }
 
 /** decode_pfb
@@ -50,6 +61,15 @@ fn decode_pfb(pfb: u8) -> (u8, bool, bool, u16) {
     return (prf, ecc, endian, bit_depth_index as u16);
 }
 
+/** css_len
+ * Gets the CSS length from its first two bytes
+ * Parameters: Encoded CSS
+ * Returns: CSS length in bytes
+ */
+fn css_len(css: &[u8]) -> usize {
+    return if (css[1] >> 4) & 0b11 == CSS_EXT_PREFIX as u8 { 3 } else { 2 };
+}
+
 /** decode_css
  * Decodes Cchannel-srate-samples byte for Compact Profiles
  * Parameters: Encoded CSS
@@ -57,11 +77,16 @@ fn decode_pfb(pfb: u8) -> (u8, bool, bool, u16) {
  */
 fn decode_css(css: Vec<u8>) -> (u16, u32, u32, bool) {
     let css_int = u16::from_be_bytes(css[0..2].try_into().unwrap());
-    let chnl = (css_int >> 10) as u16 + 1;
+    let mut chnl = (css_int >> 10) as u16 + 1;
     let srate = compact::SRATES[(css_int >> 6) as usize & 0b1111];
 
-    let fsize_prefix = compact::SAMPLES[(css_int >> 4) as usize & 0b11].0;
-    let fsize = fsize_prefix * 2u32.pow(((css_int >> 1) & 0b111) as u32);
+    let mut px = (css_int >> 4) & 0b11;
+    if px == CSS_EXT_PREFIX {
+        px = (css[2] >> 6) as u16;
+        chnl += (css[2] as u16 & 0b111111) << 6;
+    }
+    // An out-of-table multiplier yields 0 samples, rejected as implausible downstream
+    let fsize = compact::index_to_samples(px << 3 | (css_int >> 1) & 0b111).unwrap_or(0);
 
     let force_flush = css_int & 1 == 1;
 
@@ -79,19 +104,21 @@ pub struct ASFH {
     pub all_set: bool,
     pub header_bytes: usize,
 
-    // Audio structure data
+    // Audio structure data, endian is the joint stereo flag in Profile 1
     pub endian: bool,
     pub bit_depth_index: u16, pub channels: u16,
     pub srate: u32, pub fsize: u32,
 
-    // Error correction
+    // Error correction, the code variant is not written to the frame, None for the FrAD default
     pub ecc: bool, pub ecc_ratio: [u8; 2],
+    pub ecc_params: Option<EccParams>,
 
     // Profile
     pub profile: u8,
 
     // LOSSLESS
     pub crc32: [u8; 4],
+    pub seq: u64,
 
     // COMPACT
     pub overlap_ratio: u16,
@@ -107,9 +134,9 @@ impl ASFH {
             endian: false, bit_depth_index: 0,
             channels: 0, srate: 0, fsize: 0,
 
-            ecc: false, ecc_ratio: [0; 2],
+            ecc: false, ecc_ratio: [0; 2], ecc_params: None,
             profile: 0,
-            overlap_ratio: 0, crc16: [0; 2], crc32: [0; 4],
+            overlap_ratio: 0, crc16: [0; 2], crc32: [0; 4], seq: 0,
         }
     }
 
@@ -122,6 +149,14 @@ impl ASFH {
         return self.channels == other.channels && self.srate == other.srate;
     }
 
+    /** bit_depth_bits
+     * Gets the bit depth of the frame in bits, looked up from the profile and bit depth index
+     * Returns: Bit depth, 0 if the profile or index is out of range
+     */
+    pub fn bit_depth_bits(&self) -> u16 {
+        return BIT_DEPTHS.get(self.profile as usize).and_then(|d| d.get(self.bit_depth_index as usize)).copied().unwrap_or(0);
+    }
+
     /** write
      * Makes a frame from audio frame and metadata and return as buffer
      * Parameters: Audio frame
@@ -145,7 +180,7 @@ impl ASFH {
             fhead.push((self.channels - 1) as u8);
             fhead.extend(self.ecc_ratio.to_vec());
             fhead.extend(self.srate.to_be_bytes().to_vec());
-            fhead.extend([0u8; 8].to_vec());
+            fhead.extend(self.seq.to_be_bytes().to_vec());
             fhead.extend(self.fsize.to_be_bytes().to_vec());
             fhead.extend(crc32(&frad).to_vec());
         }
@@ -157,6 +192,8 @@ impl ASFH {
 
     /** force_flush
      * Makes a force-flush frame and return as buffer
+     *   COMPACT: Header with force-flush flag set
+     *   LOSSLESS: Empty frame as a stream terminator
      * Returns: Frame buffer
      */
     pub fn force_flush(&mut self) -> Vec<u8> {
@@ -170,7 +207,14 @@ impl ASFH {
             fhead.extend(encode_css(channels, self.srate, self.fsize, true));
             fhead.push(0);
         }
-        else { return Vec::new(); }
+        else {
+            fhead.push((self.channels.max(1) - 1) as u8);
+            fhead.extend(self.ecc_ratio.to_vec());
+            fhead.extend(self.srate.to_be_bytes().to_vec());
+            fhead.extend([0u8; 8].to_vec());
+            fhead.extend(0u32.to_be_bytes().to_vec());
+            fhead.extend(crc32(&[]).to_vec());
+        }
 
         return fhead;
     }
@@ -201,16 +245,19 @@ impl ASFH {
 
         if COMPACT.contains(&self.profile) {
             if !self.fill_buffer(buffer, 12) { return ParseResult::Incomplete }
+            // Extended CSS shifts the rest of the header by one byte
+            let ext = css_len(&self.buffer[0x9..0xb]) - 2;
+            if !self.fill_buffer(buffer, 12 + ext) { return ParseResult::Incomplete }
 
-            let force_flush; (self.channels, self.srate, self.fsize, force_flush) = decode_css(self.buffer[0x9..0xb].to_vec());
+            let force_flush; (self.channels, self.srate, self.fsize, force_flush) = decode_css(self.buffer[0x9..0xb + ext].to_vec());
             if force_flush { self.all_set = true; return ParseResult::ForceFlush; }
-            self.overlap_ratio = self.buffer[0xb] as u16; if self.overlap_ratio != 0 { self.overlap_ratio += 1; }
+            self.overlap_ratio = self.buffer[0xb + ext] as u16; if self.overlap_ratio != 0 { self.overlap_ratio += 1; }
 
             if self.ecc {
-                if !self.fill_buffer(buffer, 16) { return ParseResult::Incomplete }
+                if !self.fill_buffer(buffer, 16 + ext) { return ParseResult::Incomplete }
 
-                self.ecc_ratio = [self.buffer[0xc], self.buffer[0xd]];
-                self.crc16 = self.buffer[0xe..0x10].try_into().unwrap();
+                self.ecc_ratio = [self.buffer[0xc + ext], self.buffer[0xd + ext]];
+                self.crc16 = self.buffer[0xe + ext..0x10 + ext].try_into().unwrap();
             }
         }
         else {
@@ -219,7 +266,7 @@ impl ASFH {
             self.channels = self.buffer[0x9] as u16 + 1;
             self.ecc_ratio = [self.buffer[0xa], self.buffer[0xb]];
             self.srate = u32::from_be_bytes(self.buffer[0xc..0x10].try_into().unwrap());
-
+            self.seq = u64::from_be_bytes(self.buffer[0x10..0x18].try_into().unwrap());
             self.fsize = u32::from_be_bytes(self.buffer[0x18..0x1c].try_into().unwrap());
             self.crc32 = self.buffer[0x1c..0x20].try_into().unwrap();
         }
@@ -233,6 +280,26 @@ impl ASFH {
         return ParseResult::Complete;
     }
 
+    /** bytes_needed
+     * Gets how many more bytes the partially read header needs, as far as its read part tells
+     *   The count may grow as more of the header is read, e.g. ECC fields or a 64-bit frame length
+     * Returns: Remaining header bytes
+     */
+    pub fn bytes_needed(&self) -> usize {
+        let buf = &self.buffer;
+        if buf.len() < 9 { return 9 - buf.len(); }
+        let (profile, ecc, _, _) = decode_pfb(buf[0x8]);
+
+        let mut target = 32;
+        if COMPACT.contains(&profile) {
+            if buf.len() < 12 { return 12 - buf.len(); }
+            let ext = css_len(&buf[0x9..0xb]) - 2;
+            target = if ecc { 16 } else { 12 } + ext;
+        }
+        if buf.len() >= target && u32::from_be_bytes(buf[0x4..0x8].try_into().unwrap()) == u32::MAX { target += 8; }
+        return target.saturating_sub(buf.len());
+    }
+
     /** clear
      * Clears the buffer and resets the header
      */
@@ -246,4 +313,22 @@ pub enum ParseResult {
     Complete,
     Incomplete,
     ForceFlush,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn css_round_trips_every_sample_count() {
+        for fsize in compact::SAMPLES_LI {
+            for (channels, srate, force_flush) in [(1, 8000, false), (2, 48000, true), (64, 96000, false), (65, 44100, true), (4096, 11025, false)] {
+                let css = encode_css(channels, srate, fsize, force_flush);
+                assert_eq!(css.len(), css_len(&css));
+                assert_eq!(decode_css(css), (channels, srate, fsize, force_flush));
+            }
+        }
+        // Counts between the listed ones are rounded up
+        assert_eq!(decode_css(encode_css(2, 48000, 2049, false)).2, 2304);
+    }
 }
\ No newline at end of file
@@ -6,37 +6,217 @@
 
 mod reedsolo;
 pub use reedsolo::RSCodec;
+use crate::FradError;
+
+// Most check bytes per data byte worth correcting, beyond it a crafted header could stall the decoder
+pub const MAX_PARITY_PER_DATA: u16 = 3;
+
+/** is_plausible
+ * Checks if an ECC ratio is worth correcting with
+ *   Implausible ratios, e.g. 1 data and 254 check bytes, still have their check bytes stripped
+ * Parameters: ECC ratio
+ * Returns: Plausibility flag
+ */
+pub fn is_plausible(ratio: [u8; 2]) -> bool {
+    return ratio[0] != 0 && ratio[1] as u16 <= ratio[0] as u16 * MAX_PARITY_PER_DATA
+        && ratio[0] as u16 + ratio[1] as u16 <= 255;
+}
+
+/** EccParams
+ * Reed-Solomon code variant, for interop with other implementations
+ *   FrAD writes fcr 0, generator 2 and primitive polynomial 0x11d; fcr 1 is common elsewhere.
+ *   Frames do not record the variant, so the reader must be told which one was used
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EccParams {
+    pub fcr: u8,
+    pub prim: u16,
+    pub generator: u8,
+}
+
+impl Default for EccParams {
+    fn default() -> Self { return EccParams { fcr: 0, prim: 0x11d, generator: 2 }; }
+}
+
+/** get_codec
+ * Gets the cached Reed-Solomon codec, rebuilding it only if the ECC ratio has changed
+ * Parameters: Codec cache, ECC ratio
+ * Returns: Reed-Solomon codec for the ECC ratio
+ */
+pub fn get_codec(cache: &mut Option<RSCodec>, ratio: [u8; 2]) -> &RSCodec {
+    return get_codec_with_params(cache, ratio, EccParams::default());
+}
+
+/** get_codec_with_params
+ * Gets the cached Reed-Solomon codec, rebuilding it only if the ECC ratio or code variant has changed
+ * Parameters: Codec cache, ECC ratio, Code variant
+ * Returns: Reed-Solomon codec for the ECC ratio and variant
+ */
+pub fn get_codec_with_params(cache: &mut Option<RSCodec>, ratio: [u8; 2], params: EccParams) -> &RSCodec {
+    let (data_size, parity_size) = (ratio[0] as usize, ratio[1] as usize);
+    match cache {
+        Some(rs) if rs.data_size == data_size && rs.parity_size == parity_size
+            && (rs.fcr, rs.prim, rs.generator) == (params.fcr, params.prim, params.generator) => {},
+        _ => { *cache = Some(RSCodec::new_with_params(data_size, parity_size, params.fcr, params.prim, params.generator)); }
+    }
+    return cache.as_ref().unwrap();
+}
 
 /** encode_rs
  * Encodes data w. Reed-Solomon ECC
  * Parameters: Data, ECC ratio
- * Returns: Encoded data
+ * Returns: Encoded data, empty for empty data as there is nothing to protect
  */
 pub fn encode(data: Vec<u8>, ratio: [u8; 2]) -> Vec<u8> {
-    let (data_size, parity_size) = (ratio[0] as usize, ratio[1] as usize);
-    let rs: RSCodec = RSCodec::new_default(data_size, parity_size);
+    if data.is_empty() { return Vec::new(); }
+    return encode_with_codec(data, get_codec(&mut None, ratio));
+}
 
-    return data.chunks(data_size).map(|chunk| {
+/** encode_with_codec
+ * Encodes data w. Reed-Solomon ECC of a given codec, e.g. one cached across frames
+ * Parameters: Data, Reed-Solomon codec
+ * Returns: Encoded data, empty for empty data as there is nothing to protect
+ */
+pub fn encode_with_codec(data: Vec<u8>, rs: &RSCodec) -> Vec<u8> {
+    return data.chunks(rs.data_size).map(|chunk| {
         rs.encode(chunk)
     }).flatten().collect();
 }
 
 /** decode_rs
  * Decodes data and corrects errors w. Reed-Solomon ECC
- * Parameters: Data, ECC ratio
+ * Parameters: Data, ECC ratio, Repair flag
  * Returns: Decoded data
  */
 pub fn decode(data: Vec<u8>, ratio: [u8; 2], repair: bool) -> Vec<u8> {
-    let (data_size, parity_size) = (ratio[0] as usize, ratio[1] as usize);
+    if data.is_empty() { return Vec::new(); }
+    return decode_with_codec(data, get_codec(&mut None, ratio), repair);
+}
+
+/** decode_with_codec
+ * Decodes data and corrects errors w. Reed-Solomon ECC of a given codec, e.g. one cached across frames
+ * Parameters: Data, Reed-Solomon codec, Repair flag
+ * Returns: Decoded data
+ */
+pub fn decode_with_codec(data: Vec<u8>, rs: &RSCodec, repair: bool) -> Vec<u8> {
+    return decode_checked(data, rs, repair).0;
+}
+
+/** decode_checked
+ * Decodes data and corrects errors w. Reed-Solomon ECC, reporting unrecoverable blocks
+ *   Failed blocks are zero-filled, and a trailing block no longer than its parity holds no data and fails.
+ *   Blocks are not checked without repair, so they all pass unless too short
+ * Parameters: Data, Reed-Solomon codec, Repair flag
+ * Returns: Decoded data, Per-block success mask
+ */
+pub fn decode_checked(data: Vec<u8>, rs: &RSCodec, repair: bool) -> (Vec<u8>, Vec<bool>) {
+    let (data_size, parity_size) = (rs.data_size, rs.parity_size);
     let block_size = data_size + parity_size;
-    let rs: RSCodec = RSCodec::new_default(data_size, parity_size);
+    let mut mask = Vec::with_capacity(data.len().div_ceil(block_size));
 
-    return data.chunks(block_size).map(|chunk| {
-        if repair {
+    let decoded = data.chunks(block_size).map(|chunk| {
+        let (block, ok) = if chunk.len() <= parity_size { (Vec::new(), false) }
+        else if repair {
             match rs.decode(chunk, None) {
-                Ok(chunk) => chunk,
-                Err(_) => vec![0; chunk.len() - parity_size]
+                Ok(chunk) => (chunk, true),
+                Err(_) => (vec![0; chunk.len() - parity_size], false)
             }
-        } else { chunk.iter().take(chunk.len() - parity_size).cloned().collect() }
+        } else { (chunk[..chunk.len() - parity_size].to_vec(), true) };
+        mask.push(ok);
+        block
     }).flatten().collect();
+    return (decoded, mask);
+}
+
+/** try_decode
+ * Decodes data and corrects errors w. Reed-Solomon ECC, failing at the first unrecoverable block
+ * Parameters: Data, Reed-Solomon codec
+ * Returns: Decoded data, or the index of the unrecoverable block
+ */
+pub fn try_decode(data: Vec<u8>, rs: &RSCodec) -> Result<Vec<u8>, FradError> {
+    let (decoded, mask) = decode_checked(data, rs, true);
+    return match mask.iter().position(|&ok| !ok) { Some(block) => Err(FradError::EccDecodeFailed(block)), None => Ok(decoded) };
+}
+
+/** encode_with_params
+ * Encodes data w. Reed-Solomon ECC of a given code variant
+ * Parameters: Data, ECC ratio, Code variant
+ * Returns: Encoded data
+ */
+pub fn encode_with_params(data: Vec<u8>, ratio: [u8; 2], params: EccParams) -> Vec<u8> {
+    return encode_with_codec(data, get_codec_with_params(&mut None, ratio, params));
+}
+
+/** decode_with_params
+ * Decodes data and corrects errors w. Reed-Solomon ECC of a given code variant
+ * Parameters: Data, ECC ratio, Code variant, Repair flag
+ * Returns: Decoded data, Per-block success mask
+ */
+pub fn decode_with_params(data: Vec<u8>, ratio: [u8; 2], params: EccParams, repair: bool) -> (Vec<u8>, Vec<bool>) {
+    return decode_checked(data, get_codec_with_params(&mut None, ratio, params), repair);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::rng::SmallRng;
+
+    #[test]
+    fn fcr_variants_round_trip() {
+        let (ratio, mut rng) = ([96, 24], SmallRng::new(7));
+        let data: Vec<u8> = (0..1000).map(|_| rng.next_u64() as u8).collect();
+        let variants = [EccParams::default(), EccParams { fcr: 1, ..EccParams::default() }];
+        let encoded = variants.map(|params| encode_with_params(data.clone(), ratio, params));
+        assert_ne!(encoded[0], encoded[1]);
+
+        for (params, encoded) in variants.iter().zip(&encoded) {
+            // Up to half the check bytes of every block can be corrected
+            let mut corrupt = encoded.clone();
+            for block in corrupt.chunks_mut(120) {
+                for _ in 0..12 { let i = rng.next_u64() as usize % block.len(); block[i] ^= 0x5a; }
+            }
+            let (decoded, mask) = decode_with_params(corrupt, ratio, *params, true);
+            assert_eq!(decoded, data, "fcr {}", params.fcr);
+            assert!(mask.iter().all(|&ok| ok));
+        }
+
+        // Clean data of one variant decodes with the other only when the check bytes are not used
+        let other = variants[1];
+        assert_eq!(decode_with_params(encoded[0].clone(), ratio, other, false).0, data);
+        let mut corrupt = encoded[0].clone();
+        corrupt[0] ^= 0xff;
+        assert_ne!(decode_with_params(corrupt, ratio, other, true).0, data);
+    }
+
+    #[test]
+    fn empty_and_parity_only_data() {
+        let mut cache = None;
+        let rs = get_codec(&mut cache, [96, 24]);
+        // Empty data has nothing to protect, and nothing to correct either way
+        assert!(encode(Vec::new(), [96, 24]).is_empty() && encode_with_codec(Vec::new(), rs).is_empty());
+        for repair in [false, true] { assert_eq!(decode_checked(Vec::new(), rs, repair), (Vec::new(), Vec::new())); }
+        assert_eq!(try_decode(Vec::new(), rs), Ok(Vec::new()));
+
+        // A trailing block no longer than its parity holds no data and fails
+        let mut data = encode_with_codec(vec![1; 100], rs);
+        data.truncate(120 + 24);
+        for repair in [false, true] { assert_eq!(decode_checked(data.clone(), rs, repair), (vec![1; 96], vec![true, false])); }
+        assert_eq!(try_decode(data, rs), Err(FradError::EccDecodeFailed(1)));
+    }
+
+    #[test]
+    fn ratio_and_codec_variants_agree() {
+        let (ratio, mut rng, mut cache) = ([20, 8], SmallRng::new(11), None);
+        let data: Vec<u8> = (0..333).map(|_| rng.next_u64() as u8).collect();
+        let encoded = encode(data.clone(), ratio);
+        assert_eq!(encoded, encode_with_codec(data.clone(), get_codec(&mut cache, ratio)));
+        assert_eq!(encoded.len(), data.len() + data.len().div_ceil(20) * 8);
+
+        let mut corrupt = encoded.clone();
+        corrupt[3] ^= 0xff;
+        for repair in [false, true] {
+            assert_eq!(decode(corrupt.clone(), ratio, repair), decode_with_codec(corrupt.clone(), get_codec(&mut cache, ratio), repair));
+        }
+        assert_eq!(decode(corrupt, ratio, true), data);
+    }
 }
\ No newline at end of file
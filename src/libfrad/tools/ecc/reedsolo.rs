@@ -4,10 +4,13 @@
  * Copyright - Tomer Filiba
  */
 
+use crate::FradError;
+
 #[derive(Debug)]
 pub enum RSError {
     DivideByZero,
     MessageTooLong,
+    MessageTooShort,
     TooManyErasures,
     TooManyErrors,
     ErrorLocationFailure,
@@ -135,15 +138,19 @@ fn _rs_calc_syndromes(msg: &[u8], parity_size: usize, fcr: u8, generator: u8, gf
     return synd;
 }
 
-fn _rs_correct_errata(msg_in: &mut [u8], synd: &[u8], err_pos: &[usize], generator: u8, gf_exp: &[u8; 512], gf_log: &[u8; 256]) -> Result<(), RSError> {
+fn _rs_correct_errata(msg_in: &mut [u8], synd: &[u8], err_pos: &[usize], fcr: u8, generator: u8, gf_exp: &[u8; 512], gf_log: &[u8; 256]) -> Result<(), RSError> {
+    // Positions past the end of a short message would underflow
+    if err_pos.iter().any(|&p| p >= msg_in.len()) {
+        return Err(RSError::ErrorLocationFailure);
+    }
     let coef_pos: Vec<_> = err_pos.iter().map(|&p| msg_in.len() - 1 - p).collect();
     let err_loc = _rs_find_errata_locator(&coef_pos, generator, gf_exp, gf_log);
-    let err_eval = _rs_find_error_evaluator(&synd[1..], &err_loc, gf_exp, gf_log);
+    let synd_rev: Vec<u8> = synd.iter().rev().cloned().collect();
+    let err_eval = _rs_find_error_evaluator(&synd_rev, &err_loc, err_loc.len() - 1, gf_exp, gf_log);
 
     let mut xvec = vec![];
     for i in 0..coef_pos.len() {
-        let l = 255 - coef_pos[i];
-        xvec.push(_gf_pow(generator, l as u8, gf_exp, gf_log));
+        xvec.push(_gf_pow(generator, coef_pos[i] as u8, gf_exp, gf_log));
     }
 
     let mut evec = vec![0; msg_in.len()];
@@ -168,6 +175,9 @@ fn _rs_correct_errata(msg_in: &mut [u8], synd: &[u8], err_pos: &[usize], generat
         }
 
         let y = _gf_poly_eval(&err_eval, xi_inv, gf_exp, gf_log);
+        // Xi^(1-fcr), the power taken modulo the field order as it may be negative
+        let power = (gf_log[*xi as usize] as i32 * (1 - fcr as i32)).rem_euclid(255) as usize;
+        let y = _gf_mul(gf_exp[power], y, gf_exp, gf_log);
         let magnitude = _gf_div(y, err_loc_prime, gf_exp, gf_log)?;
         evec[err_pos[i]] = magnitude;
     }
@@ -221,13 +231,15 @@ fn _rs_find_error_locator(synd: &[u8], parity_size: usize, erase_loc: Option<&[u
 fn _rs_find_errata_locator(e_pos: &[usize], generator: u8, gf_exp: &[u8; 512], gf_log: &[u8; 256]) -> Vec<u8> {
     let mut e_loc = vec![1];
     for i in e_pos {
-        e_loc = _gf_poly_mul(&e_loc, &[1, _gf_pow(generator, *i as u8, gf_exp, gf_log)], gf_exp, gf_log);
+        e_loc = _gf_poly_mul(&e_loc, &[_gf_pow(generator, *i as u8, gf_exp, gf_log), 1], gf_exp, gf_log);
     }
     return e_loc;
 }
 
-fn _rs_find_error_evaluator(synd: &[u8], err_loc: &[u8], gf_exp: &[u8; 512], gf_log: &[u8; 256]) -> Vec<u8> {
-    let (_, remainder) = _gf_poly_div(_gf_poly_mul(synd, err_loc, gf_exp, gf_log), &[1], gf_exp, gf_log);
+fn _rs_find_error_evaluator(synd: &[u8], err_loc: &[u8], parity_size: usize, gf_exp: &[u8; 512], gf_log: &[u8; 256]) -> Vec<u8> {
+    let mut divisor = vec![0; parity_size + 2];
+    divisor[0] = 1;
+    let (_, remainder) = _gf_poly_div(_gf_poly_mul(synd, err_loc, gf_exp, gf_log), &divisor, gf_exp, gf_log);
     return remainder;
 }
 
@@ -263,8 +275,15 @@ fn _rs_correct_msg(msg_in: &mut [u8], parity_size: usize, fcr: u8, generator: u8
     if msg_in.len() > 255 {
         return Err(RSError::MessageTooLong);
     }
+    // A message no longer than its parity holds no data, e.g. a truncated last chunk
+    if msg_in.len() <= parity_size {
+        return Err(RSError::MessageTooShort);
+    }
 
     let erase_pos = erase_pos.unwrap_or(&[]);
+    if erase_pos.iter().any(|&p| p >= msg_in.len()) {
+        return Err(RSError::ErrorLocationFailure);
+    }
     for e_pos in erase_pos {
         msg_in[*e_pos] = 0;
     }
@@ -281,14 +300,15 @@ fn _rs_correct_msg(msg_in: &mut [u8], parity_size: usize, fcr: u8, generator: u8
     let mut err_pos = vec![];
     if !only_erasures {
         let fsynd = _rs_forney_syndromes(&synd, erase_pos, msg_in.len(), generator, gf_exp, gf_log);
-        let err_loc = _rs_find_error_locator(&fsynd, parity_size, Some(&_rs_find_errata_locator(erase_pos, generator, gf_exp, gf_log)), erase_pos.len(), gf_exp, gf_log)?;
-        err_pos = _rs_find_errors(&err_loc, msg_in.len(), generator, gf_exp, gf_log)?;
+        let err_loc = _rs_find_error_locator(&fsynd, parity_size, None, erase_pos.len(), gf_exp, gf_log)?;
+        let err_loc_rev: Vec<u8> = err_loc.iter().rev().cloned().collect();
+        err_pos = _rs_find_errors(&err_loc_rev, msg_in.len(), generator, gf_exp, gf_log)?;
         if err_pos.is_empty() {
             return Err(RSError::ErrorLocationFailure);
         }
     }
 
-    let corres = _rs_correct_errata(msg_in, &synd, &(erase_pos.iter().chain(err_pos.iter()).cloned().collect::<Vec<_>>()), generator, gf_exp, gf_log);
+    let corres = _rs_correct_errata(msg_in, &synd, &(erase_pos.iter().chain(err_pos.iter()).cloned().collect::<Vec<_>>()), fcr, generator, gf_exp, gf_log);
     if corres.is_err() {
         return Err(corres.err().unwrap());
     }
@@ -321,8 +341,8 @@ fn _gf_poly_div(mut dividend: Vec<u8>, divisor: &[u8], gf_exp: &[u8; 512], gf_lo
         }
     }
 
-    let separator = -(divisor.len() as isize) + 1;
-    return (dividend[..dividend.len()-(divisor.len()-1)].to_vec(), dividend[(separator as usize)..].to_vec());
+    let separator = dividend.len() - (divisor.len() - 1);
+    return (dividend[..separator].to_vec(), dividend[separator..].to_vec());
 }
 
 // Log/antilog tables and polynomial generator routines
@@ -468,6 +488,10 @@ impl RSCodec {
         return RSCodec::new(data_size, parity_size, 0, 0x11d, 2, 8);
     }
 
+    pub fn new_with_params(data_size: usize, parity_size: usize, fcr: u8, prim: u16, generator: u8) -> RSCodec {
+        return RSCodec::new(data_size, parity_size, fcr, prim, generator, 8);
+    }
+
     pub fn encode(&self, data: &[u8]) -> Vec<u8> {
         let chunk_size = self.data_size;
 
@@ -479,9 +503,8 @@ impl RSCodec {
         return chunks.into_iter().flatten().collect();
     }
 
-    pub fn decode(&self, data: &[u8], erase_pos: Option<&[usize]>) -> Result<Vec<u8>, RSError> {
-        let chunk_size = self.data_size + self.parity_size;
-        let enc_chunk_size = chunk_size + self.parity_size;
+    pub fn decode(&self, data: &[u8], erase_pos: Option<&[usize]>) -> Result<Vec<u8>, FradError> {
+        let enc_chunk_size = self.data_size + self.parity_size;
         let erase_pos = erase_pos.unwrap_or(&[]);
 
         let mut chunks = Vec::new();
@@ -495,10 +518,7 @@ impl RSCodec {
                 .collect();
 
             let chunk_dec = _rs_correct_msg(&mut chunk.to_vec(), self.parity_size, self.fcr, self.generator, Some(&chunk_erase_pos), false, &self.gf_exp, &self.gf_log);
-            if chunk_dec.is_err() {
-                return Err(chunk_dec.err().unwrap());
-            }
-            let (decoded, _, _) = chunk_dec.unwrap();
+            let Ok((decoded, _, _)) = chunk_dec else { return Err(FradError::EccDecodeFailed(chunk_index)); };
             chunks.push(decoded);
         }
 
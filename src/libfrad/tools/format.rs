@@ -0,0 +1,155 @@
+/**                            Frame Payload Layout                           */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Byte layout of the frame payload of each profile, for third-party codecs
+ *   The payload is the frame data after the header and before ECC
+ * Dependencies: miniz_oxide
+ */
+
+use crate::{
+    backend::SplitFront,
+    fourier::{profile4, profiles::{compact, COMPACT, LOSSLESS}, backend::signal::WindowType},
+    tools::asfh::ASFH,
+};
+use miniz_oxide::{deflate, inflate};
+
+// Optional predictor order byte leading the packed samples of lossless profiles
+pub const PREDICTOR_LEN: usize = 1;
+// Mode byte leading the integer samples of Profile 4: integer flag, byte width - 1 and predictor order
+pub const INT_MODE_LEN: usize = 1;
+pub const INT_MODE_FLAG: u8 = 0x80;
+// Marker and per-channel f64be value of a constant frame
+pub const DC_MARKER_LEN: usize = 1;
+pub const DC_VALUE_LEN: usize = 8;
+// Window marker and window index leading a windowed zlib stream
+pub const WINDOW_PREFIX_LEN: usize = 2;
+// u32be length of the side info, thresholds in Profile 1 and TNS LPC coefficients in Profile 2
+pub const SIDE_LEN_LEN: usize = 4;
+// Zlib compression level of the Compact profile encoders
+pub const ZLIB_LEVEL: u8 = 10;
+
+/** FrameLayout
+ * Frame payload split into its fields
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameLayout {
+    // [ Predictor order(optional) | Packed samples ]
+    Packed { order: u8, samples: Vec<u8> },
+    // [ Mode(0x80 | byte width - 1 << 2 | predictor order) | Integer samples ], Profile 4 only
+    Integer { bits: u16, order: u8, samples: Vec<u8> },
+    // Bit stream of [ Predictor order(2) | Rice partitions ] per channel, kept whole as nothing in it is byte-aligned
+    Rice(Vec<u8>),
+    // [ DC marker | DC value of each channel ]
+    Constant(Vec<f64>),
+    // [ Window marker | Window index ](non-rectangular only) + zlib([ Side info length | Side info | Frequencies ])
+    Transform { window: WindowType, side: Vec<u8>, freqs: Vec<u8> },
+}
+
+impl FrameLayout {
+    /** describe
+     * Describes the payload layout of a profile
+     * Parameters: Profile
+     * Returns: Layout description, empty for unknown profiles
+     */
+    pub fn describe(profile: u8) -> &'static str {
+        return match profile {
+            0 => "[ Predictor order u8 (optional) | DCT coefficients packed as floats in interleaved order ]",
+            4 => "[ Predictor order u8 (optional) | PCM samples packed as floats in interleaved order ] or \
+                  [ Mode u8: 0x80 | (byte width - 1) << 2 | predictor order | PCM samples as signed 8, 16, 24 or 32-bit integers in interleaved order ]",
+            3 => "Bit stream of [ Fixed predictor order (2 bits) | Partitions of 256 residuals: \
+                  [ Rice parameter (6 bits) | Rice codes, quotients from 32 on escaped to bit depth + 3 raw bits ] ] per channel",
+            1 => "[ 0x00 | DC value f64be per channel ] or [ 0x01 | Window index u8 ](optional) + \
+                  zlib([ Thresholds length u32be | Exp-Golomb thresholds | Exp-Golomb frequencies ])",
+            2 => "[ 0x00 | DC value f64be per channel ] or [ 0x01 | Window index u8 ](optional) + \
+                  zlib([ LPC length u32be | Exp-Golomb LPC coefficients | Exp-Golomb frequencies ])",
+            _ => "",
+        };
+    }
+
+    /** parse
+     * Splits a frame payload into its fields
+     * Parameters: Frame payload, Frame header
+     * Returns: Frame layout, None if the payload does not fit the profile
+     */
+    pub fn parse(frad: &[u8], asfh: &ASFH) -> Option<FrameLayout> {
+        if asfh.profile == 3 { return Some(FrameLayout::Rice(frad.to_vec())); }
+        if asfh.profile == 4 && asfh.channels != 0 && profile4::is_int_frame(frad, asfh.bit_depth_index, asfh.channels, asfh.fsize) {
+            let mode = frad[0];
+            let bits = (((mode >> 2) & 3) as u16 + 1) * 8;
+            let words = asfh.fsize as usize * asfh.channels as usize;
+            if frad.len() != words * bits as usize / 8 + INT_MODE_LEN { return None; }
+            return Some(FrameLayout::Integer { bits, order: mode & 0x03, samples: frad[INT_MODE_LEN..].to_vec() });
+        }
+        if LOSSLESS.contains(&asfh.profile) {
+            // The predictor byte is detected by the payload being one byte longer than the packed samples
+            let bits = asfh.bit_depth_bits() as usize;
+            if bits == 0 { return None; }
+            let packed = (asfh.fsize as usize * asfh.channels as usize * bits).div_ceil(8);
+            if frad.len() == packed + PREDICTOR_LEN { return Some(FrameLayout::Packed { order: frad[0], samples: frad[1..].to_vec() }); }
+            return Some(FrameLayout::Packed { order: 0, samples: frad.to_vec() });
+        }
+        if !COMPACT.contains(&asfh.profile) { return None; }
+
+        if frad.first() == Some(&compact::DC_MARKER) {
+            return Some(FrameLayout::Constant(frad[DC_MARKER_LEN..].chunks_exact(DC_VALUE_LEN).map(|x| f64::from_be_bytes(x.try_into().unwrap())).collect()));
+        }
+        let mut frad = frad.to_vec();
+        let window = compact::window_decode(&mut frad)?;
+        let mut data = inflate::decompress_to_vec_zlib(&frad).ok()?;
+        if data.len() < SIDE_LEN_LEN { return None; }
+        let side_len = u32::from_be_bytes(data.split_front(SIDE_LEN_LEN).try_into().unwrap()) as usize;
+        if data.len() < side_len { return None; }
+        let side = data.split_front(side_len);
+        return Some(FrameLayout::Transform { window, side, freqs: data });
+    }
+
+    /** serialise
+     * Joins the fields back into a frame payload, byte-identical to the encoders
+     * Returns: Frame payload
+     */
+    pub fn serialise(&self) -> Vec<u8> {
+        return match self {
+            FrameLayout::Packed { order: 0, samples } => samples.clone(),
+            FrameLayout::Packed { order, samples } => [*order].into_iter().chain(samples.iter().copied()).collect(),
+            FrameLayout::Integer { bits, order, samples } => {
+                let mode = INT_MODE_FLAG | ((bits / 8 - 1) as u8) << 2 | order;
+                [mode].into_iter().chain(samples.iter().copied()).collect()
+            },
+            FrameLayout::Rice(stream) => stream.clone(),
+            FrameLayout::Constant(dc) => [compact::DC_MARKER].into_iter().chain(dc.iter().flat_map(|x| x.to_be_bytes())).collect(),
+            FrameLayout::Transform { window, side, freqs } => {
+                let data: Vec<u8> = (side.len() as u32).to_be_bytes().into_iter().chain(side.iter().copied()).chain(freqs.iter().copied()).collect();
+                compact::window_encode(deflate::compress_to_vec_zlib(&data, ZLIB_LEVEL), *window)
+            }
+        };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fourier::backend::u8pack::{self, Predictor};
+
+    #[test]
+    fn integer_frames_parse_and_serialise() {
+        let mut asfh = ASFH::new();
+        (asfh.profile, asfh.channels, asfh.fsize) = (4, 2, 4);
+        for (bits, index) in [(8, 0), (16, 1), (24, 2), (32, 3)] {
+            asfh.bit_depth_index = index;
+            let pcm = [0.0, -1.0, 0.5, 0.25, -0.5, 0.0, 0.125, -0.125];
+            let frad = u8pack::pack_int(&pcm, bits, false, 2, Predictor::Delta).unwrap();
+            let layout = FrameLayout::parse(&frad, &asfh).unwrap();
+            assert_eq!(layout, FrameLayout::Integer { bits, order: 1, samples: frad[INT_MODE_LEN..].to_vec() });
+            assert_eq!(layout.serialise(), frad);
+        }
+    }
+
+    #[test]
+    fn float_frames_stay_packed() {
+        let mut asfh = ASFH::new();
+        (asfh.profile, asfh.channels, asfh.fsize, asfh.bit_depth_index) = (4, 1, 2, 1);
+        let frad = vec![0x80, 0x00, 0x3c, 0x00];
+        assert_eq!(FrameLayout::parse(&frad, &asfh), Some(FrameLayout::Packed { order: 0, samples: frad.clone() }));
+    }
+}
\ No newline at end of file
@@ -2,15 +2,90 @@
 /**
  * Copyright 2024 HaמuL
  * Description: FrAD Header Builder and Parser
+ *   Frame table of contents block, for seeking without scanning the frames
+ *   [ 0xf7 | Block length | Entry count | (Sample, Byte offset) * Entry count ]
+ *   Every number is a u64 stored as 10 big-endian septets, as in the index footer,
+ *   so parsers unaware of the block find no block signature inside it while skipping.
+ *   Samples count from the stream start, and byte offsets from the first byte after the header,
+ *   so the table stays valid when the header is rewritten.
  */
 
-use crate::{backend::SplitFront, common::SIGNATURE};
+use crate::{backend::SplitFront, common::SIGNATURE, fourier::FORMAT_VERSION, tools::index::{from_septets, to_septets, NUM_LEN}};
 
 const COMMENT: [u8; 2] = [0xfa, 0xaa];
 const IMAGE: [u8; 1] = [0xf5];
+const TOC: [u8; 1] = [0xf7];
 
 const COMMENT_HEAD_LENGTH: usize = 12;
 const IMAGE_HEAD_LENGTH: usize = 10;
+const TOC_HEAD_LENGTH: usize = 1 + NUM_LEN;
+
+// Reserved metadata key for structured CBOR metadata
+pub const STRUCTURED_KEY: &str = "FrAD-CBOR";
+// Reserved metadata keys for the silence added around the audio, in samples per channel
+pub const PREROLL_KEY: &str = "FrAD-Preroll";
+pub const POSTROLL_KEY: &str = "FrAD-Postroll";
+// Reserved metadata key for the original sample count per channel, pre-roll and post-roll excluded
+pub const SAMPLES_KEY: &str = "FrAD-Samples";
+
+/** PictureType
+ * APIC picture type of the embedded image
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PictureType {
+    Other, FileIcon, OtherFileIcon, FrontCover, BackCover, Leaflet, Media,
+    LeadArtist, Artist, Conductor, Band, Composer, Lyricist, RecordingLocation,
+    DuringRecording, DuringPerformance, ScreenCapture, BrightFish, Illustration,
+    BandLogo, PublisherLogo,
+}
+
+// Picture types in APIC code order, with their CLI names
+const PICTURE_TYPES: [(PictureType, &str); 21] = [
+    (PictureType::Other, "other"), (PictureType::FileIcon, "file-icon"), (PictureType::OtherFileIcon, "other-file-icon"),
+    (PictureType::FrontCover, "front-cover"), (PictureType::BackCover, "back-cover"), (PictureType::Leaflet, "leaflet"),
+    (PictureType::Media, "media"), (PictureType::LeadArtist, "lead-artist"), (PictureType::Artist, "artist"),
+    (PictureType::Conductor, "conductor"), (PictureType::Band, "band"), (PictureType::Composer, "composer"),
+    (PictureType::Lyricist, "lyricist"), (PictureType::RecordingLocation, "recording-location"),
+    (PictureType::DuringRecording, "during-recording"), (PictureType::DuringPerformance, "during-performance"),
+    (PictureType::ScreenCapture, "screen-capture"), (PictureType::BrightFish, "bright-fish"),
+    (PictureType::Illustration, "illustration"), (PictureType::BandLogo, "band-logo"), (PictureType::PublisherLogo, "publisher-logo"),
+];
+
+impl PictureType {
+    /** from_u8
+     * Gets the picture type of an APIC code
+     * Parameters: APIC code
+     * Returns: Picture type, None if the code is unknown
+     */
+    pub fn from_u8(code: u8) -> Option<PictureType> { return PICTURE_TYPES.get(code as usize).map(|&(x, _)| x); }
+
+    /** to_u8
+     * Gets the APIC code of the picture type
+     * Returns: APIC code
+     */
+    pub fn to_u8(self) -> u8 { return PICTURE_TYPES.iter().position(|&(x, _)| x == self).unwrap() as u8; }
+
+    /** from_name
+     * Gets the picture type of a name, e.g. front-cover
+     * Parameters: Picture type name
+     * Returns: Picture type, None if the name is unknown
+     */
+    pub fn from_name(name: &str) -> Option<PictureType> {
+        return PICTURE_TYPES.iter().find(|&&(_, n)| n == name).map(|&(x, _)| x);
+    }
+
+    /** name
+     * Gets the name of the picture type
+     * Returns: Picture type name
+     */
+    pub fn name(self) -> &'static str { return PICTURE_TYPES[self.to_u8() as usize].1; }
+
+    /** names
+     * Gets the names of every picture type
+     * Returns: Picture type names in APIC code order
+     */
+    pub fn names() -> Vec<&'static str> { return PICTURE_TYPES.iter().map(|&(_, n)| n).collect(); }
+}
 
 /** comment
  * Generates a comment block
@@ -37,10 +112,8 @@ fn comment(title: &str, data: &[u8]) -> Vec<u8> {
  * Parameters: Data, Picture type
  * Returns: Image block
  */
-fn image(data: Vec<u8>, itype: Option<u8>) -> Vec<u8> {
-    let mut itype = itype.unwrap_or(3);
-    itype = if itype > 20 { 3 } else { itype };
-    let apictype = [0b01000000 | itype];
+fn image(data: Vec<u8>, itype: PictureType) -> Vec<u8> {
+    let apictype = [0b01000000 | itype.to_u8()];
     let block_length = (data.len() + 10).to_be_bytes();
 
     let mut block = Vec::new();
@@ -52,12 +125,30 @@ fn image(data: Vec<u8>, itype: Option<u8>) -> Vec<u8> {
     return block;
 }
 
+/** toc
+ * Generates a table of contents block, sample position first as in the index footer
+ * Parameters: Sample position and byte offset of each frame
+ * Returns: Table of contents block
+ */
+fn toc(entries: &[(u64, u64)]) -> Vec<u8> {
+    let mut block = TOC.to_vec();
+    block.extend(to_septets((TOC_HEAD_LENGTH + NUM_LEN * (1 + entries.len() * 2)) as u64));
+    block.extend(to_septets(entries.len() as u64));
+    for &(sample, offset) in entries {
+        block.extend(to_septets(sample));
+        block.extend(to_septets(offset));
+    }
+
+    return block;
+}
+
 /** builder
- * Builds a header from metadata and image
- * Parameters: Metadata, Image
- * Returns: FrAD Header
+ * Builds a header from metadata, images and frame table of contents
+ *   [ Signature | Format version | Reserved(3) | Header length | Reserved(48) | Blocks ]
+ * Parameters: Metadata, Images with their picture types(empty images are skipped), Table of contents(empty for none)
+ * Returns: FrAD Header, stamped with the current format version
  */
-pub fn builder(meta: &Vec<(String, Vec<u8>)>, img: Vec<u8>, itype: Option<u8>) -> Vec<u8> {
+pub fn builder(meta: &Vec<(String, Vec<u8>)>, images: &[(Vec<u8>, PictureType)], contents: &[(u64, u64)]) -> Vec<u8> {
     let mut blocks = Vec::new();
 
     if !meta.is_empty() {
@@ -65,15 +156,19 @@ pub fn builder(meta: &Vec<(String, Vec<u8>)>, img: Vec<u8>, itype: Option<u8>) -
             blocks.extend(comment(&meta[i].0, &meta[i].1));
         }
     }
-    if !img.is_empty() {
-        blocks.extend(image(img, itype));
+    for (img, itype) in images {
+        if !img.is_empty() { blocks.extend(image(img.clone(), *itype)); }
+    }
+    if !contents.is_empty() {
+        blocks.extend(toc(contents));
     }
 
     let length = (64 + blocks.len() as u64).to_be_bytes().to_vec();
 
     let mut header = Vec::new();
     header.extend(SIGNATURE);
-    header.extend(vec![0; 4]);
+    header.push(FORMAT_VERSION);
+    header.extend(vec![0; 3]);
     header.extend(length);
     header.extend(vec![0; 48]);
     header.extend(blocks);
@@ -81,32 +176,91 @@ pub fn builder(meta: &Vec<(String, Vec<u8>)>, img: Vec<u8>, itype: Option<u8>) -
     return header;
 }
 
+/** builder_single
+ * Builds a header with at most one image, see builder
+ * Parameters: Metadata, Image, Picture type(default front cover), Table of contents(empty for none)
+ * Returns: FrAD Header
+ */
+pub fn builder_single(meta: &Vec<(String, Vec<u8>)>, img: Vec<u8>, itype: Option<PictureType>, contents: &[(u64, u64)]) -> Vec<u8> {
+    return builder(meta, &[(img, itype.unwrap_or(PictureType::FrontCover))], contents);
+}
+
+/** version
+ * Gets the format version of a header
+ * Parameters: Header, at least its first 5 bytes
+ * Returns: Format version, None if the data is not a header
+ */
+pub fn version(header: &[u8]) -> Option<u8> {
+    if header.len() < 5 || header[0..4] != SIGNATURE { return None; }
+    return Some(header[4]);
+}
+
 /** parser
- * Parses a header into metadata and image
+ * Parses a header into metadata, images and frame table of contents
+ *   Malformed blocks are skipped, and invalid UTF-8 in titles is replaced
  * Parameters: Header
- * Returns: Metadata in bytes, Image in bytes
+ * Returns: Metadata in bytes, Images in bytes with their picture types(front cover for unknown codes) in header order,
+ *          Sample position and byte offset of each frame(empty if no table)
  */
-pub fn parser(mut header: Vec<u8>) -> (Vec<(String, Vec<u8>)>, Vec<u8>, u8) {
-    let mut meta = Vec::new();
-    let (mut img, mut itype) = (Vec::new(), 0);
+pub fn parser(mut header: Vec<u8>) -> (Vec<(String, Vec<u8>)>, Vec<(Vec<u8>, PictureType)>, Vec<(u64, u64)>) {
+    let (mut meta, mut images, mut contents) = (Vec::new(), Vec::new(), Vec::new());
     while header.len() > 1 {
+        // Skip a byte if no valid block starts here or the block overruns the header
+        let block_length = match block_length(&header) {
+            Some(length) if length <= header.len() as u64 => length as usize,
+            _ => { header.split_front(1); continue; }
+        };
+
         if header[..2] == COMMENT {
-            let block_length = u48be_to_u64(&header[2..8]) as usize;
             let title_length = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+            if title_length > block_length - COMMENT_HEAD_LENGTH { header.split_front(1); continue; }
             let mut block = header.split_front(block_length).split_off(COMMENT_HEAD_LENGTH);
 
-            let title = String::from_utf8(block.split_front(title_length)).unwrap();
+            let title = String::from_utf8_lossy(&block.split_front(title_length)).into_owned();
             meta.push((title, block));
         }
-        else if header[..1] == IMAGE {
-            itype = header[1] & 0b00011111;
-            let block_length = u64::from_be_bytes(header[2..10].try_into().unwrap());
-
-            img = header.split_front(block_length as usize).split_off(IMAGE_HEAD_LENGTH);
+        else if header[..1] == TOC {
+            let count = from_septets(&header[TOC_HEAD_LENGTH..]);
+            if Some(block_length as u64) != count.checked_mul(NUM_LEN as u64 * 2).and_then(|x| x.checked_add((TOC_HEAD_LENGTH + NUM_LEN) as u64)) {
+                header.split_front(1); continue;
+            }
+            let block = header.split_front(block_length).split_off(TOC_HEAD_LENGTH + NUM_LEN);
+            contents = block.chunks_exact(NUM_LEN * 2).map(|x| (from_septets(&x[..NUM_LEN]), from_septets(&x[NUM_LEN..]))).collect();
+        }
+        else {
+            let itype = PictureType::from_u8(header[1] & 0b00011111).unwrap_or(PictureType::FrontCover);
+            images.push((header.split_front(block_length).split_off(IMAGE_HEAD_LENGTH), itype));
         }
-        else { header.split_front(1); }
     }
-    return (meta, img, itype);
+    return (meta, images, contents);
+}
+
+/** parser_single
+ * Parses a header keeping only its first image, see parser
+ * Parameters: Header
+ * Returns: Metadata in bytes, Image in bytes(empty if none), Picture type(front cover if none),
+ *          Sample position and byte offset of each frame
+ */
+pub fn parser_single(header: Vec<u8>) -> (Vec<(String, Vec<u8>)>, Vec<u8>, PictureType, Vec<(u64, u64)>) {
+    let (meta, images, contents) = parser(header);
+    let (img, itype) = images.into_iter().next().unwrap_or((Vec::new(), PictureType::FrontCover));
+    return (meta, img, itype, contents);
+}
+
+/** block_length
+ * Gets the length of the header block at the start of the data
+ * Parameters: Data starting with a block, at least 10 bytes, 11 for a table of contents
+ * Returns: Block length, None if not a valid block
+ */
+pub fn block_length(data: &[u8]) -> Option<u64> {
+    if data.len() < IMAGE_HEAD_LENGTH { return None; }
+    let (length, head_length) =
+        if data[..2] == COMMENT { (u48be_to_u64(&data[2..8]), COMMENT_HEAD_LENGTH) }
+        else if data[..1] == IMAGE { (u64::from_be_bytes(data[2..10].try_into().unwrap()), IMAGE_HEAD_LENGTH) }
+        else if data[..1] == TOC && data.len() >= TOC_HEAD_LENGTH { (from_septets(&data[1..TOC_HEAD_LENGTH]), TOC_HEAD_LENGTH + NUM_LEN) }
+        else { return None; };
+    if length < head_length as u64 { return None; }
+    return Some(length);
 }
 
 /** u48be_to_u64
@@ -117,4 +271,69 @@ pub fn parser(mut header: Vec<u8>) -> (Vec<(String, Vec<u8>)>, Vec<u8>, u8) {
 fn u48be_to_u64(data: &[u8]) -> u64 {
     if data.len() != 6 { return 0; }
     return u64::from_be_bytes([vec![0; 2], data.to_vec()].concat().try_into().unwrap());
+}
+
+/** set_structured
+ * Stores a structured value as CBOR under the reserved key, replacing any existing one
+ * Parameters: Metadata, Serialisable value
+ * Returns: Serialisation result
+ */
+#[cfg(feature = "cbor")]
+pub fn set_structured<T: serde::Serialize>(meta: &mut Vec<(String, Vec<u8>)>, value: &T) -> Result<(), ciborium::ser::Error<std::io::Error>> {
+    let mut data = Vec::new();
+    ciborium::into_writer(value, &mut data)?;
+    meta.retain(|(key, _)| key != STRUCTURED_KEY);
+    meta.push((STRUCTURED_KEY.to_string(), data));
+    return Ok(());
+}
+
+/** get_structured
+ * Reads the structured CBOR value from the reserved key
+ * Parameters: Metadata
+ * Returns: Deserialised value, None if not present
+ */
+#[cfg(feature = "cbor")]
+pub fn get_structured<T: serde::de::DeserializeOwned>(meta: &[(String, Vec<u8>)]) -> Option<Result<T, ciborium::de::Error<std::io::Error>>> {
+    let (_, data) = meta.iter().rev().find(|(key, _)| key == STRUCTURED_KEY)?;
+    return Some(ciborium::from_reader(data.as_slice()));
+}
+
+/** set_roll
+ * Records the pre-roll and post-roll silence lengths, replacing existing ones
+ * Parameters: Metadata, Pre-roll samples, Post-roll samples
+ */
+pub fn set_roll(meta: &mut Vec<(String, Vec<u8>)>, preroll: u64, postroll: u64) {
+    meta.retain(|(key, _)| key != PREROLL_KEY && key != POSTROLL_KEY);
+    if preroll != 0 { meta.push((PREROLL_KEY.to_string(), preroll.to_string().into_bytes())); }
+    if postroll != 0 { meta.push((POSTROLL_KEY.to_string(), postroll.to_string().into_bytes())); }
+}
+
+/** get_roll
+ * Reads the pre-roll and post-roll silence lengths
+ * Parameters: Metadata
+ * Returns: Pre-roll samples, Post-roll samples, 0 if not present or invalid
+ */
+pub fn get_roll(meta: &[(String, Vec<u8>)]) -> (u64, u64) {
+    let get = |name: &str| meta.iter().rev().find(|(key, _)| key == name)
+        .and_then(|(_, data)| String::from_utf8_lossy(data).trim().parse().ok()).unwrap_or(0);
+    return (get(PREROLL_KEY), get(POSTROLL_KEY));
+}
+
+/** set_total_samples
+ * Records the original sample count, replacing an existing one
+ * Parameters: Metadata, Samples per channel
+ */
+pub fn set_total_samples(meta: &mut Vec<(String, Vec<u8>)>, samples: u64) {
+    meta.retain(|(key, _)| key != SAMPLES_KEY);
+    meta.push((SAMPLES_KEY.to_string(), samples.to_string().into_bytes()));
+}
+
+/** get_total_samples
+ * Reads the original sample count, so the duration is known without scanning the frames
+ * Parameters: Metadata
+ * Returns: Samples per channel, None if not present or invalid
+ */
+pub fn get_total_samples(meta: &[(String, Vec<u8>)]) -> Option<u64> {
+    let (_, data) = meta.iter().rev().find(|(key, _)| key == SAMPLES_KEY)?;
+    return String::from_utf8_lossy(data).trim().parse().ok();
 }
\ No newline at end of file
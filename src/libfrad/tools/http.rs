@@ -0,0 +1,109 @@
+/**                             HTTP Range Reader                             */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Seekable reader for remote FrAD files over HTTP Range requests
+ * Dependencies: ureq
+ */
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Bytes fetched per range request
+const CHUNK_SIZE: u64 = 65536;
+
+/** http_error
+ * Converts a ureq error into an I/O error
+ * Parameters: ureq error
+ * Returns: I/O error
+ */
+fn http_error(e: ureq::Error) -> io::Error {
+    return io::Error::other(e.to_string());
+}
+
+/** RangeReader
+ * Struct for reading a remote file with HTTP Range requests
+ */
+pub struct RangeReader {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    pos: u64,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+impl RangeReader {
+    /** new
+     * Opens a remote file, checking the server supports range requests
+     * Parameters: URL
+     * Returns: Range reader
+     */
+    pub fn new(url: &str) -> io::Result<RangeReader> {
+        let agent = ureq::Agent::new();
+        let res = agent.get(url).set("Range", "bytes=0-0").call().map_err(http_error)?;
+        if res.status() != 206 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Server does not support range requests"));
+        }
+
+        // Content-Range: bytes 0-0/<length>
+        let len = res.header("Content-Range")
+            .and_then(|x| x.rsplit('/').next())
+            .and_then(|x| x.trim().parse::<u64>().ok())
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, "Unknown content length"))?;
+
+        return Ok(RangeReader { agent, url: url.to_string(), len, pos: 0, buffer: Vec::new(), buffer_start: 0 });
+    }
+
+    /** len
+     * Returns the length of the remote file
+     * Returns: Length in bytes
+     */
+    pub fn len(&self) -> u64 { return self.len; }
+
+    /** is_empty
+     * Checks if the remote file is empty
+     * Returns: Empty flag
+     */
+    pub fn is_empty(&self) -> bool { return self.len == 0; }
+
+    /** fetch
+     * Fetches a chunk starting at the current position into the buffer
+     */
+    fn fetch(&mut self) -> io::Result<()> {
+        let end = (self.pos + CHUNK_SIZE).min(self.len) - 1;
+        let res = self.agent.get(&self.url).set("Range", &format!("bytes={}-{}", self.pos, end)).call().map_err(http_error)?;
+        if res.status() != 206 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Server does not support range requests"));
+        }
+
+        self.buffer.clear();
+        res.into_reader().take(end - self.pos + 1).read_to_end(&mut self.buffer)?;
+        self.buffer_start = self.pos;
+        return Ok(());
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() { return Ok(0); }
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.pos < self.buffer_start || self.pos >= buffer_end { self.fetch()?; }
+
+        let offset = (self.pos - self.buffer_start) as usize;
+        let n = buf.len().min(self.buffer.len() - offset);
+        buf[..n].copy_from_slice(&self.buffer[offset..offset + n]);
+        self.pos += n as u64;
+        return Ok(n);
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.len.checked_add_signed(x),
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x),
+        };
+        self.pos = target.ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position"))?;
+        return Ok(self.pos);
+    }
+}
\ No newline at end of file
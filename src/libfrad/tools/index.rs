@@ -0,0 +1,136 @@
+/**                            Random-access Index                            */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Frame index footer for O(1) seeking
+ *   [ Signature "FrIX" | Entry count | (Sample, Byte offset) * Entry count | Footer length | "XIrF" ]
+ *   Every number is a u64 stored as 10 big-endian septets, so no byte in the footer
+ *   has the high bit set and it can never contain a frame signature;
+ *   decoders unaware of the footer skip it as trailing garbage.
+ *   Byte offsets count from the first byte of the encoded stream, i.e. after the file header.
+ */
+
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom};
+
+pub const INDEX_SIGN: [u8; 4] = *b"FrIX";
+pub const INDEX_END: [u8; 4] = *b"XIrF";
+pub(crate) const NUM_LEN: usize = 10;
+
+/** to_septets
+ * Encodes u64 into 10 bytes of 7 bits each
+ * Parameters: Number
+ * Returns: Septets
+ */
+pub(crate) fn to_septets(x: u64) -> [u8; NUM_LEN] {
+    let mut out = [0; NUM_LEN];
+    for (i, b) in out.iter_mut().enumerate() { *b = (x >> (7 * (NUM_LEN - 1 - i))) as u8 & 0x7f; }
+    return out;
+}
+
+/** from_septets
+ * Decodes 10 septets into u64
+ * Parameters: Septets
+ * Returns: Number
+ */
+pub(crate) fn from_septets(data: &[u8]) -> u64 {
+    return data.iter().take(NUM_LEN).fold(0, |acc, &b| (acc << 7) | (b & 0x7f) as u64);
+}
+
+/** build
+ * Builds an index footer
+ * Parameters: Sample position and byte offset of each frame
+ * Returns: Index footer
+ */
+pub fn build(entries: &[(u64, u64)]) -> Vec<u8> {
+    let mut footer = INDEX_SIGN.to_vec();
+    footer.extend(to_septets(entries.len() as u64));
+    for &(sample, offset) in entries {
+        footer.extend(to_septets(sample));
+        footer.extend(to_septets(offset));
+    }
+    let length = footer.len() + NUM_LEN + INDEX_END.len();
+    footer.extend(to_septets(length as u64));
+    footer.extend(INDEX_END);
+    return footer;
+}
+
+/** footer_len
+ * Gets the byte length of an index footer
+ * Parameters: Entry count
+ * Returns: Footer length
+ */
+pub fn footer_len(count: usize) -> usize {
+    return INDEX_SIGN.len() + NUM_LEN * (2 + count * 2) + INDEX_END.len();
+}
+
+// Footer length and end signature
+const TRAILER_LEN: usize = NUM_LEN + 4;
+
+/** footer_length
+ * Gets the footer length from the trailer at the end of a stream
+ * Parameters: Trailer, Stream length
+ * Returns: Footer length, None if the trailer is not an index trailer
+ */
+fn footer_length(trailer: &[u8], end: u64) -> Option<u64> {
+    if trailer[NUM_LEN..] != INDEX_END { return None; }
+    let length = from_septets(&trailer[..NUM_LEN]);
+    if length > end || length < (TRAILER_LEN + 4 + NUM_LEN) as u64 { return None; }
+    return Some(length);
+}
+
+/** parse_footer
+ * Parses a whole index footer
+ * Parameters: Footer
+ * Returns: Sample position and byte offset of each frame, None if the footer is invalid
+ */
+fn parse_footer(footer: &[u8]) -> Option<Vec<(u64, u64)>> {
+    if footer[..4] != INDEX_SIGN { return None; }
+    let count = from_septets(&footer[4..]) as usize;
+    let body = &footer[4 + NUM_LEN..footer.len() - TRAILER_LEN];
+    if body.len() != count.checked_mul(NUM_LEN * 2)? { return None; }
+    return Some(body.chunks_exact(NUM_LEN * 2).map(|x| (from_septets(&x[..NUM_LEN]), from_septets(&x[NUM_LEN..]))).collect());
+}
+
+/** read_slice
+ * Reads the index footer at the end of an in-memory stream
+ * Parameters: Stream
+ * Returns: Sample position and byte offset of each frame, None if there is no valid index
+ */
+pub fn read_slice(frad: &[u8]) -> Option<Vec<(u64, u64)>> {
+    let end = frad.len();
+    if end < TRAILER_LEN + 4 + NUM_LEN { return None; }
+    let length = footer_length(&frad[end - TRAILER_LEN..], end as u64)? as usize;
+    return parse_footer(&frad[end - length..]);
+}
+
+/** read
+ * Reads the index footer at the end of a stream
+ * Parameters: Seekable stream
+ * Returns: Sample position and byte offset of each frame, None if there is no valid index
+ */
+#[cfg(feature = "std")]
+pub fn read<R: Read + Seek>(reader: &mut R) -> Option<Vec<(u64, u64)>> {
+    // 1. Footer length from the trailer
+    let mut trailer = [0u8; TRAILER_LEN];
+    let end = reader.seek(SeekFrom::End(0)).ok()?;
+    if end < (TRAILER_LEN + 4 + NUM_LEN) as u64 { return None; }
+    reader.seek(SeekFrom::End(-(TRAILER_LEN as i64))).ok()?;
+    reader.read_exact(&mut trailer).ok()?;
+    let length = footer_length(&trailer, end)?;
+
+    // 2. Whole footer
+    let mut footer = vec![0u8; length as usize];
+    reader.seek(SeekFrom::End(-(length as i64))).ok()?;
+    reader.read_exact(&mut footer).ok()?;
+    return parse_footer(&footer);
+}
+
+/** lookup
+ * Finds the last frame starting at or before a sample position
+ * Parameters: Index entries, Sample position
+ * Returns: Sample position and byte offset of the frame, None if the index is empty
+ */
+pub fn lookup(entries: &[(u64, u64)], sample: u64) -> Option<(u64, u64)> {
+    let i = entries.partition_point(|&(s, _)| s <= sample);
+    return entries.get(i.saturating_sub(1)).copied();
+}
\ No newline at end of file
@@ -1,3 +1,11 @@
 pub mod asfh;
 pub mod ecc;
-pub mod head;
\ No newline at end of file
+pub mod format;
+pub mod head;
+pub mod index;
+pub mod requant;
+pub mod split;
+pub mod validate;
+pub mod wav;
+#[cfg(feature = "http")]
+pub mod http;
\ No newline at end of file
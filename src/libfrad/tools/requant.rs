@@ -0,0 +1,94 @@
+/**                               Re-quantiser                                */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Approximate loss level increase for Profile 1 frames
+ * Dependencies: miniz_oxide
+ */
+
+use crate::{backend::SplitFront, fourier::{profiles::compact, tools::p1tools}, FradError};
+use miniz_oxide::{deflate, inflate};
+
+/** requant
+ * Re-quantises a Profile 1 frame as if it were encoded with a higher loss level
+ *   This is an approximation; masking thresholds are scaled, not recalculated,
+ *   and rounding errors of the original quantisation are carried over,
+ *   so the result is not equivalent to re-encoding from PCM.
+ *   Constant frames hold no quantised data and are returned unchanged
+ * Parameters: Profile 1 frame data(without header and ECC), Loss level multiplier
+ * Returns: Re-quantised frame data, unchanged for a non-positive or non-finite multiplier,
+ *   or an error if the frame is not decodable
+ */
+pub fn requant(mut frad: Vec<u8>, factor: f64) -> Result<Vec<u8>, FradError> {
+    if !factor.is_finite() || factor <= 0.0 || frad.first() == Some(&compact::DC_MARKER) { return Ok(frad); }
+
+    // 1. Window prefix and zlib decompression
+    let window = compact::window_decode(&mut frad).ok_or(FradError::InvalidFrame(1))?;
+    let mut data = match inflate::decompress_to_vec_zlib(&frad) {
+        Ok(x) if x.len() >= 4 => x,
+        _ => { return Err(FradError::InvalidFrame(1)); }
+    };
+
+    // 2. Splitting thresholds and frequencies
+    let thres_len = u32::from_be_bytes(data.split_front(4).try_into().unwrap()) as usize;
+    if data.len() < thres_len { return Err(FradError::InvalidFrame(1)); }
+    let thres_gol = data.split_front(thres_len);
+
+    // 3. Scaling thresholds up and masked frequencies down by the multiplier
+    //    quant is a power function, so this is the same as scaling before quantisation
+    let thres: Vec<i64> = p1tools::exp_golomb_decode(thres_gol, usize::MAX).into_iter()
+        .map(|x| p1tools::quant(p1tools::dequant(x as f64) * factor).round() as i64).collect();
+    let freqs: Vec<i64> = p1tools::exp_golomb_decode(data, usize::MAX).into_iter()
+        .map(|x| p1tools::quant(p1tools::dequant(x as f64) / factor).round() as i64).collect();
+
+    // 4. Exponential Golomb-Rice encoding and connecting data
+    let thres_gol = p1tools::exp_golomb_encode(thres);
+    let freqs_gol = p1tools::exp_golomb_encode(freqs);
+    let frad: Vec<u8> = (thres_gol.len() as u32).to_be_bytes().to_vec().into_iter().chain(thres_gol).chain(freqs_gol).collect();
+
+    // 5. Zlib compression and window prefix
+    return Ok(compact::window_encode(deflate::compress_to_vec_zlib(&frad, 10), window));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fourier::{backend::{scratch::Scratch, signal::WindowType}, profile1};
+
+    /** frame
+     * Encodes a stereo Profile 1 frame of 2048 samples
+     */
+    fn frame(pcm: Vec<Vec<f64>>, window: WindowType, dc_frames: bool) -> (Vec<u8>, u16) {
+        let (frad, bit_depth_index, _, _) = profile1::analogue(pcm, 16, 48000, profile1::AnalogueParams { dc_frames, window, ..Default::default() }, &mut Scratch::new());
+        return (frad, bit_depth_index);
+    }
+
+    fn tone() -> Vec<Vec<f64>> {
+        return (0..2048).map(|i| vec![0.5 * (i as f64 * 0.05).sin(), 0.3 * (i as f64 * 0.013).sin()]).collect();
+    }
+
+    #[test]
+    fn requant_keeps_the_window() {
+        for window in [WindowType::Rectangular, WindowType::Hamming] {
+            let (frad, bit_depth_index) = frame(tone(), window, false);
+            let requantised = requant(frad.clone(), 4.0).unwrap();
+            assert!(requantised.len() < frad.len());
+            let (mut a, mut b) = (frad, requantised.clone());
+            assert_eq!(compact::window_decode(&mut a), compact::window_decode(&mut b));
+            assert!(profile1::digital(requantised, bit_depth_index, 2, 48000, 2048, false, &mut None).is_ok());
+        }
+    }
+
+    #[test]
+    fn requant_passes_constant_frames() {
+        let (frad, _) = frame(vec![vec![0.25, -0.25]; 2048], WindowType::Hamming, true);
+        assert_eq!(frad[0], compact::DC_MARKER);
+        assert_eq!(requant(frad.clone(), 4.0).unwrap(), frad);
+    }
+
+    #[test]
+    fn requant_rejects_undecodable_frames() {
+        assert_eq!(requant(vec![0x78, 0x01, 0x02], 2.0), Err(FradError::InvalidFrame(1)));
+        assert_eq!(requant(vec![compact::WINDOW_MARKER, 0xff, 0x78], 2.0), Err(FradError::InvalidFrame(1)));
+        assert_eq!(requant(vec![0x78, 0x01, 0x02], f64::NAN), Ok(vec![0x78, 0x01, 0x02]));
+    }
+}
\ No newline at end of file
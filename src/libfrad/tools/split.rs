@@ -0,0 +1,116 @@
+/**                             Channel Splitter                              */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Splits a multichannel FrAD stream into mono streams, for stem extraction
+ */
+
+use crate::{
+    backend::{Endian::Big, PCMFormat, SignFind},
+    common::{FRM_SIGN, SIGNATURE},
+    decoder::{DecodeResult, Decoder},
+    encoder::Encoder,
+    error::FradError,
+    fourier::{remap_depth_index, AVAILABLE},
+    tools::{asfh::{ASFH, ParseResult::Complete}, head},
+};
+
+/** first_header
+ * Reads the header of the first frame of a stream
+ * Parameters: FrAD stream, Start of the frames
+ * Returns: Frame header, None if the stream has no frame
+ */
+fn first_header(frad: &[u8], start: usize) -> Option<ASFH> {
+    let pos = start + frad[start..].find_sign(&FRM_SIGN)?;
+    let mut asfh = ASFH::new();
+    asfh.buffer = FRM_SIGN.to_vec();
+    let mut head = frad[pos + FRM_SIGN.len()..frad.len().min(pos + 64)].to_vec();
+    return match asfh.read(&mut head) { Complete => Some(asfh), _ => None };
+}
+
+/** restart
+ * Flushes the mono encoders and opens new ones for a segment
+ *   Each encoder keeps the profile, bit depth, frame size, ECC and overlap of the segment's first frame
+ * Parameters: Mono encoders, Output streams, File header, Frame header, Channel count
+ * Returns: Encoder setup result
+ */
+fn restart(encoders: &mut Vec<Encoder>, outputs: &mut Vec<Vec<u8>>, header: &[u8], asfh: &ASFH, channels: usize) -> Result<(), FradError> {
+    for (encoder, output) in encoders.iter_mut().zip(outputs.iter_mut()) { output.extend(encoder.flush()?.buf); }
+    while outputs.len() < channels { outputs.push(header.to_vec()); }
+    if !AVAILABLE.contains(&asfh.profile) { return Err(FradError::InvalidProfile(asfh.profile)); }
+
+    encoders.clear();
+    for _ in 0..channels {
+        let mut encoder = Encoder::new(asfh.profile, PCMFormat::F64(Big))?;
+        encoder.set_srate(asfh.srate)?;
+        encoder.set_channels(1)?;
+        encoder.set_bit_depth(asfh.bit_depth_bits())?;
+        encoder.set_frame_size(asfh.fsize)?;
+        if asfh.ecc { encoder.set_ecc(true, asfh.ecc_ratio)?; }
+        encoder.set_ecc_params(asfh.ecc_params);
+        encoder.set_overlap_ratio(asfh.overlap_ratio);
+        encoder.set_little_endian(asfh.endian);
+        encoder.set_frame_sequence(asfh.seq != 0);
+        encoders.push(encoder);
+    }
+    return Ok(());
+}
+
+/** write
+ * Encodes every channel of decoded PCM to its mono stream
+ * Parameters: Mono encoders, Output streams, Decoded PCM
+ * Returns: Encoding result
+ */
+fn write(encoders: &mut [Encoder], outputs: &mut [Vec<u8>], decoded: &DecodeResult) -> Result<(), FradError> {
+    for (c, (encoder, output)) in encoders.iter_mut().zip(outputs.iter_mut()).enumerate() {
+        let bytes: Vec<u8> = decoded.pcm.iter().flat_map(|s| s[c].to_be_bytes()).collect();
+        output.extend(encoder.process(&bytes)?.buf);
+    }
+    return Ok(());
+}
+
+/** split_channels
+ * Decodes a FrAD stream and re-encodes each channel as its own mono stream,
+ *   keeping the file header without its table of contents, as the frames move.
+ *   LOSSLESS profiles stay bit-exact per channel; COMPACT ones are re-encoded at the default loss level.
+ *   Channels missing from a segment get no audio for it
+ * Parameters: FrAD stream, with or without the file header
+ * Returns: Mono FrAD streams in channel order
+ */
+pub fn split_channels(frad: &[u8]) -> Result<Vec<Vec<u8>>, FradError> {
+    // 1. File header
+    let (mut header, mut start) = (Vec::new(), 0);
+    if frad.len() >= 16 && frad[0..4] == SIGNATURE {
+        start = (u64::from_be_bytes(frad[8..16].try_into().unwrap()) as usize).clamp(64, frad.len());
+        let (meta, images, _) = head::parser(frad[..start].to_vec());
+        header = head::builder(&meta, &images, &[]);
+    }
+
+    // 2. Header of the first segment, read ahead as the decoder reports it only for the next one
+    let mut next = first_header(frad, start);
+    if let (Some(asfh), Some(version)) = (next.as_mut(), head::version(frad)) {
+        if let Some(index) = remap_depth_index(version, asfh.profile, asfh.bit_depth_index) { asfh.bit_depth_index = index; }
+    }
+
+    // 3. Decode and re-encode, restarting the encoders after every format change
+    let (mut encoders, mut outputs) = (Vec::new(), Vec::new());
+    let mut decoder = Decoder::new(true);
+    decoder.set_output_format(Some(PCMFormat::F64(Big)));
+    let mut decoded = decoder.process(frad);
+    loop {
+        if !decoded.is_empty() {
+            if let Some(asfh) = next.take() { restart(&mut encoders, &mut outputs, &header, &asfh, decoded.channels())?; }
+            write(&mut encoders, &mut outputs, &decoded)?;
+        }
+        if decoded.crit { next = Some(decoder.get_asfh().clone()); }
+        if decoder.is_empty() { break; }
+        decoded = decoder.process(&[]);
+    }
+    let decoded = decoder.flush();
+    if !decoded.is_empty() {
+        if let Some(asfh) = next.take() { restart(&mut encoders, &mut outputs, &header, &asfh, decoded.channels())?; }
+        write(&mut encoders, &mut outputs, &decoded)?;
+    }
+
+    for (encoder, output) in encoders.iter_mut().zip(outputs.iter_mut()) { output.extend(encoder.flush()?.buf); }
+    return Ok(outputs);
+}
\ No newline at end of file
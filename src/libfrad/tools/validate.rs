@@ -0,0 +1,153 @@
+/**                             Stream Validator                              */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Structural health check of a whole FrAD stream
+ */
+
+use crate::{
+    backend::SignFind,
+    common::{crc16_ansi, crc32, FRM_SIGN, SIGNATURE},
+    fourier::{profiles::{COMPACT, LOSSLESS}, AVAILABLE, SEGMAX},
+    tools::{asfh::{ASFH, ParseResult::{Complete, ForceFlush, Incomplete}}, index},
+};
+
+/** StreamEnd
+ * How a stream ends
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamEnd {
+    // No frame found at all
+    Empty,
+    // Last frame is followed by a force-flush frame or a lossless terminator
+    Terminated,
+    // Last frame is complete, but no terminator follows
+    Unterminated,
+    // Last header or frame is cut short
+    Truncated,
+}
+
+/** ValidationReport
+ * Structural issues of a stream, frames are counted from 0 excluding terminators
+ */
+#[derive(Clone, Debug)]
+pub struct ValidationReport {
+    pub frames: usize,
+    // Bytes outside any frame, the file header and the index footer excluded
+    pub skipped_bytes: usize,
+    pub has_index: bool,
+    pub crc_mismatches: Vec<usize>,
+    // Frames with an invalid profile, bit depth, sample rate or sample count
+    pub implausible_frames: Vec<usize>,
+    // Frames shorter than the previous one that are not the last of their segment
+    pub short_frames: Vec<usize>,
+    // (Frame, Old profile, New profile)
+    pub profile_switches: Vec<(usize, u8, u8)>,
+    // (Frame, Old sample rate and channels, New sample rate and channels)
+    pub format_changes: Vec<(usize, (u32, u16), (u32, u16))>,
+    // (Frame, ECC enabled)
+    pub ecc_transitions: Vec<(usize, bool)>,
+    pub end: StreamEnd,
+}
+
+impl ValidationReport {
+    /** is_clean
+     * Check if the stream has no structural errors,
+     *   profile, format and ECC changes are legal and only informational
+     * Returns: Clean flag
+     */
+    pub fn is_clean(&self) -> bool {
+        return self.skipped_bytes == 0 && self.crc_mismatches.is_empty() && self.implausible_frames.is_empty()
+            && self.short_frames.is_empty() && self.end != StreamEnd::Truncated;
+    }
+}
+
+/** implausible
+ * Check if a frame header holds values no encoder writes
+ * Parameters: Frame header
+ * Returns: Implausible flag
+ */
+fn implausible(asfh: &ASFH) -> bool {
+    return !AVAILABLE.contains(&asfh.profile) || asfh.bit_depth_bits() == 0
+        || asfh.srate == 0 || asfh.fsize == 0 || asfh.fsize > SEGMAX[asfh.profile as usize] || asfh.frmbytes == 0;
+}
+
+/** validate
+ * Scans every frame of a stream and reports structural issues that per-frame CRC cannot see
+ * Parameters: FrAD stream, with or without the file header
+ * Returns: Validation report
+ */
+pub fn validate(frad: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport {
+        frames: 0, skipped_bytes: 0, has_index: false,
+        crc_mismatches: Vec::new(), implausible_frames: Vec::new(), short_frames: Vec::new(),
+        profile_switches: Vec::new(), format_changes: Vec::new(), ecc_transitions: Vec::new(),
+        end: StreamEnd::Empty,
+    };
+
+    // 1. Skip the file header and cut the index footer
+    let mut pos = 0;
+    if frad.len() >= 16 && frad[0..4] == SIGNATURE {
+        pos = (u64::from_be_bytes(frad[8..16].try_into().unwrap()) as usize).clamp(64, frad.len());
+    }
+    let mut end = frad.len();
+    if let Some(entries) = index::read_slice(frad) {
+        report.has_index = true;
+        end -= index::footer_len(entries.len());
+    }
+
+    // 2. Walk the frames
+    let (mut prev, mut pending_short, mut terminated): (Option<ASFH>, Option<usize>, bool) = (None, None, false);
+    while pos < end {
+        let Some(i) = frad[pos..end].find_sign(&FRM_SIGN) else {
+            report.skipped_bytes += end - pos; break;
+        };
+        report.skipped_bytes += i; pos += i;
+
+        // 2.1. Header, fed from a copy as ASFH consumes its input
+        let mut asfh = ASFH::new();
+        asfh.buffer = FRM_SIGN.to_vec();
+        let mut head = frad[pos + FRM_SIGN.len()..end.min(pos + 64)].to_vec();
+        let result = asfh.read(&mut head);
+        pos += asfh.buffer.len();
+
+        let terminator = match result {
+            Incomplete => { report.end = StreamEnd::Truncated; break; },
+            ForceFlush => true,
+            Complete => LOSSLESS.contains(&asfh.profile) && asfh.frmbytes == 0 && asfh.fsize == 0,
+        };
+        if terminator {
+            report.end = StreamEnd::Terminated;
+            (pending_short, terminated) = (None, true);
+            continue;
+        }
+
+        // 2.2. Frame body
+        if end - pos < asfh.frmbytes as usize { report.end = StreamEnd::Truncated; break; }
+        let body = &frad[pos..pos + asfh.frmbytes as usize];
+        pos += body.len();
+        let no = report.frames;
+        report.frames += 1;
+        report.end = StreamEnd::Unterminated;
+
+        if implausible(&asfh) { report.implausible_frames.push(no); }
+        if LOSSLESS.contains(&asfh.profile) && crc32(body) != asfh.crc32
+        || COMPACT.contains(&asfh.profile) && asfh.ecc && crc16_ansi(body) != asfh.crc16 {
+            report.crc_mismatches.push(no);
+        }
+
+        // 2.3. Changes from the previous frame of the segment
+        if let Some(p) = &prev {
+            let segment = !terminated && asfh.profile == p.profile && asfh.criteq(p);
+            if asfh.profile != p.profile { report.profile_switches.push((no, p.profile, asfh.profile)); }
+            if !asfh.criteq(p) { report.format_changes.push((no, (p.srate, p.channels), (asfh.srate, asfh.channels))); }
+            if asfh.ecc != p.ecc { report.ecc_transitions.push((no, asfh.ecc)); }
+
+            // A short frame may only end its segment
+            if let Some(short) = pending_short.take() { if segment { report.short_frames.push(short); } }
+            if segment && asfh.fsize < p.fsize { pending_short = Some(no); }
+        }
+        (prev, terminated) = (Some(asfh), false);
+    }
+
+    return report;
+}
\ No newline at end of file
@@ -0,0 +1,357 @@
+/**                               WAV Container                               */
+/**
+ * Copyright 2024 HaמuL
+ * Description: RIFF/WAVE container reader and writer for PCM
+ *   [ "RIFF" | RIFF size | "WAVE" | "fmt " | 16 | Format tag | Channels | Sample rate
+ *     | Byte rate | Block align | Bits | "data" | Data size | PCM ]
+ *   RF64 carries the 64-bit sizes in a "ds64" chunk, Wave64 uses GUID chunk IDs with 64-bit sizes
+ */
+
+use crate::{backend::{Endian::Little, PCMFormat}, FradError};
+#[cfg(feature = "std")]
+use crate::Decoder;
+#[cfg(feature = "std")]
+use std::io::{copy, sink, Read, Seek, SeekFrom, Write};
+
+pub const HEADER_LEN: usize = 44;
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+#[cfg(feature = "std")]
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+// Wave64 GUIDs, chunk GUIDs other than "riff" are the FourCC followed by this suffix
+const W64_RIFF: [u8; 16] = [0x72, 0x69, 0x66, 0x66, 0x2e, 0x91, 0xcf, 0x11, 0xa5, 0xd6, 0x28, 0xdb, 0x04, 0xc1, 0x00, 0x00];
+#[cfg(feature = "std")]
+const W64_SUFFIX: [u8; 12] = [0xf3, 0xac, 0xd3, 0x11, 0x8c, 0xd1, 0x00, 0xc0, 0x4f, 0x8e, 0xdb, 0x8a];
+
+/** WavInfo
+ * Stream format of a WAV input
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct WavInfo {
+    pub srate: u32,
+    pub channels: u16,
+    pub fmt: PCMFormat,
+    // Size of the data chunk in bytes, None if the writer streamed it without a size
+    pub data_len: Option<u64>,
+}
+
+/** is_wav
+ * Checks the magic of a RIFF/WAVE, RF64 or Wave64 stream
+ * Parameters: First bytes of the stream, at least 12 for RIFF and 16 for Wave64
+ * Returns: WAV flag
+ */
+pub fn is_wav(head: &[u8]) -> bool {
+    return head.len() >= 12 && (&head[0..4] == b"RIFF" || &head[0..4] == b"RF64") && &head[8..12] == b"WAVE"
+        || head.len() >= 16 && head[0..16] == W64_RIFF;
+}
+
+/** pcm_format
+ * Maps a WAV format tag and container width to a PCM format
+ * Parameters: Format tag, Bits per sample of the container
+ * Returns: Little-endian PCM format, None if unsupported
+ */
+#[cfg(feature = "std")]
+fn pcm_format(tag: u16, bits: u32) -> Option<PCMFormat> {
+    return match (tag, bits) {
+        (FORMAT_PCM, 8) => Some(PCMFormat::U8),
+        (FORMAT_PCM, 16) => Some(PCMFormat::I16(Little)),
+        (FORMAT_PCM, 24) => Some(PCMFormat::I24(Little)),
+        (FORMAT_PCM, 32) => Some(PCMFormat::I32(Little)),
+        (FORMAT_PCM, 64) => Some(PCMFormat::I64(Little)),
+        (FORMAT_FLOAT, 32) => Some(PCMFormat::F32(Little)),
+        (FORMAT_FLOAT, 64) => Some(PCMFormat::F64(Little)),
+        _ => None
+    };
+}
+
+/** parse_fmt
+ * Parses the body of a "fmt " chunk
+ * Parameters: Chunk body
+ * Returns: Sample rate, Channel count and PCM format
+ */
+#[cfg(feature = "std")]
+fn parse_fmt(body: &[u8]) -> Result<(u32, u16, PCMFormat), FradError> {
+    if body.len() < 16 { return Err(FradError::InvalidWav("fmt chunk is too short")); }
+    let mut tag = u16::from_le_bytes([body[0], body[1]]);
+    let channels = u16::from_le_bytes([body[2], body[3]]);
+    let srate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+    let align = u16::from_le_bytes([body[12], body[13]]);
+    if channels == 0 || srate == 0 { return Err(FradError::InvalidWav("zero channels or sample rate")); }
+
+    // WAVE_FORMAT_EXTENSIBLE keeps the actual tag at the head of its subformat GUID
+    if tag == FORMAT_EXTENSIBLE {
+        if body.len() < 26 { return Err(FradError::InvalidWav("extensible fmt chunk is too short")); }
+        tag = u16::from_le_bytes([body[24], body[25]]);
+    }
+    // The container width decides the sample layout, e.g. 24 valid bits in 32-bit containers are read as 32-bit
+    let fmt = pcm_format(tag, align as u32 * 8 / channels as u32).ok_or(FradError::InvalidWav("unsupported sample format"))?;
+    return Ok((srate, channels, fmt));
+}
+
+/** read_header
+ * Reads a WAV header up to the start of the PCM data
+ * Parameters: Reader positioned at the start of the stream
+ * Returns: WAV stream format, the reader is left at the first PCM byte
+ */
+#[cfg(feature = "std")]
+pub fn read_header<R: Read>(reader: &mut R) -> Result<WavInfo, FradError> {
+    let io = |e: std::io::Error| FradError::Io(e.kind());
+    let skip = |reader: &mut R, n: u64| copy(&mut reader.take(n), &mut sink()).map_err(io);
+
+    // 1. RIFF/RF64 or Wave64 magic
+    let mut magic = [0u8; 12];
+    reader.read_exact(&mut magic).map_err(io)?;
+    let w64 = magic == W64_RIFF[..12];
+    if w64 {
+        let mut rest = [0u8; 28];
+        reader.read_exact(&mut rest).map_err(io)?;
+        if rest[0..4] != W64_RIFF[12..] || &rest[12..16] != b"wave" || rest[16..] != W64_SUFFIX {
+            return Err(FradError::InvalidWav("not a Wave64 stream"));
+        }
+    }
+    else if !is_wav(&magic) { return Err(FradError::InvalidWav("not a RIFF/WAVE, RF64 or Wave64 stream")); }
+
+    // 2. Walk the chunks until the data chunk
+    let (mut format, mut ds64_data): (Option<(u32, u16, PCMFormat)>, Option<u64>) = (None, None);
+    loop {
+        // 2.1. Chunk ID and body size, Wave64 sizes include the 24-byte chunk header and pad to 8 bytes
+        let (id, size, pad) = if w64 {
+            let mut chunk = [0u8; 24];
+            reader.read_exact(&mut chunk).map_err(io)?;
+            let size = u64::from_le_bytes(chunk[16..24].try_into().unwrap()).saturating_sub(24);
+            let id: [u8; 4] = if chunk[4..16] == W64_SUFFIX { chunk[0..4].try_into().unwrap() } else { [0; 4] };
+            (id, Some(size), (8 - size % 8) % 8)
+        }
+        else {
+            let mut chunk = [0u8; 8];
+            reader.read_exact(&mut chunk).map_err(io)?;
+            let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+            let id: [u8; 4] = chunk[0..4].try_into().unwrap();
+            // RF64 and streaming writers leave the data size at u32::MAX
+            let size = if size == u32::MAX && &id == b"data" { ds64_data } else { Some(size as u64) };
+            (id, size, size.unwrap_or(0) % 2)
+        };
+
+        // 2.2. Chunk body
+        match &id {
+            b"data" => {
+                let (srate, channels, fmt) = format.ok_or(FradError::InvalidWav("data chunk before fmt chunk"))?;
+                return Ok(WavInfo { srate, channels, fmt, data_len: size });
+            },
+            b"fmt " => {
+                let mut body = vec![0u8; size.unwrap_or(0).min(1024) as usize];
+                reader.read_exact(&mut body).map_err(io)?;
+                format = Some(parse_fmt(&body)?);
+                skip(reader, size.unwrap_or(0) - body.len() as u64 + pad)?;
+            },
+            b"ds64" if !w64 => {
+                let mut body = [0u8; 24];
+                reader.read_exact(&mut body).map_err(io)?;
+                ds64_data = Some(u64::from_le_bytes(body[8..16].try_into().unwrap()));
+                skip(reader, size.unwrap_or(0).saturating_sub(24) + pad)?;
+            },
+            _ => { skip(reader, size.unwrap_or(0) + pad)?; }
+        }
+    }
+}
+
+/** check_format
+ * Checks explicitly set stream settings against a WAV header, which takes precedence over them
+ *   A wrong channel count would interleave the PCM wrongly, so it must not be silently used
+ * Parameters: WAV stream format, Sample rate and Channel count, 0 if not set
+ * Returns: Error naming the first setting that conflicts with the header
+ */
+pub fn check_format(info: &WavInfo, srate: u32, channels: u16) -> Result<(), FradError> {
+    if srate != 0 && srate != info.srate { return Err(FradError::WavHeaderConflict("Sample rate", srate, info.srate)); }
+    if channels != 0 && channels != info.channels { return Err(FradError::WavHeaderConflict("Channel count", channels as u32, info.channels as u32)); }
+    return Ok(());
+}
+
+/** wav_format
+ * Maps a PCM format to its little-endian WAV counterpart
+ * Parameters: PCM format
+ * Returns: WAV format tag and PCM format to write, None if WAV cannot hold the format
+ */
+pub fn wav_format(fmt: PCMFormat) -> Option<(u16, PCMFormat)> {
+    return match fmt {
+        PCMFormat::U8 => Some((FORMAT_PCM, PCMFormat::U8)),
+        PCMFormat::I16(_) => Some((FORMAT_PCM, PCMFormat::I16(Little))),
+        PCMFormat::I24(_) => Some((FORMAT_PCM, PCMFormat::I24(Little))),
+        PCMFormat::I32(_) => Some((FORMAT_PCM, PCMFormat::I32(Little))),
+        PCMFormat::F32(_) => Some((FORMAT_FLOAT, PCMFormat::F32(Little))),
+        PCMFormat::F64(_) => Some((FORMAT_FLOAT, PCMFormat::F64(Little))),
+        _ => None
+    };
+}
+
+/** header
+ * Builds a canonical 44-byte WAV header
+ * Parameters: WAV format tag, PCM format, Sample rate, Channel count, Data size in bytes
+ * Returns: WAV header, sizes saturate at u32::MAX as streaming writers do
+ */
+pub fn header(tag: u16, fmt: PCMFormat, srate: u32, channels: u16, data_len: u64) -> Vec<u8> {
+    let align = channels as u32 * fmt.bit_depth() as u32 / 8;
+    let riff_len = (data_len + data_len % 2 + HEADER_LEN as u64 - 8).min(u32::MAX as u64) as u32;
+
+    let mut head = Vec::with_capacity(HEADER_LEN);
+    head.extend(b"RIFF"); head.extend(riff_len.to_le_bytes()); head.extend(b"WAVE");
+    head.extend(b"fmt "); head.extend(16u32.to_le_bytes());
+    head.extend(tag.to_le_bytes());
+    head.extend(channels.to_le_bytes());
+    head.extend(srate.to_le_bytes());
+    head.extend((srate * align).to_le_bytes());
+    head.extend((align as u16).to_le_bytes());
+    head.extend((fmt.bit_depth() as u16).to_le_bytes());
+    head.extend(b"data"); head.extend((data_len.min(u32::MAX as u64) as u32).to_le_bytes());
+    return head;
+}
+
+/** decode_to_wav
+ * Decodes a whole FrAD stream into a WAV file
+ * Parameters: FrAD stream, Seekable WAV output, PCM format (written little-endian)
+ * Returns: Error if the format does not fit WAV, the stream changes sample rate or channels, or I/O fails
+ */
+#[cfg(feature = "std")]
+pub fn decode_to_wav<R: Read, W: Write + Seek>(mut reader: R, mut writer: W, fmt: PCMFormat) -> Result<(), FradError> {
+    let io = |e: std::io::Error| FradError::Io(e.kind());
+    let (tag, fmt) = wav_format(fmt).ok_or(FradError::UnsupportedWavFormat(fmt))?;
+
+    // 1. Placeholder header, patched once the stream format and length are known
+    let start = writer.stream_position().map_err(io)?;
+    writer.write_all(&[0u8; HEADER_LEN]).map_err(io)?;
+
+    let mut decoder = Decoder::new(false);
+    decoder.set_output_format(Some(fmt));
+    let (mut srate, mut channels, mut data_len) = (0u32, 0u16, 0u64);
+
+    // 2. Decode, refusing any change of the stream format
+    let mut buf = vec![0u8; 32768];
+    let mut eof = false;
+    while !eof {
+        let readlen = reader.read(&mut buf).map_err(io)?;
+        eof = readlen == 0;
+        let decoded = if eof { decoder.flush() } else { decoder.process(&buf[..readlen]) };
+        if decoded.is_empty() { continue; }
+
+        let chs = decoded.channels() as u16;
+        if channels == 0 { (srate, channels) = (decoded.srate, chs); }
+        else if (srate, channels) != (decoded.srate, chs) { return Err(FradError::WavFormatChange(decoded.srate, chs)); }
+        writer.write_all(&decoded.bytes).map_err(io)?;
+        data_len += decoded.bytes.len() as u64;
+    }
+
+    // 3. Word-align the data chunk and patch the header
+    if data_len % 2 == 1 { writer.write_all(&[0]).map_err(io)?; }
+    let end = writer.stream_position().map_err(io)?;
+    writer.seek(SeekFrom::Start(start)).map_err(io)?;
+    writer.write_all(&header(tag, fmt, srate, channels, data_len)).map_err(io)?;
+    writer.seek(SeekFrom::Start(end)).map_err(io)?;
+    return writer.flush().map_err(io);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /** fmt_body
+     * Makes a 16-byte "fmt " chunk body
+     */
+    fn fmt_body(tag: u16, channels: u16, srate: u32, bits: u16) -> Vec<u8> {
+        let align = channels * bits / 8;
+        return [&tag.to_le_bytes()[..], &channels.to_le_bytes(), &srate.to_le_bytes(), &(srate * align as u32).to_le_bytes(),
+            &align.to_le_bytes(), &bits.to_le_bytes()].concat();
+    }
+
+    /** riff
+     * Makes a RIFF/WAVE or RF64 stream of chunks with 32-bit sizes, u32::MAX sizes are written as given
+     */
+    fn riff(magic: &[u8; 4], chunks: &[(&[u8; 4], Option<u32>, Vec<u8>)]) -> Vec<u8> {
+        let mut wav = [&magic[..], &u32::MAX.to_le_bytes(), b"WAVE"].concat();
+        for (id, size, body) in chunks {
+            wav.extend(*id); wav.extend(size.unwrap_or(body.len() as u32).to_le_bytes()); wav.extend(body);
+            if body.len() % 2 == 1 { wav.push(0); }
+        }
+        return wav;
+    }
+
+    /** read
+     * Reads the header of a stream and the bytes left after it
+     */
+    fn read(wav: Vec<u8>) -> (Result<WavInfo, FradError>, Vec<u8>) {
+        let mut reader = Cursor::new(wav);
+        let info = read_header(&mut reader);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        return (info, rest);
+    }
+
+    #[test]
+    fn reads_riff_past_odd_chunks() {
+        let wav = riff(b"RIFF", &[(b"LIST", None, vec![1, 2, 3]), (b"fmt ", None, fmt_body(1, 2, 48000, 24)), (b"data", None, vec![9; 12])]);
+        assert!(is_wav(&wav));
+        let (info, rest) = read(wav);
+        let info = info.unwrap();
+        assert_eq!((info.srate, info.channels, info.fmt, info.data_len), (48000, 2, PCMFormat::I24(Little), Some(12)));
+        assert_eq!(rest, vec![9; 12]);
+    }
+
+    #[test]
+    fn reads_extensible_and_streamed_sizes() {
+        // Subformat GUID of IEEE float, 24 valid bits would still sit in 32-bit containers
+        let mut body = fmt_body(FORMAT_EXTENSIBLE, 6, 96000, 32);
+        body.extend([22, 0, 24, 0, 0x3f, 0, 0, 0]);
+        body.extend([&FORMAT_FLOAT.to_le_bytes()[..], &[0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xaa, 0, 0x38, 0x9b, 0x71]].concat());
+        let (info, rest) = read(riff(b"RIFF", &[(b"fmt ", None, body), (b"data", Some(u32::MAX), vec![7; 24])]));
+        let info = info.unwrap();
+        assert_eq!((info.srate, info.channels, info.fmt, info.data_len), (96000, 6, PCMFormat::F32(Little), None));
+        assert_eq!(rest.len(), 24);
+    }
+
+    #[test]
+    fn reads_rf64_data_size_from_ds64() {
+        let big = 5_000_000_000u64;
+        let ds64 = [&(big + 100).to_le_bytes()[..], &big.to_le_bytes(), &(big / 4).to_le_bytes(), &0u32.to_le_bytes()].concat();
+        let wav = riff(b"RF64", &[(b"ds64", None, ds64), (b"fmt ", None, fmt_body(1, 2, 44100, 16)), (b"data", Some(u32::MAX), vec![0; 8])]);
+        assert!(is_wav(&wav));
+        let info = read(wav).0.unwrap();
+        assert_eq!((info.srate, info.channels, info.fmt, info.data_len), (44100, 2, PCMFormat::I16(Little), Some(big)));
+    }
+
+    #[test]
+    fn reads_wave64() {
+        let chunk = |id: &[u8; 4], body: Vec<u8>| {
+            let mut chunk = [&id[..], &W64_SUFFIX, &(body.len() as u64 + 24).to_le_bytes()].concat();
+            let pad = (8 - body.len() % 8) % 8;
+            chunk.extend(body); chunk.extend(vec![0; pad]);
+            return chunk;
+        };
+        let mut wav = [&W64_RIFF[..], &0u64.to_le_bytes(), b"wave", &W64_SUFFIX].concat();
+        wav.extend(chunk(b"junk", vec![1; 5]));
+        wav.extend(chunk(b"fmt ", fmt_body(3, 1, 48000, 64)));
+        wav.extend(chunk(b"data", vec![5; 16]));
+        assert!(is_wav(&wav));
+        let (info, rest) = read(wav);
+        let info = info.unwrap();
+        assert_eq!((info.srate, info.channels, info.fmt, info.data_len), (48000, 1, PCMFormat::F64(Little), Some(16)));
+        assert_eq!(rest, vec![5; 16]);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(!is_wav(b"RIFF\0\0\0\0AVI "));
+        assert_eq!(read(b"RIFF\0\0\0\0AVI LIST".to_vec()).0.unwrap_err(), FradError::InvalidWav("not a RIFF/WAVE, RF64 or Wave64 stream"));
+        assert_eq!(read(riff(b"RIFF", &[(b"data", None, vec![0; 4])])).0.unwrap_err(), FradError::InvalidWav("data chunk before fmt chunk"));
+        assert_eq!(read(riff(b"RIFF", &[(b"fmt ", None, fmt_body(1, 0, 48000, 16))])).0.unwrap_err(), FradError::InvalidWav("zero channels or sample rate"));
+        assert_eq!(read(riff(b"RIFF", &[(b"fmt ", None, fmt_body(3, 2, 48000, 16))])).0.unwrap_err(), FradError::InvalidWav("unsupported sample format"));
+    }
+
+    #[test]
+    fn explicit_settings_must_match_the_header() {
+        let info = WavInfo { srate: 48000, channels: 2, fmt: PCMFormat::I16(Little), data_len: None };
+        for (srate, channels) in [(0, 0), (48000, 0), (0, 2), (48000, 2)] { assert_eq!(check_format(&info, srate, channels), Ok(())); }
+        assert_eq!(check_format(&info, 44100, 2), Err(FradError::WavHeaderConflict("Sample rate", 44100, 48000)));
+        assert_eq!(check_format(&info, 0, 6), Err(FradError::WavHeaderConflict("Channel count", 6, 2)));
+    }
+}
\ No newline at end of file
@@ -5,7 +5,7 @@
  */
 
 mod tools; mod common;
-mod encoder; mod decoder; mod repairer; mod header;
+mod encoder; mod decoder; mod repairer; mod header; mod transcoder; mod splitter; mod verifier;
 
 use std::{env, path::Path};
 
@@ -18,7 +18,10 @@ const GENERAL_HELP:    &str = include_str!("help/general.txt");
 const ENCODE_HELP:     &str = include_str!("help/encode.txt");
 const DECODE_HELP:     &str = include_str!("help/decode.txt");
 const REPAIR_HELP:     &str = include_str!("help/repair.txt");
+const TRANSCODE_HELP:  &str = include_str!("help/transcode.txt");
+const SPLIT_HELP:      &str = include_str!("help/split.txt");
 const PLAY_HELP:       &str = include_str!("help/play.txt");
+const VERIFY_HELP:     &str = include_str!("help/verify.txt");
 const METADATA_HELP:   &str = include_str!("help/metadata.txt");
 const JSONMETA_HELP:   &str = include_str!("help/jsonmeta.txt");
 const VORBISMETA_HELP: &str = include_str!("help/vorbismeta.txt");
@@ -42,6 +45,15 @@ fn main() {
     else if tools::cli::REPAIR_OPT.contains(&action.as_str()) {
         repairer::repair(input, params);
     }
+    else if tools::cli::TRANSCODE_OPT.contains(&action.as_str()) {
+        transcoder::transcode(input, params);
+    }
+    else if tools::cli::SPLIT_OPT.contains(&action.as_str()) {
+        splitter::split(input, params);
+    }
+    else if tools::cli::VERIFY_OPT.contains(&action.as_str()) {
+        verifier::verify(input, params);
+    }
     else if tools::cli::METADATA_OPT.contains(&action.as_str()) {
         header::modify(input, metaaction, params);
     }
@@ -51,7 +63,10 @@ fn main() {
             if tools::cli::ENCODE_OPT.contains(&input.as_str()) { ENCODE_HELP }
             else if tools::cli::DECODE_OPT.contains(&input.as_str()) { DECODE_HELP }
             else if tools::cli::REPAIR_OPT.contains(&input.as_str()) { REPAIR_HELP }
+            else if tools::cli::TRANSCODE_OPT.contains(&input.as_str()) { TRANSCODE_HELP }
+            else if tools::cli::SPLIT_OPT.contains(&input.as_str()) { SPLIT_HELP }
             else if tools::cli::PLAY_OPT.contains(&input.as_str()) { PLAY_HELP }
+            else if tools::cli::VERIFY_OPT.contains(&input.as_str()) { VERIFY_HELP }
             else if tools::cli::METADATA_OPT.contains(&input.as_str()) { METADATA_HELP }
             else if tools::cli::JSONMETA_OPT.contains(&input.as_str()) { JSONMETA_HELP }
             else if tools::cli::VORBISMETA_OPT.contains(&input.as_str()) { VORBISMETA_HELP }
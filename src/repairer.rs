@@ -4,27 +4,43 @@
  * Description: Repairer implementation example
  */
 
-use frad::Repairer;
+use frad::{Repairer, RepairStats};
 use crate::{
-    common::{check_overwrite, format_si, get_file_stem, read_exact, write_safe, PIPEIN, PIPEOUT},
-    tools::{cli::CliParams, process::ProcessInfo}
+    common::{check_overwrite, or_exit, format_progress, format_si, get_file_stem, get_input_size, read_exact, write_safe, PIPEIN, PIPEOUT},
+    tools::{cli::CliParams, process::{LogFormat, ProcessInfo}}
 };
 use std::{fs::File, io::{Read, Write}, path::Path, process::exit};
 
 use same_file::is_same_file;
 
 /** logging_repair
- * Logs a message to stderr
- * Parameters: Log level, Processing info, line feed flag
+ * Logs a message to stderr, with the frame counts once done even if the progress is not logged
+ * Parameters: Log level, Log format, Processing info, line feed flag, Repair statistics
  */
-pub fn logging_repair(loglevel: u8, log: &ProcessInfo, linefeed: bool) {
-    if loglevel == 0 { return; }
+pub fn logging_repair(loglevel: u8, logfmt: LogFormat, log: &ProcessInfo, linefeed: bool, stats: RepairStats) {
+    let summary = || eprintln!("frames={} corrected={} uncorrectable={}", stats.frames_total, stats.frames_corrected, stats.frames_uncorrectable);
+    if loglevel == 0 { if linefeed { summary(); } return; }
+    if logfmt == LogFormat::Json {
+        let mut record = match log.get_record(linefeed) { Some(record) => record, None => return };
+        if linefeed {
+            record["frames"] = stats.frames_total.into();
+            record["corrected"] = stats.frames_corrected.into();
+            record["uncorrectable"] = stats.frames_uncorrectable.into();
+        }
+        eprintln!("{}", record);
+        return;
+    }
     let total_size = log.get_total_size() as f64;
-    eprint!("size={}B speed={}B/s    \r",
-        format_si(total_size),
-        format_si(total_size / log.start_time.elapsed().as_secs_f64())
-    );
-    if linefeed { eprintln!(); }
+    let status = format!("size={}B speed={}B/s", format_si(total_size), format_si(total_size / log.start_time.elapsed().as_secs_f64()));
+    if !log.draw_bar(status, linefeed) {
+        eprint!("size={}B speed={}B/s{}    \r",
+            format_si(total_size),
+            format_si(total_size / log.start_time.elapsed().as_secs_f64()),
+            format_progress(log.get_progress())
+        );
+        if linefeed { eprintln!(); }
+    }
+    if linefeed { summary(); }
 }
 
 /** repair
@@ -56,8 +72,16 @@ pub fn repair(rfile: String, params: CliParams) {
     let mut readfile: Box<dyn Read> = if !rpipe { Box::new(File::open(&rfile).unwrap()) } else { Box::new(std::io::stdin()) };
     let mut writefile: Box<dyn Write> = if !wpipe { Box::new(File::create(&wfile).unwrap()) } else { Box::new(std::io::stdout()) };
 
-    let mut repairer = Repairer::new(params.ecc_ratio);
+    let mut repairer = match Repairer::new(params.ecc_ratio) {
+        Ok(repairer) => repairer,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Setting ECC to default 96 24");
+            or_exit(Repairer::new([96, 24]))
+        }
+    };
     let mut procinfo = ProcessInfo::new();
+    procinfo.set_total_input_bytes(get_input_size(&rfile));
     loop {
         let mut buffer = vec![0; 32768];
         let bytes_read = read_exact(&mut readfile, &mut buffer);
@@ -65,13 +89,14 @@ pub fn repair(rfile: String, params: CliParams) {
 
         let repaired = repairer.process(&buffer[..bytes_read]);
         procinfo.update(repaired.len(), 0, 0);
+        procinfo.update_input(bytes_read);
         write_safe(&mut writefile, &repaired);
-        logging_repair(params.loglevel, &procinfo, false);
+        logging_repair(params.loglevel, params.logfmt, &procinfo, false, repairer.stats());
     }
     let repaired = repairer.flush();
     procinfo.update(repaired.len(), 0, 0);
     write_safe(&mut writefile, &repaired);
-    logging_repair(params.loglevel, &procinfo, true);
+    if !params.quiet { logging_repair(params.loglevel, params.logfmt, &procinfo, true, repairer.stats()); }
 
     if params.overwrite_repair && !(rpipe || wpipe) {
         std::fs::rename(wfile, rfile).unwrap();
@@ -0,0 +1,31 @@
+/**                             Split application                             */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Channel splitter implementation example
+ */
+
+use frad::split_channels;
+use crate::{common::{check_overwrite, get_file_stem, or_exit, PIPEIN}, tools::cli::CliParams};
+use std::{fs, process::exit};
+
+/** split
+ * Splits a multichannel FrAD file into mono FrAD files, one per channel
+ * Parameters: Input file, CLI parameters
+ * Returns: Mono FrAD files named <stem>.ch<N>.frad
+ */
+pub fn split(input: String, params: CliParams) {
+    if input.is_empty() { eprintln!("Input file must be given"); exit(1); }
+    if PIPEIN.contains(&input.as_str()) { eprintln!("Pipe input is not supported for splitting"); exit(1); }
+
+    let stem = if params.output.is_empty() { get_file_stem(&input) } else { params.output.trim_end_matches(".frad").to_string() };
+    let frad = fs::read(&input).unwrap_or_else(|err| { eprintln!("Error reading file: {}", err); exit(1); });
+    let outputs = or_exit(split_channels(&frad));
+    if outputs.is_empty() { eprintln!("No audio frames found in {}", input); exit(1); }
+
+    for (c, output) in outputs.iter().enumerate() {
+        let path = format!("{}.ch{}.frad", stem, c);
+        check_overwrite(&path, params.overwrite);
+        fs::write(&path, output).unwrap_or_else(|err| { eprintln!("Error writing file: {}", err); exit(1); });
+        if params.loglevel > 0 { eprintln!("{}", path); }
+    }
+}
\ No newline at end of file
@@ -4,8 +4,9 @@
  * Description: Simple CLI parser for FrAD Executable
  */
 
-use frad::{Endian::{Big, Little}, PCMFormat};
-use std::{collections::VecDeque, env::Args, fs::read_to_string, process::exit};
+use frad::{head::PictureType, DitherKind, Endian::{Big, Little}, PCMFormat, Predictor, WindowKind, WindowType};
+use crate::tools::{id3, pcmproc::DownmixTarget, process::LogFormat, vorbismeta};
+use std::{collections::VecDeque, env::Args, fs::{read, read_to_string}, process::exit, str::FromStr};
 
 use base64::{prelude::BASE64_STANDARD, Engine};
 use serde_json::{from_str, Value};
@@ -14,7 +15,10 @@ use serde_json::{from_str, Value};
 pub const ENCODE_OPT: [&str; 2] = ["encode", "enc"];
 pub const DECODE_OPT: [&str; 2] = ["decode", "dec"];
 pub const REPAIR_OPT: [&str; 2] = ["repair", "ecc"];
+pub const TRANSCODE_OPT: [&str; 2] = ["transcode", "tc"];
+pub const SPLIT_OPT: [&str; 2] = ["split", "sp"];
 pub const PLAY_OPT: [&str; 2] = ["play", "p"];
+pub const VERIFY_OPT: [&str; 2] = ["verify", "vf"];
 pub const METADATA_OPT: [&str; 2] = ["meta", "metadata"];
 pub const JSONMETA_OPT: [&str; 2] = ["jsonmeta", "jm"];
 pub const VORBISMETA_OPT: [&str; 2] = ["vorbismeta", "vm"];
@@ -26,6 +30,9 @@ pub const META_REMOVE: &str = "remove";
 pub const META_RMIMG: &str = "rm-img";
 pub const META_OVERWRITE: &str = "overwrite";
 pub const META_PARSE: &str = "parse";
+pub const META_FIX: &str = "fix";
+pub const META_REPLAYGAIN: &str = "replaygain";
+pub const META_TOC: &str = "toc";
 
 // CLI Parameters
 pub struct CliParams {
@@ -39,14 +46,41 @@ pub struct CliParams {
     pub profile: u8,
     pub overlap_ratio: u16,
     pub losslevel: u8,
+    pub frame_seq: bool,
+    pub dc_frames: bool,
+    pub verify_output: bool,
+    pub resample: bool,
+    pub predictor: Predictor,
+    pub window: WindowType,
+    pub overlap_window: WindowKind,
+    pub dither: DitherKind,
+    pub warn_inexact: bool,
+    pub build_index: bool,
+    pub preroll: u64,
+    pub postroll: u64,
     pub enable_ecc: bool,
     pub ecc_ratio: [u8; 2],
     pub overwrite: bool,
     pub overwrite_repair: bool,
     pub meta: Vec<(String, Vec<u8>)>,
     pub image_path: String,
+    pub image_type: Option<PictureType>,
+    pub images: Vec<(Vec<u8>, PictureType)>,
+    pub files: Vec<String>,
+    pub reference: f64,
     pub loglevel: u8,
+    pub logfmt: LogFormat,
+    pub quiet: bool,
     pub speed: f64,
+    pub preserve_pitch: bool,
+    pub transpose: bool,
+    pub trim: bool,
+    pub max_splits: usize,
+    pub wav: bool,
+    pub loudness: bool,
+    pub downmix: Option<DownmixTarget>,
+    pub normalize: Option<f64>,
+    pub bitrate: Option<u32>,
 }
 
 impl CliParams {
@@ -62,14 +96,41 @@ impl CliParams {
             profile: 4,
             overlap_ratio: 16,
             losslevel: 0,
+            frame_seq: false,
+            dc_frames: false,
+            verify_output: false,
+            resample: false,
+            predictor: Predictor::None,
+            window: WindowType::Rectangular,
+            overlap_window: WindowKind::Hann,
+            dither: DitherKind::None,
+            warn_inexact: false,
+            build_index: false,
+            preroll: 0,
+            postroll: 0,
             enable_ecc: false,
             ecc_ratio: [96, 24],
             overwrite: false,
             overwrite_repair: false,
             meta: Vec::new(),
             image_path: String::new(),
+            image_type: None,
+            images: Vec::new(),
+            files: Vec::new(),
+            reference: -18.0,
             loglevel: 0,
+            logfmt: LogFormat::Text,
+            quiet: false,
             speed: 1.0,
+            preserve_pitch: false,
+            transpose: false,
+            trim: false,
+            max_splits: 0,
+            wav: false,
+            loudness: false,
+            downmix: None,
+            normalize: None,
+            bitrate: None,
         }
     }
     pub fn set_meta_from_json(&mut self, meta_path: String) {
@@ -97,21 +158,16 @@ impl CliParams {
     }
     pub fn set_meta_from_vorbis(&mut self, meta_path: String) {
         let contents = match read_to_string(meta_path) { Ok(c) => c, Err(_) => { return; } };
-        let mut meta: Vec<(String, Vec<u8>)> = Vec::new();
-        for line in contents.lines() {
-            let mut parts = line.splitn(2, '=');
-            let key = parts.next().unwrap();
-            match parts.next() {
-                Some(value) => meta.push((key.to_string(), value.as_bytes().to_vec())),
-                None => {
-                    if let Some(last) = meta.last_mut() {
-                        last.1.extend(format!("\n{}", key).as_str().as_bytes());
-                    }
-                    else { meta.push(("".to_string(), key.as_bytes().to_vec())); }
-                }
-            }
+        self.meta = vorbismeta::parse(&contents);
+    }
+    pub fn set_meta_from_id3(&mut self, tag_path: String) {
+        let data = match read(tag_path) { Ok(d) => d, Err(_) => { return; } };
+        let (meta, images) = match id3::parse(&data) { Some(tag) => tag, None => { return; } };
+        self.meta.extend(meta);
+        for (img, itype) in images {
+            self.images.retain(|(_, t)| *t != itype);
+            self.images.push((img, itype));
         }
-        self.meta = meta;
     }
     pub fn set_pcm_format(&mut self, fmt: &str) {
         self.pcm = match fmt.to_lowercase().as_str() {
@@ -153,6 +209,30 @@ impl CliParams {
     pub fn set_loglevel(&mut self, loglevel: String) { self.loglevel = loglevel.parse().unwrap(); }
 }
 
+/** next_value
+ * Pops the value of an option, recording an error if missing
+ * Parameters: Remaining arguments, Option name, Error list
+ * Returns: Option value
+ */
+fn next_value(args: &mut VecDeque<String>, key: &str, errors: &mut Vec<String>) -> Option<String> {
+    let value = args.pop_front();
+    if value.is_none() { errors.push(format!("Missing value for option: {key}")); }
+    return value;
+}
+
+/** next_number
+ * Pops the numeric value of an option, recording an error if missing or invalid
+ * Parameters: Remaining arguments, Option name, Error list
+ * Returns: Parsed option value
+ */
+fn next_number<T: FromStr>(args: &mut VecDeque<String>, key: &str, errors: &mut Vec<String>) -> Option<T> {
+    let value = next_value(args, key, errors)?;
+    match value.parse() {
+        Ok(x) => return Some(x),
+        Err(_) => { errors.push(format!("Invalid number for option {key}: {value}")); return None; }
+    }
+}
+
 /** parse
  * Parse CLI arguments and return the action, input file, and parameters
  * Parameters: arguments
@@ -174,44 +254,102 @@ pub fn parse(args: Args) -> (String, String, String, CliParams) {
     if args.is_empty() { return (action, String::new(), String::new(), params); }
     let input = args.pop_front().unwrap();
 
+    let mut errors: Vec<String> = Vec::new();
     while !args.is_empty() {
-        let key = args.pop_front().unwrap();
+        let arg = args.pop_front().unwrap();
 
-        if key.starts_with("-") {
-            let key = key.trim_start_matches("-");
+        if arg.starts_with("-") {
+            let key = arg.trim_start_matches("-");
 
             match key.to_lowercase().as_str() {
                 // universal
-                "output" | "out" | "o" => params.output = args.pop_front().unwrap(),
-                "pcm" | "format" | "fmt" | "f" => params.set_pcm_format(&args.pop_front().unwrap()),
+                "output" | "out" | "o" => params.output = next_value(&mut args, &arg, &mut errors).unwrap_or(params.output),
+                "pcm" | "format" | "fmt" | "f" => { if let Some(fmt) = next_value(&mut args, &arg, &mut errors) { params.set_pcm_format(&fmt); } },
                 "ecc" | "enable-ecc" | "e" => {
                     params.enable_ecc = true;
                     if !args.is_empty() && args[0].parse::<u8>().is_ok() {
-                        params.ecc_ratio = [args.pop_front().unwrap().parse().unwrap(), args.pop_front().unwrap().parse().unwrap()];
+                        params.ecc_ratio = [
+                            next_number(&mut args, &arg, &mut errors).unwrap_or(params.ecc_ratio[0]),
+                            next_number(&mut args, &arg, &mut errors).unwrap_or(params.ecc_ratio[1])
+                        ];
                     }
                 }
                 "y" | "force" => params.overwrite = true,
                 "overwrite" | "ow" => params.overwrite_repair = true,
 
                 // encode settings
-                "bits" | "bit" | "b" => params.bits = args.pop_front().unwrap().parse().unwrap(),
-                "srate" | "sample-rate" | "sr" => params.srate = args.pop_front().unwrap().parse().unwrap(),
-                "chnl" | "channels" | "channel" | "ch" => params.channels = args.pop_front().unwrap().parse().unwrap(),
-                "frame-size" | "fsize" | "fr" => params.frame_size = args.pop_front().unwrap().parse().unwrap(),
-                "overlap-ratio" | "overlap" | "olap" => params.overlap_ratio = args.pop_front().unwrap().parse().unwrap(),
+                "bits" | "bit" | "b" => params.bits = next_number(&mut args, &arg, &mut errors).unwrap_or(params.bits),
+                "srate" | "sample-rate" | "sr" => params.srate = next_number(&mut args, &arg, &mut errors).unwrap_or(params.srate),
+                "chnl" | "channels" | "channel" | "ch" => params.channels = next_number(&mut args, &arg, &mut errors).unwrap_or(params.channels),
+                "frame-size" | "fsize" | "fr" => params.frame_size = next_number(&mut args, &arg, &mut errors).unwrap_or(params.frame_size),
+                "overlap-ratio" | "overlap" | "olap" => params.overlap_ratio = next_number(&mut args, &arg, &mut errors).unwrap_or(params.overlap_ratio),
                 "le" | "little-endian" => params.little_endian = true,
-                "profile" | "prf" | "p" => params.profile = args.pop_front().unwrap().parse().unwrap(),
-                "losslevel" | "level" | "lv" => params.losslevel = args.pop_front().unwrap().parse().unwrap(),
+                "profile" | "prf" | "p" => params.profile = next_number(&mut args, &arg, &mut errors).unwrap_or(params.profile),
+                "losslevel" | "level" | "lv" => params.losslevel = next_number(&mut args, &arg, &mut errors).unwrap_or(params.losslevel),
+                "bitrate" | "br" => params.bitrate = next_number(&mut args, &arg, &mut errors).or(params.bitrate),
+                "frame-seq" | "seq" => params.frame_seq = true,
+                "dc-frames" | "dc" => params.dc_frames = true,
+                "verify-output" | "verify" => params.verify_output = true,
+                "resample" => params.resample = true,
+                "predictor" | "pred" => {
+                    match next_value(&mut args, &arg, &mut errors).map(|x| x.to_lowercase()).as_deref() {
+                        Some("none") => params.predictor = Predictor::None,
+                        Some("delta") => params.predictor = Predictor::Delta,
+                        Some("delta2") => params.predictor = Predictor::Delta2,
+                        Some(pred) => errors.push(format!("Invalid predictor: {pred}")),
+                        None => {}
+                    }
+                }
+                "window" | "win" => {
+                    match next_value(&mut args, &arg, &mut errors).map(|x| x.to_lowercase()).as_deref() {
+                        Some("rect") | Some("rectangular") => params.window = WindowType::Rectangular,
+                        Some("hamming") => params.window = WindowType::Hamming,
+                        Some(win) => errors.push(format!("Invalid window: {win}")),
+                        None => {}
+                    }
+                }
+                "overlap-window" | "olap-window" | "owin" => {
+                    match next_value(&mut args, &arg, &mut errors).map(|x| x.to_lowercase()).as_deref() {
+                        Some("hann") | Some("hanning") => params.overlap_window = WindowKind::Hann,
+                        Some("sine") => params.overlap_window = WindowKind::Sine,
+                        Some("vorbis") => params.overlap_window = WindowKind::Vorbis,
+                        Some("linear") => params.overlap_window = WindowKind::Linear,
+                        Some(win) => errors.push(format!("Invalid overlap window: {win}")),
+                        None => {}
+                    }
+                }
+                "dither" => {
+                    match next_value(&mut args, &arg, &mut errors).map(|x| x.to_lowercase()).as_deref() {
+                        Some("none") => params.dither = DitherKind::None,
+                        Some("tpdf") => params.dither = DitherKind::Tpdf,
+                        Some(dither) => errors.push(format!("Invalid dither: {dither}")),
+                        None => {}
+                    }
+                }
+                "warn-inexact" | "wi" => params.warn_inexact = true,
+                "index" | "idx" => params.build_index = true,
+                "preroll" | "pre" => params.preroll = next_number(&mut args, &arg, &mut errors).unwrap_or(params.preroll),
+                "postroll" | "post" => params.postroll = next_number(&mut args, &arg, &mut errors).unwrap_or(params.postroll),
 
                 // metadata settings
                 "tag" | "meta" | "m" => {
-                    let value = args.pop_front().unwrap();
+                    let Some(value) = next_value(&mut args, &arg, &mut errors) else { continue; };
                     if metaaction == META_REMOVE { params.meta.push((value, Vec::new())); }
-                    else { params.meta.push((value, args.pop_front().unwrap().as_bytes().to_vec())); }
+                    else if let Some(data) = next_value(&mut args, &arg, &mut errors) { params.meta.push((value, data.as_bytes().to_vec())); }
                 }
-                "jsonmeta" | "jm" => params.set_meta_from_json(args.pop_front().unwrap()),
-                "vorbismeta" | "vm" => params.set_meta_from_vorbis(args.pop_front().unwrap()),
-                "img" | "image" => params.image_path = args.pop_front().unwrap(),
+                "jsonmeta" | "jm" => { if let Some(path) = next_value(&mut args, &arg, &mut errors) { params.set_meta_from_json(path); } },
+                "vorbismeta" | "vm" => { if let Some(path) = next_value(&mut args, &arg, &mut errors) { params.set_meta_from_vorbis(path); } },
+                "id3" => { if let Some(path) = next_value(&mut args, &arg, &mut errors) { params.set_meta_from_id3(path); } },
+                "img" | "image" => params.image_path = next_value(&mut args, &arg, &mut errors).unwrap_or(params.image_path),
+                "image-type" | "itype" => {
+                    if let Some(name) = next_value(&mut args, &arg, &mut errors) {
+                        match PictureType::from_name(&name) {
+                            Some(itype) => params.image_type = Some(itype),
+                            None => errors.push(format!("Invalid image type: {name}, available: {}", PictureType::names().join(", "))),
+                        }
+                    }
+                },
+                "reference" | "ref" => params.reference = next_number(&mut args, &arg, &mut errors).unwrap_or(params.reference),
                 "log" | "v" => {
                     if !args.is_empty() && args[0].parse::<u8>().is_ok() {
                         let value = args.pop_front().unwrap();
@@ -219,11 +357,46 @@ pub fn parse(args: Args) -> (String, String, String, CliParams) {
                     }
                     else { params.set_loglevel("1".to_string()); }
                 }
-                "speed" | "spd" => params.speed = args.pop_front().unwrap().parse().unwrap(),
-                "keys" | "key" | "k" => params.speed = 2.0f64.powf(args.pop_front().unwrap().parse::<f64>().unwrap() / 12.0),
-                _ => {}
+                "log-format" | "logfmt" => {
+                    match next_value(&mut args, &arg, &mut errors).map(|x| x.to_lowercase()).as_deref() {
+                        Some("text") => params.logfmt = LogFormat::Text,
+                        Some("json") => params.logfmt = LogFormat::Json,
+                        Some(fmt) => errors.push(format!("Invalid log format: {fmt}")),
+                        None => {}
+                    }
+                }
+                "quiet" | "q" => params.quiet = true,
+                "speed" | "spd" => { if let Some(speed) = next_number(&mut args, &arg, &mut errors) { (params.speed, params.transpose) = (speed, false); } },
+                "keys" | "key" | "k" => { if let Some(keys) = next_number::<f64>(&mut args, &arg, &mut errors) { (params.speed, params.transpose) = (2.0f64.powf(keys / 12.0), true); } },
+                "preserve-pitch" | "pp" => params.preserve_pitch = true,
+                "trim" => params.trim = true,
+                "max-splits" | "splits" => params.max_splits = next_number(&mut args, &arg, &mut errors).unwrap_or(params.max_splits),
+                "wav" => params.wav = true,
+                "loudness" | "lufs" => params.loudness = true,
+                "downmix" | "dmx" => {
+                    match next_value(&mut args, &arg, &mut errors).map(|x| x.to_lowercase()).as_deref() {
+                        Some("stereo") => params.downmix = Some(DownmixTarget::Stereo),
+                        Some("mono") => params.downmix = Some(DownmixTarget::Mono),
+                        Some(layout) => errors.push(format!("Invalid downmix layout: {layout}")),
+                        None => {}
+                    }
+                }
+                "normalize" | "normalise" | "norm" => params.normalize = next_number(&mut args, &arg, &mut errors).or(params.normalize),
+                _ => errors.push(format!("Unknown option: {arg}")),
             }
         }
+        else if metaaction == META_REPLAYGAIN { params.files.push(arg); }
+        else { errors.push(format!("Unexpected argument: {arg}")); }
+    }
+
+    // JSON logging implies logging, and quiet overrides both
+    if params.logfmt == LogFormat::Json && params.loglevel == 0 { params.loglevel = 1; }
+    if params.quiet { params.loglevel = 0; }
+
+    if !errors.is_empty() {
+        for error in errors { eprintln!("{error}"); }
+        eprintln!("Type `{executable} help {action}` for available options.");
+        exit(1);
     }
 
     return (action, metaaction, input, params);
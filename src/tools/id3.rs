@@ -0,0 +1,300 @@
+/**                                   ID3v2                                   */
+/**
+ * Copyright 2024 HaמuL
+ * Description: ID3v2.3/2.4 tag reader, mapping frames to FrAD comments and images
+ */
+
+use frad::head::PictureType;
+
+// ID3 frame IDs to FrAD comment titles
+const FRAME_TITLES: [(&str, &str); 24] = [
+    ("TIT1", "GROUPING"), ("TIT2", "TITLE"), ("TIT3", "SUBTITLE"),
+    ("TPE1", "ARTIST"), ("TPE2", "ALBUMARTIST"), ("TPE3", "CONDUCTOR"), ("TPE4", "REMIXER"),
+    ("TALB", "ALBUM"), ("TCOM", "COMPOSER"), ("TEXT", "LYRICIST"), ("TCON", "GENRE"),
+    ("TRCK", "TRACKNUMBER"), ("TPOS", "DISCNUMBER"), ("TYER", "DATE"), ("TDRC", "DATE"),
+    ("TCOP", "COPYRIGHT"), ("TPUB", "LABEL"), ("TSRC", "ISRC"), ("TBPM", "BPM"),
+    ("TKEY", "KEY"), ("TLAN", "LANGUAGE"), ("TENC", "ENCODEDBY"),
+    ("COMM", "COMMENT"), ("USLT", "LYRICS"),
+];
+
+/** title_of
+ * Gets the FrAD comment title of an ID3 frame ID
+ * Parameters: Frame ID
+ * Returns: Comment title, None if the frame is not mapped
+ */
+fn title_of(id: &str) -> Option<&'static str> {
+    return FRAME_TITLES.iter().find(|&&(x, _)| x == id).map(|&(_, title)| title);
+}
+
+/** synchsafe
+ * Reads a 28-bit synchsafe integer
+ * Parameters: 4 bytes
+ * Returns: Integer
+ */
+fn synchsafe(b: &[u8]) -> usize {
+    return b.iter().fold(0, |acc, &x| (acc << 7) | (x & 0x7f) as usize);
+}
+
+/** unsync
+ * Reverts unsynchronisation, 0xff 0x00 -> 0xff
+ * Parameters: Data
+ * Returns: Original data
+ */
+fn unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &x) in data.iter().enumerate() {
+        if x == 0x00 && i > 0 && data[i - 1] == 0xff { continue; }
+        out.push(x);
+    }
+    return out;
+}
+
+/** split_text
+ * Splits the first string of the given text encoding off the data
+ * Parameters: Data, Text encoding(0: ISO-8859-1, 1: UTF-16 with BOM, 2: UTF-16BE, 3: UTF-8)
+ * Returns: Decoded string, Rest of the data after the terminator
+ */
+fn split_text(data: &[u8], enc: u8) -> (String, &[u8]) {
+    // 1. Find the terminator, two bytes wide and aligned for UTF-16
+    let wide = enc == 1 || enc == 2;
+    let end = if wide { data.chunks_exact(2).position(|x| x == [0, 0]).map(|x| x * 2) }
+    else { data.iter().position(|&x| x == 0) };
+    let (text, rest) = match end {
+        Some(end) => (&data[..end], &data[end + if wide { 2 } else { 1 }..]),
+        None => (data, &data[data.len()..]),
+    };
+
+    // 2. Decode
+    let string = match enc {
+        0 => text.iter().map(|&x| x as char).collect(),
+        1 | 2 => {
+            let (mut buf, mut le) = (Vec::new(), false);
+            for (i, x) in text.chunks_exact(2).enumerate() {
+                let unit = if le { u16::from_le_bytes([x[0], x[1]]) } else { u16::from_be_bytes([x[0], x[1]]) };
+                if i == 0 && enc == 1 && (unit == 0xfeff || unit == 0xfffe) { le = unit == 0xfffe; continue; }
+                buf.push(unit);
+            }
+            String::from_utf16_lossy(&buf)
+        }
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+    return (string, rest);
+}
+
+/** text_values
+ * Decodes all strings of a text frame body, without its encoding byte
+ * Parameters: Data, Text encoding
+ * Returns: Non-empty strings
+ */
+fn text_values(mut data: &[u8], enc: u8) -> Vec<String> {
+    let mut values = Vec::new();
+    while !data.is_empty() {
+        let (text, rest) = split_text(data, enc);
+        if !text.is_empty() { values.push(text); }
+        data = rest;
+    }
+    return values;
+}
+
+/** frame
+ * Maps one ID3 frame into comments or an image
+ * Parameters: Frame ID, Frame body, Metadata, Images
+ */
+fn frame(id: &str, body: &[u8], meta: &mut Vec<(String, Vec<u8>)>, images: &mut Vec<(Vec<u8>, PictureType)>) {
+    if body.is_empty() { return; }
+    let (enc, data) = (body[0], &body[1..]);
+
+    match id {
+        // Encoding, MIME type, Picture type, Description, Image
+        "APIC" => {
+            let (_, data) = split_text(data, 0);
+            if data.is_empty() { return; }
+            let itype = PictureType::from_u8(data[0]).unwrap_or(PictureType::Other);
+            let (_, img) = split_text(&data[1..], enc);
+            if img.is_empty() { return; }
+            images.retain(|(_, t)| *t != itype);
+            images.push((img.to_vec(), itype));
+        }
+        // Encoding, Description, Value
+        "TXXX" => {
+            let (desc, data) = split_text(data, enc);
+            for value in text_values(data, enc) { meta.push((desc.to_uppercase(), value.into_bytes())); }
+        }
+        // Encoding, Language, Description, Text
+        "COMM" | "USLT" => {
+            if data.len() < 3 { return; }
+            let (desc, data) = split_text(&data[3..], enc);
+            let (text, _) = split_text(data, enc);
+            // iTunes keeps its own data in described comments
+            if text.is_empty() || (id == "COMM" && !desc.is_empty()) { return; }
+            meta.push((title_of(id).unwrap().to_string(), text.into_bytes()));
+        }
+        _ => {
+            let title = match title_of(id) { Some(title) => title, None => return };
+            for value in text_values(data, enc) { meta.push((title.to_string(), value.into_bytes())); }
+        }
+    }
+}
+
+/** parse
+ * Reads an ID3v2.3 or 2.4 tag at the start of the data
+ * Parameters: Data
+ * Returns: Metadata and images, None if there is no supported tag
+ */
+pub fn parse(data: &[u8]) -> Option<(Vec<(String, Vec<u8>)>, Vec<(Vec<u8>, PictureType)>)> {
+    // 1. Tag header: "ID3", Version, Revision, Flags, Size
+    if data.len() < 10 || &data[0..3] != b"ID3" { return None; }
+    let (ver, flags) = (data[3], data[5]);
+    if ver != 3 && ver != 4 { return None; }
+    let size = synchsafe(&data[6..10]);
+    let mut tag = data[10..data.len().min(10 + size)].to_vec();
+
+    // 2. v2.3 unsynchronises the whole tag, v2.4 each frame
+    if ver == 3 && flags & 0x80 != 0 { tag = unsync(&tag); }
+
+    // 3. Skip the extended header
+    let mut pos = 0;
+    if flags & 0x40 != 0 && tag.len() >= 4 {
+        pos = if ver == 4 { synchsafe(&tag[0..4]) } else { u32::from_be_bytes(tag[0..4].try_into().unwrap()) as usize + 4 };
+    }
+
+    // 4. Frames: ID, Size, Flags, Body
+    let (mut meta, mut images) = (Vec::new(), Vec::new());
+    while pos + 10 <= tag.len() {
+        let head = &tag[pos..pos + 10];
+        if head[0] == 0 { break; } // padding
+        let id = String::from_utf8_lossy(&head[0..4]).to_string();
+        let fsize = if ver == 4 { synchsafe(&head[4..8]) } else { u32::from_be_bytes(head[4..8].try_into().unwrap()) as usize };
+        let fmt = head[9];
+        pos += 10;
+        if pos + fsize > tag.len() { break; }
+        let mut body = &tag[pos..pos + fsize];
+        pos += fsize;
+
+        // Compressed or encrypted frames are left out
+        let unsynced;
+        if ver == 4 {
+            if fmt & 0x0c != 0 { continue; }
+            if fmt & 0x40 != 0 && !body.is_empty() { body = &body[1..]; } // group ID
+            if fmt & 0x01 != 0 && body.len() >= 4 { body = &body[4..]; } // data length indicator
+            if fmt & 0x02 != 0 || flags & 0x80 != 0 { unsynced = unsync(body); body = &unsynced; }
+        }
+        else {
+            if fmt & 0xc0 != 0 { continue; }
+            if fmt & 0x20 != 0 && !body.is_empty() { body = &body[1..]; } // group ID
+        }
+        frame(&id, body, &mut meta, &mut images);
+    }
+    return Some((meta, images));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** to_synchsafe
+     * Writes a 28-bit synchsafe integer
+     */
+    fn to_synchsafe(n: usize) -> [u8; 4] {
+        return [(n >> 21) as u8 & 0x7f, (n >> 14) as u8 & 0x7f, (n >> 7) as u8 & 0x7f, n as u8 & 0x7f];
+    }
+
+    /** to_unsync
+     * Unsynchronises data, 0xff -> 0xff 0x00
+     */
+    fn to_unsync(data: &[u8]) -> Vec<u8> {
+        return data.iter().flat_map(|&x| if x == 0xff { vec![0xff, 0x00] } else { vec![x] }).collect();
+    }
+
+    /** id3_frame
+     * Makes a frame, its size synchsafe in v2.4 and plain in v2.3
+     */
+    fn id3_frame(ver: u8, id: &str, body: &[u8], fmt: u8) -> Vec<u8> {
+        let size = if ver == 4 { to_synchsafe(body.len()) } else { (body.len() as u32).to_be_bytes() };
+        return [id.as_bytes(), &size, &[0, fmt], body].concat();
+    }
+
+    /** id3_tag
+     * Makes a tag of frames
+     */
+    fn id3_tag(ver: u8, flags: u8, frames: &[u8]) -> Vec<u8> {
+        return [b"ID3", &[ver, 0, flags][..], &to_synchsafe(frames.len()), frames].concat();
+    }
+
+    fn text(s: &str) -> Vec<u8> { return [&[3u8][..], s.as_bytes()].concat(); }
+
+    #[test]
+    fn frame_sizes_are_synchsafe_in_v24_only() {
+        // Over 127 bytes the synchsafe and plain sizes differ
+        let title = "T".repeat(200);
+        for ver in [3, 4] {
+            let frames = [id3_frame(ver, "TIT2", &text(&title), 0), id3_frame(ver, "TPE1", &text("Artist"), 0)].concat();
+            let (meta, images) = parse(&id3_tag(ver, 0, &frames)).unwrap();
+            assert_eq!(meta, vec![("TITLE".to_string(), title.clone().into_bytes()), ("ARTIST".to_string(), b"Artist".to_vec())]);
+            assert!(images.is_empty());
+        }
+    }
+
+    #[test]
+    fn unsynchronisation_of_v23_tags_and_v24_frames() {
+        let image = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0xff];
+        let apic = [&[0u8][..], b"image/jpeg\0", &[3], b"\0", &image].concat();
+
+        // v2.3 unsynchronises the whole tag, frame sizes count the original data
+        let frames = [id3_frame(3, "APIC", &apic, 0), id3_frame(3, "TALB", &text("Album"), 0)].concat();
+        let (meta, images) = parse(&id3_tag(3, 0x80, &to_unsync(&frames))).unwrap();
+        assert_eq!(images, vec![(image.clone(), PictureType::FrontCover)]);
+        assert_eq!(meta, vec![("ALBUM".to_string(), b"Album".to_vec())]);
+
+        // v2.4 unsynchronises each flagged frame, frame sizes count the unsynchronised data
+        let frames = [id3_frame(4, "APIC", &to_unsync(&apic), 0x02), id3_frame(4, "TALB", &text("Album"), 0)].concat();
+        let (meta, images) = parse(&id3_tag(4, 0, &frames)).unwrap();
+        assert_eq!(images, vec![(image, PictureType::FrontCover)]);
+        assert_eq!(meta, vec![("ALBUM".to_string(), b"Album".to_vec())]);
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let utf16 = |s: &str, le: bool| -> Vec<u8> {
+            let bom: &[u8] = if le { &[0xff, 0xfe] } else { &[0xfe, 0xff] };
+            let units = s.encode_utf16().flat_map(|u| if le { u.to_le_bytes() } else { u.to_be_bytes() });
+            return [&[1u8][..], bom].concat().into_iter().chain(units).chain([0, 0]).collect();
+        };
+        for le in [false, true] {
+            let frames = id3_frame(3, "TIT2", &utf16("Café 夜", le), 0);
+            let (meta, _) = parse(&id3_tag(3, 0, &frames)).unwrap();
+            assert_eq!(meta, vec![("TITLE".to_string(), "Café 夜".as_bytes().to_vec())]);
+        }
+    }
+
+    #[test]
+    fn described_comments_are_skipped() {
+        let frames = [
+            id3_frame(4, "COMM", &[&[3u8][..], b"eng", b"iTunNORM\0", b" 00000A2F"].concat(), 0),
+            id3_frame(4, "COMM", &[&[3u8][..], b"eng", b"\0", b"Liner notes"].concat(), 0),
+            id3_frame(4, "USLT", &[&[3u8][..], b"eng", b"Verse\0", b"La la"].concat(), 0),
+        ].concat();
+        let (meta, _) = parse(&id3_tag(4, 0, &frames)).unwrap();
+        assert_eq!(meta, vec![("COMMENT".to_string(), b"Liner notes".to_vec()), ("LYRICS".to_string(), b"La la".to_vec())]);
+    }
+
+    #[test]
+    fn pictures_are_routed_by_type() {
+        let apic = |kind: u8, data: &[u8]| id3_frame(4, "APIC", &[&[0u8][..], b"image/png\0", &[kind], b"Cover\0", data].concat(), 0);
+        let frames = [apic(3, b"front"), apic(4, b"back"), apic(3, b"front2"), apic(200, b"unknown")].concat();
+        let (_, images) = parse(&id3_tag(4, 0, &frames)).unwrap();
+        // A later picture of the same type replaces the earlier one, unknown types are Other
+        assert_eq!(images, vec![
+            (b"back".to_vec(), PictureType::BackCover),
+            (b"front2".to_vec(), PictureType::FrontCover),
+            (b"unknown".to_vec(), PictureType::Other),
+        ]);
+    }
+
+    #[test]
+    fn unsupported_tags() {
+        assert!(parse(b"ID3").is_none());
+        assert!(parse(&[b"ID3", &[2u8, 0, 0][..], &[0, 0, 0, 0]].concat()).is_none());
+        assert!(parse(b"RIFF\0\0\0\0WAVEfmt ").is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,258 @@
+/**                              Loudness Meter                               */
+/**
+ * Copyright 2024 HaמuL
+ * Description: ITU-R BS.1770 / EBU R128 loudness meter,
+ *   integrated loudness, loudness range and true peak
+ */
+
+use std::f64::consts::PI;
+
+// Absolute and relative gates in LUFS / LU
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+// Relative gate of the loudness range, EBU Tech 3342
+const RANGE_GATE: f64 = -20.0;
+// Sub-blocks of a 3 s short-term window
+const SHORT_SUBS: usize = 30;
+// Interpolation filter taps per oversampling phase of the true peak meter
+const PEAK_TAPS: usize = 12;
+
+/** Biquad
+ * Second-order IIR filter coefficients
+ */
+#[derive(Clone, Copy)]
+struct Biquad { b: [f64; 3], a: [f64; 2] }
+
+impl Biquad {
+    /** k_weighting
+     * Designs the two K-weighting stages for a sample rate
+     * Parameters: Sample rate
+     * Returns: High-shelf stage, High-pass stage
+     */
+    fn k_weighting(srate: u32) -> [Biquad; 2] {
+        let fs = srate as f64;
+
+        // Stage 1: high shelf modelling the acoustic effect of the head
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / fs).tan();
+        let vh = 10.0f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        };
+
+        // Stage 2: RLB high pass
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        };
+        return [shelf, highpass];
+    }
+
+    /** run
+     * Filters one sample in transposed direct form II
+     * Parameters: Input sample, Filter state
+     * Returns: Output sample
+     */
+    fn run(&self, x: f64, z: &mut [f64; 2]) -> f64 {
+        let y = self.b[0] * x + z[0];
+        z[0] = self.b[1] * x - self.a[0] * y + z[1];
+        z[1] = self.b[2] * x - self.a[1] * y;
+        return y;
+    }
+}
+
+/** oversampler
+ * Designs the polyphase interpolation filter of the true peak meter,
+ *   oversampling to at least 192 kHz with a Blackman-windowed sinc
+ * Parameters: Sample rate
+ * Returns: Taps of every phase, one phase of a single tap if no oversampling is needed
+ */
+fn oversampler(srate: u32) -> Vec<Vec<f64>> {
+    let factor = if srate < 96000 { 4 } else if srate < 192000 { 2 } else { return vec![vec![1.0]]; };
+    let n = factor * PEAK_TAPS;
+    let centre = (n - 1) as f64 / 2.0;
+    let taps: Vec<f64> = (0..n).map(|i| {
+        let t = (i as f64 - centre) / factor as f64;
+        let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+        let w = 2.0 * PI * i as f64 / (n - 1) as f64;
+        return sinc * (0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos());
+    }).collect();
+
+    // Each phase is normalised to unity gain at DC
+    return (0..factor).map(|p| {
+        let phase: Vec<f64> = taps.iter().skip(p).step_by(factor).copied().collect();
+        let sum: f64 = phase.iter().sum();
+        return phase.iter().map(|x| x / sum).collect();
+    }).collect();
+}
+
+/** percentile
+ * Gets a percentile of sorted values by the nearest rank
+ * Parameters: Sorted values, Fraction from 0 to 1
+ * Returns: Percentile value
+ */
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    return sorted[((sorted.len() - 1) as f64 * fraction).round() as usize];
+}
+
+/** channel_weight
+ * Gets the BS.1770 weight of a channel, surround channels of 5.1 are boosted and LFE is excluded
+ * Parameters: Channel index, Channel count
+ * Returns: Channel weight
+ */
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    if channels != 6 { return 1.0; }
+    return match channel { 3 => 0.0, 4 | 5 => 1.41, _ => 1.0 };
+}
+
+/** lufs
+ * Converts a K-weighted mean square power to loudness
+ * Parameters: Power
+ * Returns: Loudness in LUFS
+ */
+fn lufs(power: f64) -> f64 { return -0.691 + 10.0 * power.log10(); }
+
+/** Loudness
+ * Struct for streaming loudness measurement
+ */
+pub struct Loudness {
+    srate: u32,
+    channels: usize,
+    filters: [Biquad; 2],
+    state: Vec<[[f64; 2]; 2]>,
+
+    // 100 ms sub-blocks, four of them make one 400 ms gating block and thirty one short-term window
+    sumsq: Vec<f64>,
+    sub_len: usize,
+    subs: Vec<f64>,
+    blocks: Vec<f64>,
+    shorts: Vec<f64>,
+
+    // True peak interpolation filter and the last samples of every channel
+    phases: Vec<Vec<f64>>,
+    history: Vec<[f64; PEAK_TAPS]>,
+    peak: f64,
+}
+
+impl Loudness {
+    pub fn new() -> Loudness {
+        return Loudness {
+            srate: 0,
+            channels: 0,
+            filters: Biquad::k_weighting(48000),
+            state: Vec::new(),
+
+            sumsq: Vec::new(),
+            sub_len: 0,
+            subs: Vec::new(),
+            blocks: Vec::new(),
+            shorts: Vec::new(),
+
+            phases: Vec::new(),
+            history: Vec::new(),
+            peak: 0.0,
+        };
+    }
+
+    /** reset
+     * Resets the filters for a new stream format, measured blocks and the peak are kept
+     * Parameters: Sample rate, Channel count
+     */
+    fn reset(&mut self, srate: u32, channels: usize) {
+        self.srate = srate;
+        self.channels = channels;
+        self.filters = Biquad::k_weighting(srate);
+        self.state = vec![[[0.0; 2]; 2]; channels];
+        self.sumsq = vec![0.0; channels];
+        self.sub_len = 0;
+        self.subs.clear();
+        self.phases = oversampler(srate);
+        self.history = vec![[0.0; PEAK_TAPS]; channels];
+    }
+
+    /** process
+     * Measures a chunk of PCM
+     * Parameters: PCM (samples × channels), Sample rate
+     */
+    pub fn process(&mut self, pcm: &[Vec<f64>], srate: u32) {
+        if pcm.is_empty() || pcm[0].is_empty() || srate < 10 { return; }
+        if srate != self.srate || pcm[0].len() != self.channels { self.reset(srate, pcm[0].len()); }
+        let sub_size = (srate / 10) as usize;
+
+        for sample in pcm {
+            for (c, &x) in sample.iter().enumerate().take(self.channels) {
+                let y = self.filters[1].run(self.filters[0].run(x, &mut self.state[c][0]), &mut self.state[c][1]);
+                self.sumsq[c] += y * y;
+
+                // True peak of the oversampled signal, never below the sample peak
+                let hist = &mut self.history[c];
+                hist.copy_within(1.., 0); hist[PEAK_TAPS - 1] = x;
+                for phase in &self.phases {
+                    let y: f64 = phase.iter().enumerate().map(|(k, h)| h * hist[PEAK_TAPS - 1 - k]).sum();
+                    self.peak = self.peak.max(y.abs());
+                }
+                self.peak = self.peak.max(x.abs());
+            }
+            self.sub_len += 1;
+            if self.sub_len < sub_size { continue; }
+
+            // Close the sub-block, then the gating block ending with it
+            let power = (0..self.channels).map(|c| channel_weight(c, self.channels) * self.sumsq[c]).sum::<f64>() / sub_size as f64;
+            self.sumsq.iter_mut().for_each(|x| *x = 0.0);
+            self.sub_len = 0;
+
+            self.subs.push(power);
+            if self.subs.len() > SHORT_SUBS { self.subs.remove(0); }
+            if self.subs.len() >= 4 { self.blocks.push(self.subs[self.subs.len() - 4..].iter().sum::<f64>() / 4.0); }
+            if self.subs.len() == SHORT_SUBS { self.shorts.push(self.subs.iter().sum::<f64>() / SHORT_SUBS as f64); }
+        }
+    }
+
+    /** blocks
+     * Gets the mean square power of every gating block measured so far
+     * Returns: Gating block powers
+     */
+    pub fn blocks(&self) -> &[f64] { return &self.blocks; }
+
+    /** range
+     * Calculates the loudness range of the short-term windows measured so far, EBU Tech 3342
+     * Returns: Loudness range in LU, None if every window is gated out
+     */
+    pub fn range(&self) -> Option<f64> {
+        let absolute: Vec<f64> = self.shorts.iter().copied().filter(|&p| lufs(p) > ABSOLUTE_GATE).collect();
+        if absolute.is_empty() { return None; }
+        let relative = lufs(absolute.iter().sum::<f64>() / absolute.len() as f64) + RANGE_GATE;
+
+        let mut gated: Vec<f64> = absolute.into_iter().map(lufs).filter(|&l| l > relative).collect();
+        if gated.is_empty() { return None; }
+        gated.sort_by(|a, b| a.total_cmp(b));
+        return Some(percentile(&gated, 0.95) - percentile(&gated, 0.10));
+    }
+
+    /** true_peak
+     * Gets the true peak of everything measured so far
+     * Returns: True peak in dBTP, negative infinity for silence
+     */
+    pub fn true_peak(&self) -> f64 { return 20.0 * self.peak.log10(); }
+
+    /** integrated
+     * Calculates the gated integrated loudness of gating blocks
+     * Parameters: Gating block powers, possibly from several streams
+     * Returns: Integrated loudness in LUFS, None if every block is gated out
+     */
+    pub fn integrated(blocks: &[f64]) -> Option<f64> {
+        let mean = |gate: f64| {
+            let gated: Vec<f64> = blocks.iter().copied().filter(|&p| lufs(p) > gate).collect();
+            if gated.is_empty() { None } else { Some(gated.iter().sum::<f64>() / gated.len() as f64) }
+        };
+
+        let relative = lufs(mean(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+        return Some(lufs(mean(relative.max(ABSOLUTE_GATE))?));
+    }
+}
\ No newline at end of file
@@ -1,2 +1,8 @@
 pub mod cli;
-pub mod process;
\ No newline at end of file
+pub mod id3;
+pub mod loudness;
+pub mod pcmproc;
+pub mod process;
+pub mod resample;
+pub mod timestretch;
+pub mod vorbismeta;
\ No newline at end of file
@@ -0,0 +1,142 @@
+/**                              PCM Processing                               */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Channel processing and peak normalisation of decoded PCM
+ */
+
+use std::f64::consts::FRAC_1_SQRT_2 as M3DB;
+
+/** DownmixTarget
+ * Channel layout to downmix to
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum DownmixTarget {
+    Stereo,
+    Mono,
+}
+
+impl DownmixTarget {
+    /** channels
+     * Gets the channel count of the target layout
+     * Returns: Channel count
+     */
+    pub fn channels(&self) -> u16 {
+        return match self { DownmixTarget::Stereo => 2, DownmixTarget::Mono => 1 };
+    }
+}
+
+/** stereo_gains
+ * Gets the ITU-R BS.775 left and right gains of each channel, in WAVE channel order
+ *   Centre and surrounds at -3 dB, LFE dropped
+ * Parameters: Channel count
+ * Returns: Left and right gains per channel, None for unknown layouts
+ */
+fn stereo_gains(channels: u16) -> Option<&'static [(f64, f64)]> {
+    return match channels {
+        // L R
+        2 => Some(&[(1.0, 0.0), (0.0, 1.0)]),
+        // L R C
+        3 => Some(&[(1.0, 0.0), (0.0, 1.0), (M3DB, M3DB)]),
+        // L R Ls Rs
+        4 => Some(&[(1.0, 0.0), (0.0, 1.0), (M3DB, 0.0), (0.0, M3DB)]),
+        // L R C Ls Rs
+        5 => Some(&[(1.0, 0.0), (0.0, 1.0), (M3DB, M3DB), (M3DB, 0.0), (0.0, M3DB)]),
+        // 5.1: L R C LFE Ls Rs
+        6 => Some(&[(1.0, 0.0), (0.0, 1.0), (M3DB, M3DB), (0.0, 0.0), (M3DB, 0.0), (0.0, M3DB)]),
+        // 6.1: L R C LFE Cs Ls Rs, the back centre split into both surrounds
+        7 => Some(&[(1.0, 0.0), (0.0, 1.0), (M3DB, M3DB), (0.0, 0.0), (0.5, 0.5), (M3DB, 0.0), (0.0, M3DB)]),
+        // 7.1: L R C LFE Lb Rb Ls Rs
+        8 => Some(&[(1.0, 0.0), (0.0, 1.0), (M3DB, M3DB), (0.0, 0.0), (M3DB, 0.0), (0.0, M3DB), (M3DB, 0.0), (0.0, M3DB)]),
+        _ => None,
+    };
+}
+
+/** can_downmix
+ * Checks if a channel count has a known layout to downmix from
+ * Parameters: Channel count
+ * Returns: true for 2 to 8 channels
+ */
+pub fn can_downmix(channels: u16) -> bool {
+    return stereo_gains(channels).is_some();
+}
+
+/** downmix
+ * Downmixes interleaved PCM to stereo or mono
+ *   Mono is the average of the stereo downmix
+ * Parameters: Interleaved PCM, Channel count, Target layout
+ * Returns: Downmixed interleaved PCM and its channel count,
+ *   unchanged if already at or below the target or for unknown layouts
+ */
+pub fn downmix(pcm: &[f64], in_ch: u16, layout: DownmixTarget) -> (Vec<f64>, u16) {
+    if in_ch <= layout.channels() { return (pcm.to_vec(), in_ch); }
+    let gains = match stereo_gains(in_ch) { Some(gains) => gains, None => return (pcm.to_vec(), in_ch) };
+
+    let mut out = Vec::with_capacity(pcm.len() / in_ch as usize * layout.channels() as usize);
+    for sample in pcm.chunks_exact(in_ch as usize) {
+        let (l, r) = sample.iter().zip(gains).fold((0.0, 0.0), |(l, r), (x, (gl, gr))| (l + x * gl, r + x * gr));
+        match layout {
+            DownmixTarget::Stereo => out.extend([l, r]),
+            DownmixTarget::Mono => out.push((l + r) / 2.0),
+        }
+    }
+    return (out, layout.channels());
+}
+
+/** db_to_linear
+ * Converts a level in dBFS to full scale amplitude
+ * Parameters: Level in dBFS
+ * Returns: Amplitude, 1.0 at 0 dBFS
+ */
+pub fn db_to_linear(db: f64) -> f64 {
+    return 10.0f64.powf(db / 20.0);
+}
+
+/** peak_gain
+ * Gets the gain taking a peak to the target level
+ * Parameters: Absolute peak of the PCM, Target peak in dBFS
+ * Returns: Linear gain, 1.0 for silence or a non-finite peak
+ */
+pub fn peak_gain(peak: f64, target_db: f64) -> f64 {
+    if !peak.is_finite() || peak <= 0.0 { return 1.0; }
+    return db_to_linear(target_db) / peak;
+}
+
+/** peak
+ * Gets the absolute peak of PCM
+ * Parameters: PCM
+ * Returns: Absolute peak, 0.0 for empty PCM
+ */
+pub fn peak(pcm: &[Vec<f64>]) -> f64 {
+    return pcm.iter().flatten().fold(0.0f64, |a, x| a.max(x.abs()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn db_to_linear_levels() {
+        assert_eq!(db_to_linear(0.0), 1.0);
+        assert!((db_to_linear(-6.0) - 0.501187).abs() < 1e-6);
+        assert!((db_to_linear(20.0) - 10.0).abs() < 1e-12);
+        assert_eq!(db_to_linear(f64::NEG_INFINITY), 0.0);
+    }
+
+    #[test]
+    fn peak_gain_takes_the_peak_to_the_target() {
+        assert!((peak_gain(0.5, 0.0) - 2.0).abs() < 1e-12);
+        assert!((peak_gain(0.25, -6.0) * 0.25 - db_to_linear(-6.0)).abs() < 1e-12);
+        assert!((peak_gain(2.0, 0.0) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn peak_gain_leaves_silence_and_non_finite_peaks() {
+        for peak in [0.0, -0.0, f64::NAN, f64::INFINITY, -1.0] { assert_eq!(peak_gain(peak, -1.0), 1.0); }
+    }
+
+    #[test]
+    fn peaks() {
+        assert_eq!(peak(&[]), 0.0);
+        assert_eq!(peak(&[vec![0.25, -0.75], vec![0.5, 0.0]]), 0.75);
+    }
+}
\ No newline at end of file
@@ -4,7 +4,23 @@
  * Description: Process information container
  */
 
-use std::{collections::HashMap, time::Instant};
+use std::{cell::Cell, collections::HashMap, time::{Duration, Instant}};
+
+#[cfg(feature = "progress-bar")]
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::{json, Value};
+
+// Shortest interval between two structured progress records
+const RECORD_INTERVAL: Duration = Duration::from_millis(500);
+
+/** LogFormat
+ * Progress log output format
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
 
 /** ProcessInfo
  * Struct for process information
@@ -12,9 +28,14 @@ use std::{collections::HashMap, time::Instant};
 pub struct ProcessInfo {
     pub start_time: Instant,
     t_block: Option<Instant>,
+    last_record: Cell<Option<Instant>>,
     total_size: u128,
+    input_size: u128,
+    total_input: u64,
     duration: HashMap<u32, u128>,
     bitrate: HashMap<u32, u128>,
+    #[cfg(feature = "progress-bar")]
+    bar: std::cell::OnceCell<ProgressBar>,
 }
 
 impl ProcessInfo {
@@ -22,9 +43,14 @@ impl ProcessInfo {
         ProcessInfo {
             start_time: Instant::now(),
             t_block: None,
+            last_record: Cell::new(None),
             duration: HashMap::new(),
             total_size: 0,
+            input_size: 0,
+            total_input: 0,
             bitrate: HashMap::new(),
+            #[cfg(feature = "progress-bar")]
+            bar: std::cell::OnceCell::new(),
         }
     }
 
@@ -39,6 +65,27 @@ impl ProcessInfo {
         self.bitrate.insert(srate, if self.bitrate.contains_key(&srate) { self.bitrate[&srate] } else { 0 } + size as u128);
     }
 
+    /** set_total_input_bytes
+     * Sets the total input size for progress estimation
+     * Parameters: Total input size in bytes, 0 if unknown
+     */
+    pub fn set_total_input_bytes(&mut self, total: u64) { self.total_input = total; }
+
+    /** update_input
+     * Accumulates processed input size
+     * Parameters: Input size
+     */
+    pub fn update_input(&mut self, size: usize) { self.input_size += size as u128; }
+
+    /** get_progress
+     * Gets the percentage of processed input
+     * Returns: Progress in percent, None if total input size is unknown
+     */
+    pub fn get_progress(&self) -> Option<f64> {
+        if self.total_input == 0 { return None; }
+        return Some((self.input_size as f64 / self.total_input as f64 * 100.0).min(100.0));
+    }
+
     /** get_duration
      * Gets the total duration of the stream in f64 seconds
      * Returns: Total duration
@@ -67,6 +114,49 @@ impl ProcessInfo {
         return if encoding_time > 0.0 { total_duration / encoding_time } else { 0.0 };
     }
 
+    /** get_record
+     * Gets the progress record for structured logging, at most one per RECORD_INTERVAL until done
+     * Parameters: Done flag
+     * Returns: JSON object of size, time, bitrate, speed, position, progress and done flag, None if throttled
+     */
+    pub fn get_record(&self, done: bool) -> Option<Value> {
+        let now = Instant::now();
+        if !done && self.last_record.get().is_some_and(|t| now.duration_since(t) < RECORD_INTERVAL) { return None; }
+        self.last_record.set(Some(now));
+        return Some(json!({
+            "size": self.total_size as u64,
+            "time": self.get_duration(),
+            "bitrate": self.get_bitrate(),
+            "speed": self.get_speed(),
+            "position": self.input_size as u64,
+            "progress": self.get_progress(),
+            "elapsed": self.start_time.elapsed().as_secs_f64(),
+            "done": done,
+        }));
+    }
+
+    /** draw_bar
+     * Draws a progress bar with percent and ETA in place of the text log line, for inputs of known size
+     *   Only with the progress-bar feature, pipes and inputs of unknown size fall back to the line
+     * Parameters: Status message, Finish flag
+     * Returns: Drawn flag
+     */
+    #[cfg(feature = "progress-bar")]
+    pub fn draw_bar(&self, message: String, finish: bool) -> bool {
+        if self.total_input == 0 { return false; }
+        let bar = self.bar.get_or_init(|| {
+            let bar = ProgressBar::new(self.total_input);
+            bar.set_style(ProgressStyle::with_template("[{bar:32}] {percent:>3}% ETA {eta} {msg}").unwrap().progress_chars("=> "));
+            bar
+        });
+        bar.set_position(self.input_size as u64);
+        bar.set_message(message);
+        if finish { bar.finish(); }
+        return true;
+    }
+    #[cfg(not(feature = "progress-bar"))]
+    pub fn draw_bar(&self, _message: String, _finish: bool) -> bool { return false; }
+
     /** get_total_size
      * Getter for private total_size
      * Returns: Total size
@@ -89,4 +179,18 @@ impl ProcessInfo {
             self.t_block = None;
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_throttled_until_done() {
+        let log = ProcessInfo::new();
+        assert!(log.get_record(false).is_some());
+        assert!(log.get_record(false).is_none());
+        let record = log.get_record(true).unwrap();
+        assert_eq!(record["done"], true);
+    }
 }
\ No newline at end of file
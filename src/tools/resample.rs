@@ -0,0 +1,127 @@
+/**                                Resampler                                  */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Band-limited windowed sinc resampler for interleaved f64 PCM
+ */
+
+use std::f64::consts::PI;
+
+// Zero crossings of the sinc on each side at the cutoff, and the cutoff relative to the lower Nyquist
+const ZERO_CROSSINGS: f64 = 16.0;
+const ROLLOFF: f64 = 0.95;
+
+/** Resampler
+ * Struct for streaming sample rate conversion
+ *   Output sample k sits at input position k * from / to, interpolated by a Blackman-windowed sinc
+ *   whose cutoff follows the lower of the two Nyquist frequencies
+ */
+pub struct Resampler {
+    from: u64, to: u64,
+    channels: usize,
+    cutoff: f64,
+    half: i64,
+
+    // Interleaved input from frame `offset`, `total` frames received so far
+    buffer: Vec<f64>,
+    offset: u64,
+    total: u64,
+    next: u64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: u16) -> Resampler {
+        let cutoff = (to as f64 / from as f64).min(1.0) * ROLLOFF;
+        return Resampler {
+            from: from as u64, to: to as u64,
+            channels: channels as usize,
+            cutoff,
+            half: (ZERO_CROSSINGS / cutoff).ceil() as i64,
+
+            buffer: Vec::new(),
+            offset: 0,
+            total: 0,
+            next: 0,
+        };
+    }
+
+    /** output_len
+     * Gets the output length of an input length
+     * Parameters: Input length in samples per channel
+     * Returns: Output length in samples per channel
+     */
+    pub fn output_len(&self, input_len: u64) -> u64 {
+        return (input_len * self.to).div_ceil(self.from);
+    }
+
+    /** kernel
+     * Windowed sinc tap
+     * Parameters: Distance from the output position in input samples
+     * Returns: Tap weight
+     */
+    fn kernel(&self, d: f64) -> f64 {
+        let x = d / self.half as f64;
+        if x.abs() >= 1.0 { return 0.0; }
+        let window = 0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos();
+        let t = PI * self.cutoff * d;
+        return window * if t == 0.0 { 1.0 } else { t.sin() / t };
+    }
+
+    /** emit
+     * Interpolates every output sample whose taps are all buffered, or all the rest at the end
+     * Parameters: End of stream flag
+     * Returns: Interleaved resampled PCM
+     */
+    fn emit(&mut self, end: bool) -> Vec<f64> {
+        let mut out = Vec::new();
+        let mut taps = Vec::with_capacity(2 * self.half as usize);
+        loop {
+            // 1. Output position in input samples
+            let num = self.next * self.from;
+            let (base, frac) = ((num / self.to) as i64, (num % self.to) as f64 / self.to as f64);
+            if end && num >= self.total * self.to { break; }
+            if !end && base + self.half >= self.total as i64 { break; }
+
+            // 2. Taps normalised to unity gain, shared by all channels
+            taps.clear();
+            taps.extend((base - self.half + 1..=base + self.half).map(|i| self.kernel((i - base) as f64 - frac)));
+            let gain: f64 = taps.iter().sum();
+
+            // 3. Weighted sum, samples outside the stream are silence
+            let start = out.len();
+            out.resize(start + self.channels, 0.0);
+            for (i, w) in (base - self.half + 1..=base + self.half).zip(&taps) {
+                if i < self.offset as i64 || i >= self.total as i64 { continue; }
+                let frame = (i as u64 - self.offset) as usize * self.channels;
+                out[start..].iter_mut().zip(&self.buffer[frame..frame + self.channels]).for_each(|(y, x)| *y += x * w / gain);
+            }
+            self.next += 1;
+        }
+
+        // 4. Drop the input no later output reaches
+        let keep = ((self.next * self.from / self.to) as i64 - self.half + 1).max(self.offset as i64) as u64;
+        self.buffer.drain(..((keep - self.offset) as usize * self.channels).min(self.buffer.len()));
+        self.offset = keep;
+        return out;
+    }
+
+    /** process
+     * Resamples a chunk of PCM, holding back the samples whose taps reach past it
+     * Parameters: Interleaved f64 PCM
+     * Returns: Interleaved resampled PCM
+     */
+    pub fn process(&mut self, pcm: &[f64]) -> Vec<f64> {
+        if self.channels == 0 { return Vec::new(); }
+        self.buffer.extend(pcm);
+        self.total += (pcm.len() / self.channels) as u64;
+        return self.emit(false);
+    }
+
+    /** flush
+     * Resamples the held back samples, padding the stream with silence
+     * Returns: Interleaved resampled PCM
+     */
+    pub fn flush(&mut self) -> Vec<f64> {
+        if self.channels == 0 { return Vec::new(); }
+        return self.emit(true);
+    }
+}
\ No newline at end of file
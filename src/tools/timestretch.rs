@@ -0,0 +1,236 @@
+/**                              Time Stretcher                               */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Phase vocoder for pitch-preserving speed change
+ * Dependencies: rustfft
+ */
+
+use std::{f64::consts::PI, sync::Arc};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+// Analysis window size and synthesis hop size
+const WINDOW_SIZE: usize = 2048;
+const SYNTH_HOP: usize = WINDOW_SIZE / 4;
+// Sum of squared Hann windows overlapping at 1/4 hop
+const WINDOW_GAIN: f64 = 1.5;
+// Speed range, keeping the analysis hop at least one sample and at most one window
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 4.0;
+
+/** wrap_phase
+ * Wraps a phase value into -PI ~ PI
+ * Parameters: Phase
+ * Returns: Wrapped phase
+ */
+fn wrap_phase(x: f64) -> f64 {
+    return x - 2.0 * PI * (x / (2.0 * PI)).round();
+}
+
+/** TimeStretch
+ * Struct for streaming phase vocoder
+ */
+pub struct TimeStretch {
+    speed: f64,
+    channels: usize,
+    window: Vec<f64>,
+    fft: Arc<dyn Fft<f64>>,
+    ifft: Arc<dyn Fft<f64>>,
+
+    input: Vec<Vec<f64>>,
+    output: Vec<Vec<f64>>,
+    analysis_pos: f64,
+    prev_start: f64,
+    last_phase: Vec<Vec<f64>>,
+    synth_phase: Vec<Vec<f64>>,
+    started: bool,
+}
+
+impl TimeStretch {
+    /** new
+     * Creates a stretcher, clamping the speed to MIN_SPEED ~ MAX_SPEED
+     * Parameters: Playback speed
+     * Returns: Time stretcher
+     */
+    pub fn new(speed: f64) -> TimeStretch {
+        let mut planner = FftPlanner::new();
+        return TimeStretch {
+            speed: if speed.is_finite() && speed > 0.0 { speed.clamp(MIN_SPEED, MAX_SPEED) } else { 1.0 },
+            channels: 0,
+            window: (0..WINDOW_SIZE).map(|n| 0.5 * (1.0 - (2.0 * PI * n as f64 / WINDOW_SIZE as f64).cos())).collect(),
+            fft: planner.plan_fft_forward(WINDOW_SIZE),
+            ifft: planner.plan_fft_inverse(WINDOW_SIZE),
+
+            input: Vec::new(),
+            output: Vec::new(),
+            analysis_pos: 0.0,
+            prev_start: 0.0,
+            last_phase: Vec::new(),
+            synth_phase: Vec::new(),
+            started: false,
+        };
+    }
+
+    /** reset
+     * Resets the stretcher state for a new channel layout
+     * Parameters: Channel count
+     */
+    fn reset(&mut self, channels: usize) {
+        self.channels = channels;
+        self.input = vec![Vec::new(); channels];
+        self.output = vec![vec![0.0; WINDOW_SIZE]; channels];
+        self.analysis_pos = 0.0;
+        self.prev_start = 0.0;
+        self.last_phase = vec![vec![0.0; WINDOW_SIZE]; channels];
+        self.synth_phase = vec![vec![0.0; WINDOW_SIZE]; channels];
+        self.started = false;
+    }
+
+    /** frame
+     * Processes one analysis frame and overlap-adds it to the output
+     * Parameters: Frame start in input buffer, Analysis hop size
+     * Returns: Synthesised PCM of one synthesis hop
+     */
+    fn frame(&mut self, start: usize, hop: f64) -> Vec<Vec<f64>> {
+        let mut ret = Vec::with_capacity(self.channels);
+        for c in 0..self.channels {
+            let mut spec: Vec<Complex<f64>> = self.input[c][start..start + WINDOW_SIZE].iter()
+                .zip(&self.window).map(|(x, w)| Complex::new(x * w, 0.0)).collect();
+            self.fft.process(&mut spec);
+
+            for (k, bin) in spec.iter_mut().enumerate() {
+                let (mag, phase) = bin.to_polar();
+                if !self.started || hop <= 0.0 { self.synth_phase[c][k] = phase; }
+                else {
+                    // Instantaneous frequency from the phase advance over the analysis hop
+                    let omega = 2.0 * PI * k as f64 / WINDOW_SIZE as f64;
+                    let delta = wrap_phase(phase - self.last_phase[c][k] - omega * hop);
+                    self.synth_phase[c][k] += (omega + delta / hop) * SYNTH_HOP as f64;
+                }
+                self.last_phase[c][k] = phase;
+                *bin = Complex::from_polar(mag, self.synth_phase[c][k]);
+            }
+            self.ifft.process(&mut spec);
+
+            for (i, x) in spec.iter().enumerate() {
+                self.output[c][i] += x.re / WINDOW_SIZE as f64 * self.window[i] / WINDOW_GAIN;
+            }
+            let mut hop_out: Vec<f64> = self.output[c].drain(..SYNTH_HOP).collect();
+            self.output[c].extend(vec![0.0; SYNTH_HOP]);
+            hop_out.iter_mut().for_each(|x| *x = x.clamp(-1.0, 1.0));
+            ret.push(hop_out);
+        }
+        self.started = true;
+        return ret;
+    }
+
+    /** process
+     * Time-stretches the input PCM, flushing the old layout first on a channel count change
+     * Parameters: PCM (samples × channels)
+     * Returns: Time-stretched PCM (samples × channels)
+     */
+    pub fn process(&mut self, pcm: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let channels = match pcm.first() { Some(s) if !s.is_empty() => s.len(), _ => return Vec::new() };
+        if self.speed == 1.0 { return pcm; }
+        if channels != self.channels {
+            let mut ret = self.flush();
+            self.reset(channels);
+            ret.extend(self.process(pcm));
+            return ret;
+        }
+        for sample in pcm { for (c, x) in sample.into_iter().take(channels).enumerate() { self.input[c].push(x); } }
+
+        let mut ret: Vec<Vec<f64>> = vec![Vec::new(); self.channels];
+        let hop = SYNTH_HOP as f64 * self.speed;
+        while self.analysis_pos as usize + WINDOW_SIZE <= self.input[0].len() {
+            let start = self.analysis_pos as usize;
+            for (c, hop_out) in self.frame(start, start as f64 - self.prev_start).into_iter().enumerate() { ret[c].extend(hop_out); }
+            self.prev_start = start as f64;
+            self.analysis_pos += hop;
+        }
+
+        // Drop consumed input
+        let consumed = (self.analysis_pos as usize).min(self.prev_start as usize);
+        self.input.iter_mut().for_each(|x| { x.drain(..consumed); });
+        self.analysis_pos -= consumed as f64;
+        self.prev_start -= consumed as f64;
+
+        return (0..ret[0].len()).map(|i| ret.iter().map(|x| x[i]).collect()).collect();
+    }
+
+    /** flush
+     * Flushes the remaining input
+     * Returns: Time-stretched PCM (samples × channels)
+     */
+    pub fn flush(&mut self) -> Vec<Vec<f64>> {
+        if self.channels == 0 || self.speed == 1.0 || self.input[0].is_empty() { return Vec::new(); }
+        let ret = self.process(vec![vec![0.0; self.channels]; WINDOW_SIZE]);
+        self.reset(self.channels);
+        return ret;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** sine
+     * Generates a stereo sine for the stretcher
+     * Parameters: Sample count, Channel count
+     * Returns: PCM (samples × channels)
+     */
+    fn sine(len: usize, channels: usize) -> Vec<Vec<f64>> {
+        return (0..len).map(|i| vec![0.5 * (i as f64 * 0.05).sin(); channels]).collect();
+    }
+
+    /** stretch
+     * Runs the whole input through a stretcher in 1000-sample blocks
+     * Parameters: Stretcher, PCM
+     * Returns: Time-stretched PCM
+     */
+    fn stretch(ts: &mut TimeStretch, pcm: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let mut ret = Vec::new();
+        for block in pcm.chunks(1000) { ret.extend(ts.process(block.to_vec())); }
+        ret.extend(ts.flush());
+        return ret;
+    }
+
+    #[test]
+    fn extreme_speeds_stay_finite() {
+        for speed in [1e-6, 0.001, 0.3, 3.0, 1e6, f64::NAN, f64::INFINITY, -2.0] {
+            let out = stretch(&mut TimeStretch::new(speed), sine(20000, 2));
+            assert!(out.iter().flatten().all(|x| x.is_finite()), "speed {}", speed);
+        }
+    }
+
+    #[test]
+    fn output_length_follows_speed() {
+        for speed in [0.5, 2.0] {
+            let out = stretch(&mut TimeStretch::new(speed), sine(40000, 1));
+            let expected = 40000.0 / speed;
+            assert!((out.len() as f64 - expected).abs() < WINDOW_SIZE as f64 * 2.0, "speed {}: {}", speed, out.len());
+        }
+    }
+
+    #[test]
+    fn empty_input_is_ignored() {
+        let mut ts = TimeStretch::new(2.0);
+        assert!(ts.process(Vec::new()).is_empty());
+        assert!(ts.process(vec![Vec::new(); 10]).is_empty());
+        assert!(ts.flush().is_empty());
+    }
+
+    #[test]
+    fn channel_change_keeps_buffered_input() {
+        let mut ts = TimeStretch::new(0.5);
+        let mut out = ts.process(sine(1500, 2));
+        let before = out.len();
+        out.extend(ts.process(sine(1500, 1)));
+        out.extend(ts.flush());
+
+        // The stereo tail is written out before any mono samples
+        let stereo = out.iter().take_while(|s| s.len() == 2).count();
+        assert!(stereo > before);
+        assert!(out[stereo..].iter().all(|s| s.len() == 1));
+        assert!(stereo as f64 >= 1500.0 / 0.5 - WINDOW_SIZE as f64);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,136 @@
+/**                                VorbisMeta                                 */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Reader and writer of VorbisMeta, the key=value metadata text format
+ *   Backslash escapes newlines(\n), carriage returns(\r), backslashes(\\) and '=' in keys(\=),
+ *   so any UTF-8 key and value written is read back as it was.
+ *   Unescaped lines without '=' still continue the previous value, as in hand-written files
+ */
+
+/** escape
+ * Escapes a key or value for a line
+ * Parameters: Text, Key flag(escape '=' as well)
+ * Returns: Escaped text
+ */
+fn escape(text: &str, key: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '=' if key => out.push_str("\\="),
+            _ => out.push(c),
+        }
+    }
+    return out;
+}
+
+/** split_line
+ * Splits a line at its first unescaped '=' and unescapes both sides
+ *   Unknown escapes are kept as they are
+ * Parameters: Line
+ * Returns: Key and value, None if the line has no unescaped '='
+ */
+fn split_line(line: &str) -> (String, Option<String>) {
+    // Key, then value once an unescaped '=' is met
+    let mut parts = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '=' && parts.len() == 1 { parts.push(String::new()); continue; }
+        let out = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('=') => out.push('='),
+                Some(x) => { out.push('\\'); out.push(x); },
+                None => out.push('\\'),
+            },
+            _ => out.push(c),
+        }
+    }
+    let value = if parts.len() > 1 { parts.pop() } else { None };
+    return (parts.pop().unwrap(), value);
+}
+
+/** parse
+ * Parses VorbisMeta text
+ * Parameters: VorbisMeta text
+ * Returns: Metadata
+ */
+pub fn parse(contents: &str) -> Vec<(String, Vec<u8>)> {
+    let mut meta: Vec<(String, Vec<u8>)> = Vec::new();
+    for line in contents.lines() {
+        match split_line(line) {
+            (key, Some(value)) => meta.push((key, value.into_bytes())),
+            (text, None) => {
+                if let Some(last) = meta.last_mut() { last.1.extend(format!("\n{}", text).as_bytes()); }
+                else { meta.push((String::new(), text.into_bytes())); }
+            }
+        }
+    }
+    return meta;
+}
+
+/** build
+ * Writes metadata as VorbisMeta text, one line per entry
+ * Parameters: Metadata
+ * Returns: VorbisMeta text, Keys of the entries left out as their values are not UTF-8
+ */
+pub fn build(meta: &[(String, Vec<u8>)]) -> (String, Vec<String>) {
+    let (mut text, mut skipped) = (String::new(), Vec::new());
+    for (key, value) in meta {
+        match std::str::from_utf8(value) {
+            Ok(value) => text.push_str(&format!("{}={}\n", escape(key, true), escape(value, false))),
+            Err(_) => skipped.push(key.clone()),
+        }
+    }
+    return (text, skipped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** entries
+     * Makes metadata from string pairs
+     */
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, Vec<u8>)> {
+        return pairs.iter().map(|(k, v)| (k.to_string(), v.as_bytes().to_vec())).collect();
+    }
+
+    #[test]
+    fn round_trips_escaped_entries() {
+        let meta = entries(&[
+            ("TITLE", "a=b=c"),
+            ("KEY=WITH=EQUALS", "=leading and trailing="),
+            ("LYRICS", "line one\nline two\r\nline three\n"),
+            ("PATH", "C:\\new\\rtf \\= \\\\"),
+            ("아티스트", "하뮬 – המול 🎵"),
+            ("", "empty key"),
+            ("EMPTY", ""),
+            ("TITLE", "duplicate keys stay in order"),
+        ]);
+        let (text, skipped) = build(&meta);
+        assert!(skipped.is_empty());
+        assert_eq!(text.lines().count(), meta.len());
+        assert_eq!(parse(&text), meta);
+    }
+
+    #[test]
+    fn skips_values_that_are_not_utf8() {
+        let mut meta = entries(&[("TITLE", "kept")]);
+        meta.push(("BINARY".to_string(), vec![0xff, 0xfe, 0x00]));
+        let (text, skipped) = build(&meta);
+        assert_eq!(skipped, vec!["BINARY".to_string()]);
+        assert_eq!(parse(&text), entries(&[("TITLE", "kept")]));
+    }
+
+    #[test]
+    fn reads_hand_written_continuations() {
+        let text = "opening line\nCOMMENT=first\nsecond\nthird\nTITLE=x\\qy\\";
+        assert_eq!(parse(text), entries(&[("", "opening line"), ("COMMENT", "first\nsecond\nthird"), ("TITLE", "x\\qy\\")]));
+    }
+}
\ No newline at end of file
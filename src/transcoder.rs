@@ -0,0 +1,98 @@
+/**                           Transcode application                           */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Transcoder implementation example, re-encoding FrAD to another profile
+ */
+
+use frad::{head, profiles::{compact, COMPACT}, DecodeResult, Decoder, Encoder, Endian::Big, PCMFormat, BIT_DEPTHS};
+use crate::{
+    common::{get_file_stem, get_input_size, or_exit, read_exact, write_safe, PIPEIN},
+    encoder::{configure, logging_encode, set_files, warn_inexact},
+    header::read_header,
+    tools::{cli::CliParams, process::ProcessInfo}
+};
+use std::{io::Write, process::exit};
+
+/** feed
+ * Re-encodes decoded PCM, restarting the encoder on a format change
+ * Parameters: Encoder, Decoded PCM, Source bit depth, CLI parameters, Output file, Process info
+ */
+fn feed(encoder: &mut Encoder, decoded: DecodeResult, src_bits: u16, params: &CliParams, writefile: &mut Box<dyn Write>, procinfo: &mut ProcessInfo) {
+    if decoded.is_empty() { return; }
+    let channels = decoded.channels() as u16;
+
+    // 1. Flush the previous segment and set the new format
+    if (encoder.get_srate(), encoder.get_channels()) != (decoded.srate, channels) {
+        if COMPACT.contains(&params.profile) && !compact::SRATES.contains(&decoded.srate) {
+            eprintln!("Sample rate {} is not supported by profile {}, valid rates: {:?}", decoded.srate, params.profile, compact::SRATES);
+            exit(1);
+        }
+        let encoded = or_exit(encoder.flush());
+        procinfo.update(encoded.buf.len(), encoded.samples, encoder.get_srate());
+        write_safe(writefile, &encoded.buf);
+        or_exit(encoder.set_srate(decoded.srate));
+        or_exit(encoder.set_channels(channels));
+    }
+    // 2. Keep the source bit depth unless one is given or the profile lacks it
+    if encoder.get_bit_depth() == 0 {
+        let depths = BIT_DEPTHS[params.profile as usize];
+        or_exit(encoder.set_bit_depth(if params.bits != 0 { params.bits } else if depths.contains(&src_bits) && src_bits != 0 { src_bits } else { 16 }));
+    }
+
+    // 3. Encode
+    let encoded = or_exit(encoder.process(&decoded.bytes));
+    procinfo.update(encoded.buf.len(), encoded.samples, encoder.get_srate());
+    write_safe(writefile, &encoded.buf);
+}
+
+/** transcode
+ * Decodes a FrAD stream and re-encodes it to the target profile, keeping its metadata and images
+ * Parameters: Input file, CLI parameters
+ * Returns: Transcoded FrAD on File or stdout
+ */
+pub fn transcode(input: String, mut params: CliParams) {
+    if input.is_empty() { eprintln!("Input file must be given"); exit(1); }
+
+    // 1. Header of the source, pipe input has none to read ahead
+    let (meta, images) = if !PIPEIN.contains(&input.as_str()) { read_header(&input) } else { (Vec::new(), Vec::new()) };
+    let (preroll, postroll) = head::get_roll(&meta);
+    let total = head::get_total_samples(&meta).map(|n| preroll + n + postroll);
+
+    // Default output beside the input, as the same extension would overwrite it
+    if params.output.is_empty() { params.output = format!("{}_p{}", get_file_stem(&input), params.profile); }
+    let input_size = get_input_size(&input);
+    let (mut readfile, mut writefile) = set_files(input, params.output.clone(), params.profile, params.overwrite);
+
+    let mut decoder = Decoder::new(params.enable_ecc);
+    decoder.set_output_format(Some(PCMFormat::F64(Big)));
+    decoder.set_overlap_window(params.overlap_window);
+    decoder.set_total_samples(total);
+    let mut encoder = or_exit(Encoder::new(params.profile, PCMFormat::F64(Big)));
+    configure(&mut encoder, &params);
+
+    write_safe(&mut writefile, &head::builder(&meta, &images, &[]));
+
+    // 2. Decode and re-encode
+    let mut procinfo = ProcessInfo::new();
+    procinfo.set_total_input_bytes(input_size);
+    loop {
+        let mut buf = vec![0u8; 32768];
+        let readlen = read_exact(&mut readfile, &mut buf);
+        if readlen == 0 && decoder.is_empty() { break; }
+
+        let decoded = decoder.process(&buf[..readlen]);
+        procinfo.update_input(readlen);
+        let src_bits = decoder.get_asfh().bit_depth_bits();
+        feed(&mut encoder, decoded, src_bits, &params, &mut writefile, &mut procinfo);
+        logging_encode(params.loglevel, params.logfmt, &procinfo, false);
+    }
+    let decoded = decoder.flush();
+    let src_bits = decoder.get_asfh().bit_depth_bits();
+    feed(&mut encoder, decoded, src_bits, &params, &mut writefile, &mut procinfo);
+
+    let encoded = or_exit(encoder.flush());
+    procinfo.update(encoded.buf.len(), encoded.samples, encoder.get_srate());
+    write_safe(&mut writefile, &encoded.buf);
+    logging_encode(params.loglevel, params.logfmt, &procinfo, true);
+    warn_inexact(&encoder);
+}
\ No newline at end of file
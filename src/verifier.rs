@@ -0,0 +1,142 @@
+/**                            Verify application                             */
+/**
+ * Copyright 2024 HaמuL
+ * Description: Round-trip verifier, encoding and decoding in memory against the source PCM
+ */
+
+use frad::{f64cvt::any_to_f64, profiles::LOSSLESS, Decoder, Encoder};
+use crate::{
+    common::{get_input_size, or_exit, read_exact, PIPEIN},
+    encoder::{configure, read_wav, warn_inexact},
+    tools::cli::CliParams
+};
+use std::{collections::VecDeque, fs::File, io::Read, path::Path, process::exit};
+
+/** Comparison
+ * Running comparison of the decoded PCM with the source PCM
+ */
+struct Comparison {
+    channels: usize,
+    // Source samples not decoded yet, interleaved
+    reference: VecDeque<f64>,
+    compared: u64, extra: u64,
+    // First differing sample: index, channel, source, decoded
+    first_diff: Option<(u64, usize, f64, f64)>,
+    diffs: u64,
+    peak: f64, err_energy: f64, ref_energy: f64,
+}
+
+impl Comparison {
+    fn new(channels: usize) -> Comparison {
+        return Comparison {
+            channels, reference: VecDeque::new(),
+            compared: 0, extra: 0,
+            first_diff: None, diffs: 0,
+            peak: 0.0, err_energy: 0.0, ref_energy: 0.0,
+        };
+    }
+
+    /** compare
+     * Compares decoded PCM with the oldest source samples
+     * Parameters: Decoded PCM
+     */
+    fn compare(&mut self, pcm: &[Vec<f64>]) {
+        for sample in pcm {
+            // Decoded past the source, e.g. the padding of the last Compact frame
+            if self.reference.len() < self.channels { self.extra += 1; continue; }
+            for c in 0..self.channels {
+                let x = self.reference.pop_front().unwrap();
+                let y = sample.get(c).copied().unwrap_or(f64::NAN);
+                let err = (x - y).abs();
+                if x != y {
+                    if self.first_diff.is_none() { self.first_diff = Some((self.compared, c, x, y)); }
+                    self.diffs += 1;
+                }
+                self.peak = if err.is_nan() { f64::INFINITY } else { self.peak.max(err) };
+                (self.err_energy, self.ref_energy) = (self.err_energy + err * err, self.ref_energy + x * x);
+            }
+            self.compared += 1;
+        }
+    }
+
+    /** missing
+     * Gets the source samples per channel never decoded
+     * Returns: Sample count
+     */
+    fn missing(&self) -> u64 { return (self.reference.len() / self.channels) as u64; }
+}
+
+/** dbfs
+ * Converts a linear level to dBFS
+ * Parameters: Linear level
+ * Returns: Level in dBFS
+ */
+fn dbfs(x: f64) -> f64 { return 20.0 * x.log10(); }
+
+/** verify
+ * Encodes PCM or WAV and decodes it back in memory, comparing every sample with the source
+ *   Lossless profiles must round-trip bit-exact, lossy ones report their peak and RMS errors
+ * Parameters: Input file, CLI parameters
+ */
+pub fn verify(input: String, mut params: CliParams) {
+    if input.is_empty() { eprintln!("Input file must be given"); exit(1); }
+    let rpipe = PIPEIN.contains(&input.as_str());
+    if !rpipe && !Path::new(&input).exists() { eprintln!("Input file doesn't exist"); exit(1); }
+
+    let readfile: Box<dyn Read> = if !rpipe { Box::new(File::open(&input).unwrap()) } else { Box::new(std::io::stdin()) };
+    let (mut readfile, _) = read_wav(readfile, get_input_size(&input), &mut params);
+    if params.srate == 0 { eprintln!("Sample rate should be set except zero"); exit(1); }
+    if params.channels == 0 { eprintln!("Channel count should be set except zero"); exit(1); }
+
+    // 1. Encoder as the encode action would set it, and a decoder reading back its output
+    let mut encoder = or_exit(Encoder::new(params.profile, params.pcm));
+    or_exit(encoder.set_srate(params.srate));
+    or_exit(encoder.set_channels(params.channels));
+    or_exit(encoder.set_bit_depth(params.bits));
+    configure(&mut encoder, &params);
+    // Source samples the bit depth cannot hold are the usual cause of a mismatch, so say so
+    encoder.set_check_inexact(!params.quiet);
+    let mut decoder = Decoder::new(false);
+    decoder.set_overlap_window(params.overlap_window);
+
+    // 2. Source samples are queued until their decoded counterparts come out
+    let bytes_per_sample = params.pcm.bit_depth() / 8;
+    let mut cmp = Comparison::new(params.channels as usize);
+    let mut rest = Vec::new();
+    loop {
+        let mut buf = vec![0u8; 32768];
+        let readlen = read_exact(&mut readfile, &mut buf);
+        if readlen == 0 { break; }
+
+        rest.extend(&buf[..readlen]);
+        let whole = rest.len() / bytes_per_sample * bytes_per_sample;
+        cmp.reference.extend(rest.drain(..whole).collect::<Vec<u8>>().chunks(bytes_per_sample).map(|x| any_to_f64(x, &params.pcm)));
+
+        let encoded = or_exit(encoder.process(&buf[..readlen]));
+        cmp.compare(&decoder.process(&encoded.buf).pcm);
+    }
+    let encoded = or_exit(encoder.flush());
+    cmp.compare(&decoder.process(&encoded.buf).pcm);
+    cmp.compare(&decoder.flush().pcm);
+    warn_inexact(&encoder);
+
+    // 3. Report
+    let lossless = LOSSLESS.contains(&params.profile);
+    println!("Profile {}, {} bits, {} samples x {} channels compared", params.profile, params.bits, cmp.compared, cmp.channels);
+    if cmp.missing() != 0 { println!("{} source samples were not decoded", cmp.missing()); }
+    if cmp.extra != 0 { println!("{} samples were decoded past the source", cmp.extra); }
+
+    if lossless {
+        if let Some((index, channel, x, y)) = cmp.first_diff {
+            println!("First difference at sample {}, channel {}: {} -> {}", index, channel, x, y);
+            println!("{} samples differ, max error {:e} ({:.2} dBFS)", cmp.diffs, cmp.peak, dbfs(cmp.peak));
+        }
+        if cmp.first_diff.is_some() || cmp.missing() != 0 || cmp.extra != 0 { println!("Not lossless"); exit(1); }
+        println!("Bit-exact");
+    }
+    else {
+        let rms = (cmp.err_energy / (cmp.compared * cmp.channels as u64).max(1) as f64).sqrt();
+        println!("Peak error {:e} ({:.2} dBFS)", cmp.peak, dbfs(cmp.peak));
+        println!("RMS error {:e} ({:.2} dBFS), SNR {:.2} dB", rms, dbfs(rms), 10.0 * (cmp.ref_energy / cmp.err_energy).log10());
+    }
+}
\ No newline at end of file